@@ -0,0 +1,50 @@
+// 各基准测试共用的编译、创建 VM 和计时代码（每个基准测试只用到其中一部分）
+#![allow(dead_code)]
+
+use dkv_script::{CompileResult, Compiler, Lexer, Parser, VM};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+pub fn compile(source: &str, level: u8) -> CompileResult {
+    let ast = Parser::new(Lexer::new(source.to_string())).parse();
+    Compiler::new().with_optimization_level(level).compile(&ast)
+}
+
+// 创建 VM，print 的输出被丢弃；模拟 DKV 服务器：GET 返回 "OK"，其余命令返回命令长度
+pub fn new_vm(compile_result: &CompileResult) -> VM {
+    let mut vm = VM::new(compile_result.clone());
+    vm.set_print_handler(Some(|text: &str| {
+        black_box(text);
+    }));
+    vm.set_dkv_command_handler(Some(|command: &str| match command.starts_with("GET ") {
+        true => Ok("OK".to_string()),
+        false => Ok(command.len().to_string()),
+    }));
+    vm
+}
+
+// 执行 routine runs 次，返回最后一次的结果和最短耗时
+pub fn fastest<T>(runs: usize, mut routine: impl FnMut() -> T) -> (T, Duration) {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..runs {
+        let start = Instant::now();
+        result = Some(black_box(routine()));
+        best = best.min(start.elapsed());
+    }
+    (result.expect("runs must be positive"), best)
+}
+
+// 运行程序 runs 次（不计创建 VM 的时间），返回执行的指令数和最短耗时
+pub fn measure_run(compile_result: &CompileResult, runs: usize) -> (u64, Duration) {
+    let mut instructions = 0;
+    let mut best = Duration::MAX;
+    for _ in 0..runs {
+        let mut vm = new_vm(compile_result);
+        let start = Instant::now();
+        vm.run();
+        best = best.min(start.elapsed());
+        instructions = vm.instruction_count();
+    }
+    (instructions, best)
+}
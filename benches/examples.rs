@@ -0,0 +1,108 @@
+// examples/ 中各脚本的编译和执行时间：cargo bench --bench examples [过滤字符串]
+// 仿照 criterion：先预热，再按估算的迭代次数采集多个样本，输出单次迭代的均值、标准差和中位数，
+// 并与上一次运行保存的结果比较
+mod common;
+
+use common::{compile, new_vm};
+use std::collections::HashMap;
+use std::fs;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const SAMPLE_TIME: Duration = Duration::from_millis(5);
+const SAMPLES: usize = 40;
+
+type Phase<'a> = (&'static str, Box<dyn FnMut() + 'a>);
+
+struct Estimate {
+    mean: f64,
+    std_dev: f64,
+    median: f64,
+}
+
+// 预热并估算每个样本的迭代次数，然后采集样本，返回单次迭代的耗时（纳秒）
+fn bench<F: FnMut()>(mut routine: F) -> Estimate {
+    let start = Instant::now();
+    let mut warm_up_iterations = 0u64;
+    while start.elapsed() < WARM_UP {
+        routine();
+        warm_up_iterations += 1;
+    }
+    let per_iteration = start.elapsed().as_nanos() as f64 / warm_up_iterations as f64;
+    let iterations = ((SAMPLE_TIME.as_nanos() as f64 / per_iteration) as u64).max(1);
+
+    let mut samples: Vec<f64> = (0..SAMPLES).map(|_| {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            routine();
+        }
+        start.elapsed().as_nanos() as f64 / iterations as f64
+    }).collect();
+    samples.sort_by(f64::total_cmp);
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let variance = samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64;
+    Estimate { mean, std_dev: variance.sqrt(), median: samples[samples.len() / 2] }
+}
+
+fn format_time(nanos: f64) -> String {
+    match nanos {
+        nanos if nanos < 1e3 => format!("{:.1} ns", nanos),
+        nanos if nanos < 1e6 => format!("{:.2} µs", nanos / 1e3),
+        nanos => format!("{:.2} ms", nanos / 1e6),
+    }
+}
+
+// 上一次运行的结果保存在 target 目录中
+fn baseline_path() -> PathBuf {
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from("target"), PathBuf::from);
+    target_dir.join("examples-bench-baseline.txt")
+}
+
+fn main() {
+    // cargo bench 会传入 --bench，其余参数为过滤字符串
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let baseline: HashMap<String, f64> = fs::read_to_string(baseline_path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.rsplit_once(' '))
+        .filter_map(|(name, mean)| Some((name.to_string(), mean.parse().ok()?)))
+        .collect();
+
+    let mut paths: Vec<PathBuf> = fs::read_dir("examples").expect("examples directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "dkvs"))
+        .collect();
+    paths.sort();
+
+    let mut results = Vec::new();
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let source = fs::read_to_string(&path).unwrap();
+        let compile_result = compile(&source, 2);
+        let phases: [Phase; 2] = [
+            ("compile", Box::new(|| {
+                black_box(compile(black_box(&source), 2));
+            })),
+            ("run", Box::new(|| new_vm(black_box(&compile_result)).run())),
+        ];
+        for (phase, routine) in phases {
+            let id = format!("{}/{}", name, phase);
+            if filter.as_ref().is_some_and(|filter| !id.contains(filter.as_str())) {
+                continue;
+            }
+            let estimate = bench(routine);
+            let change = match baseline.get(&id) {
+                Some(previous) => format!("{:+.1}%", (estimate.mean / previous - 1.0) * 100.0),
+                None => String::new(),
+            };
+            println!("{:<36} mean {:>10} ± {:>10}  median {:>10}  {}",
+                id, format_time(estimate.mean), format_time(estimate.std_dev), format_time(estimate.median), change);
+            results.push(format!("{} {}", id, estimate.mean));
+        }
+    }
+    if let Err(err) = fs::write(baseline_path(), results.join("\n")) {
+        eprintln!("Cannot save baseline: {}", err);
+    }
+}
@@ -0,0 +1,157 @@
+// 词法分析、语法分析、编译和执行各阶段的耗时与吞吐量：cargo bench --bench pipeline [过滤字符串]
+// 词法、语法分析和编译按源码字节数计算吞吐量，执行按 VM 分派的指令数计算
+mod common;
+
+use common::{fastest, measure_run};
+use dkv_script::{Compiler, Lexer, Parser, TokenType};
+use std::hint::black_box;
+use std::time::Duration;
+
+const RUNS: usize = 10;
+
+const ARITHMETIC: &str = r#"
+fn main() {
+    let i: int = 0;
+    let sum: int = 0;
+    let x: float = 0.5;
+    while i < 200000 {
+        sum = sum / 2 + i * 3 - i / 7;
+        x = x * 1.000001 + 0.25;
+        i++;
+    }
+    print(sum);
+    print(x);
+}
+"#;
+
+const FIB: &str = r#"
+fn fib(n int) -> int {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+fn main() {
+    print(fib(22));
+}
+"#;
+
+const STRING_CONCAT: &str = r#"
+fn main() {
+    let text: string = "";
+    let i: int = 0;
+    while i < 20000 {
+        text = text + "k" + string(i - i / 10 * 10);
+        i++;
+    }
+    print(len(text));
+}
+"#;
+
+const COMMANDS: &str = r#"
+fn main() {
+    let i: int = 0;
+    let ok: int = 0;
+    while i < 20000 {
+        command(f"SET key:{i} {i}");
+        if command("GET key:" + string(i)) == "OK" {
+            ok++;
+        }
+        i++;
+    }
+    print(ok);
+}
+"#;
+
+// 生成包含大量函数、结构体和枚举的源码，主要用于测量前端和编译器
+fn large_source() -> String {
+    let mut source = String::new();
+    for n in 0..400 {
+        source += &format!(r#"
+struct Point{n} {{ x: int, y: int, name: string }}
+enum Reply{n} {{ Ok, Value(string, int) }}
+/// 第 {n} 组函数
+fn step{n}(p Point{n}, limit int) -> int {{
+    let total: int = 0;
+    let i: int = 0;
+    while i < limit {{
+        if i / 2 * 2 == i {{
+            total = total + p.x * i;
+        }} else {{
+            total = total - p.y;
+        }}
+        i++;
+    }}
+    let r: Reply{n} = Reply{n}::Value(f"{{p.name}}-{{total}}", total);
+    return match r {{
+        Reply{n}::Ok => 0,
+        Reply{n}::Value(text, value) => value + len(text),
+    }};
+}}
+"#);
+    }
+    source += "fn main() {\n    let total: int = 0;\n";
+    for n in 0..400 {
+        source += &format!("    total = total + step{n}(Point{n} {{ x: {n}, y: 1, name: \"p{n}\" }}, 10);\n");
+    }
+    source += "    print(total);\n}\n";
+    source
+}
+
+struct Phases {
+    lex: Duration,
+    parse: Duration,
+    compile: Duration,
+    run: Duration,
+    instructions: u64,
+}
+
+fn lex(source: &str) -> usize {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut tokens = 0;
+    while lexer.next_token().token_type != TokenType::Eof {
+        tokens += 1;
+    }
+    tokens
+}
+
+// 每个阶段运行 RUNS 次，取最短耗时
+fn measure(source: &str) -> Phases {
+    let (_, lex_time) = fastest(RUNS, || lex(black_box(source)));
+    let (ast, parse) = fastest(RUNS, || Parser::new(Lexer::new(black_box(source).to_string())).parse());
+    let (compile_result, compile) = fastest(RUNS, || Compiler::new().with_optimization_level(2).compile(black_box(&ast)));
+    let (instructions, run) = measure_run(&compile_result, RUNS);
+    Phases { lex: lex_time, parse, compile, run, instructions }
+}
+
+fn megabytes_per_second(bytes: usize, time: Duration) -> f64 {
+    bytes as f64 / time.as_secs_f64() / 1e6
+}
+
+fn main() {
+    // cargo bench 会传入 --bench，其余参数为过滤字符串
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let large = large_source();
+    let workloads = [
+        ("arithmetic loop", ARITHMETIC),
+        ("fib", FIB),
+        ("string concat", STRING_CONCAT),
+        ("commands", COMMANDS),
+        ("large source", large.as_str()),
+    ];
+    println!("{:<16} {:>9} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "workload", "bytes", "lex", "MB/s", "parse", "MB/s", "compile", "MB/s", "run", "Minsn/s");
+    for (name, source) in workloads {
+        if filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            continue;
+        }
+        let phases = measure(source);
+        let bytes = source.len();
+        println!("{:<16} {:>9} {:>10.2?} {:>10.1} {:>10.2?} {:>10.1} {:>10.2?} {:>10.1} {:>10.2?} {:>10.1}",
+            name, bytes,
+            phases.lex, megabytes_per_second(bytes, phases.lex),
+            phases.parse, megabytes_per_second(bytes, phases.parse),
+            phases.compile, megabytes_per_second(bytes, phases.compile),
+            phases.run, phases.instructions as f64 / phases.run.as_secs_f64() / 1e6);
+    }
+}
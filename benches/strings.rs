@@ -0,0 +1,82 @@
+// 字符串密集的脚本（拼接 DKV 命令、读写字符串变量）的执行时间：cargo bench --bench strings
+mod common;
+
+use common::{compile, measure_run};
+
+const RUNS: usize = 30;
+
+const WORKLOADS: [(&str, &str); 3] = [
+    ("command builder", r#"
+const PREFIX: string = "user:session:0123456789abcdef:";
+fn main() {
+    let value: string = "payload-0123456789-";
+    let i: int = 0;
+    while i < 5 {
+        value = value + value;
+        i++;
+    }
+    i = 0;
+    let sent: int = 0;
+    while i < 20000 {
+        let key: string = PREFIX + string(i);
+        let payload: string = value;
+        command(f"SET {key} {payload}");
+        command("GET " + key);
+        sent = sent + 2;
+        i++;
+    }
+    print(sent);
+}
+"#),
+    ("string locals", r#"
+fn main() {
+    let text: string = "the quick brown fox jumps over the lazy dog. ";
+    let i: int = 0;
+    while i < 6 {
+        text = text + text;
+        i++;
+    }
+    let copy: string = "";
+    i = 0;
+    let total: int = 0;
+    while i < 50000 {
+        copy = text;
+        if copy == text {
+            total = total + 1;
+        }
+        i++;
+    }
+    print(total);
+}
+"#),
+    ("string arguments", r#"
+fn check(key string, expected string) -> bool {
+    return key == expected;
+}
+fn main() {
+    let key: string = "config:feature-flags:";
+    let i: int = 0;
+    while i < 6 {
+        key = key + key;
+        i++;
+    }
+    let hits: int = 0;
+    i = 0;
+    while i < 50000 {
+        if check(key, key) {
+            hits = hits + 1;
+        }
+        i++;
+    }
+    print(hits);
+}
+"#),
+];
+
+fn main() {
+    println!("{:<18} {:>12}", "workload", "time");
+    for (name, source) in WORKLOADS {
+        let (_, time) = measure_run(&compile(source, 2), RUNS);
+        println!("{:<18} {:>12.2?}", name, time);
+    }
+}
@@ -0,0 +1,57 @@
+// 比较优化级别 1 和 2（超级指令）下 VM 执行的指令数和耗时：cargo bench --bench superinstructions
+mod common;
+
+use common::{compile, measure_run};
+
+const RUNS: usize = 10;
+
+const WORKLOADS: [(&str, &str); 3] = [
+    ("counting loop", r#"
+fn main() {
+    let i: int = 0;
+    let sum: int = 0;
+    while i < 200000 {
+        sum = sum + 3;
+        i++;
+    }
+    print(sum);
+}
+"#),
+    ("nested loops", r#"
+fn main() {
+    let total: int = 0;
+    let i: int = 0;
+    for i = 0; i < 300; i++ {
+        let j: int = 0;
+        while j < i {
+            total = total + 1;
+            j++;
+        }
+    }
+    print(total);
+}
+"#),
+    ("countdown", r#"
+fn main() {
+    let n: int = 100000;
+    let steps: int = 0;
+    while 0 < n {
+        n--;
+        steps++;
+    }
+    print(steps);
+}
+"#),
+];
+
+fn main() {
+    println!("{:<16} {:>14} {:>14} {:>10} {:>12} {:>12} {:>8}", "workload", "insns -O1", "insns -O2", "saved", "time -O1", "time -O2", "speedup");
+    for (name, source) in WORKLOADS {
+        let (base_instructions, base_time) = measure_run(&compile(source, 1), RUNS);
+        let (fused_instructions, fused_time) = measure_run(&compile(source, 2), RUNS);
+        let saved = 100.0 * (1.0 - fused_instructions as f64 / base_instructions as f64);
+        let speedup = base_time.as_secs_f64() / fused_time.as_secs_f64();
+        println!("{:<16} {:>14} {:>14} {:>9.1}% {:>12.2?} {:>12.2?} {:>7.2}x",
+            name, base_instructions, fused_instructions, saved, base_time, fused_time, speedup);
+    }
+}
@@ -17,11 +17,21 @@ typedef char* (*DkvCommandHandlerFn)(const char* command, void* user_data);
 typedef struct DkvScriptVM DkvScriptVM;
 typedef struct DkvScriptCompileResult DkvScriptCompileResult;
 
+// 线程安全：
+// - 编译结果创建后不再修改，多个线程可以同时用同一个编译结果创建 VM；
+//   这些 VM 共享编译后的程序，释放编译结果不影响已创建的 VM
+// - VM 可以转移到其他线程执行，但同一时刻只能被一个线程使用（调用方负责加锁）；
+//   命令处理函数在执行 VM 的线程中调用，user_data 需要能在该线程中使用
+
 // 暴露给 C 的函数
 ResultCode dkv_script_compile(const char* source, DkvScriptCompileResult** result);
 ResultCode dkv_script_create_vm(DkvScriptCompileResult* compile_result, DkvScriptVM** vm);
 ResultCode dkv_script_run_vm(DkvScriptVM* vm);
+// 把全局变量恢复为初始值，之后可以再次运行；同一个编译结果创建的 VM 共享编译后的程序
+ResultCode dkv_script_reset_vm(DkvScriptVM* vm);
+ResultCode dkv_script_rerun_vm(DkvScriptVM* vm);
 ResultCode dkv_script_set_dkv_command_handler(DkvScriptVM* vm, DkvCommandHandlerFn handler, void* user_data);
+ResultCode dkv_script_set_command_throws(DkvScriptVM* vm, int command_throws);
 void dkv_script_free_compile_result(DkvScriptCompileResult* result);
 void dkv_script_free_vm(DkvScriptVM* vm);
 
@@ -81,6 +81,18 @@ public:
         }
     }
     
+    // 重置后再次运行，不需要重新编译和创建虚拟机
+    void rerun() {
+        if (!vm_) {
+            throw std::runtime_error("No VM available");
+        }
+
+        ResultCode result = dkv_script_rerun_vm(vm_);
+        if (result != SUCCESS) {
+            throw std::runtime_error("Failed to run VM");
+        }
+    }
+    
     // 编译并运行脚本（便捷方法）
     void execute(const std::string& source) {
         compile(source);
@@ -1,28 +1,98 @@
-// 抽象语法树（AST）节点类型
+use crate::token::Span;
+
+// 抽象语法树（AST）节点类型。表达式节点以及函数定义、import、return、赋值、字段赋值、自增和自减语句的最后一个字段
+// 是源码区间，用于编译错误的定位（函数定义、赋值、自增和自减为名字的区间，字段赋值为被赋值字段的区间，
+// import 为模块路径的区间，return 为关键字的区间）
 #[derive(Debug, Clone)]
 pub enum ASTNode {
     // 程序
     Program(Vec<Box<ASTNode>>),
     Block(Vec<Box<ASTNode>>),
-    FunctionDef(String, Vec<(String, String)>, Box<ASTNode>),
+    // 函数定义：名称、参数（名称和类型）、返回类型（未声明时为 None）、函数体
+    FunctionDef(String, Vec<(String, String)>, Option<String>, Box<ASTNode>, Span),
+    // 结构体定义：名称、字段（名称和类型）
+    StructDef(String, Vec<(String, String)>),
+    // 枚举定义：名称、各变体（名称和负载类型）
+    EnumDef(String, Vec<(String, Vec<String>)>),
+    // 导入模块：模块路径、别名（`import "lib/util.dkvs" as u;`）、按名字导入的函数和全局变量
+    // （`import { greet } from "lib/util.dkvs";`，此时没有别名）、模块路径的区间
+    Import(String, Option<String>, Vec<String>, Span),
+    // 带文档注释（///）的函数定义、结构体定义、枚举定义、变量声明或常量声明
+    Documented(String, Box<ASTNode>),
     // 语句
     VariableDecl(String, String, Option<Box<ASTNode>>),
-    Assignment(String, Box<ASTNode>),
+    // 常量声明：名称、类型、编译期求值的表达式
+    ConstDecl(String, String, Box<ASTNode>),
+    Assignment(String, Box<ASTNode>, Span),
+    // 字段赋值：`object.field = value`，object 为变量或字段访问，区间为 `object.field`
+    FieldAssignment(Box<ASTNode>, String, Box<ASTNode>, Span),
     IfStatement(Box<ASTNode>, Box<ASTNode>, Option<Box<ASTNode>>),
     ForLoop(Option<Box<ASTNode>>, Option<Box<ASTNode>>, Option<Box<ASTNode>>, Box<ASTNode>),
     WhileLoop(Box<ASTNode>, Box<ASTNode>),
-    FunctionCall(String, Vec<Box<ASTNode>>),
-    Return(Option<Box<ASTNode>>),
+    FunctionCall(String, Vec<Box<ASTNode>>, Span),
+    // 调用表达式的值（函数值），如 make_counter()()
+    IndirectCall(Box<ASTNode>, Vec<Box<ASTNode>>, Span),
+    Return(Option<Box<ASTNode>>, Span),
+    // try 语句：try 代码块、catch（错误变量名和代码块）、finally 代码块，catch 和 finally 至少有一个
+    TryCatch(Box<ASTNode>, Option<(String, Box<ASTNode>)>, Option<Box<ASTNode>>),
+    // 抛出异常：字符串（作为错误信息）或 catch 得到的错误值
+    Throw(Box<ASTNode>),
+    // 表达式语句，表达式的值被丢弃
+    ExprStatement(Box<ASTNode>),
     // 表达式
-    BinaryExpr(Box<ASTNode>, String, Box<ASTNode>),
-    UnaryExpr(String, Box<ASTNode>),
+    BinaryExpr(Box<ASTNode>, String, Box<ASTNode>, Span),
+    UnaryExpr(String, Box<ASTNode>, Span),
+    // 类型转换：`expr as type` 或 `type(expr)`
+    Cast(Box<ASTNode>, String, Span),
+    // 下标访问：`expr[index]`
+    Index(Box<ASTNode>, Box<ASTNode>, Span),
+    // 字段访问：`expr.field`
+    FieldAccess(Box<ASTNode>, String, Span),
+    // 结构体字面量：`User { name: "a", age: 1 }`
+    StructLiteral(String, Vec<(String, Box<ASTNode>)>, Span),
+    // 枚举值：`Reply::Error("x")`，无负载的变体没有参数
+    EnumVariant(String, String, Vec<Box<ASTNode>>, Span),
+    // match 表达式：被匹配的值和各分支（模式、分支体、模式的区间）。分支体为表达式或代码块
+    Match(Box<ASTNode>, Vec<(Pattern, Box<ASTNode>, Span)>, Span),
     // 自增自减表达式
-    Increment(String),
-    Decrement(String),
+    Increment(String, Span),
+    Decrement(String, Span),
     // 字面量
-    IntLiteral(i32),
-    FloatLiteral(f32),
-    BoolLiteral(bool),
-    StringLiteral(String),
-    Identifier(String),
-}
\ No newline at end of file
+    IntLiteral(i32, Span),
+    FloatLiteral(f32, Span),
+    BoolLiteral(bool, Span),
+    StringLiteral(String, Span),
+    // 插值字符串，各部分依次转换为字符串后拼接
+    Interpolation(Vec<Box<ASTNode>>, Span),
+    ArrayLiteral(Vec<Box<ASTNode>>, Span),
+    Identifier(String, Span),
+    // 匿名函数：参数、返回类型、函数体，可以捕获外层函数的局部变量
+    Lambda(Vec<(String, String)>, Option<String>, Box<ASTNode>, Span),
+    // 语法错误处的占位节点，只出现在 parse_with_diagnostics 返回的部分 AST 中
+    Error,
+}
+
+impl ASTNode {
+    /// 节点在源码中的区间；没有记录区间的节点（如代码块）为默认值
+    pub fn span(&self) -> Span {
+        match self {
+            ASTNode::FunctionDef(.., span) | ASTNode::Return(.., span) | ASTNode::Import(.., span) |
+            ASTNode::Assignment(.., span) | ASTNode::FieldAssignment(.., span) | ASTNode::FunctionCall(.., span) | ASTNode::IndirectCall(.., span) |
+            ASTNode::BinaryExpr(.., span) | ASTNode::UnaryExpr(.., span) | ASTNode::Cast(.., span) |
+            ASTNode::Index(.., span) | ASTNode::FieldAccess(.., span) | ASTNode::StructLiteral(.., span) |
+            ASTNode::EnumVariant(.., span) | ASTNode::Match(.., span) | ASTNode::Increment(.., span) |
+            ASTNode::Decrement(.., span) | ASTNode::IntLiteral(.., span) | ASTNode::FloatLiteral(.., span) |
+            ASTNode::BoolLiteral(.., span) | ASTNode::StringLiteral(.., span) | ASTNode::Interpolation(.., span) |
+            ASTNode::ArrayLiteral(.., span) | ASTNode::Identifier(.., span) | ASTNode::Lambda(.., span) => *span,
+            _ => Span::default(),
+        }
+    }
+}
+// match 分支的模式
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    // 枚举变体：枚举名、变体名、负载绑定的变量名（`_` 表示忽略）
+    Variant(String, String, Vec<String>),
+    // `_`，匹配任意值
+    Wildcard,
+}
@@ -1,17 +1,44 @@
 use dkv_script::*;
 use std::env;
 use std::fs;
+use std::io::IsTerminal;
 use std::path::Path;
+
+// 编译选项：错误信息的输出方式、模块搜索路径、优化级别和 VM 后端
+struct CompileOptions {
+    json: bool,
+    color: bool,
+    module_paths: Vec<String>,
+    optimization_level: u8,
+    backend: Backend,
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // 以 - 开头的参数是选项，其余为命令和文件
+    let (options, args): (Vec<String>, Vec<String>) = env::args().partition(|arg| arg.starts_with('-'));
+    let compile_options = match parse_compile_options(&options) {
+        Ok(compile_options) => compile_options,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
     if args.len() < 3 {
-        println!("Usage: dkvc <command> <file>");
+        println!("Usage: dkvc <command> <file> [options]");
         println!("Commands:");
         println!("  compile    Compile DKV script to binary");
         println!("  run        Run DKV script file");
         println!("  execute    Execute compiled binary file");
         println!("  tokenize   Display token sequence for debugging");
         println!("  print_ast  Display abstract syntax tree for debugging");
+        println!("  doc        Display documentation of functions, structs, enums and globals");
+        println!("Options:");
+        println!("  --error-format=text|json  Output format of compile errors (default: text)");
+        println!("  --color=auto|always|never Colorize compile errors (default: auto)");
+        println!("  --module-path=<dir>       Additional directory to search for imported modules (repeatable)");
+        println!("  -O1                       Fold constant expressions and remove unreachable code");
+        println!("  -O2, -O                   Also fuse common instruction sequences into superinstructions");
+        println!("  --backend=stack|register  VM backend used by run and execute (default: stack)");
         return;
     }
 
@@ -20,17 +47,17 @@ fn main() {
 
     match command.as_str() {
         "compile" => {
-            if let Err(err) = compile_file(file_path) {
+            if let Err(err) = compile_file(file_path, &compile_options) {
                 eprintln!("Error compiling file: {}", err);
             }
         },
         "run" => {
-            if let Err(err) = run_file(file_path) {
+            if let Err(err) = run_file(file_path, &compile_options) {
                 eprintln!("Error running file: {}", err);
             }
         },
         "execute" => {
-            if let Err(err) = execute_file(file_path) {
+            if let Err(err) = execute_file(file_path, &compile_options) {
                 eprintln!("Error executing file: {}", err);
             }
         },
@@ -40,16 +67,78 @@ fn main() {
             }
         },
         "print_ast" => {
-            if let Err(err) = print_ast_file(file_path) {
+            if let Err(err) = print_ast_file(file_path, &compile_options) {
                 eprintln!("Error printing AST: {}", err);
             }
         },
+        "doc" => {
+            if let Err(err) = doc_file(file_path) {
+                eprintln!("Error generating documentation: {}", err);
+            }
+        },
         _ => {
             println!("Unknown command: {}", command);
         },
     }
 }
 
+fn parse_compile_options(options: &[String]) -> Result<CompileOptions, String> {
+    // 自动模式：stderr 是终端且没有设置 NO_COLOR 时使用颜色
+    let auto_color = std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let mut compile_options = CompileOptions {
+        json: false,
+        color: auto_color,
+        module_paths: Vec::new(),
+        optimization_level: 0,
+        backend: Backend::Stack,
+    };
+    for option in options {
+        match option.as_str() {
+            "--error-format=text" => compile_options.json = false,
+            "--error-format=json" => compile_options.json = true,
+            "--color=auto" => compile_options.color = auto_color,
+            "--color=always" => compile_options.color = true,
+            "--color=never" => compile_options.color = false,
+            "-O1" => compile_options.optimization_level = 1,
+            "-O" | "-O2" => compile_options.optimization_level = 2,
+            "--backend=stack" => compile_options.backend = Backend::Stack,
+            "--backend=register" => compile_options.backend = Backend::Register,
+            _ if option.starts_with("--module-path=") => {
+                compile_options.module_paths.push(option["--module-path=".len()..].to_string());
+            },
+            _ => return Err(format!("Unknown option: {}", option)),
+        }
+    }
+    Ok(compile_options)
+}
+
+// 输出诊断信息：文本格式带源码片段写到 stderr，JSON 格式写到 stdout
+fn report_diagnostics(source: &str, file_path: &str, diagnostics: &[Diagnostic], compile_options: &CompileOptions) {
+    if compile_options.json {
+        let items: Vec<String> = diagnostics.iter().map(Diagnostic::to_json).collect();
+        println!("[{}]", items.join(","));
+    } else {
+        let renderer = DiagnosticRenderer::new(source, file_path).with_color(compile_options.color);
+        eprint!("{}", renderer.render_all(diagnostics));
+    }
+}
+
+fn compile_or_report(source: &str, file_path: &str, compile_options: &CompileOptions) -> Result<CompileResult, Box<dyn std::error::Error>> {
+    // import 的模块先在脚本所在目录中查找，再在 --module-path 指定的目录中查找
+    let resolver = compile_options.module_paths.iter()
+        .fold(ModuleResolver::default(), |resolver, path| resolver.with_search_path(path));
+    let base_dir = Path::new(file_path).parent().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+    let compiler = Compiler::new()
+        .with_optimization_level(compile_options.optimization_level)
+        .with_backend(compile_options.backend);
+    resolver.resolve(source, &base_dir)
+        .and_then(|modules| compiler.compile_modules_with_diagnostics(&modules))
+        .map_err(|diagnostics| {
+            report_diagnostics(source, file_path, &diagnostics, compile_options);
+            format!("{} error(s)", diagnostics.len()).into()
+        })
+}
+
 fn tokenize_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let source = std::fs::read_to_string(file_path)?;
     let mut lexer = Lexer::new(source);
@@ -67,15 +156,28 @@ fn tokenize_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     for token in tokens {
         println!("{:?} at line {}, column {}", token.token_type, token.line, token.column);
     }
+
+    let diagnostics = lexer.take_diagnostics();
+    for diagnostic in &diagnostics {
+        eprintln!("error: {}", diagnostic);
+    }
+    if !diagnostics.is_empty() {
+        return Err(format!("{} error(s)", diagnostics.len()).into());
+    }
     
     Ok(())
 }
 
-fn print_ast_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn print_ast_file(file_path: &str, compile_options: &CompileOptions) -> Result<(), Box<dyn std::error::Error>> {
     let source = std::fs::read_to_string(file_path)?;
-    let lexer = Lexer::new(source);
+    let lexer = Lexer::new(source.clone());
     let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
+    let (ast, diagnostics) = parser.parse_with_diagnostics();
+
+    // 有语法错误时仍然输出部分 AST
+    if !diagnostics.is_empty() {
+        report_diagnostics(&source, file_path, &diagnostics, compile_options);
+    }
     
     println!("Abstract Syntax Tree:");
     println!("{:#?}", ast);
@@ -83,7 +185,59 @@ fn print_ast_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn compile_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn doc_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let source = std::fs::read_to_string(file_path)?;
+    let lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        return Err("ROOT node is not ASTNode::Program".into());
+    };
+    for stmt in statements {
+        let (doc, node) = match *stmt {
+            ASTNode::Documented(doc, node) => (Some(doc), node),
+            node => (None, Box::new(node)),
+        };
+        let signature = match *node {
+            ASTNode::FunctionDef(name, params, return_type, ..) => {
+                let params: Vec<String> = params.iter().map(|(name, type_name)| format!("{} {}", name, type_name)).collect();
+                match return_type {
+                    Some(return_type) => format!("fn {}({}) -> {}", name, params.join(", "), return_type),
+                    None => format!("fn {}({})", name, params.join(", ")),
+                }
+            },
+            ASTNode::VariableDecl(name, type_name, _) => format!("let {}: {}", name, type_name),
+            ASTNode::ConstDecl(name, type_name, _) => format!("const {}: {}", name, type_name),
+            ASTNode::StructDef(name, fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, type_name)| format!("{}: {}", name, type_name)).collect();
+                format!("struct {} {{ {} }}", name, fields.join(", "))
+            },
+            ASTNode::EnumDef(name, variants) => {
+                let variants: Vec<String> = variants.iter().map(|(name, payload_types)| {
+                    if payload_types.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}({})", name, payload_types.join(", "))
+                    }
+                }).collect();
+                format!("enum {} {{ {} }}", name, variants.join(", "))
+            },
+            _ => continue,
+        };
+        println!("{}", signature);
+        if let Some(doc) = doc {
+            for line in doc.lines() {
+                println!("    {}", line);
+            }
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn compile_file(file_path: &str, compile_options: &CompileOptions) -> Result<(), Box<dyn std::error::Error>> {
     // 读取源文件
     let source = fs::read_to_string(file_path)?;
     
@@ -91,7 +245,7 @@ fn compile_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let output_path = Path::new(file_path).with_extension("cdkvs");
     
     // 执行编译
-    let compile_result = do_compile(&source)?;
+    let compile_result = compile_or_report(&source, file_path, compile_options)?;
     
     // 保存编译结果
     save_to_file(&compile_result, &output_path.to_string_lossy())?;
@@ -100,27 +254,27 @@ fn compile_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn run_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn run_file(file_path: &str, compile_options: &CompileOptions) -> Result<(), Box<dyn std::error::Error>> {
     // 读取源文件
     let source = fs::read_to_string(file_path)?;
     
     // 执行编译
-    let compile_result = do_compile(&source)?;
+    let compile_result = compile_or_report(&source, file_path, compile_options)?;
     
     // 运行程序
     let mut vm = VM::new(compile_result);
-    vm.run();
+    vm.try_run().map_err(|error| format!("Uncaught exception: {}", error))?;
     
     Ok(())
 }
 
-fn execute_file(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // 加载编译后的二进制文件
-    let compile_result = load_from_file(file_path)?;
+fn execute_file(file_path: &str, compile_options: &CompileOptions) -> Result<(), Box<dyn std::error::Error>> {
+    // 加载编译后的二进制文件（文件中只有栈式字节码，寄存器后端在加载后翻译）
+    let compile_result = load_from_file(file_path)?.with_backend(compile_options.backend);
     
     // 运行程序
     let mut vm = VM::new(compile_result);
-    vm.run();
+    vm.try_run().map_err(|error| format!("Uncaught exception: {}", error))?;
     
     Ok(())
 }
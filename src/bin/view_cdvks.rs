@@ -1,6 +1,7 @@
 use std::env;
 use dkv_script::{load_from_file};
 use dkv_script::Constant;
+use dkv_script::Type;
 
 fn main() {
     // 获取命令行参数
@@ -21,7 +22,7 @@ fn main() {
 
     println!("=== DKV Binary File Info ===");
     println!("File: {}", file_path);
-    println!("Version: 1.0");
+    println!("Version: 1.4");
     println!("Entry Point: Function #{}", compile_result.entrypoint);
     println!();
 
@@ -69,9 +70,57 @@ fn main() {
             println!("  Bytecode:");
             print_bytecode(&func.bytecode);
         }
+
+        // 打印异常处理表
+        if !func.handlers.is_empty() {
+            println!("  Exception Handlers:");
+            for handler in &func.handlers {
+                println!("    [{:4}, {:4}) -> {:4} (stack depth {})", handler.start, handler.end, handler.target, handler.stack_depth);
+            }
+        }
         println!();
     }
     
+    // 打印结构体类型
+    println!("=== Structs ({}) ===", compile_result.structs.len());
+    for (i, struct_info) in compile_result.structs.iter().enumerate() {
+        println!("Struct #{}: {}", i, struct_info.name);
+        for field in &struct_info.fields {
+            match field.field_type {
+                Type::Struct(struct_index) => println!("  {}: struct #{}", field.name, struct_index),
+                Type::Enum(enum_index) => println!("  {}: enum #{}", field.name, enum_index),
+                field_type => println!("  {}: {}", field.name, field_type.name()),
+            }
+        }
+    }
+    println!();
+
+    // 打印枚举类型
+    println!("=== Enums ({}) ===", compile_result.enums.len());
+    for (i, enum_info) in compile_result.enums.iter().enumerate() {
+        println!("Enum #{}: {}", i, enum_info.name);
+        for variant in &enum_info.variants {
+            let payload: Vec<String> = variant.payload_types.iter().map(|payload_type| match payload_type {
+                Type::Struct(struct_index) => format!("struct #{}", struct_index),
+                Type::Enum(enum_index) => format!("enum #{}", enum_index),
+                payload_type => payload_type.name().to_string(),
+            }).collect();
+            if payload.is_empty() {
+                println!("  {}", variant.name);
+            } else {
+                println!("  {}({})", variant.name, payload.join(", "));
+            }
+        }
+    }
+    println!();
+
+    // 打印导出的常量
+    println!("=== Exported Constants ({}) ===", compile_result.exported_constants.len());
+    for info in &compile_result.exported_constants {
+        println!("{}: (const #{})", info.name, info.const_index);
+    }
+    println!();
+
     println!("=== End of File Info ===");
 }
 
@@ -88,11 +137,19 @@ fn print_bytecode(bytecode: &[u8]) {
             0x03 => "StoreGlobal",
             0x04 => "LoadLocal",
             0x05 => "StoreLocal",
+            0x06 => "Pop",
+            0x07 => "Dup",
+            0x08 => "Swap",
+            0x09 => "NewCell",
+            0x0A => "LoadUpvalue",
+            0x0B => "LoadCell",
+            0x0C => "StoreCell",
 
             0x10 => "Not",
             0x11 => "Inc",
             0x12 => "Dec",
             0x13 => "Neg",
+            0x14 => "Cast",
 
             0x1A => "Add",
             0x1B => "Sub",
@@ -106,11 +163,29 @@ fn print_bytecode(bytecode: &[u8]) {
             0x24 => "CmpGt",
             0x25 => "CmpGe",
 
+            0x30 => "Index",
+            0x31 => "NewArray",
+            0x32 => "Concat",
+            0x33 => "NewStruct",
+            0x34 => "GetField",
+            0x35 => "SetField",
+            0x36 => "NewEnum",
+            0x37 => "IsVariant",
+
             0x50 => "Jmp",
             0x51 => "Jz",
 
             0x60 => "Call",
             0x61 => "Ret",
+            0x62 => "MakeClosure",
+            0x63 => "CallIndirect",
+            0x64 => "Throw",
+
+            0x70 => "IncLocal",
+            0x71 => "DecLocal",
+            0x72 => "AddConstLocal",
+            0x73 => "LoadLocal2",
+            0x74 => "CmpLtJz",
 
             0xFE => "Syscall",
             0xFF => "Exit",
@@ -138,7 +213,7 @@ fn print_bytecode(bytecode: &[u8]) {
         // 跳过操作数
         i += 8;
     }
-    if bytecode.len() % 10 != 0 {
+    if !bytecode.len().is_multiple_of(10) {
         println!();
     }
 }
\ No newline at end of file
@@ -1,11 +1,14 @@
-use crate::compiler::{CompileResult, Constant, FunctionInfo, GlobalVarInfo};
+use crate::compiler::{CompileResult, Constant, ConstantInfo, EnumInfo, ExceptionHandler, FieldInfo, FunctionInfo, GlobalVarInfo, StructInfo, VariantInfo};
+use crate::types::{Type, TYPE_TAG_ENUM, TYPE_TAG_NIL, TYPE_TAG_STRUCT};
 use std::fs::File;
 use std::io::{Read, Write};
 
 // 魔数 "SBYT"
 const MAGIC_NUMBER: [u8; 4] = [0x53, 0x42, 0x59, 0x54];
-// 版本号 1.0
-const VERSION: [u8; 2] = [0x01, 0x00];
+// 版本号 1.4。1.1 在函数表之后增加了结构体类型表，1.2 在其后增加了枚举类型表，
+// 1.3 在每个函数的字节码之后增加了异常处理表，1.4 在枚举类型表之后增加了导出常量表。
+// 仍可读取 1.0 以来的旧版本，缺少的部分视为空
+const VERSION: [u8; 2] = [0x01, 0x04];
 
 // 常量类型
 const CONST_TYPE_NIL: u8 = 0;
@@ -82,11 +85,100 @@ pub fn save_to_file(compile_result: &CompileResult, file_path: &str) -> std::io:
         let bytecode_len = func.bytecode.len() as u16;
         file.write_all(&bytecode_len.to_le_bytes())?;
         file.write_all(&func.bytecode)?;
+        let handler_count = func.handlers.len() as u16;
+        file.write_all(&handler_count.to_le_bytes())?;
+        for handler in &func.handlers {
+            for value in [handler.start, handler.end, handler.target, handler.stack_depth] {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+
+    // 写入结构体类型表
+    let struct_count = compile_result.structs.len() as u16;
+    file.write_all(&struct_count.to_le_bytes())?;
+    for struct_info in &compile_result.structs {
+        let name_len = struct_info.name.len() as u16;
+        file.write_all(&name_len.to_le_bytes())?;
+        file.write_all(struct_info.name.as_bytes())?;
+        let field_count = struct_info.fields.len() as u16;
+        file.write_all(&field_count.to_le_bytes())?;
+        for field in &struct_info.fields {
+            let name_len = field.name.len() as u16;
+            file.write_all(&name_len.to_le_bytes())?;
+            file.write_all(field.name.as_bytes())?;
+            write_type(&mut file, field.field_type)?;
+        }
+    }
+
+    // 写入枚举类型表
+    let enum_count = compile_result.enums.len() as u16;
+    file.write_all(&enum_count.to_le_bytes())?;
+    for enum_info in &compile_result.enums {
+        let name_len = enum_info.name.len() as u16;
+        file.write_all(&name_len.to_le_bytes())?;
+        file.write_all(enum_info.name.as_bytes())?;
+        let variant_count = enum_info.variants.len() as u16;
+        file.write_all(&variant_count.to_le_bytes())?;
+        for variant in &enum_info.variants {
+            let name_len = variant.name.len() as u16;
+            file.write_all(&name_len.to_le_bytes())?;
+            file.write_all(variant.name.as_bytes())?;
+            file.write_all(&[variant.payload_types.len() as u8])?;
+            for payload_type in &variant.payload_types {
+                write_type(&mut file, *payload_type)?;
+            }
+        }
+    }
+
+    // 写入导出常量表
+    let exported_count = compile_result.exported_constants.len() as u16;
+    file.write_all(&exported_count.to_le_bytes())?;
+    for info in &compile_result.exported_constants {
+        let name_len = info.name.len() as u16;
+        file.write_all(&name_len.to_le_bytes())?;
+        file.write_all(info.name.as_bytes())?;
+        file.write_all(&info.const_index.to_le_bytes())?;
     }
 
     Ok(())
 }
 
+// 写入类型：类型标签，结构体和枚举类型后跟 2 字节的类型表索引
+fn write_type(file: &mut File, var_type: Type) -> std::io::Result<()> {
+    file.write_all(&[var_type.tag()])?;
+    if let Type::Struct(index) | Type::Enum(index) = var_type {
+        file.write_all(&index.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_u16(file: &mut File) -> std::io::Result<u16> {
+    let mut value = [0u8; 2];
+    file.read_exact(&mut value)?;
+    Ok(u16::from_le_bytes(value))
+}
+
+// 读取 write_type 写入的类型
+fn read_type(file: &mut File) -> std::io::Result<Type> {
+    let mut tag = [0u8; 1];
+    file.read_exact(&mut tag)?;
+    let mut read_index = || -> std::io::Result<u16> {
+        let mut index = [0u8; 2];
+        file.read_exact(&mut index)?;
+        Ok(u16::from_le_bytes(index))
+    };
+    Ok(match tag[0] {
+        TYPE_TAG_STRUCT => Type::Struct(read_index()?),
+        TYPE_TAG_ENUM => Type::Enum(read_index()?),
+        // any 类型没有单独的标签，与 nil 共用
+        TYPE_TAG_NIL => Type::Any,
+        tag => Type::from_tag(tag).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Unknown type tag")
+        })?,
+    })
+}
+
 // 从二进制文件加载程序
 pub fn load_from_file(file_path: &str) -> std::io::Result<CompileResult> {
     let mut file = File::open(file_path)?;
@@ -103,12 +195,15 @@ pub fn load_from_file(file_path: &str) -> std::io::Result<CompileResult> {
 
     let mut version = [0u8; 2];
     file.read_exact(&mut version)?;
-    if version != VERSION {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Unsupported version",
-        ));
-    }
+    let minor_version = match version {
+        [major, minor] if major == VERSION[0] && minor <= VERSION[1] => minor,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Unsupported version",
+            ));
+        },
+    };
 
     let mut entrypoint = [0u8; 2];
     file.read_exact(&mut entrypoint)?;
@@ -214,19 +309,103 @@ pub fn load_from_file(file_path: &str) -> std::io::Result<CompileResult> {
         let bytecode_len = u16::from_le_bytes(bytecode_len);
         let mut bytecode = vec![0u8; bytecode_len as usize];
         file.read_exact(&mut bytecode)?;
+        let mut handlers = Vec::new();
+        if minor_version >= 3 {
+            let handler_count = read_u16(&mut file)?;
+            for _ in 0..handler_count {
+                handlers.push(ExceptionHandler {
+                    start: read_u16(&mut file)?,
+                    end: read_u16(&mut file)?,
+                    target: read_u16(&mut file)?,
+                    stack_depth: read_u16(&mut file)?,
+                });
+            }
+        }
 
         functions.push(FunctionInfo {
             name,
             param_count: param_count[0],
             local_count: local_count[0],
             bytecode,
+            handlers,
         });
     }
 
+    // 读取结构体类型表
+    let mut structs = Vec::new();
+    if minor_version >= 1 {
+        let mut struct_count = [0u8; 2];
+        file.read_exact(&mut struct_count)?;
+        let struct_count = u16::from_le_bytes(struct_count);
+        for _ in 0..struct_count {
+            let name = read_name(&mut file)?;
+            let mut field_count = [0u8; 2];
+            file.read_exact(&mut field_count)?;
+            let field_count = u16::from_le_bytes(field_count);
+            let mut fields = Vec::new();
+            for _ in 0..field_count {
+                let name = read_name(&mut file)?;
+                let field_type = read_type(&mut file)?;
+                fields.push(FieldInfo { name, field_type });
+            }
+            structs.push(StructInfo { name, fields });
+        }
+    }
+
+    // 读取枚举类型表
+    let mut enums = Vec::new();
+    if minor_version >= 2 {
+        let mut enum_count = [0u8; 2];
+        file.read_exact(&mut enum_count)?;
+        let enum_count = u16::from_le_bytes(enum_count);
+        for _ in 0..enum_count {
+            let name = read_name(&mut file)?;
+            let mut variant_count = [0u8; 2];
+            file.read_exact(&mut variant_count)?;
+            let variant_count = u16::from_le_bytes(variant_count);
+            let mut variants = Vec::new();
+            for _ in 0..variant_count {
+                let name = read_name(&mut file)?;
+                let mut payload_count = [0u8; 1];
+                file.read_exact(&mut payload_count)?;
+                let payload_types = (0..payload_count[0]).map(|_| read_type(&mut file)).collect::<std::io::Result<_>>()?;
+                variants.push(VariantInfo { name, payload_types });
+            }
+            enums.push(EnumInfo { name, variants });
+        }
+    }
+
+    // 读取导出常量表
+    let mut exported_constants = Vec::new();
+    if minor_version >= 4 {
+        let exported_count = read_u16(&mut file)?;
+        for _ in 0..exported_count {
+            let name = read_name(&mut file)?;
+            let const_index = read_u16(&mut file)?;
+            exported_constants.push(ConstantInfo { name, const_index });
+        }
+    }
+
     Ok(CompileResult {
         constants,
         global_vars,
         functions,
+        structs,
+        enums,
+        exported_constants,
         entrypoint,
+        register_functions: Vec::new(),
+    })
+}
+
+// 读取带 u16 长度前缀的 UTF-8 名字
+fn read_name(file: &mut File) -> std::io::Result<String> {
+    let mut name_len = [0u8; 2];
+    file.read_exact(&mut name_len)?;
+    let name_len = u16::from_le_bytes(name_len);
+    let mut name = vec![0u8; name_len as usize];
+    file.read_exact(&mut name)?;
+    String::from_utf8(name).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid UTF-8 string")
     })
 }
\ No newline at end of file
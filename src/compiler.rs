@@ -1,13 +1,14 @@
-use crate::{ast::ASTNode, SYSCALL};
+use crate::{ast::{ASTNode, Pattern}, diagnostic::{suggest_similar, Diagnostic}, module::{Module, ModuleResolver}, optimizer::optimize_function, register::{translate_function, RegisterFunction}, stdlib::count_placeholders, token::Span, types::{Type, ENUM_OPTION, ENUM_RESULT, VARIANT_ERR, VARIANT_NONE, VARIANT_OK, VARIANT_SOME}, vm::{eval_binary_constant, eval_cast_constant, eval_unary_constant}, SYSCALL};
 use core::panic;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use num_derive::FromPrimitive;
 
-const OPLEN: usize = 9;
-const OP_ARGOFF: usize = 1;
+pub(crate) const OPLEN: usize = 9;
+pub(crate) const OP_ARGOFF: usize = 1;
 
 // 字节码指令
-#[derive(FromPrimitive, Debug, Clone, Copy)]
+#[derive(FromPrimitive, Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum OpCode {
     LoadConst = 0x01,
@@ -15,11 +16,19 @@ pub enum OpCode {
     StoreGlobal = 0x03,
     LoadLocal = 0x04,
     StoreLocal = 0x05,
+    Pop = 0x06,
+    Dup = 0x07,
+    Swap = 0x08,
+    NewCell = 0x09,
+    LoadUpvalue = 0x0A,
+    LoadCell = 0x0B,
+    StoreCell = 0x0C,
 
     Not = 0x10,
     Inc = 0x11,
     Dec = 0x12,
     Neg = 0x13,
+    Cast = 0x14,
 
     Add = 0x1A,
     Sub = 0x1B,
@@ -32,10 +41,30 @@ pub enum OpCode {
     CmpGt = 0x23,
     CmpLe = 0x24,
     CmpGe = 0x25,
+
+    Index = 0x30,
+    NewArray = 0x31,
+    Concat = 0x32,
+    NewStruct = 0x33,
+    GetField = 0x34,
+    SetField = 0x35,
+    NewEnum = 0x36,
+    IsVariant = 0x37,
+
     Jmp = 0x50,
     Jz = 0x51,
     Call = 0x60,
     Ret = 0x61,
+    MakeClosure = 0x62,
+    CallIndirect = 0x63,
+    Throw = 0x64,
+
+    // 超级指令，由优化级别 2 的窥孔优化生成
+    IncLocal = 0x70,
+    DecLocal = 0x71,
+    AddConstLocal = 0x72,
+    LoadLocal2 = 0x73,
+    CmpLtJz = 0x74,
 
     Syscall = 0xFE,
     Exit = 0xFF,
@@ -57,6 +86,18 @@ pub struct FunctionInfo {
     pub param_count: u8,
     pub local_count: u8,
     pub bytecode: Vec<u8>,
+    // 异常处理表，内层 try 的处理程序排在外层之前
+    pub handlers: Vec<ExceptionHandler>,
+}
+
+// 异常处理表项：[start, end) 范围内的指令抛出异常时，把栈恢复到 stack_depth（相对于局部变量之上），
+// 压入错误值并跳转到 target
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExceptionHandler {
+    pub start: u16,
+    pub end: u16,
+    pub target: u16,
+    pub stack_depth: u16,
 }
 
 // 全局变量信息
@@ -66,11 +107,68 @@ pub struct GlobalVarInfo {
     pub const_index: Option<u16>,
 }
 
+// 导出的常量（顶层的 const 声明）：全名（模块中的常量带模块前缀）和值在常量池中的索引
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstantInfo {
+    pub name: String,
+    pub const_index: u16,
+}
+
 // 局部变量信息
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct LocalVarInfo {
     pub name: String,
     pub const_index: Option<u16>,
+    pub var_type: Type,
+    // 被闭包捕获的变量存放在共享单元中（见 NewCell）
+    pub captured: bool,
+}
+
+// 结构体类型信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructInfo {
+    pub name: String,
+    pub fields: Vec<FieldInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldInfo {
+    pub name: String,
+    pub field_type: Type,
+}
+
+// 枚举类型信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumInfo {
+    pub name: String,
+    pub variants: Vec<VariantInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantInfo {
+    pub name: String,
+    pub payload_types: Vec<Type>,
+}
+
+impl EnumInfo {
+    // 内置枚举：Option 和 Result，索引见 types::ENUM_OPTION 和 types::ENUM_RESULT
+    fn builtins() -> Vec<EnumInfo> {
+        let variant = |name: &str, payload_types: Vec<Type>| VariantInfo {
+            name: name.to_string(),
+            payload_types,
+        };
+        vec![
+            EnumInfo {
+                name: "Option".to_string(),
+                variants: vec![variant("Some", vec![Type::Any]), variant("None", Vec::new())],
+            },
+            EnumInfo {
+                name: "Result".to_string(),
+                variants: vec![variant("Ok", vec![Type::Any]), variant("Err", vec![Type::String])],
+            },
+        ]
+    }
 }
 
 // 编译结果
@@ -79,7 +177,108 @@ pub struct CompileResult {
     pub constants: Vec<Constant>,
     pub global_vars: Vec<GlobalVarInfo>,
     pub functions: Vec<FunctionInfo>,
+    pub structs: Vec<StructInfo>,
+    pub enums: Vec<EnumInfo>,
+    pub exported_constants: Vec<ConstantInfo>,
     pub entrypoint: u16,
+    // 寄存器后端翻译出的函数，与 functions 一一对应；为空时 VM 执行栈式字节码
+    pub register_functions: Vec<RegisterFunction>,
+}
+
+/// VM 后端：栈式字节码（默认），或由栈式字节码翻译出的寄存器指令
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backend {
+    #[default]
+    Stack,
+    Register,
+}
+
+impl CompileResult {
+    /// 选择执行程序的 VM 后端。寄存器后端由栈式字节码翻译得到，可用于从 .cdkvs 文件加载的程序
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.register_functions = match backend {
+            Backend::Stack => Vec::new(),
+            Backend::Register => self.functions.iter()
+                .map(|function| translate_function(function, &self.functions, &self.structs, &self.enums))
+                .collect(),
+        };
+        self
+    }
+
+    /// 按全名查找导出的常量的值
+    pub fn constant(&self, name: &str) -> Option<&Constant> {
+        self.exported_constants.iter()
+            .find(|info| info.name == name)
+            .map(|info| &self.constants[info.const_index as usize])
+    }
+}
+
+// 闭包捕获的变量来自外层函数的局部变量，或外层闭包自己捕获的变量
+#[derive(Debug, Clone, Copy)]
+enum UpvalueSource {
+    Local(u8),
+    Upvalue(u16),
+}
+
+struct UpvalueInfo {
+    name: String,
+    source: UpvalueSource,
+    var_type: Type,
+}
+
+// 变量的存储位置
+#[derive(Debug, Clone, Copy)]
+enum VarRef {
+    // 局部变量索引，以及是否存放在单元中
+    Local(u8, bool),
+    Upvalue(u16),
+    Global(u16),
+}
+
+// 正在编译的 try 代码块或 catch 代码块
+struct TryContext {
+    // 已结束的受保护范围。return 执行外层的 finally 时会中断受保护的范围
+    ranges: Vec<(usize, usize)>,
+    // 当前受保护范围的起点，None 表示暂时不受保护
+    start: Option<usize>,
+    // 离开代码块时要执行的 finally
+    finally: Option<Box<ASTNode>>,
+}
+
+impl TryContext {
+    fn open(&mut self, pos: usize) {
+        if self.start.is_none() {
+            self.start = Some(pos);
+        }
+    }
+
+    fn close(&mut self, pos: usize) {
+        if let Some(start) = self.start.take() {
+            if start < pos {
+                self.ranges.push((start, pos));
+            }
+        }
+    }
+}
+
+// 编译匿名函数时保存的外层函数状态
+struct FunctionScope {
+    function: String,
+    return_type: Option<Type>,
+    local_vars: Vec<LocalVarInfo>,
+    local_vars_map: HashMap<String, usize>,
+    upvalues: Vec<UpvalueInfo>,
+    captured: HashSet<String>,
+    in_global_scope: bool,
+    stack_depth: i32,
+    handlers: Vec<ExceptionHandler>,
+    try_contexts: Vec<TryContext>,
+}
+
+// 用户函数的签名：参数类型和声明的返回类型（未声明时为 None，不检查返回值）
+struct FunctionSignature {
+    param_types: Vec<Type>,
+    return_type: Option<Type>,
 }
 
 pub struct Compiler {
@@ -90,13 +289,57 @@ pub struct Compiler {
 
     // 符号表
     global_var_map: HashMap<String, usize>,
+    global_var_types: Vec<Type>,
+    // 全局变量的声明语句是否已编译（顶层代码只能引用已声明的全局变量）
+    global_var_declared: Vec<bool>,
     function_map: HashMap<String, u16>,
+    // const 声明的值和类型，按全名索引；使用处直接内联为常量
+    named_constants: HashMap<String, (Constant, Type)>,
+    exported_constants: Vec<ConstantInfo>,
+    structs: Vec<StructInfo>,
+    struct_map: HashMap<String, u16>,
+    enums: Vec<EnumInfo>,
+    enum_map: HashMap<String, u16>,
+    // 推导 match 分支类型时，分支中模式绑定的变量类型（此时变量尚未声明）
+    pattern_bindings: RefCell<Vec<(String, Type)>>,
+    function_signatures: HashMap<u16, FunctionSignature>,
     syscall_map: HashMap<String, SYSCALL>, // syscall函数列表，存储函数名和对应的系统调用编号
 
     // 用于跟踪当前函数
     current_local_vars: Vec<LocalVarInfo>,
     current_local_vars_map: HashMap<String, usize>,
     in_global_scope: bool,
+    // 正在编译的是模块的顶层语句（不在代码块中），其中的 let 使用 declare_program 预先登记的全局变量
+    top_level_statement: bool,
+    current_function: String,
+    current_return_type: Option<Type>,
+    // 当前函数捕获的外层变量
+    current_upvalues: Vec<UpvalueInfo>,
+    // 当前函数中被内部匿名函数引用的名字，同名局部变量存放在单元中
+    current_captured: HashSet<String>,
+    // 当前函数的异常处理表和正在编译的 try/catch 代码块（从外到内）
+    current_handlers: Vec<ExceptionHandler>,
+    try_contexts: Vec<TryContext>,
+    // 外层函数（编译匿名函数时）
+    enclosing_scopes: Vec<FunctionScope>,
+
+    // 当前模块的函数和全局变量名的前缀（主程序为空，模块为 "模块名::"）
+    module_prefix: String,
+    // 当前模块中 import 的模块别名对应的前缀
+    module_aliases: HashMap<String, String>,
+    // 当前模块中按名字导入的函数和全局变量对应的全名
+    imported_names: HashMap<String, String>,
+
+    // 已收集的编译错误
+    diagnostics: Vec<Diagnostic>,
+
+    // 按已生成的指令推算的当前栈深度（相对于当前函数或入口代码开始时）
+    stack_depth: i32,
+
+    // 优化级别：0 不优化，1 折叠常量表达式并删除不可达代码，2 另外合并超级指令
+    optimization_level: u8,
+    // 生成的代码由哪个 VM 后端执行
+    backend: Backend,
 }
 
 impl Default for Compiler {
@@ -110,6 +353,30 @@ impl Compiler {
         let mut syscall_map = HashMap::new();
         syscall_map.insert("print".to_string(), SYSCALL::PRINT);
         syscall_map.insert("command".to_string(), SYSCALL::DKVCOMMAND);
+        syscall_map.insert("try_command".to_string(), SYSCALL::TRYCOMMAND);
+        syscall_map.insert("parse_int".to_string(), SYSCALL::PARSEINT);
+        syscall_map.insert("parse_float".to_string(), SYSCALL::PARSEFLOAT);
+        syscall_map.insert("len".to_string(), SYSCALL::LEN);
+        syscall_map.insert("substr".to_string(), SYSCALL::SUBSTR);
+        syscall_map.insert("find".to_string(), SYSCALL::FIND);
+        syscall_map.insert("split".to_string(), SYSCALL::SPLIT);
+        syscall_map.insert("join".to_string(), SYSCALL::JOIN);
+        syscall_map.insert("trim".to_string(), SYSCALL::TRIM);
+        syscall_map.insert("upper".to_string(), SYSCALL::UPPER);
+        syscall_map.insert("lower".to_string(), SYSCALL::LOWER);
+        syscall_map.insert("starts_with".to_string(), SYSCALL::STARTSWITH);
+        syscall_map.insert("ends_with".to_string(), SYSCALL::ENDSWITH);
+        syscall_map.insert("replace".to_string(), SYSCALL::REPLACE);
+        syscall_map.insert("format".to_string(), SYSCALL::FORMAT);
+
+        let enums = EnumInfo::builtins();
+        let enum_map = enums.iter().enumerate().map(|(i, info)| (info.name.clone(), i as u16)).collect();
+        debug_assert_eq!(enums[ENUM_OPTION as usize].name, "Option");
+        debug_assert_eq!(enums[ENUM_OPTION as usize].variants[VARIANT_SOME as usize].name, "Some");
+        debug_assert_eq!(enums[ENUM_OPTION as usize].variants[VARIANT_NONE as usize].name, "None");
+        debug_assert_eq!(enums[ENUM_RESULT as usize].name, "Result");
+        debug_assert_eq!(enums[ENUM_RESULT as usize].variants[VARIANT_OK as usize].name, "Ok");
+        debug_assert_eq!(enums[ENUM_RESULT as usize].variants[VARIANT_ERR as usize].name, "Err");
 
         Compiler {
             constants: Vec::new(),
@@ -118,22 +385,91 @@ impl Compiler {
             main_function_index: u16::MAX,
 
             global_var_map: HashMap::new(),
+            global_var_types: Vec::new(),
+            global_var_declared: Vec::new(),
             function_map: HashMap::new(),
+            named_constants: HashMap::new(),
+            exported_constants: Vec::new(),
+            structs: Vec::new(),
+            struct_map: HashMap::new(),
+            enums,
+            enum_map,
+            pattern_bindings: RefCell::new(Vec::new()),
+            function_signatures: HashMap::new(),
             syscall_map,
             current_local_vars: Vec::new(),
             current_local_vars_map: HashMap::new(),
             in_global_scope: true,
+            top_level_statement: false,
+            current_function: String::new(),
+            current_return_type: None,
+            current_upvalues: Vec::new(),
+            current_captured: HashSet::new(),
+            current_handlers: Vec::new(),
+            try_contexts: Vec::new(),
+            enclosing_scopes: Vec::new(),
+            module_prefix: String::new(),
+            module_aliases: HashMap::new(),
+            imported_names: HashMap::new(),
+            diagnostics: Vec::new(),
+            stack_depth: 0,
+            optimization_level: 0,
+            backend: Backend::Stack,
+        }
+    }
+
+    /// 设置优化级别（默认为 0）。级别 1 在编译期计算常量表达式，化简条件为常量的分支，
+    /// 合并跳转链并删除不可达代码；级别 2 另外把常见的指令序列合并为超级指令（如 IncLocal）
+    pub fn with_optimization_level(mut self, level: u8) -> Self {
+        self.optimization_level = level;
+        self
+    }
+
+    /// 设置 VM 后端（默认为栈式字节码）。寄存器后端在优化之后把每个函数翻译为三地址码，
+    /// 局部变量和临时值分配到寄存器中
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// 编译整个程序，遇到错误时 panic（报告第一个错误）
+    pub fn compile(self, ast: &ASTNode) -> CompileResult {
+        match self.compile_with_diagnostics(ast) {
+            Ok(result) => result,
+            Err(diagnostics) => panic!("{}", diagnostics[0]),
+        }
+    }
+
+    /// 编译整个程序并返回收集到的错误（目前为未知的变量、标识符和函数，类型错误，函数调用的参数和返回值的错误，常量、结构体、match 和 import 的错误）
+    pub fn compile_with_diagnostics(self, ast: &ASTNode) -> Result<CompileResult, Vec<Diagnostic>> {
+        let module = Module {
+            name: String::new(),
+            ast: Box::new(ast.clone()),
+            imports: HashMap::new(),
+        };
+        self.compile_modules_with_diagnostics(&[module])
+    }
+
+    /// 编译 ModuleResolver 解析出的所有模块并链接为一个程序，遇到错误时 panic（报告第一个错误）
+    pub fn compile_modules(self, modules: &[Module]) -> CompileResult {
+        match self.compile_modules_with_diagnostics(modules) {
+            Ok(result) => result,
+            Err(diagnostics) => panic!("{}", diagnostics[0]),
         }
     }
 
-    pub fn compile(mut self, ast: &ASTNode) -> CompileResult {
+    /// 编译所有模块并链接为一个程序。modules 按依赖顺序排列，最后一个为主程序；
+    /// 各模块的顶层代码按同样的顺序在入口函数中执行
+    pub fn compile_modules_with_diagnostics(mut self, modules: &[Module]) -> Result<CompileResult, Vec<Diagnostic>> {
         self.add_constant(Constant::Nil);
         // Generate Entrypoint Function
         let entrypoint_function_index = {
             let mut entrypoint_bytecode = Vec::new();
-            self.visit_ast_with_bytecode(ast, &mut entrypoint_bytecode);
+            self.visit_modules_with_bytecode(modules, &mut entrypoint_bytecode);
             if self.main_function_index != u16::MAX {
                 self.emit_opcode_with_arg(&mut entrypoint_bytecode, OpCode::Call, self.main_function_index as u64);
+                // 丢弃 main 的返回值
+                self.emit_opcode(&mut entrypoint_bytecode, OpCode::Pop);
             }
             self.emit_opcode(&mut entrypoint_bytecode, OpCode::Exit);
 
@@ -142,27 +478,364 @@ impl Compiler {
                 param_count: 0,
                 local_count: 0,
                 bytecode: entrypoint_bytecode,
+                handlers: std::mem::take(&mut self.current_handlers),
             });
             self.functions.len() as u16 - 1
         };
-        CompileResult {
+        if !self.diagnostics.is_empty() {
+            return Err(self.diagnostics);
+        }
+        if self.optimization_level >= 1 {
+            for function in &mut self.functions {
+                optimize_function(function, &self.constants, self.optimization_level);
+            }
+        }
+        let result = CompileResult {
             constants: self.constants,
             global_vars: self.global_vars,
             functions: self.functions,
+            structs: self.structs,
+            enums: self.enums,
+            exported_constants: self.exported_constants,
             entrypoint: entrypoint_function_index,
-        }
+            register_functions: Vec::new(),
+        };
+        Ok(result.with_backend(self.backend))
     }
     
-    fn visit_ast_with_bytecode(&mut self, ast: &ASTNode, bytecode: &mut Vec<u8>) {
-        if let ASTNode::Program(statements) = ast {
-                for stmt in statements {
-                    self.visit_statement(stmt, bytecode);
-                }
+    fn visit_modules_with_bytecode(&mut self, modules: &[Module], bytecode: &mut Vec<u8>) {
+        // 先登记所有模块的顶层定义，被导入的模块在前
+        for module in modules {
+            self.enter_module(module);
+            self.declare_program(Self::module_statements(module));
+        }
+        for module in modules {
+            self.enter_module(module);
+            for stmt in Self::module_statements(module) {
+                self.top_level_statement = true;
+                self.visit_statement(stmt, bytecode);
+            }
+        }
+    }
+
+    fn module_statements(module: &Module) -> &[Box<ASTNode>] {
+        match module.ast.as_ref() {
+            ASTNode::Program(statements) => statements,
+            _ => panic!("ROOT node is not ASTNode::Program"),
+        }
+    }
+
+    // 切换到模块的命名空间，登记模块中的 import
+    fn enter_module(&mut self, module: &Module) {
+        self.module_prefix = if module.name.is_empty() {
+            String::new()
         } else {
-            panic!("ROOT node is not ASTNode::Program");
+            format!("{}::", module.name)
+        };
+        self.module_aliases.clear();
+        self.imported_names.clear();
+        for stmt in Self::module_statements(module) {
+            let ASTNode::Import(path, alias, names, _) = stmt.as_ref() else {
+                continue;
+            };
+            // 未解析的 import 由 visit_statement 报告
+            let Some(target) = module.imports.get(path) else {
+                continue;
+            };
+            match alias {
+                Some(alias) => {
+                    self.module_aliases.insert(alias.clone(), format!("{}::", target));
+                },
+                None => {
+                    for name in names {
+                        self.imported_names.insert(name.clone(), format!("{}::{}", target, name));
+                    }
+                },
+            }
+        }
+    }
+
+    // 当前模块中定义的函数或全局变量的全名
+    fn declared_name(&self, name: &str) -> String {
+        format!("{}{}", self.module_prefix, name)
+    }
+
+    // 当前模块中引用的函数或全局变量的全名：alias::name 和按名字导入的名字为导入的模块中的名字，
+    // 其余为当前模块中的名字
+    fn qualify(&self, name: &str) -> String {
+        if let Some((alias, member)) = name.split_once("::") {
+            return match self.module_aliases.get(alias) {
+                Some(prefix) => format!("{}{}", prefix, member),
+                None => name.to_string(),
+            };
+        }
+        match self.imported_names.get(name) {
+            Some(qualified) => qualified.clone(),
+            None => self.declared_name(name),
+        }
+    }
+
+    fn lookup_function(&self, name: &str) -> Option<u16> {
+        self.function_map.get(&self.qualify(name)).copied()
+    }
+
+    fn lookup_constant(&self, name: &str) -> Option<&(Constant, Type)> {
+        self.named_constants.get(&self.qualify(name))
+    }
+
+    // 顶层名字（函数、全局变量和常量）是否已被使用
+    fn is_declared(&self, qualified: &str) -> bool {
+        self.function_map.contains_key(qualified) || self.global_var_map.contains_key(qualified)
+            || self.named_constants.contains_key(qualified)
+    }
+
+    // 登记常量：在编译期求值，按声明的类型转换后放入常量池。错误报告在初始化表达式上
+    fn declare_constant(&mut self, name: &str, type_name: &str, expr: &ASTNode) {
+        let qualified = self.declared_name(name);
+        if self.is_declared(&qualified) {
+            return self.error(Diagnostic::new(format!("Duplicate constant: {}", name), expr.span()));
+        }
+        let const_type = self.resolve_type(type_name);
+        let value = match self.eval_typed_constant(name, const_type, expr) {
+            Ok(value) => value,
+            Err(message) => {
+                self.error(Diagnostic::new(message, expr.span()));
+                // 出错的常量仍然登记（值为空、类型未知），使用它的地方不再重复报错
+                self.named_constants.insert(qualified, (Constant::Nil, Type::Any));
+                return;
+            },
+        };
+        let const_index = self.add_constant(value.clone());
+        self.exported_constants.push(ConstantInfo {
+            name: qualified.clone(),
+            const_index,
+        });
+        self.named_constants.insert(qualified, (value, const_type));
+    }
+
+    // 求值常量的初始化表达式并转换为声明的类型
+    fn eval_typed_constant(&self, name: &str, const_type: Type, expr: &ASTNode) -> Result<Constant, String> {
+        if !matches!(const_type, Type::Int | Type::Float | Type::Bool | Type::String) {
+            return Err(format!("Constant {} must have type int, float, bool or string, found {}", name, self.type_name(const_type)));
+        }
+        let cannot_evaluate = |msg| format!("Cannot evaluate constant {}: {}", name, msg);
+        let value = self.eval_constant(expr).map_err(cannot_evaluate)?;
+        let value_type = Self::constant_type(&value);
+        if !value_type.is_assignable_to(const_type) {
+            return Err(format!("Type mismatch in constant {}: expected {}, found {}", name, self.type_name(const_type), self.type_name(value_type)));
+        }
+        // int 值赋给 float 常量时提升为 float
+        eval_cast_constant(&value, const_type).map_err(cannot_evaluate)
+    }
+
+    // 编译期求值常量表达式：字面量、其他常量，以及它们的运算和类型转换
+    fn eval_constant(&self, expr: &ASTNode) -> Result<Constant, String> {
+        match expr {
+            ASTNode::IntLiteral(value, _) => Ok(Constant::Int(*value)),
+            ASTNode::FloatLiteral(value, _) => Ok(Constant::Float(*value)),
+            ASTNode::BoolLiteral(value, _) => Ok(Constant::Bool(*value)),
+            ASTNode::StringLiteral(value, _) => Ok(Constant::String(value.clone())),
+            // 与常量同名的局部变量遮蔽常量
+            ASTNode::Identifier(name, _) => match self.lookup_constant(name) {
+                Some((value, _)) if self.lookup_var_type(name).is_none() => Ok(value.clone()),
+                _ => Err(format!("{} is not a constant", name)),
+            },
+            ASTNode::UnaryExpr(op, expr, _) => eval_unary_constant(op, &self.eval_constant(expr)?),
+            ASTNode::BinaryExpr(left, op, right, _) => {
+                eval_binary_constant(op, &self.eval_constant(left)?, &self.eval_constant(right)?)
+            },
+            ASTNode::Cast(expr, type_name, _) => match Type::from_name(type_name) {
+                Some(target) => eval_cast_constant(&self.eval_constant(expr)?, target),
+                None => Err(format!("Unknown type: {}", type_name)),
+            },
+            _ => Err("expression is not a compile-time constant".to_string()),
+        }
+    }
+
+    // 优化时在编译期计算表达式并生成 LoadConst，返回常量索引。
+    // 求值出错（如除以零）时不折叠，错误留到运行时抛出
+    fn emit_folded_constant(&mut self, expr: &ASTNode, bytecode: &mut Vec<u8>) -> Option<u16> {
+        if self.optimization_level == 0 {
+            return None;
+        }
+        let value = self.eval_constant(expr).ok()?;
+        let const_idx = self.add_constant(value);
+        self.emit_load_const(bytecode, const_idx);
+        Some(const_idx)
+    }
+
+    fn constant_type(value: &Constant) -> Type {
+        match value {
+            Constant::Nil => Type::Any,
+            Constant::Int(_) => Type::Int,
+            Constant::Float(_) => Type::Float,
+            Constant::Bool(_) => Type::Bool,
+            Constant::String(_) => Type::String,
+        }
+    }
+
+    // 预先登记顶层的结构体、枚举、函数和全局变量，使函数体可以引用定义在后面的函数和全局变量（如相互递归）
+    fn declare_program(&mut self, statements: &[Box<ASTNode>]) {
+        let statements: Vec<&ASTNode> = statements.iter()
+            .map(|stmt| match stmt.as_ref() {
+                ASTNode::Documented(_, stmt) => stmt.as_ref(),
+                stmt => stmt,
+            })
+            .collect();
+
+        // 先登记所有结构体和枚举的名字，字段和负载类型可以引用其他结构体和枚举
+        for stmt in &statements {
+            match stmt {
+                ASTNode::StructDef(name, _) => {
+                    if self.struct_map.contains_key(name) || self.enum_map.contains_key(name) {
+                        panic!("Duplicate struct: {}", name);
+                    }
+                    self.struct_map.insert(name.clone(), self.structs.len() as u16);
+                    self.structs.push(StructInfo {
+                        name: name.clone(),
+                        fields: Vec::new(),
+                    });
+                },
+                ASTNode::EnumDef(name, _) => {
+                    if self.struct_map.contains_key(name) || self.enum_map.contains_key(name) {
+                        panic!("Duplicate enum: {}", name);
+                    }
+                    self.enum_map.insert(name.clone(), self.enums.len() as u16);
+                    self.enums.push(EnumInfo {
+                        name: name.clone(),
+                        variants: Vec::new(),
+                    });
+                },
+                _ => {},
+            }
+        }
+        for stmt in &statements {
+            if let ASTNode::StructDef(name, fields) = stmt {
+                let mut field_infos: Vec<FieldInfo> = Vec::new();
+                for (field_name, type_name) in fields {
+                    if field_infos.iter().any(|field| &field.name == field_name) {
+                        panic!("Duplicate field {} in struct {}", field_name, name);
+                    }
+                    field_infos.push(FieldInfo {
+                        name: field_name.clone(),
+                        field_type: self.resolve_type(type_name),
+                    });
+                }
+                self.structs[self.struct_map[name] as usize].fields = field_infos;
+            }
+            if let ASTNode::EnumDef(name, variants) = stmt {
+                let mut variant_infos: Vec<VariantInfo> = Vec::new();
+                for (variant_name, type_names) in variants {
+                    if variant_infos.iter().any(|variant| &variant.name == variant_name) {
+                        panic!("Duplicate variant {} in enum {}", variant_name, name);
+                    }
+                    variant_infos.push(VariantInfo {
+                        name: variant_name.clone(),
+                        payload_types: type_names.iter().map(|type_name| self.resolve_type(type_name)).collect(),
+                    });
+                }
+                self.enums[self.enum_map[name] as usize].variants = variant_infos;
+            }
+        }
+
+        for stmt in statements {
+            match stmt {
+                ASTNode::FunctionDef(name, params, return_type, ..) => {
+                    let qualified = self.declared_name(name);
+                    if self.is_declared(&qualified) {
+                        panic!("Duplicate function: {}", name);
+                    }
+                    let param_types = params.iter().map(|(_, param_type)| self.resolve_type(param_type)).collect();
+                    let return_type = return_type.as_deref().map(|type_name| self.resolve_type(type_name));
+
+                    // 先占位，函数体编译完成后替换
+                    let func_index = self.functions.len() as u16;
+                    self.functions.push(FunctionInfo {
+                        name: qualified.clone(),
+                        param_count: params.len() as u8,
+                        local_count: 0,
+                        bytecode: Vec::new(),
+                        handlers: Vec::new(),
+                    });
+                    self.function_map.insert(qualified, func_index);
+                    self.function_signatures.insert(func_index, FunctionSignature {
+                        param_types,
+                        return_type,
+                    });
+                    // 只有主程序的 main 是入口
+                    if name == "main" && self.module_prefix.is_empty() {
+                        self.main_function_index = func_index;
+                    }
+                },
+                ASTNode::VariableDecl(name, type_name, _) => {
+                    let qualified = self.declared_name(name);
+                    if self.is_declared(&qualified) {
+                        panic!("Duplicate global variable: {}", name);
+                    }
+                    let var_type = self.resolve_type(type_name);
+                    self.add_global(&qualified, var_type);
+                },
+                // 常量按声明顺序求值，只能引用之前声明的常量
+                ASTNode::ConstDecl(name, type_name, expr) => self.declare_constant(name, type_name, expr),
+                _ => {},
+            }
+        }
+    }
+
+    fn resolve_type(&self, type_name: &str) -> Type {
+        match Type::from_name(type_name) {
+            Some(var_type) => var_type,
+            None => match (self.struct_map.get(type_name), self.enum_map.get(type_name)) {
+                (Some(struct_index), _) => Type::Struct(*struct_index),
+                (_, Some(enum_index)) => Type::Enum(*enum_index),
+                _ => panic!("Unknown type: {}", type_name),
+            },
+        }
+    }
+
+    // 用于错误信息的类型名，结构体和枚举显示为其名字
+    fn type_name(&self, var_type: Type) -> &str {
+        match var_type {
+            Type::Struct(struct_index) => &self.structs[struct_index as usize].name,
+            Type::Enum(enum_index) => &self.enums[enum_index as usize].name,
+            _ => var_type.name(),
+        }
+    }
+
+    // 查找结构体字段，返回字段索引和类型
+    fn lookup_field(&self, object_type: Type, field: &str) -> Result<(u16, Type), String> {
+        match object_type {
+            Type::Struct(struct_index) => {
+                let struct_info = &self.structs[struct_index as usize];
+                match struct_info.fields.iter().position(|field_info| field_info.name == field) {
+                    Some(field_index) => Ok((field_index as u16, struct_info.fields[field_index].field_type)),
+                    None => Err(format!("Struct {} has no field {}", struct_info.name, field)),
+                }
+            },
+            // 错误值的字段固定为 message 和 trace
+            Type::Error => match field {
+                "message" => Ok((0, Type::String)),
+                "trace" => Ok((1, Type::Array)),
+                _ => Err(format!("Type error has no field {}", field)),
+            },
+            // 类型未知时无法在编译期确定字段位置
+            Type::Any => Err(format!("Cannot access field {} of a value of unknown type", field)),
+            _ => Err(format!("Type {} has no field {}", self.type_name(object_type), field)),
         }
     }
 
+    fn add_global(&mut self, name: &str, var_type: Type) -> u16 {
+        self.global_vars.push(GlobalVarInfo {
+            name: name.to_string(),
+            const_index: None,
+        });
+        self.global_var_types.push(var_type);
+        self.global_var_declared.push(false);
+        let global_index = self.global_vars.len() - 1;
+        self.global_var_map.insert(name.to_string(), global_index);
+        global_index as u16
+    }
+
     fn visit_block(&mut self, block: &ASTNode, bytecode: &mut Vec<u8>) {
         if let ASTNode::Block(statements) = block {
             for stmt in statements {
@@ -171,47 +844,176 @@ impl Compiler {
         }
     }
 
-    fn visit_assignment(&mut self, name: &str, expr: &ASTNode, bytecode: &mut Vec<u8>) {
-        self.visit_expression(expr, bytecode);
+    fn visit_assignment(&mut self, name: &str, expr: &ASTNode, span: Span, bytecode: &mut Vec<u8>) {
+        let var_type = match self.lookup_var_type(name) {
+            Some(var_type) => var_type,
+            None => return self.unknown_variable(name, span),
+        };
+        self.visit_expression_as(expr, var_type, bytecode);
 
-        if let Some(var_index_value) = self.lookup_local(name) {
-            self.emit_store_local(bytecode, var_index_value);
-        } else if let Some(var_index_value) = self.lookup_global(name) {
-            self.emit_store_global(bytecode, var_index_value);
-        } else {
-            panic!("Unknown variable: {}", name);
+        match self.resolve_var(name) {
+            Some(var) => self.emit_store_var(bytecode, var),
+            None => self.unknown_variable(name, span),
         }
     }
 
-    fn visit_increment(&mut self, name: &str, bytecode: &mut Vec<u8>) {
-       if let Some(var_index_value) = self.lookup_local(name) {
-            self.emit_load_local(bytecode, var_index_value);
-            self.emit_opcode(bytecode, OpCode::Inc);
-            self.emit_store_local(bytecode, var_index_value);
-        } else if let Some(var_index_value) = self.lookup_global(name) {
-            self.emit_load_global(bytecode, var_index_value);
-            self.emit_opcode(bytecode, OpCode::Inc);
-            self.emit_store_global(bytecode, var_index_value);
-        } else {
-            panic!("Unknown variable: {}", name);
+    // 结构体是值类型：a.b.c = v 依次取出 a 和 a.b，修改后逐层写回，最后存回变量 a
+    fn visit_field_assignment(&mut self, object: &ASTNode, field: &str, value: &ASTNode, span: Span, bytecode: &mut Vec<u8>) {
+        // 从外到内收集字段路径
+        let mut path = vec![field];
+        let mut root = object;
+        while let ASTNode::FieldAccess(inner, inner_field, _) = root {
+            path.push(inner_field);
+            root = inner;
+        }
+        path.reverse();
+        let ASTNode::Identifier(name, name_span) = root else {
+            return self.error(Diagnostic::new("Invalid assignment target".to_string(), span));
+        };
+        let Some(var) = self.resolve_var(name) else {
+            return self.unknown_variable(name, *name_span);
+        };
+
+        // 压入根变量和每一层中间结构体，同时推导每层字段的位置和类型
+        self.emit_load_var(bytecode, var);
+        let mut object_type = self.lookup_var_type(name).unwrap_or(Type::Any);
+        let mut field_indexes = Vec::new();
+        for (depth, field) in path.iter().enumerate() {
+            if object_type == Type::Error {
+                return self.error(Diagnostic::new(format!("Cannot assign field {} of error", field), span));
+            }
+            let (field_index, field_type) = match self.lookup_field(object_type, field) {
+                Ok(field_info) => field_info,
+                Err(_) if object_type == Type::Any => {
+                    return self.error(Diagnostic::new(format!("Cannot assign field {} of a value of unknown type", field), span));
+                },
+                Err(message) => return self.error(Diagnostic::new(message, span)),
+            };
+            field_indexes.push(field_index);
+            if depth + 1 < path.len() {
+                self.emit_opcode(bytecode, OpCode::Dup);
+                self.emit_opcode_with_arg(bytecode, OpCode::GetField, field_index as u64);
+            }
+            object_type = field_type;
+        }
+
+        self.visit_expression_as(value, object_type, bytecode);
+        for field_index in field_indexes.iter().rev() {
+            self.emit_opcode_with_arg(bytecode, OpCode::SetField, *field_index as u64);
         }
+        self.emit_store_var(bytecode, var);
     }
 
-    fn visit_decrement(&mut self, name: &str, bytecode: &mut Vec<u8>) {
-        if let Some(var_index_value) = self.lookup_local(name) {
-            self.emit_load_local(bytecode, var_index_value);
-            self.emit_opcode(bytecode, OpCode::Dec);
-            self.emit_store_local(bytecode, var_index_value);
-        } else if let Some(var_index_value) = self.lookup_global(name) {
-            self.emit_load_global(bytecode, var_index_value);
-            self.emit_opcode(bytecode, OpCode::Dec);
-            self.emit_store_global(bytecode, var_index_value);
+    // 声明变量并用栈顶的值初始化：顶层代码中为全局变量，函数中为局部变量。
+    // top_level 表示模块的顶层 let 语句，只有它可以使用 declare_program 为它预先登记的全局变量
+    fn declare_variable(&mut self, name: &str, var_type: Type, const_index: Option<u16>, top_level: bool, bytecode: &mut Vec<u8>) {
+        if self.in_global_scope {
+            // 顶层声明已由 declare_program 登记；代码块中的声明在这里新建全局变量
+            let qualified = self.declared_name(name);
+            let global_index = match self.global_var_map.get(&qualified) {
+                Some(&index) if !self.global_var_declared[index] => {
+                    if !top_level {
+                        panic!("Variable {} conflicts with the global variable declared later at the top level", name);
+                    }
+                    index as u16
+                },
+                _ => self.add_global(&qualified, var_type),
+            };
+            self.global_vars[global_index as usize].const_index = const_index;
+            self.global_var_declared[global_index as usize] = true;
+            // 为全局变量生成初始化字节码
+            self.emit_store_global(bytecode, global_index);
         } else {
-            panic!("Unknown variable: {}", name);
+            let captured = self.current_captured.contains(name);
+            self.current_local_vars.push(LocalVarInfo {
+                name: name.to_string(),
+                const_index,
+                var_type,
+                captured,
+            });
+            let local_index = (self.current_local_vars.len() - 1) as u8;
+            self.current_local_vars_map.insert(name.to_string(), local_index as usize);
+            // 为局部变量生成初始化字节码，被捕获的变量每次声明都新建一个单元
+            if captured {
+                self.emit_opcode(bytecode, OpCode::NewCell);
+            }
+            self.emit_store_local(bytecode, local_index);
+        }
+    }
+
+    // 记录编译错误并继续编译；出错后生成的代码不会被使用
+    fn error(&mut self, diagnostic: Diagnostic) {
+        let diagnostic = match self.module_prefix.strip_suffix("::") {
+            Some(module) if diagnostic.has_location() => ModuleResolver::in_module(diagnostic, module),
+            _ => diagnostic,
+        };
+        self.diagnostics.push(diagnostic);
+    }
+
+    // 记录未知变量错误，并在作用域内有相近的名字时给出建议
+    fn unknown_variable(&mut self, name: &str, span: Span) {
+        if self.lookup_constant(name).is_some() {
+            self.error(Diagnostic::new(format!("Cannot assign to constant: {}", name), span));
+            return;
+        }
+        let diagnostic = Diagnostic::new(format!("Unknown variable: {}", name), span);
+        let diagnostic = self.suggest_name(diagnostic, name);
+        self.error(diagnostic);
+    }
+
+    // 作用域内有与 name 相近的变量名时在诊断信息中给出建议（只建议当前模块中的全局变量）
+    fn suggest_name(&self, diagnostic: Diagnostic, name: &str) -> Diagnostic {
+        let globals = self.global_var_map.keys()
+            .filter_map(|global| global.strip_prefix(self.module_prefix.as_str()))
+            .filter(|global| !global.contains("::"));
+        let candidates = self.current_local_vars_map.keys().map(String::as_str).chain(globals);
+        match suggest_similar(name, candidates) {
+            Some(suggestion) => diagnostic.with_help(format!("did you mean `{}`?", suggestion)),
+            None => diagnostic,
+        }
+    }
+
+    fn visit_increment(&mut self, name: &str, span: Span, bytecode: &mut Vec<u8>) {
+        match self.resolve_var(name) {
+            Some(var) => {
+                self.emit_load_var(bytecode, var);
+                self.emit_opcode(bytecode, OpCode::Inc);
+                self.emit_store_var(bytecode, var);
+            },
+            None => self.unknown_variable(name, span),
+        }
+    }
+
+    fn visit_decrement(&mut self, name: &str, span: Span, bytecode: &mut Vec<u8>) {
+        match self.resolve_var(name) {
+            Some(var) => {
+                self.emit_load_var(bytecode, var);
+                self.emit_opcode(bytecode, OpCode::Dec);
+                self.emit_store_var(bytecode, var);
+            },
+            None => self.unknown_variable(name, span),
         }
     }
 
     fn visit_statement(&mut self, stmt: &ASTNode, bytecode: &mut Vec<u8>) {
+        let depth = self.stack_depth;
+        let errors = self.diagnostics.len();
+        self.visit_statement_unchecked(stmt, bytecode);
+        if self.diagnostics.len() > errors {
+            // 有错误的语句生成的代码不会被使用，只需恢复栈深度以便继续检查后面的语句
+            self.stack_depth = depth;
+            return;
+        }
+        // 每条语句执行前后栈深度必须一致，否则循环中会不断泄漏栈空间
+        assert_eq!(self.stack_depth, depth, "Statement is not stack-neutral: {:?}", stmt);
+    }
+
+    fn visit_statement_unchecked(&mut self, stmt: &ASTNode, bytecode: &mut Vec<u8>) {
+        // 文档注释包装的语句仍是顶层语句，其余语句中嵌套的语句都不是
+        let top_level = match stmt {
+            ASTNode::Documented(_, _) => self.top_level_statement,
+            _ => std::mem::take(&mut self.top_level_statement),
+        };
         match stmt {
             ASTNode::Block(statements) => {
                 for stmt in statements {
@@ -219,46 +1021,74 @@ impl Compiler {
                 }
             },
             ASTNode::VariableDecl(name, _type, initializer) => {
+                let var_type = self.resolve_type(_type);
                 let const_index = if let Some(expr) = initializer {
                     // 根据表达式生成初始化字节码
-                    self.visit_expression(expr, bytecode)
+                    self.visit_expression_as(expr, var_type, bytecode)
+                } else if var_type == Type::Array {
+                    // 数组默认值为空数组
+                    self.emit_opcode_with_arg(bytecode, OpCode::NewArray, 0);
+                    None
                 } else {
                     // 默认值
-                    Some(match _type.as_str() {
-                        "int" => self.add_constant(Constant::Int(0)),
-                        "float" => self.add_constant(Constant::Float(0.0)),
-                        "bool" => self.add_constant(Constant::Bool(false)),
-                        "string" => self.add_constant(Constant::String("".to_string())),
-                        _ => panic!("Unknown type: {}", _type),
-                    })
+                    let default_value = match var_type {
+                        Type::Int => Constant::Int(0),
+                        Type::Float => Constant::Float(0.0),
+                        Type::Bool => Constant::Bool(false),
+                        Type::Function | Type::Struct(_) | Type::Enum(_) | Type::Error => Constant::Nil,
+                        _ => Constant::String("".to_string()),
+                    };
+                    let const_idx = self.add_constant(default_value);
+                    self.emit_load_const(bytecode, const_idx);
+                    Some(const_idx)
                 };
-
-                if self.in_global_scope {
-                    self.global_vars.push(GlobalVarInfo {
-                        name: name.clone(),
-                        const_index: const_index,
-                    });
-                    let global_index = (self.global_vars.len() - 1) as u16;
-                    self.global_var_map.insert(name.clone(), global_index as usize);
-                    // 为全局变量生成初始化字节码
-                    self.emit_store_global(bytecode, global_index);
-                } else {
-                    self.current_local_vars.push(LocalVarInfo {
-                        name: name.clone(),
-                        const_index: const_index,
-                    });
-                    let local_index = (self.current_local_vars.len() - 1) as u8;
-                    self.current_local_vars_map.insert(name.clone(), local_index as usize);
-                    println!("Add local {} index:{}", name, local_index);
-                    // 为局部变量生成初始化字节码
-                    self.emit_store_local(bytecode, local_index);
+                self.declare_variable(name, var_type, const_index, top_level, bytecode);
+            },
+            ASTNode::Documented(_, stmt) => self.visit_statement(stmt, bytecode),
+            ASTNode::Import(path, alias, names, span) => {
+                // 模块已由 ModuleResolver 加载，别名已由 enter_module 登记
+                if !self.in_global_scope {
+                    return self.error(Diagnostic::new(format!("Import of {} must be at the top level", path), *span));
+                }
+                let resolved = match alias {
+                    Some(alias) => self.module_aliases.contains_key(alias),
+                    None => names.iter().all(|name| self.imported_names.contains_key(name)),
+                };
+                if !resolved {
+                    return self.error(Diagnostic::new(format!("Unresolved import: {}", path), *span));
+                }
+                for name in names {
+                    if !self.is_declared(&self.qualify(name)) {
+                        let message = format!("Module {} has no function, global variable or constant {}", path, name);
+                        self.error(Diagnostic::new(message, *span));
+                    }
+                }
+            },
+            ASTNode::ConstDecl(name, _, expr) => {
+                // 值已由 declare_program 求值
+                if !self.in_global_scope || !self.named_constants.contains_key(&self.declared_name(name)) {
+                    self.error(Diagnostic::new(format!("Constant {} must be declared at the top level", name), expr.span()));
+                }
+            },
+            ASTNode::Assignment(name, expr, span) => self.visit_assignment(name, expr, *span, bytecode),
+            ASTNode::FieldAssignment(object, field, value, span) => self.visit_field_assignment(object, field, value, *span, bytecode),
+            ASTNode::StructDef(name, _) => {
+                // 字段已由 declare_program 登记
+                if !self.struct_map.contains_key(name) {
+                    panic!("Struct {} must be defined at the top level", name);
+                }
+            },
+            ASTNode::EnumDef(name, _) => {
+                // 变体已由 declare_program 登记
+                if !self.enum_map.contains_key(name) {
+                    panic!("Enum {} must be defined at the top level", name);
                 }
             },
-            ASTNode::Assignment(name, expr) => self.visit_assignment(name, expr, bytecode),
-            ASTNode::Increment(var_name) => self.visit_increment(var_name, bytecode),
-            ASTNode::Decrement(var_name) => self.visit_decrement(var_name, bytecode),
+            ASTNode::Increment(var_name, span) => self.visit_increment(var_name, *span, bytecode),
+            ASTNode::Decrement(var_name, span) => self.visit_decrement(var_name, *span, bytecode),
             ASTNode::IfStatement(condition, then_branch, else_branch) => {
                 // 生成求值字节码
+                self.check_condition(condition);
                 self.visit_expression(condition, bytecode);
 
                 // 为 JZ 预留空间。该JZ负责条件为false则跳转到else分支或if结束
@@ -286,8 +1116,8 @@ impl Compiler {
             ASTNode::ForLoop(init, condition, update, body) => {
                 // 初始化循环变量
                 if let Some(init) = init {
-                    if let ASTNode::Assignment(name, expr) = &**init {
-                        self.visit_assignment(name, expr, bytecode);
+                    if let ASTNode::Assignment(name, expr, span) = &**init {
+                        self.visit_assignment(name, expr, *span, bytecode);
                     } else {
                         panic!("For loop init must be an assignment");
                     }
@@ -295,6 +1125,7 @@ impl Compiler {
                 let loop_start = bytecode.len();
                 // 生成求值字节码
                 if let Some(condition) = condition {
+                    self.check_condition(condition);
                     self.visit_expression(condition, bytecode);
                 } else {
                     let const_idx = self.add_constant(Constant::Bool(true));
@@ -308,12 +1139,12 @@ impl Compiler {
 
                 if let Some(update) = update {
                     match &**update {
-                        ASTNode::Assignment(name, expr) => self.visit_assignment(name, expr, bytecode),
-                        ASTNode::Increment(var_name) => {
-                            self.visit_increment(var_name, bytecode);
+                        ASTNode::Assignment(name, expr, span) => self.visit_assignment(name, expr, *span, bytecode),
+                        ASTNode::Increment(var_name, span) => {
+                            self.visit_increment(var_name, *span, bytecode);
                         },
-                        ASTNode::Decrement(var_name) => {
-                            self.visit_decrement(var_name, bytecode);
+                        ASTNode::Decrement(var_name, span) => {
+                            self.visit_decrement(var_name, *span, bytecode);
                         },
                         _ => panic!("For loop update must be an assignment"),
                     }
@@ -330,6 +1161,7 @@ impl Compiler {
             ASTNode::WhileLoop(condition, body) => {
                 let loop_start = bytecode.len();
                 // 生成求值字节码
+                self.check_condition(condition);
                 self.visit_expression(condition, bytecode);
                 // 为 JZ 预留空间
                 let jz_pos = bytecode.len();
@@ -345,79 +1177,63 @@ impl Compiler {
                 let jz_offset = bytecode.len() - jz_pos;
                 self.set_arg_at(bytecode, jz_pos, jz_offset as u64);
             },
-            ASTNode::FunctionDef(name, params, body) => {
-                let func_index = self.functions.len() as u16;
-                self.function_map.insert(name.clone(), func_index);
-                if name == "main" {
-                    self.main_function_index = func_index;
+            ASTNode::FunctionDef(name, params, _, body, span) => {
+                // 签名已由 declare_program 登记
+                let qualified = self.declared_name(name);
+                let func_index = match self.function_map.get(&qualified) {
+                    Some(index) if top_level => *index,
+                    _ => return self.error(Diagnostic::new(format!("Function {} must be defined at the top level", name), *span)),
+                };
+                let signature = &self.function_signatures[&func_index];
+                let param_types = signature.param_types.clone();
+                let return_type = signature.return_type;
+                let (function, _) = self.compile_function(&qualified, params, param_types, return_type, body, *span);
+                self.functions[func_index as usize] = function;
+            },
+            ASTNode::FunctionCall(..) => self.visit_discarded_expression(stmt, bytecode),
+            ASTNode::ExprStatement(expr) => self.visit_discarded_expression(expr, bytecode),
+            ASTNode::Return(expr_opt, span) => {
+                if let Some(expr) = expr_opt {
+                    match self.current_return_type {
+                        Some(return_type) => {
+                            let found = self.type_of(expr);
+                            if found.is_assignable_to(return_type) {
+                                self.visit_expression_as(expr, return_type, bytecode);
+                            } else {
+                                let message = format!("Type mismatch in return from {}: expected {}, found {}",
+                                    self.current_function, self.type_name(return_type), self.type_name(found));
+                                self.error(Diagnostic::new(message, expr.span()));
+                                self.visit_expression(expr, bytecode);
+                            }
+                        },
+                        None => {
+                            self.visit_expression(expr, bytecode);
+                        },
+                    }
+                } else {
+                    if let Some(return_type) = self.current_return_type {
+                        let message = format!("Function {} must return a value of type {}", self.current_function, self.type_name(return_type));
+                        self.error(Diagnostic::new(message, *span));
+                    }
+                    // 返回空值
+                    let const_idx= self.add_constant(Constant::Nil);
+                    self.emit_load_const(bytecode, const_idx);
                 }
-
-                // 记录参数作为局部变量
-                self.current_local_vars.reserve(params.len());
-                self.current_local_vars_map.reserve(params.len());
-
-                let param_count = params.len() as u8;
-                for (i, (param_name, _)) in params.iter().enumerate() {
-                    self.current_local_vars.push(LocalVarInfo {
-                        name: param_name.clone(),
-                        const_index: None,
-                    });
-                    self.current_local_vars_map.insert(param_name.clone(), i);
+                // 返回值留在栈上，先执行外层 try 的 finally
+                self.visit_finally_before_return(bytecode);
+                self.emit_opcode(bytecode, OpCode::Ret);
+                for context in &mut self.try_contexts {
+                    context.open(bytecode.len());
                 }
-                
-                // 编译函数体到字节码，同时分析局部变量
-                let mut bytecode = Vec::new();
-                self.in_global_scope = false;
-                self.visit_block(body, &mut bytecode);
-                self.in_global_scope = true;
-
-                let const_idx= self.add_constant(Constant::Nil);
-                self.emit_load_const(&mut bytecode, const_idx);
-                self.emit_opcode(&mut bytecode, OpCode::Ret);
-
-                // 计算总局部变量数
-                let local_count = self.current_local_vars.len() as u8;
-
-                self.functions.push(FunctionInfo {
-                    name: name.clone(),
-                    param_count,
-                    local_count,
-                    bytecode,
-                });
-                
-                // 清空当前函数的局部变量映射，准备处理下一个函数
-                self.current_local_vars.clear();
-                self.current_local_vars_map.clear();
             },
-            ASTNode::FunctionCall(name, args) => {
-                // 参数逆序入栈
-                for arg in args.iter().rev() {
-                    self.visit_expression(arg, bytecode);
+            ASTNode::TryCatch(body, catch, finally) => self.visit_try(body, catch.as_ref(), finally.as_deref(), bytecode),
+            ASTNode::Throw(expr) => {
+                let expr_type = self.type_of(expr);
+                if !matches!(expr_type, Type::String | Type::Error | Type::Any) {
+                    self.error(Diagnostic::new(format!("Cannot throw a value of type {}", self.type_name(expr_type)), expr.span()));
                 }
-
-                let syscall_num = self.syscall_map.get(name).copied();
-                if let Some(syscall_num) = syscall_num {
-                    // 是系统调用，生成Syscall指令
-                    self.emit_opcode_with_arg(bytecode, OpCode::Syscall, syscall_num as u64);
-                } else {
-                    // 不是系统调用，继续使用Call指令
-                    let func_index = if let Some(index) = self.function_map.get(name) {
-                        *index
-                    } else {
-                        panic!("Unknown function: {}", name);
-                    };
-                    self.emit_opcode_with_arg(bytecode, OpCode::Call, func_index as u64);
-                }
-            },
-            ASTNode::Return(expr_opt) => {
-                if let Some(expr) = expr_opt {
-                    self.visit_expression(expr, bytecode);
-                } else {
-                    // 返回空值
-                    let const_idx= self.add_constant(Constant::Nil);
-                    self.emit_load_const(bytecode, const_idx);
-                }
-                self.emit_opcode(bytecode, OpCode::Ret);
+                self.visit_expression(expr, bytecode);
+                self.emit_opcode(bytecode, OpCode::Throw);
             },
             _ => {
                 panic!("Unsupported statement type {:?}", *stmt);
@@ -425,61 +1241,313 @@ impl Compiler {
         }
     }
 
+    // 编译 try 语句。finally 的代码在每个出口各生成一份：try 或 catch 正常结束后、return 之前，
+    // 以及未被 catch 的异常的处理程序中（执行后重新抛出）
+    fn visit_try(&mut self, body: &ASTNode, catch: Option<&(String, Box<ASTNode>)>, finally: Option<&ASTNode>, bytecode: &mut Vec<u8>) {
+        let depth = self.stack_depth;
+        let mut end_jumps = Vec::new();
+
+        // try 代码块
+        let mut protected = self.visit_protected_block(body, finally, bytecode);
+        if let Some(finally) = finally {
+            self.visit_block(finally, bytecode);
+        }
+        end_jumps.push(bytecode.len());
+        self.emit_opcode_with_arg(bytecode, OpCode::Jmp, 0);
+
+        if let Some((name, catch_body)) = catch {
+            // catch 的处理程序：错误值存入 catch 的变量
+            self.add_handlers(&protected, bytecode.len(), depth);
+            self.stack_depth = depth + 1;
+            self.declare_variable(name, Type::Error, None, false, bytecode);
+            // catch 代码块中抛出的异常仍要执行 finally
+            protected = self.visit_protected_block(catch_body, finally, bytecode);
+            if let Some(finally) = finally {
+                self.visit_block(finally, bytecode);
+            }
+            end_jumps.push(bytecode.len());
+            self.emit_opcode_with_arg(bytecode, OpCode::Jmp, 0);
+        }
+
+        if let Some(finally) = finally {
+            // finally 的处理程序：错误值留在栈上，执行 finally 后重新抛出
+            self.add_handlers(&protected, bytecode.len(), depth);
+            self.stack_depth = depth + 1;
+            self.visit_block(finally, bytecode);
+            self.emit_opcode(bytecode, OpCode::Throw);
+        }
+
+        for jmp_pos in end_jumps {
+            let jmp_offset = bytecode.len() - jmp_pos;
+            self.set_arg_at(bytecode, jmp_pos, jmp_offset as u64);
+        }
+        self.stack_depth = depth;
+    }
+
+    // 编译受 try 保护的代码块，返回受保护的指令范围
+    fn visit_protected_block(&mut self, block: &ASTNode, finally: Option<&ASTNode>, bytecode: &mut Vec<u8>) -> Vec<(usize, usize)> {
+        self.try_contexts.push(TryContext {
+            ranges: Vec::new(),
+            start: Some(bytecode.len()),
+            finally: finally.map(|finally| Box::new(finally.clone())),
+        });
+        self.visit_block(block, bytecode);
+        let mut context = self.try_contexts.pop().expect("no try context");
+        context.close(bytecode.len());
+        context.ranges
+    }
+
+    fn add_handlers(&mut self, ranges: &[(usize, usize)], target: usize, stack_depth: i32) {
+        for (start, end) in ranges {
+            self.current_handlers.push(ExceptionHandler {
+                start: *start as u16,
+                end: *end as u16,
+                target: target as u16,
+                stack_depth: stack_depth as u16,
+            });
+        }
+    }
+
+    // return 离开 try/catch 代码块前，从内到外依次执行各层的 finally。
+    // 执行某一层的 finally 时，这一层及内层的 try 不再保护，外层的 try 仍然保护
+    fn visit_finally_before_return(&mut self, bytecode: &mut Vec<u8>) {
+        if self.try_contexts.iter().all(|context| context.finally.is_none()) {
+            return;
+        }
+        for context in &mut self.try_contexts {
+            context.close(bytecode.len());
+        }
+        for i in (0..self.try_contexts.len()).rev() {
+            let Some(finally) = self.try_contexts[i].finally.clone() else {
+                continue;
+            };
+            let inner = self.try_contexts.split_off(i);
+            for context in &mut self.try_contexts {
+                context.open(bytecode.len());
+            }
+            self.visit_block(&finally, bytecode);
+            for context in &mut self.try_contexts {
+                context.close(bytecode.len());
+            }
+            self.try_contexts.extend(inner);
+        }
+    }
+
+    // 求值后丢弃结果，保证语句执行前后栈深度不变
+    fn visit_discarded_expression(&mut self, expr: &ASTNode, bytecode: &mut Vec<u8>) {
+        // 作为语句的 match 不产生值，分支可以是任意代码块
+        if let ASTNode::Match(scrutinee, arms, span) = expr {
+            return self.visit_match(scrutinee, arms, *span, false, bytecode);
+        }
+        let depth = self.stack_depth;
+        self.visit_expression(expr, bytecode);
+        // print 等无返回值的内置函数不压栈，此时不需要弹出
+        for _ in depth..self.stack_depth {
+            self.emit_opcode(bytecode, OpCode::Pop);
+        }
+    }
+
     fn visit_expression(&mut self, expr: &ASTNode, bytecode: &mut Vec<u8>) -> Option<u16> {
         match expr {
-            ASTNode::IntLiteral(value) => {
+            ASTNode::IntLiteral(value, _) => {
                 let const_idx = self.add_constant(Constant::Int(*value));
                 self.emit_load_const(bytecode, const_idx);
                 Some(const_idx)
             },
-            ASTNode::FloatLiteral(value) => {
+            ASTNode::FloatLiteral(value, _) => {
                 let const_idx = self.add_constant(Constant::Float(*value));
                 self.emit_load_const(bytecode, const_idx);
                 Some(const_idx)
             }
-            ASTNode::BoolLiteral(value) => {
+            ASTNode::BoolLiteral(value, _) => {
                 let const_idx = self.add_constant(Constant::Bool(*value));
                 self.emit_load_const(bytecode, const_idx);
                 Some(const_idx)
             }
-            ASTNode::StringLiteral(value) => {
+            ASTNode::StringLiteral(value, _) => {
                 let const_idx = self.add_constant(Constant::String(value.clone()));
                 self.emit_load_const(bytecode, const_idx);
                 Some(const_idx)
             }
-            ASTNode::Identifier(name) => {
-                if let Some(local_index) = self.lookup_local(name) {
-                    // 局部变量
-                    self.emit_load_local(bytecode, local_index);
-                } else if let Some(global_index) = self.lookup_global(name) {
-                   self.emit_load_global(bytecode, global_index);
+            ASTNode::Identifier(name, span) => {
+                if let Some(var) = self.resolve_var(name) {
+                    self.emit_load_var(bytecode, var);
+                } else if let Some((value, _)) = self.lookup_constant(name) {
+                    // 常量内联为常量池中的值
+                    let const_idx = self.add_constant(value.clone());
+                    self.emit_load_const(bytecode, const_idx);
+                    return Some(const_idx);
+                } else if let Some(func_index) = self.lookup_function(name) {
+                    // 具名函数作为值使用，生成不捕获变量的闭包
+                    self.emit_opcode_with_arg(bytecode, OpCode::MakeClosure, func_index as u64);
                 } else {
-                    panic!("Unknown identifier: {}", name);
+                    let diagnostic = Diagnostic::new(format!("Unknown identifier: {}", name), *span);
+                    let diagnostic = self.suggest_name(diagnostic, name);
+                    self.error(diagnostic);
+                    let const_idx = self.add_constant(Constant::Nil);
+                    self.emit_load_const(bytecode, const_idx);
                 }
                 None
             },
-            ASTNode::FunctionCall(name, args) => {
-                // 参数逆序入栈
-                for arg in args.iter().rev() {
-                    self.visit_expression(arg, bytecode);
+            ASTNode::FunctionCall(name, args, span) => {
+                self.visit_function_call(name, args, *span, bytecode);
+                None
+            },
+            ASTNode::FieldAccess(object, field, span) => {
+                let object_type = self.type_of(object);
+                self.visit_expression(object, bytecode);
+                match self.lookup_field(object_type, field) {
+                    Ok((field_index, _)) => self.emit_opcode_with_arg(bytecode, OpCode::GetField, field_index as u64),
+                    Err(message) => self.error(Diagnostic::new(message, *span)),
                 }
-
-                let syscall_num = self.syscall_map.get(name).copied();
-                if let Some(syscall_num) = syscall_num {
-                    // 是系统调用，生成Syscall指令
-                    self.emit_opcode_with_arg(bytecode, OpCode::Syscall, syscall_num as u64);
-                } else {
-                    // 不是系统调用，继续使用Call指令
-                    let func_index = if let Some(index) = self.function_map.get(name) {
-                        *index
+                None
+            },
+            ASTNode::StructLiteral(name, fields, span) => {
+                let Some(struct_index) = self.struct_map.get(name).copied() else {
+                    self.error(Diagnostic::new(format!("Unknown struct: {}", name), *span));
+                    self.visit_invalid_call(fields.iter().map(|(_, value)| value), bytecode);
+                    return None;
+                };
+                let struct_info = self.structs[struct_index as usize].clone();
+                let mut valid = true;
+                for (i, (field_name, _)) in fields.iter().enumerate() {
+                    let message = if !struct_info.fields.iter().any(|field| &field.name == field_name) {
+                        format!("Struct {} has no field {}", name, field_name)
+                    } else if fields[..i].iter().any(|(other, _)| other == field_name) {
+                        format!("Field {} specified more than once", field_name)
                     } else {
-                        panic!("Unknown function: {}", name);
+                        continue;
                     };
-                    self.emit_opcode_with_arg(bytecode, OpCode::Call, func_index as u64);
+                    self.error(Diagnostic::new(message, *span));
+                    valid = false;
+                }
+                for field in &struct_info.fields {
+                    if !fields.iter().any(|(field_name, _)| field_name == &field.name) {
+                        self.error(Diagnostic::new(format!("Missing field {} in struct {}", field.name, name), *span));
+                        valid = false;
+                    }
+                }
+                if !valid {
+                    self.visit_invalid_call(fields.iter().map(|(_, value)| value), bytecode);
+                    return None;
+                }
+                // 按定义顺序压入各字段的值
+                for field in &struct_info.fields {
+                    let (_, value) = fields.iter().find(|(field_name, _)| field_name == &field.name).expect("field checked above");
+                    self.visit_expression_as(value, field.field_type, bytecode);
+                }
+                self.emit_opcode_with_arg(bytecode, OpCode::NewStruct, struct_index as u64);
+                None
+            },
+            ASTNode::EnumVariant(enum_name, variant_name, args, span) => {
+                let (enum_index, variant_index) = match self.lookup_variant(enum_name, variant_name) {
+                    Ok(variant) => variant,
+                    Err(message) => {
+                        self.error(Diagnostic::new(message, *span));
+                        self.visit_invalid_call(args, bytecode);
+                        return None;
+                    },
+                };
+                let payload_types = self.enums[enum_index as usize].variants[variant_index as usize].payload_types.clone();
+                if args.len() != payload_types.len() {
+                    let message = format!("Variant {}::{} expects {} values, got {}", enum_name, variant_name, payload_types.len(), args.len());
+                    self.error(Diagnostic::new(message, *span));
+                    self.visit_invalid_call(args, bytecode);
+                    return None;
+                }
+                for (arg, payload_type) in args.iter().zip(payload_types) {
+                    self.visit_expression_as(arg, payload_type, bytecode);
+                }
+                let arg = enum_index as u64 | (variant_index as u64) << 16;
+                self.emit_opcode_with_arg(bytecode, OpCode::NewEnum, arg);
+                None
+            },
+            ASTNode::Match(scrutinee, arms, span) => {
+                self.visit_match(scrutinee, arms, *span, true, bytecode);
+                None
+            },
+            ASTNode::IndirectCall(callee, args, _) => {
+                let callee_type = self.type_of(callee);
+                if !callee_type.is_assignable_to(Type::Function) {
+                    self.error(Diagnostic::new(format!("Cannot call a value of type {}", self.type_name(callee_type)), callee.span()));
+                }
+                self.visit_indirect_call(args, bytecode, |compiler, bytecode| {
+                    compiler.visit_expression(callee, bytecode);
+                });
+                None
+            },
+            ASTNode::Lambda(params, return_type, body, span) => {
+                let param_types = params.iter().map(|(_, param_type)| self.resolve_type(param_type)).collect();
+                let return_type = return_type.as_deref().map(|type_name| self.resolve_type(type_name));
+
+                // 先占位，函数体中的匿名函数会继续追加到函数表
+                let func_index = self.functions.len() as u16;
+                self.functions.push(FunctionInfo {
+                    name: String::new(),
+                    param_count: params.len() as u8,
+                    local_count: 0,
+                    bytecode: Vec::new(),
+                    handlers: Vec::new(),
+                });
+                let name = format!("{}$lambda{}", self.current_function, func_index);
+                let (function, upvalues) = self.compile_function(&name, params, param_types, return_type, body, *span);
+                self.functions[func_index as usize] = function;
+
+                // 依次压入捕获变量的单元，再生成闭包
+                for source in &upvalues {
+                    match *source {
+                        UpvalueSource::Local(local_index) => self.emit_load_local(bytecode, local_index),
+                        UpvalueSource::Upvalue(upvalue_index) => {
+                            self.emit_opcode_with_arg(bytecode, OpCode::LoadUpvalue, upvalue_index as u64);
+                        },
+                    }
+                }
+                let arg = func_index as u64 | (upvalues.len() as u64) << 16;
+                self.emit_opcode_with_arg(bytecode, OpCode::MakeClosure, arg);
+                None
+            },
+            ASTNode::Interpolation(parts, _) => {
+                for part in parts {
+                    let part_type = self.type_of(part);
+                    if part_type == Type::Nil {
+                        self.error(Diagnostic::new(format!("Cannot interpolate a value of type {}", self.type_name(part_type)), part.span()));
+                    }
+                    self.visit_expression(part, bytecode);
+                }
+                self.emit_opcode_with_arg(bytecode, OpCode::Concat, parts.len() as u64);
+                None
+            },
+            ASTNode::ArrayLiteral(elements, _) => {
+                for element in elements {
+                    self.visit_expression(element, bytecode);
+                }
+                self.emit_opcode_with_arg(bytecode, OpCode::NewArray, elements.len() as u64);
+                None
+            },
+            ASTNode::Index(target, index, _) => {
+                let target_type = self.type_of(target);
+                if !matches!(target_type, Type::String | Type::Array | Type::Any) {
+                    self.error(Diagnostic::new(format!("Cannot index into {}", self.type_name(target_type)), target.span()));
+                }
+                let index_type = self.type_of(index);
+                if !index_type.is_assignable_to(Type::Int) {
+                    self.error(Diagnostic::new(format!("Index must be int, found {}", self.type_name(index_type)), index.span()));
                 }
+                self.visit_expression(target, bytecode);
+                self.visit_expression(index, bytecode);
+                self.emit_opcode(bytecode, OpCode::Index);
                 None
             },
-            ASTNode::BinaryExpr(left, op, right) => {
+            ASTNode::BinaryExpr(left, op, right, span) => {
+                let left_type = self.type_of(left);
+                let right_type = self.type_of(right);
+                match Type::binary_result(op, left_type, right_type) {
+                    Err(msg) => self.error(Diagnostic::new(msg, *span)),
+                    Ok(_) => if let Some(const_idx) = self.emit_folded_constant(expr, bytecode) {
+                        return Some(const_idx);
+                    },
+                }
+
                 // 从左到右求值
                 self.visit_expression(left, bytecode);
                 self.visit_expression(right, bytecode);
@@ -503,8 +1571,18 @@ impl Compiler {
                 self.emit_opcode(bytecode, opcode);
                 None
             },
-            ASTNode::UnaryExpr(op, expr) => {
-                self.visit_expression(expr, bytecode);
+            ASTNode::UnaryExpr(op, operand, span) => {
+                let operand_type = self.type_of(operand);
+                let valid = match op.as_str() {
+                    "-" => operand_type.is_numeric(),
+                    _ => operand_type.is_assignable_to(Type::Bool),
+                };
+                if !valid {
+                    self.error(Diagnostic::new(format!("Invalid operand type for '{}': {}", op, self.type_name(operand_type)), *span));
+                } else if let Some(const_idx) = self.emit_folded_constant(expr, bytecode) {
+                    return Some(const_idx);
+                }
+                self.visit_expression(operand, bytecode);
 
                 // 根据操作符类型，生成相应的字节码
                 match op.as_str() {
@@ -518,10 +1596,684 @@ impl Compiler {
                 }
                 None
             },
+            ASTNode::Cast(operand, type_name, span) => {
+                let Some(target) = Type::from_name(type_name) else {
+                    self.error(Diagnostic::new(format!("Unknown type: {}", type_name), *span));
+                    return self.visit_expression(operand, bytecode);
+                };
+                let source = self.type_of(operand);
+                if !source.can_cast_to(target) {
+                    self.error(Diagnostic::new(format!("Cannot convert {} to {}", self.type_name(source), self.type_name(target)), *span));
+                    return self.visit_expression(operand, bytecode);
+                }
+                if let Some(const_idx) = self.emit_folded_constant(expr, bytecode) {
+                    return Some(const_idx);
+                }
+                self.visit_expression(operand, bytecode);
+                // 源类型与目标类型相同时无需转换
+                if source != target {
+                    self.emit_opcode_with_arg(bytecode, OpCode::Cast, target.tag() as u64);
+                }
+                None
+            },
             _ => panic!("Unexpected expression type {:?}", *expr),
         }
     }
 
+    fn visit_function_call(&mut self, name: &str, args: &[Box<ASTNode>], span: Span, bytecode: &mut Vec<u8>) {
+        // 变量优先于同名的函数：调用变量中保存的函数值
+        if let Some(var) = self.resolve_var(name) {
+            let var_type = self.lookup_var_type(name).unwrap_or(Type::Any);
+            if !var_type.is_assignable_to(Type::Function) {
+                self.error(Diagnostic::new(format!("Cannot call {} of type {}", name, self.type_name(var_type)), span));
+            }
+            self.visit_indirect_call(args, bytecode, |compiler, bytecode| compiler.emit_load_var(bytecode, var));
+            return;
+        }
+
+        let syscall_num = self.syscall_map.get(name).copied();
+        let param_types: Option<Vec<Type>> = match syscall_num {
+            Some(syscall) => syscall.param_types().map(|param_types| param_types.to_vec()),
+            None => match self.lookup_function(name) {
+                Some(func_index) => Some(self.function_signatures[&func_index].param_types.clone()),
+                None => {
+                    self.error(Diagnostic::new(format!("Unknown function: {}", name), span));
+                    return self.visit_invalid_call(args, bytecode);
+                },
+            },
+        };
+        if let Some(param_types) = &param_types {
+            if args.len() != param_types.len() {
+                let message = format!("Function {} expects {} arguments, got {}", name, param_types.len(), args.len());
+                self.error(Diagnostic::new(message, span));
+                return self.visit_invalid_call(args, bytecode);
+            }
+            let mut mismatched = false;
+            for (arg, param_type) in args.iter().zip(param_types) {
+                let arg_type = self.type_of(arg);
+                if !arg_type.is_assignable_to(*param_type) {
+                    let message = format!("Type mismatch in call to {}: expected {}, found {}", name, self.type_name(*param_type), self.type_name(arg_type));
+                    self.error(Diagnostic::new(message, arg.span()));
+                    mismatched = true;
+                }
+            }
+            if mismatched {
+                return self.visit_invalid_call(args, bytecode);
+            }
+        }
+        // format 的参数个数可变，但第一个参数必须是格式字符串；格式字符串是常量时，参数个数必须与占位符个数一致
+        if let Some(SYSCALL::FORMAT) = syscall_num {
+            let Some(format_arg) = args.first() else {
+                self.error(Diagnostic::new(format!("Function {} expects at least 1 argument, got 0", name), span));
+                return self.visit_invalid_call(args, bytecode);
+            };
+            let format_type = self.type_of(format_arg);
+            if !format_type.is_assignable_to(Type::String) {
+                let message = format!("Type mismatch in call to {}: expected {}, found {}", name, self.type_name(Type::String), self.type_name(format_type));
+                self.error(Diagnostic::new(message, format_arg.span()));
+                return self.visit_invalid_call(args, bytecode);
+            }
+            if let Ok(Constant::String(fmt)) = self.eval_constant(format_arg) {
+                let placeholders = count_placeholders(&fmt);
+                if placeholders != args.len() - 1 {
+                    let message = format!("Format string expects {} arguments, got {}", placeholders, args.len() - 1);
+                    self.error(Diagnostic::new(message, span));
+                    return self.visit_invalid_call(args, bytecode);
+                }
+            }
+        }
+
+        // 参数逆序入栈，已知参数类型时按参数类型做隐式转换
+        for (i, arg) in args.iter().enumerate().rev() {
+            match &param_types {
+                Some(param_types) => self.visit_expression_as(arg, param_types[i], bytecode),
+                None => self.visit_expression(arg, bytecode),
+            };
+        }
+
+        if let Some(syscall_num) = syscall_num {
+            // 是系统调用，生成Syscall指令。操作数低16位为调用号，其后16位为参数个数
+            let arg = syscall_num as u64 | (args.len() as u64) << 16;
+            self.emit_opcode_with_arg(bytecode, OpCode::Syscall, arg);
+        } else {
+            // 不是系统调用，继续使用Call指令
+            let func_index = self.lookup_function(name).expect("function checked above");
+            self.emit_opcode_with_arg(bytecode, OpCode::Call, func_index as u64);
+        }
+    }
+
+    // 有错误的调用（或结构体、枚举值的构造）：仍然编译各参数以报告其中的错误，结果用空值代替
+    fn visit_invalid_call<'a>(&mut self, args: impl IntoIterator<Item = &'a Box<ASTNode>>, bytecode: &mut Vec<u8>) {
+        for arg in args {
+            self.visit_discarded_expression(arg, bytecode);
+        }
+        let const_idx = self.add_constant(Constant::Nil);
+        self.emit_load_const(bytecode, const_idx);
+    }
+
+    // 调用函数值：参数逆序入栈，再压入函数值。参数类型在编译期未知，参数个数在运行时检查
+    fn visit_indirect_call<F>(&mut self, args: &[Box<ASTNode>], bytecode: &mut Vec<u8>, load_callee: F)
+    where
+        F: FnOnce(&mut Self, &mut Vec<u8>),
+    {
+        for arg in args.iter().rev() {
+            self.visit_expression(arg, bytecode);
+        }
+        load_callee(self, bytecode);
+        self.emit_opcode_with_arg(bytecode, OpCode::CallIndirect, args.len() as u64);
+    }
+
+    /// 按目标类型编译表达式，必要时插入 int -> float 的隐式转换
+    fn visit_expression_as(&mut self, expr: &ASTNode, target: Type, bytecode: &mut Vec<u8>) -> Option<u16> {
+        let source = self.type_of(expr);
+        if !source.is_assignable_to(target) {
+            self.error(Diagnostic::new(format!("Type mismatch: expected {}, found {}", self.type_name(target), self.type_name(source)), expr.span()));
+            return self.visit_expression(expr, bytecode);
+        }
+        if source.needs_promotion_to(target) {
+            // 整数字面量直接在编译期转换
+            if let ASTNode::IntLiteral(value, _) = expr {
+                let const_idx = self.add_constant(Constant::Float(*value as f32));
+                self.emit_load_const(bytecode, const_idx);
+                return Some(const_idx);
+            }
+            self.visit_expression(expr, bytecode);
+            self.emit_opcode_with_arg(bytecode, OpCode::Cast, target.tag() as u64);
+            return None;
+        }
+        self.visit_expression(expr, bytecode)
+    }
+
+    // 查找枚举变体，返回枚举索引和变体编号
+    fn lookup_variant(&self, enum_name: &str, variant_name: &str) -> Result<(u16, u16), String> {
+        let Some(enum_index) = self.enum_map.get(enum_name).copied() else {
+            return Err(format!("Unknown enum: {}", enum_name));
+        };
+        match self.enums[enum_index as usize].variants.iter().position(|variant| variant.name == variant_name) {
+            Some(variant_index) => Ok((enum_index, variant_index as u16)),
+            None => Err(format!("Enum {} has no variant {}", enum_name, variant_name)),
+        }
+    }
+
+    // 模式中绑定的变量及其类型，`_` 不绑定。模式有错误时（由 check_match_arms 报告）类型未知
+    fn pattern_binding_types(&self, pattern: &Pattern) -> Vec<(String, Type, u16)> {
+        let Pattern::Variant(enum_name, variant_name, bindings) = pattern else {
+            return Vec::new();
+        };
+        let payload_types = match self.lookup_variant(enum_name, variant_name) {
+            Ok((enum_index, variant_index)) => self.enums[enum_index as usize].variants[variant_index as usize].payload_types.as_slice(),
+            Err(_) => &[],
+        };
+        bindings.iter().enumerate()
+            .filter(|(_, binding)| binding.as_str() != "_")
+            .map(|(i, binding)| (binding.clone(), payload_types.get(i).copied().unwrap_or(Type::Any), i as u16))
+            .collect()
+    }
+
+    // match 的值类型：各表达式分支类型的公共类型；代码块分支不产生值
+    fn match_type(&self, arms: &[(Pattern, Box<ASTNode>, Span)]) -> Result<Type, String> {
+        let mut result: Option<Type> = None;
+        for (pattern, body, _) in arms {
+            if matches!(body.as_ref(), ASTNode::Block(_)) {
+                continue;
+            }
+            let bindings = self.pattern_binding_types(pattern);
+            let depth = self.pattern_bindings.borrow().len();
+            self.pattern_bindings.borrow_mut().extend(bindings.into_iter().map(|(name, var_type, _)| (name, var_type)));
+            let arm_type = self.type_of(body);
+            self.pattern_bindings.borrow_mut().truncate(depth);
+
+            result = Some(match result {
+                None => arm_type,
+                Some(result) if result == arm_type => result,
+                Some(Type::Any) => Type::Any,
+                Some(_) if arm_type == Type::Any => Type::Any,
+                Some(Type::Int | Type::Float) if matches!(arm_type, Type::Int | Type::Float) => Type::Float,
+                Some(result) => {
+                    return Err(format!("Match arms have incompatible types: {} and {}", self.type_name(result), self.type_name(arm_type)));
+                },
+            });
+        }
+        Ok(result.unwrap_or(Type::Nil))
+    }
+
+    // 编译 match：被匹配的值留在栈上，依次用 IsVariant 检查各分支的变体。
+    // 进入分支后取出负载存入绑定的变量，弹出被匹配的值，再执行分支体。
+    // want_value 为 true 时每个分支在栈上留下 match 的值（以 return 结束的代码块分支除外）
+    fn visit_match(&mut self, scrutinee: &ASTNode, arms: &[(Pattern, Box<ASTNode>, Span)], span: Span, want_value: bool, bytecode: &mut Vec<u8>) {
+        self.check_match_arms(scrutinee, arms, span);
+        let match_type = if want_value {
+            self.match_type(arms).unwrap_or_else(|message| {
+                self.error(Diagnostic::new(message, span));
+                Type::Any
+            })
+        } else {
+            Type::Nil
+        };
+
+        self.visit_expression(scrutinee, bytecode);
+        let depth = self.stack_depth;
+        let mut end_jumps = Vec::new();
+        for (pattern, body, pattern_span) in arms {
+            self.stack_depth = depth;
+            let mut jz_pos = None;
+            // 未知变体的错误已由 check_match_arms 报告
+            if let Pattern::Variant(enum_name, variant_name, _) = pattern {
+                if let Ok((enum_index, variant_index)) = self.lookup_variant(enum_name, variant_name) {
+                    let arg = enum_index as u64 | (variant_index as u64) << 16;
+                    self.emit_opcode_with_arg(bytecode, OpCode::IsVariant, arg);
+                    jz_pos = Some(bytecode.len());
+                    self.emit_opcode_with_arg(bytecode, OpCode::Jz, 0);
+                }
+                for (name, var_type, payload_index) in self.pattern_binding_types(pattern) {
+                    self.emit_opcode(bytecode, OpCode::Dup);
+                    self.emit_opcode_with_arg(bytecode, OpCode::GetField, payload_index as u64);
+                    self.declare_variable(&name, var_type, None, false, bytecode);
+                }
+            }
+            self.emit_opcode(bytecode, OpCode::Pop);
+
+            let diverges = match body.as_ref() {
+                ASTNode::Block(_) => {
+                    self.visit_block(body, bytecode);
+                    let diverges = Self::always_returns(body);
+                    if want_value && !diverges {
+                        self.error(Diagnostic::new("Match arm used as a value must be an expression or return".to_string(), *pattern_span));
+                    }
+                    diverges
+                },
+                _ if want_value => {
+                    self.visit_expression_as(body, match_type, bytecode);
+                    false
+                },
+                _ => {
+                    self.visit_discarded_expression(body, bytecode);
+                    false
+                },
+            };
+            if !diverges {
+                end_jumps.push(bytecode.len());
+                self.emit_opcode_with_arg(bytecode, OpCode::Jmp, 0);
+            }
+
+            // 不匹配时跳到下一个分支
+            if let Some(jz_pos) = jz_pos {
+                let jz_offset = bytecode.len() - jz_pos;
+                self.set_arg_at(bytecode, jz_pos, jz_offset as u64);
+            }
+        }
+        // 分支覆盖了所有变体，执行不会到达这里
+        for jmp_pos in end_jumps {
+            let jmp_offset = bytecode.len() - jmp_pos;
+            self.set_arg_at(bytecode, jmp_pos, jmp_offset as u64);
+        }
+        self.stack_depth = if want_value { depth } else { depth - 1 };
+    }
+
+    // 检查 match 的分支：模式与被匹配值的类型一致、没有重复或不可达的分支、覆盖所有变体。
+    // 分支的错误报告在模式上，缺少分支的错误报告在整个 match 上
+    fn check_match_arms(&mut self, scrutinee: &ASTNode, arms: &[(Pattern, Box<ASTNode>, Span)], span: Span) {
+        let scrutinee_type = self.type_of(scrutinee);
+        let enum_index = match scrutinee_type {
+            Type::Enum(enum_index) => Some(enum_index),
+            // 类型未知时按第一个变体模式确定枚举，运行时由 IsVariant 检查
+            Type::Any => arms.iter().find_map(|(pattern, _, _)| match pattern {
+                Pattern::Variant(enum_name, variant_name, _) => self.lookup_variant(enum_name, variant_name).ok(),
+                Pattern::Wildcard => None,
+            }).map(|(enum_index, _)| enum_index),
+            _ => {
+                let message = format!("Cannot match on a value of type {}", self.type_name(scrutinee_type));
+                return self.error(Diagnostic::new(message, scrutinee.span()));
+            },
+        };
+
+        let mut covered = HashSet::new();
+        let mut has_wildcard = false;
+        for (pattern, _, pattern_span) in arms {
+            if let Err(message) = self.check_pattern(pattern, enum_index, scrutinee_type, has_wildcard, &mut covered) {
+                self.error(Diagnostic::new(message, *pattern_span));
+            }
+            has_wildcard |= *pattern == Pattern::Wildcard;
+        }
+        if let (Some(enum_index), false) = (enum_index, has_wildcard) {
+            let enum_info = &self.enums[enum_index as usize];
+            let missing: Vec<String> = enum_info.variants.iter().enumerate()
+                .filter(|(i, _)| !covered.contains(&(*i as u16)))
+                .map(|(_, variant)| format!("{}::{}", enum_info.name, variant.name))
+                .collect();
+            if !missing.is_empty() {
+                self.error(Diagnostic::new(format!("Non-exhaustive match: missing {}", missing.join(", ")), span));
+            }
+        }
+        if enum_index.is_none() && !has_wildcard && arms.is_empty() {
+            self.error(Diagnostic::new("Match must have at least one arm".to_string(), span));
+        }
+    }
+
+    // 检查一个分支的模式，covered 记录已覆盖的变体
+    fn check_pattern(&self, pattern: &Pattern, enum_index: Option<u16>, scrutinee_type: Type, after_wildcard: bool,
+                     covered: &mut HashSet<u16>) -> Result<(), String> {
+        if after_wildcard {
+            return Err("Unreachable match arm after _".to_string());
+        }
+        let Pattern::Variant(enum_name, variant_name, bindings) = pattern else {
+            return Ok(());
+        };
+        let (pattern_enum, variant_index) = self.lookup_variant(enum_name, variant_name)?;
+        if Some(pattern_enum) != enum_index {
+            return Err(format!("Pattern {}::{} does not match type {}", enum_name, variant_name, self.type_name(scrutinee_type)));
+        }
+        let payload_count = self.enums[pattern_enum as usize].variants[variant_index as usize].payload_types.len();
+        if bindings.len() != payload_count {
+            return Err(format!("Pattern {}::{} expects {} bindings, got {}", enum_name, variant_name, payload_count, bindings.len()));
+        }
+        if !covered.insert(variant_index) {
+            return Err(format!("Duplicate match arm for {}::{}", enum_name, variant_name));
+        }
+        Ok(())
+    }
+
+    fn check_condition(&mut self, condition: &ASTNode) {
+        let condition_type = self.type_of(condition);
+        if !condition_type.is_assignable_to(Type::Bool) {
+            self.error(Diagnostic::new(format!("Condition must be bool, found {}", self.type_name(condition_type)), condition.span()));
+        }
+    }
+
+    /// 推导表达式的静态类型
+    fn type_of(&self, expr: &ASTNode) -> Type {
+        match expr {
+            ASTNode::IntLiteral(..) => Type::Int,
+            ASTNode::FloatLiteral(..) => Type::Float,
+            ASTNode::BoolLiteral(..) => Type::Bool,
+            ASTNode::StringLiteral(..) => Type::String,
+            // 未知的名字由 visit_expression 报告
+            ASTNode::Identifier(name, _) => match self.lookup_var_type(name) {
+                Some(var_type) => var_type,
+                None => match self.lookup_constant(name) {
+                    Some((_, const_type)) => *const_type,
+                    None if self.lookup_function(name).is_some() => Type::Function,
+                    None => Type::Any,
+                },
+            },
+            ASTNode::Lambda(..) => Type::Function,
+            ASTNode::EnumVariant(enum_name, variant_name, ..) => match self.lookup_variant(enum_name, variant_name) {
+                Ok((enum_index, _)) => Type::Enum(enum_index),
+                Err(_) => Type::Any,
+            },
+            ASTNode::Match(_, arms, _) => self.match_type(arms).unwrap_or(Type::Any),
+            // 未知的结构体和字段由 visit_expression 报告
+            ASTNode::StructLiteral(name, ..) => match self.struct_map.get(name) {
+                Some(struct_index) => Type::Struct(*struct_index),
+                None => Type::Any,
+            },
+            ASTNode::FieldAccess(object, field, _) => match self.lookup_field(self.type_of(object), field) {
+                Ok((_, field_type)) => field_type,
+                Err(_) => Type::Any,
+            },
+            // 通过函数值调用时返回值类型未知
+            ASTNode::FunctionCall(name, ..) if self.lookup_var_type(name).is_some() => Type::Any,
+            ASTNode::FunctionCall(name, ..) => match self.syscall_map.get(name) {
+                Some(syscall) => syscall.return_type(),
+                // 未声明返回类型的用户函数返回值类型未知
+                None => self.lookup_function(name)
+                    .and_then(|func_index| self.function_signatures[&func_index].return_type)
+                    .unwrap_or(Type::Any),
+            },
+            ASTNode::Interpolation(..) => Type::String,
+            ASTNode::ArrayLiteral(..) => Type::Array,
+            ASTNode::Index(target, ..) => match self.type_of(target) {
+                Type::String => Type::String,
+                _ => Type::Any,
+            },
+            // 无效的操作数类型由 visit_expression 报告
+            ASTNode::BinaryExpr(left, op, right, _) => {
+                Type::binary_result(op, self.type_of(left), self.type_of(right)).unwrap_or(Type::Any)
+            },
+            ASTNode::UnaryExpr(op, expr, _) => match op.as_str() {
+                "!" => Type::Bool,
+                _ => self.type_of(expr),
+            },
+            ASTNode::Cast(_, type_name, _) => Type::from_name(type_name).unwrap_or(Type::Any),
+            _ => Type::Any,
+        }
+    }
+
+    // 语句是否在所有执行路径上都以 return 结束（没有条件的循环永远不会正常结束）
+    fn always_returns(stmt: &ASTNode) -> bool {
+        match stmt {
+            ASTNode::Return(..) => true,
+            ASTNode::Block(statements) => statements.iter().any(|stmt| Self::always_returns(stmt)),
+            ASTNode::IfStatement(_, then_branch, Some(else_branch)) => {
+                Self::always_returns(then_branch) && Self::always_returns(else_branch)
+            },
+            ASTNode::WhileLoop(condition, _) => matches!(condition.as_ref(), ASTNode::BoolLiteral(true, _)),
+            ASTNode::Throw(_) => true,
+            // finally 以 return 结束，或 try 和 catch 都以 return（或抛出异常）结束
+            ASTNode::TryCatch(body, catch, finally) => {
+                finally.as_ref().is_some_and(|finally| Self::always_returns(finally))
+                    || Self::always_returns(body) && catch.as_ref().is_none_or(|(_, catch)| Self::always_returns(catch))
+            },
+            ASTNode::ForLoop(_, None, _, _) => true,
+            // 每个分支都以 return 结束的 match 语句
+            ASTNode::ExprStatement(expr) => match expr.as_ref() {
+                ASTNode::Match(_, arms, _) => arms.iter().all(|(_, body, _)| Self::always_returns(body)),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    // 编译函数体（具名函数或匿名函数），返回函数信息和捕获变量的来源。span 用于报告缺少 return 的错误
+    fn compile_function(&mut self, name: &str, params: &[(String, String)], param_types: Vec<Type>,
+                        return_type: Option<Type>, body: &ASTNode, span: Span) -> (FunctionInfo, Vec<UpvalueSource>) {
+        // 声明了返回类型的函数，每条执行路径都必须以 return 结束
+        if let Some(return_type) = return_type {
+            if !Self::always_returns(body) {
+                let message = format!("Function {} must return a value of type {} on all paths", name, self.type_name(return_type));
+                self.error(Diagnostic::new(message, span));
+            }
+        }
+
+        let mut captured = HashSet::new();
+        Self::collect_captured_names(body, false, &mut captured);
+        self.enter_function(name, return_type, captured);
+
+        // 记录参数作为局部变量
+        let mut bytecode = Vec::new();
+        for (i, ((param_name, _), var_type)) in params.iter().zip(param_types).enumerate() {
+            let captured = self.current_captured.contains(param_name);
+            self.current_local_vars.push(LocalVarInfo {
+                name: param_name.clone(),
+                const_index: None,
+                var_type,
+                captured,
+            });
+            self.current_local_vars_map.insert(param_name.clone(), i);
+            // 被捕获的参数在函数开始时移入单元
+            if captured {
+                self.emit_load_local(&mut bytecode, i as u8);
+                self.emit_opcode(&mut bytecode, OpCode::NewCell);
+                self.emit_store_local(&mut bytecode, i as u8);
+            }
+        }
+
+        // 编译函数体到字节码，同时分析局部变量
+        self.visit_block(body, &mut bytecode);
+        let const_idx = self.add_constant(Constant::Nil);
+        self.emit_load_const(&mut bytecode, const_idx);
+        self.emit_opcode(&mut bytecode, OpCode::Ret);
+
+        let function = FunctionInfo {
+            name: name.to_string(),
+            param_count: params.len() as u8,
+            local_count: self.current_local_vars.len() as u8,
+            bytecode,
+            handlers: std::mem::take(&mut self.current_handlers),
+        };
+        let upvalues = self.leave_function();
+        (function, upvalues)
+    }
+
+    // 保存外层函数的状态，开始编译新函数
+    fn enter_function(&mut self, name: &str, return_type: Option<Type>, captured: HashSet<String>) {
+        let scope = FunctionScope {
+            function: std::mem::replace(&mut self.current_function, name.to_string()),
+            return_type: std::mem::replace(&mut self.current_return_type, return_type),
+            local_vars: std::mem::take(&mut self.current_local_vars),
+            local_vars_map: std::mem::take(&mut self.current_local_vars_map),
+            upvalues: std::mem::take(&mut self.current_upvalues),
+            captured: std::mem::replace(&mut self.current_captured, captured),
+            in_global_scope: std::mem::replace(&mut self.in_global_scope, false),
+            stack_depth: std::mem::replace(&mut self.stack_depth, 0),
+            handlers: std::mem::take(&mut self.current_handlers),
+            try_contexts: std::mem::take(&mut self.try_contexts),
+        };
+        self.enclosing_scopes.push(scope);
+    }
+
+    // 恢复外层函数的状态，返回刚编译完的函数捕获的变量
+    fn leave_function(&mut self) -> Vec<UpvalueSource> {
+        let scope = self.enclosing_scopes.pop().expect("no enclosing function scope");
+        self.current_function = scope.function;
+        self.current_return_type = scope.return_type;
+        self.current_local_vars = scope.local_vars;
+        self.current_local_vars_map = scope.local_vars_map;
+        self.current_captured = scope.captured;
+        self.in_global_scope = scope.in_global_scope;
+        self.stack_depth = scope.stack_depth;
+        self.current_handlers = scope.handlers;
+        self.try_contexts = scope.try_contexts;
+        let upvalues = std::mem::replace(&mut self.current_upvalues, scope.upvalues);
+        upvalues.into_iter().map(|upvalue| upvalue.source).collect()
+    }
+
+    // 收集匿名函数中引用的名字（不区分是否为其自身的局部变量），外层同名局部变量需要放入单元
+    fn collect_captured_names(node: &ASTNode, in_lambda: bool, names: &mut HashSet<String>) {
+        let mut visit = |node: &ASTNode| Self::collect_captured_names(node, in_lambda, names);
+        match node {
+            ASTNode::Identifier(name, _) | ASTNode::Increment(name, _) | ASTNode::Decrement(name, _) if in_lambda => {
+                names.insert(name.clone());
+            },
+            ASTNode::Assignment(name, expr, _) => {
+                if in_lambda {
+                    names.insert(name.clone());
+                }
+                Self::collect_captured_names(expr, in_lambda, names);
+            },
+            ASTNode::FunctionCall(name, args, _) => {
+                if in_lambda {
+                    names.insert(name.clone());
+                }
+                args.iter().for_each(|arg| Self::collect_captured_names(arg, in_lambda, names));
+            },
+            ASTNode::Lambda(_, _, body, _) => Self::collect_captured_names(body, true, names),
+            ASTNode::Program(nodes) | ASTNode::Block(nodes) | ASTNode::Interpolation(nodes, _) | ASTNode::ArrayLiteral(nodes, _) => {
+                nodes.iter().for_each(|node| visit(node));
+            },
+            ASTNode::IndirectCall(callee, args, _) => {
+                visit(callee);
+                args.iter().for_each(|arg| visit(arg));
+            },
+            ASTNode::StructLiteral(_, fields, _) => fields.iter().for_each(|(_, value)| visit(value)),
+            ASTNode::EnumVariant(_, _, args, _) => args.iter().for_each(|arg| visit(arg)),
+            ASTNode::Match(scrutinee, arms, _) => {
+                visit(scrutinee);
+                arms.iter().for_each(|(_, body, _)| visit(body));
+            },
+            ASTNode::FieldAssignment(object, _, value, _) => {
+                visit(object);
+                visit(value);
+            },
+            ASTNode::VariableDecl(_, _, Some(expr)) | ASTNode::Return(Some(expr), _) | ASTNode::ExprStatement(expr) |
+            ASTNode::UnaryExpr(_, expr, _) | ASTNode::Cast(expr, ..) | ASTNode::Documented(_, expr) |
+            ASTNode::FieldAccess(expr, ..) | ASTNode::Throw(expr) => visit(expr),
+            ASTNode::TryCatch(body, catch, finally) => {
+                visit(body);
+                if let Some((_, catch)) = catch {
+                    visit(catch);
+                }
+                if let Some(finally) = finally {
+                    visit(finally);
+                }
+            },
+            ASTNode::BinaryExpr(left, _, right, _) | ASTNode::Index(left, right, _) | ASTNode::WhileLoop(left, right) => {
+                visit(left);
+                visit(right);
+            },
+            ASTNode::IfStatement(condition, then_branch, else_branch) => {
+                visit(condition);
+                visit(then_branch);
+                if let Some(else_branch) = else_branch {
+                    visit(else_branch);
+                }
+            },
+            ASTNode::ForLoop(init, condition, update, body) => {
+                for node in [init, condition, update].into_iter().flatten() {
+                    visit(node);
+                }
+                visit(body);
+            },
+            _ => {},
+        }
+    }
+
+    // 查找变量：当前函数的局部变量、捕获的外层变量、全局变量
+    fn resolve_var(&mut self, name: &str) -> Option<VarRef> {
+        if let Some(local_index) = self.current_local_vars_map.get(name) {
+            let captured = self.current_local_vars[*local_index].captured;
+            return Some(VarRef::Local(*local_index as u8, captured));
+        }
+        if let Some(upvalue_index) = self.resolve_upvalue(name) {
+            return Some(VarRef::Upvalue(upvalue_index));
+        }
+        self.lookup_global(name).map(VarRef::Global)
+    }
+
+    fn resolve_upvalue(&mut self, name: &str) -> Option<u16> {
+        if let Some(index) = self.current_upvalues.iter().position(|upvalue| upvalue.name == name) {
+            return Some(index as u16);
+        }
+        let depth = self.enclosing_scopes.len();
+        let (source, var_type) = self.resolve_in_scope(depth.checked_sub(1)?, name)?;
+        self.current_upvalues.push(UpvalueInfo {
+            name: name.to_string(),
+            source,
+            var_type,
+        });
+        Some((self.current_upvalues.len() - 1) as u16)
+    }
+
+    // 在第 depth 层外层函数中查找变量，必要时让中间各层函数也捕获它
+    fn resolve_in_scope(&mut self, depth: usize, name: &str) -> Option<(UpvalueSource, Type)> {
+        let scope = &self.enclosing_scopes[depth];
+        if let Some(local_index) = scope.local_vars_map.get(name) {
+            let local = &scope.local_vars[*local_index];
+            assert!(local.captured, "Captured variable {} is not stored in a cell", name);
+            return Some((UpvalueSource::Local(*local_index as u8), local.var_type));
+        }
+        if let Some(index) = scope.upvalues.iter().position(|upvalue| upvalue.name == name) {
+            return Some((UpvalueSource::Upvalue(index as u16), scope.upvalues[index].var_type));
+        }
+        let (source, var_type) = self.resolve_in_scope(depth.checked_sub(1)?, name)?;
+        let upvalues = &mut self.enclosing_scopes[depth].upvalues;
+        upvalues.push(UpvalueInfo {
+            name: name.to_string(),
+            source,
+            var_type,
+        });
+        Some((UpvalueSource::Upvalue((upvalues.len() - 1) as u16), var_type))
+    }
+
+    fn emit_load_var(&mut self, bytecode: &mut Vec<u8>, var: VarRef) {
+        match var {
+            VarRef::Local(local_index, false) => self.emit_load_local(bytecode, local_index),
+            VarRef::Local(local_index, true) => {
+                self.emit_load_local(bytecode, local_index);
+                self.emit_opcode(bytecode, OpCode::LoadCell);
+            },
+            VarRef::Upvalue(upvalue_index) => {
+                self.emit_opcode_with_arg(bytecode, OpCode::LoadUpvalue, upvalue_index as u64);
+                self.emit_opcode(bytecode, OpCode::LoadCell);
+            },
+            VarRef::Global(global_index) => self.emit_load_global(bytecode, global_index),
+        }
+    }
+
+    fn emit_store_var(&mut self, bytecode: &mut Vec<u8>, var: VarRef) {
+        match var {
+            VarRef::Local(local_index, false) => self.emit_store_local(bytecode, local_index),
+            VarRef::Local(local_index, true) => {
+                self.emit_load_local(bytecode, local_index);
+                self.emit_opcode(bytecode, OpCode::StoreCell);
+            },
+            VarRef::Upvalue(upvalue_index) => {
+                self.emit_opcode_with_arg(bytecode, OpCode::LoadUpvalue, upvalue_index as u64);
+                self.emit_opcode(bytecode, OpCode::StoreCell);
+            },
+            VarRef::Global(global_index) => self.emit_store_global(bytecode, global_index),
+        }
+    }
+
+    fn lookup_var_type(&self, name: &str) -> Option<Type> {
+        if let Some((_, var_type)) = self.pattern_bindings.borrow().iter().rev().find(|(binding, _)| binding == name) {
+            return Some(*var_type);
+        }
+        if let Some(local_index) = self.current_local_vars_map.get(name) {
+            return Some(self.current_local_vars[*local_index].var_type);
+        }
+        if let Some(upvalue) = self.current_upvalues.iter().find(|upvalue| upvalue.name == name) {
+            return Some(upvalue.var_type);
+        }
+        // 尚未捕获的外层局部变量
+        for scope in self.enclosing_scopes.iter().rev() {
+            if let Some(local_index) = scope.local_vars_map.get(name) {
+                return Some(scope.local_vars[*local_index].var_type);
+            }
+        }
+        self.lookup_global(name).map(|index| self.global_var_types[index as usize])
+    }
+
     fn add_constant(&mut self, constant: Constant) -> u16 {
         // 检查常量是否已存在
         for (i, c) in self.constants.iter().enumerate() {
@@ -540,11 +2292,60 @@ impl Compiler {
     }
 
     fn emit_opcode_with_arg(&mut self, bytecode: &mut Vec<u8>, opcode: OpCode, arg: u64) {
-        println!("emit_opcode_with_arg: {:?} {}", opcode, arg);
+        self.stack_depth += self.stack_effect(opcode, arg);
         bytecode.push(opcode as u8);
         bytecode.extend_from_slice(&arg.to_le_bytes());
     }
 
+    // 指令执行后栈深度的变化量
+    fn stack_effect(&self, opcode: OpCode, arg: u64) -> i32 {
+        match opcode {
+            OpCode::LoadConst | OpCode::LoadGlobal | OpCode::LoadLocal | OpCode::LoadUpvalue | OpCode::Dup => 1,
+            OpCode::StoreGlobal | OpCode::StoreLocal | OpCode::Pop => -1,
+            // 弹出单元和值
+            OpCode::StoreCell => -2,
+            OpCode::NewCell | OpCode::LoadCell | OpCode::Swap | OpCode::Not | OpCode::Inc | OpCode::Dec | OpCode::Neg | OpCode::Cast => 0,
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div |
+            OpCode::CmpEq | OpCode::CmpNe | OpCode::CmpLt | OpCode::CmpGt | OpCode::CmpLe | OpCode::CmpGe |
+            OpCode::Index => -1,
+            // 弹出 n 个值，压入一个结果
+            OpCode::NewArray | OpCode::Concat => 1 - arg as i32,
+            // 弹出各字段的值，压入结构体
+            OpCode::NewStruct => 1 - self.structs[arg as usize].fields.len() as i32,
+            OpCode::GetField => 0,
+            // 弹出值和结构体，压入修改后的结构体
+            OpCode::SetField => -1,
+            // 弹出负载，压入枚举值
+            OpCode::NewEnum => 1 - self.enums[arg as u16 as usize].variants[(arg >> 16) as u16 as usize].payload_types.len() as i32,
+            // 保留枚举值，压入比较结果
+            OpCode::IsVariant => 1,
+            OpCode::Jmp | OpCode::Exit => 0,
+            OpCode::Jz => -1,
+            OpCode::IncLocal | OpCode::DecLocal | OpCode::AddConstLocal => 0,
+            OpCode::LoadLocal2 => 2,
+            // 弹出两个操作数，不压入比较结果
+            OpCode::CmpLtJz => -2,
+            // 弹出参数，压入返回值
+            OpCode::Call => 1 - self.function_signatures.get(&(arg as u16)).map_or(0, |signature| signature.param_types.len() as i32),
+            // 弹出 n 个单元，压入闭包
+            OpCode::MakeClosure => 1 - ((arg >> 16) & 0xFFFF) as i32,
+            // 弹出函数值和参数，压入返回值
+            OpCode::CallIndirect => -(arg as i32),
+            // 弹出返回值并离开当前函数
+            OpCode::Ret => -1,
+            // 弹出错误值并跳转到处理程序
+            OpCode::Throw => -1,
+            OpCode::Syscall => {
+                let argc = ((arg >> 16) & 0xFFFF) as i32;
+                if matches!(SYSCALL::from(arg as u16), SYSCALL::PRINT) {
+                    -argc
+                } else {
+                    1 - argc
+                }
+            },
+        }
+    }
+
     fn emit_load_const(&mut self, bytecode: &mut Vec<u8>, const_index: u16) {
         self.emit_opcode_with_arg(bytecode, OpCode::LoadConst, const_index as u64);
     }
@@ -573,11 +2374,10 @@ impl Compiler {
         }
     }
 
-    fn lookup_local(&self, name: &str) -> Option<u8> {
-        self.current_local_vars_map.get(name).map(|v| *v as u8)
-    }
-
     fn lookup_global(&self, name: &str) -> Option<u16> {
-        self.global_var_map.get(name).map(|v| *v as u16)
+        // 函数体可以引用所有全局变量，顶层代码只能引用已声明的
+        self.global_var_map.get(&self.qualify(name))
+            .filter(|index| !self.in_global_scope || self.global_var_declared[**index])
+            .map(|v| *v as u16)
     }
 }
\ No newline at end of file
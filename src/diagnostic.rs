@@ -0,0 +1,364 @@
+// 诊断信息（语法错误、编译错误等）及其渲染
+use std::fmt::{self, Write};
+
+use crate::lexer::DEFAULT_TAB_WIDTH;
+use crate::token::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// 附加在源码区间上的说明
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // 主要区间。编译期错误可能没有位置信息，此时为默认值（行号为 0）
+    pub span: Span,
+    // 标在主要区间下方的说明
+    pub label: Option<String>,
+    // 其他相关位置
+    pub labels: Vec<Label>,
+    // 出错位置期望出现的标记，如 "';'"、"identifier"，可能为空
+    pub expected: Vec<String>,
+    pub notes: Vec<String>,
+    // 修改建议，如 "did you mean `count`?"
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span,
+            label: None,
+            labels: Vec::new(),
+            expected: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn with_expected(mut self, expected: Vec<String>) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help.push(help);
+        self
+    }
+
+    pub fn has_location(&self) -> bool {
+        self.span.start.line > 0
+    }
+
+    /// 以 JSON 对象的形式输出，供编辑器等工具使用
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write!(json, "{{\"severity\":{},\"message\":{}", json_string(self.severity.name()), json_string(&self.message)).unwrap();
+        if self.has_location() {
+            write!(json, ",\"span\":{}", span_json(&self.span)).unwrap();
+        } else {
+            json.push_str(",\"span\":null");
+        }
+        match &self.label {
+            Some(label) => write!(json, ",\"label\":{}", json_string(label)).unwrap(),
+            None => json.push_str(",\"label\":null"),
+        }
+        let labels: Vec<String> = self.labels.iter()
+            .map(|label| format!("{{\"span\":{},\"message\":{}}}", span_json(&label.span), json_string(&label.message)))
+            .collect();
+        write!(json, ",\"labels\":[{}]", labels.join(",")).unwrap();
+        write!(json, ",\"expected\":{}", json_array(&self.expected)).unwrap();
+        write!(json, ",\"notes\":{}", json_array(&self.notes)).unwrap();
+        write!(json, ",\"help\":{}}}", json_array(&self.help)).unwrap();
+        json
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if self.has_location() {
+            write!(f, " at line {}, column {}", self.span.start.line, self.span.start.column)?;
+        }
+        for help in &self.help {
+            write!(f, " ({})", help)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// 将诊断信息渲染为带源码片段的文本：
+///
+/// ```text
+/// error: Expected token Semicolon, but got RBrace
+///  --> script.dkvs:3:5
+///   |
+/// 3 |     }
+///   |     ^ expected ';'
+///   = help: ...
+/// ```
+pub struct DiagnosticRenderer<'a> {
+    source: &'a str,
+    file_name: &'a str,
+    color: bool,
+    tab_width: u32,
+}
+
+impl<'a> DiagnosticRenderer<'a> {
+    pub fn new(source: &'a str, file_name: &'a str) -> Self {
+        DiagnosticRenderer {
+            source,
+            file_name,
+            color: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// 是否使用 ANSI 颜色
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// 展开制表符的宽度，应与词法分析器的设置（Lexer::set_tab_width）一致，否则标记的列会错位
+    pub fn with_tab_width(mut self, tab_width: u32) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let severity_color = match diagnostic.severity {
+            Severity::Error => "31",
+            Severity::Warning => "33",
+        };
+        writeln!(out, "{}{}", self.paint(diagnostic.severity.name(), severity_color), self.paint(&format!(": {}", diagnostic.message), "1")).unwrap();
+
+        if !diagnostic.has_location() {
+            writeln!(out, " {} {}", self.paint("-->", "34"), self.file_name).unwrap();
+        } else {
+            // 行号栏宽度取所有要显示的行号中最长的
+            let max_line = diagnostic.labels.iter()
+                .map(|label| label.span.start.line)
+                .chain(std::iter::once(diagnostic.span.start.line))
+                .max()
+                .unwrap_or(1);
+            let gutter = max_line.to_string().len();
+            let start = diagnostic.span.start;
+            writeln!(out, "{:gutter$}{} {}:{}:{}", "", self.paint("-->", "34"), self.file_name, start.line, start.column).unwrap();
+            writeln!(out, "{:gutter$} {}", "", self.paint("|", "34")).unwrap();
+
+            let primary_label = match (&diagnostic.label, diagnostic.expected.is_empty()) {
+                (Some(label), _) => label.clone(),
+                (None, false) => format!("expected {}", join_alternatives(&diagnostic.expected)),
+                (None, true) => String::new(),
+            };
+            self.render_snippet(&mut out, gutter, &diagnostic.span, '^', &primary_label, severity_color);
+            for label in &diagnostic.labels {
+                self.render_snippet(&mut out, gutter, &label.span, '-', &label.message, "34");
+            }
+        }
+
+        // 标签已经显示了期望的标记时不再重复
+        if diagnostic.label.is_some() && !diagnostic.expected.is_empty() {
+            self.render_footer(&mut out, diagnostic, "note", &format!("expected {}", join_alternatives(&diagnostic.expected)));
+        }
+        for note in &diagnostic.notes {
+            self.render_footer(&mut out, diagnostic, "note", note);
+        }
+        for help in &diagnostic.help {
+            self.render_footer(&mut out, diagnostic, "help", help);
+        }
+        out
+    }
+
+    /// 渲染多条诊断信息，之间空一行
+    pub fn render_all(&self, diagnostics: &[Diagnostic]) -> String {
+        let rendered: Vec<String> = diagnostics.iter().map(|diagnostic| self.render(diagnostic)).collect();
+        rendered.join("\n")
+    }
+
+    fn render_snippet(&self, out: &mut String, gutter: usize, span: &Span, marker: char, label: &str, color: &str) {
+        let line_number = span.start.line;
+        let line = self.source_line(line_number);
+        let (text, caret_start) = expand_tabs(line, span.start.column, self.tab_width);
+        // 跨行的区间标到行尾，空区间（如文件结尾）至少标一个字符
+        let caret_len = if span.end.line == span.start.line && span.end.column > span.start.column {
+            expand_tabs(line, span.end.column, self.tab_width).1 - caret_start
+        } else if span.end.line > span.start.line {
+            text.chars().count().saturating_sub(caret_start).max(1)
+        } else {
+            1
+        };
+        let number = format!("{:>gutter$}", line_number);
+        writeln!(out, "{} {} {}", self.paint(&number, "34"), self.paint("|", "34"), text).unwrap();
+        let underline: String = std::iter::repeat_n(marker, caret_len).collect();
+        let mut marker_line = format!("{:caret_start$}{}", "", underline);
+        if !label.is_empty() {
+            marker_line.push(' ');
+            marker_line.push_str(label);
+        }
+        writeln!(out, "{:gutter$} {} {}", "", self.paint("|", "34"), self.paint(&marker_line, color)).unwrap();
+    }
+
+    fn render_footer(&self, out: &mut String, diagnostic: &Diagnostic, kind: &str, text: &str) {
+        let gutter = if diagnostic.has_location() {
+            diagnostic.span.start.line.to_string().len()
+        } else {
+            0
+        };
+        writeln!(out, "{:gutter$} {} {}: {}", "", self.paint("=", "34"), self.paint(kind, "1"), text).unwrap();
+    }
+
+    // 取第 line_number 行（从 1 开始），不含换行符
+    fn source_line(&self, line_number: u32) -> &str {
+        self.source
+            .split('\n')
+            .nth(line_number.saturating_sub(1) as usize)
+            .map(|line| line.trim_end_matches('\r'))
+            .unwrap_or("")
+    }
+
+    fn paint(&self, text: &str, code: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// 在候选名字中找出与 name 最接近的一个（编辑距离不超过名字长度的三分之一，至少为 1）
+pub(crate) fn suggest_similar<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// 编辑距离，相邻字符交换算一次编辑
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+// 将制表符展开为空格（与词法分析器的列号计算一致），并返回列号 column 在展开后文本中的位置（从 0 开始）
+fn expand_tabs(line: &str, column: u32, tab_width: u32) -> (String, usize) {
+    let tab_width = tab_width as usize;
+    let mut text = String::with_capacity(line.len());
+    let mut width = 0;
+    let mut position = None;
+    for c in line.chars() {
+        if position.is_none() && width + 1 >= column as usize {
+            position = Some(width);
+        }
+        if c == '\t' {
+            let spaces = tab_width - width % tab_width;
+            text.extend(std::iter::repeat_n(' ', spaces));
+            width += spaces;
+        } else {
+            text.push(c);
+            width += 1;
+        }
+    }
+    (text, position.unwrap_or(width))
+}
+
+fn join_alternatives(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [init @ .., last] => format!("{} or {}", init.join(", "), last),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_array(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|item| json_string(item)).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn span_json(span: &Span) -> String {
+    format!(
+        "{{\"start\":{{\"offset\":{},\"line\":{},\"column\":{}}},\"end\":{{\"offset\":{},\"line\":{},\"column\":{}}}}}",
+        span.start.offset, span.start.line, span.start.column,
+        span.end.offset, span.end.line, span.end.column
+    )
+}
@@ -1,8 +1,9 @@
 // FFI 接口部分
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_void};
+use std::sync::Arc;
 
-use crate::{CompileResult, VM};
+use crate::{Program, SendVM};
 
 // 错误类型
 type ResultCode = i32;
@@ -12,17 +13,35 @@ const ERROR: ResultCode = 1;
 // C 兼容的 DKV 命令处理函数指针类型
 type DkvCommandHandlerFn = unsafe extern "C" fn(command: *const c_char, user_data: *mut c_void) -> *mut c_char;
 
+// 线程安全约定：
+// - DkvScriptCompileResult 创建后不再修改，多个线程可以同时用它创建 VM；
+//   由它创建的 VM 共享编译后的程序，释放编译结果不影响已创建的 VM
+// - DkvScriptVM 可以转移到其他线程执行，但同一时刻只能被一个线程使用；
+//   命令处理函数在执行 VM 的线程中调用，user_data 需要能在该线程中使用
+
+// C 传入的 user_data，由调用者保证可以在执行 VM 的线程中使用
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+impl UserData {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
 // C 兼容的结构体
 #[repr(C)]
 pub struct DkvScriptVM {
-    vm: VM,
+    vm: SendVM,
     handler: Option<DkvCommandHandlerFn>,
     user_data: *mut c_void,
 }
 
+// 编译后的程序在由它创建的各 VM 之间共享
 #[repr(C)]
 pub struct DkvScriptCompileResult {
-    result: CompileResult,
+    program: Arc<Program>,
 }
 
 // 暴露给 C 的函数
@@ -41,7 +60,7 @@ pub extern "C" fn dkv_script_compile(source: *const c_char, result: *mut *mut Dk
         match crate::do_compile(source_str) {
             Ok(compile_result) => {
                 let c_result = Box::new(DkvScriptCompileResult {
-                    result: compile_result,
+                    program: Arc::new(Program::new(compile_result)),
                 });
                 *result = Box::into_raw(c_result);
                 SUCCESS
@@ -58,8 +77,8 @@ pub extern "C" fn dkv_script_create_vm(compile_result: *mut DkvScriptCompileResu
             return ERROR;
         }
         
-        let c_result = &mut *compile_result;
-        let vm_instance = VM::new(c_result.result.clone());
+        let c_result = &*compile_result;
+        let vm_instance = SendVM::from_program_send(c_result.program.clone());
         
         // 创建 C 兼容的 VM 实例
         let c_vm = Box::new(DkvScriptVM {
@@ -89,14 +108,15 @@ pub extern "C" fn dkv_script_set_dkv_command_handler(
         c_vm.user_data = user_data;
         
         // 将 C 风格的处理函数转换为 Rust 闭包
-        let handler_closure = if let Some(c_handler) = handler {
-            // 创建一个捕获 c_handler 和 user_data 的闭包
-            Some(move |command: &str| -> Result<String, String> {
+        // 创建一个捕获 c_handler 和 user_data 的闭包
+        let user_data = UserData(user_data);
+        let handler_closure = handler.map(|c_handler| {
+            move |command: &str| -> Result<String, String> {
                 // 将 Rust 字符串转换为 C 字符串
                 let c_command = CString::new(command).map_err(|e| e.to_string())?;
                 
                 // 调用 C 处理函数
-                let c_result = c_handler(c_command.as_ptr(), user_data);
+                let c_result = c_handler(c_command.as_ptr(), user_data.get());
                 if c_result.is_null() {
                     return Err("C handler returned null pointer".to_string());
                 }
@@ -111,10 +131,8 @@ pub extern "C" fn dkv_script_set_dkv_command_handler(
                 libc::free(c_result as *mut libc::c_void);
                 
                 Ok(result_str)
-            })
-        } else {
-            None
-        };
+            }
+        });
         
         // 设置处理函数
         c_vm.vm.set_dkv_command_handler(handler_closure);
@@ -130,7 +148,46 @@ pub extern "C" fn dkv_script_run_vm(vm: *mut DkvScriptVM) -> ResultCode {
         }
         
         let c_vm = &mut *vm;
-        c_vm.vm.run();
+        // 未被 catch 的异常返回错误码
+        match c_vm.vm.try_run() {
+            Ok(()) => SUCCESS,
+            Err(_) => ERROR,
+        }
+    }
+}
+
+// 把全局变量恢复为初始值，之后可以再次运行；命令处理函数等设置保持不变
+#[no_mangle]
+pub extern "C" fn dkv_script_reset_vm(vm: *mut DkvScriptVM) -> ResultCode {
+    unsafe {
+        if vm.is_null() {
+            return ERROR;
+        }
+
+        let c_vm = &mut *vm;
+        c_vm.vm.reset();
+        SUCCESS
+    }
+}
+
+// 重置后再次运行，相当于 dkv_script_reset_vm 加 dkv_script_run_vm
+#[no_mangle]
+pub extern "C" fn dkv_script_rerun_vm(vm: *mut DkvScriptVM) -> ResultCode {
+    if dkv_script_reset_vm(vm) != SUCCESS {
+        return ERROR;
+    }
+    dkv_script_run_vm(vm)
+}
+
+#[no_mangle]
+pub extern "C" fn dkv_script_set_command_throws(vm: *mut DkvScriptVM, command_throws: i32) -> ResultCode {
+    unsafe {
+        if vm.is_null() {
+            return ERROR;
+        }
+
+        let c_vm = &mut *vm;
+        c_vm.vm.set_command_throws(command_throws != 0);
         SUCCESS
     }
 }
@@ -1,102 +1,189 @@
-use crate::token::{Token, TokenType};
+use crate::diagnostic::Diagnostic;
+use crate::token::{Position, Span, StringPart, Token, TokenType};
+
+pub(crate) const DEFAULT_TAB_WIDTH: u32 = 4;
 
 pub struct Lexer {
-    source: Vec<char>,
+    source: String,
+    // 当前字节偏移
     current: usize,
+    // 本段源码在外层源码中的字节偏移（见 new_at）
+    base_offset: usize,
     line: u32,
     column: u32,
+    tab_width: u32,
+    // 尚未附加到标记上的文档注释（///）
+    pending_doc: Vec<String>,
+    // 尚未取走的词法错误
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Lexer {
     pub fn new(source: String) -> Self {
         Lexer {
-            source: source.chars().collect(),
+            source,
             current: 0,
+            base_offset: 0,
             line: 1,
             column: 1,
+            tab_width: DEFAULT_TAB_WIDTH,
+            pending_doc: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
+    /// 从指定位置开始分析一段源码（用于插值字符串中的表达式），
+    /// 这样产生的标记位置对应外层源码中的位置
+    pub fn new_at(source: String, start: Position) -> Self {
+        let mut lexer = Lexer::new(source);
+        lexer.base_offset = start.offset;
+        lexer.line = start.line;
+        lexer.column = start.column;
+        lexer
+    }
+
+    /// 设置制表符宽度（列号按制表位对齐），默认为 4
+    pub fn set_tab_width(&mut self, tab_width: u32) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    /// 取走已产生的词法错误。出错时词法分析照常继续：跳过非法字符，未结束的字符串和注释截止到源码末尾，
+    /// 超出范围的整数按 0 处理
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    // 记录从 start 到当前位置的词法错误
+    fn error(&mut self, message: String, start: Position) {
+        let span = Span::new(start, self.position());
+        self.diagnostics.push(Diagnostic::new(message, span));
+    }
+
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
 
+        // 文档注释附加到其后的第一个标记上
+        let doc = if self.pending_doc.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.pending_doc).join("\n"))
+        };
+        let mut token = self.scan_token();
+        token.doc = doc;
+        token
+    }
+
+    fn scan_token(&mut self) -> Token {
+        loop {
+            if let Some(token) = self.try_scan_token() {
+                return token;
+            }
+            // 跳过非法字符后继续
+            self.skip_whitespace();
+        }
+    }
+
+    // 读取一个标记，遇到非法字符时记录错误并返回 None
+    fn try_scan_token(&mut self) -> Option<Token> {
+        let start = self.position();
         if self.is_at_end() {
-            return Token::new(TokenType::Eof, self.line, self.column);
+            return Some(Token::with_span(TokenType::Eof, Span::new(start, start)));
         }
 
         let c = self.advance();
 
-        match c {
-            '(' => Token::new(TokenType::LParen, self.line, self.column - 1),
-            ')' => Token::new(TokenType::RParen, self.line, self.column - 1),
-            '{' => Token::new(TokenType::LBrace, self.line, self.column - 1),
-            '}' => Token::new(TokenType::RBrace, self.line, self.column - 1),
-            ';' => Token::new(TokenType::Semicolon, self.line, self.column - 1),
-            ',' => Token::new(TokenType::Comma, self.line, self.column - 1),
-            ':' => Token::new(TokenType::Colon, self.line, self.column - 1),
+        let token_type = match c {
+            '(' => TokenType::LParen,
+            ')' => TokenType::RParen,
+            '{' => TokenType::LBrace,
+            '}' => TokenType::RBrace,
+            '[' => TokenType::LBracket,
+            ']' => TokenType::RBracket,
+            ';' => TokenType::Semicolon,
+            ',' => TokenType::Comma,
+            ':' => {
+                if self.match_char(':') {
+                    TokenType::DoubleColon
+                } else {
+                    TokenType::Colon
+                }
+            },
+            '.' => TokenType::Dot,
             '+' => {
                 if self.match_char('+') {
-                    Token::new(TokenType::Increment, self.line, self.column - 2)
+                    TokenType::Increment
                 } else {
-                    Token::new(TokenType::Plus, self.line, self.column - 1)
+                    TokenType::Plus
                 }
             },
             '-' => {
                 if self.match_char('-') {
-                    Token::new(TokenType::Decrement, self.line, self.column - 2)
+                    TokenType::Decrement
+                } else if self.match_char('>') {
+                    TokenType::Arrow
                 } else {
-                    Token::new(TokenType::Minus, self.line, self.column - 1)
+                    TokenType::Minus
                 }
             },
-            '*' => Token::new(TokenType::Multiply, self.line, self.column - 1),
-            '/' => Token::new(TokenType::Divide, self.line, self.column - 1),
+            '*' => TokenType::Multiply,
+            '/' => TokenType::Divide,
             '!' => {
                 if self.match_char('=') {
-                    Token::new(TokenType::NotEquals, self.line, self.column - 2)
+                    TokenType::NotEquals
                 } else {
-                    Token::new(TokenType::Not, self.line, self.column - 1)
+                    TokenType::Not
                 }
             },
             '=' => {
                 if self.match_char('=') {
-                    Token::new(TokenType::Equals, self.line, self.column - 2)
+                    TokenType::Equals
+                } else if self.match_char('>') {
+                    TokenType::FatArrow
                 } else {
-                    Token::new(TokenType::Equal, self.line, self.column - 1)
+                    TokenType::Equal
                 }
             },
             '<' => {
                 if self.match_char('=') {
-                    Token::new(TokenType::LessThanOrEqual, self.line, self.column - 2)
+                    TokenType::LessThanOrEqual
                 } else {
-                    Token::new(TokenType::LessThan, self.line, self.column - 1)
+                    TokenType::LessThan
                 }
             },
             '>' => {
                 if self.match_char('=') {
-                    Token::new(TokenType::GreaterThanOrEqual, self.line, self.column - 2)
+                    TokenType::GreaterThanOrEqual
                 } else {
-                    Token::new(TokenType::GreaterThan, self.line, self.column - 1)
+                    TokenType::GreaterThan
                 }
             },
             '&' => {
                 if self.match_char('&') {
-                    Token::new(TokenType::And, self.line, self.column - 2)
+                    TokenType::And
                 } else {
-                    panic!("Unexpected character '&' at line {}, column {}", self.line, self.column - 1)
+                    self.error("Unexpected character '&'".to_string(), start);
+                    return None;
                 }
             },
             '|' => {
                 if self.match_char('|') {
-                    Token::new(TokenType::Or, self.line, self.column - 2)
+                    TokenType::Or
                 } else {
-                    panic!("Unexpected character '|' at line {}, column {}", self.line, self.column - 1)
+                    self.error("Unexpected character '|'".to_string(), start);
+                    return None;
                 }
             },
-            '"' => self.string(),
-            c if c.is_ascii_digit() => self.number(),
-            c if c.is_alphabetic() || c == '_' => self.identifier(),
-            _ => panic!("Unexpected character '{}' at line {}, column {}", c, self.line, self.column - 1),
-        }
+            '"' => self.string(start),
+            'f' if self.peek() == '"' => self.interpolated_string(start),
+            c if c.is_ascii_digit() => self.number(start),
+            c if c.is_alphabetic() || c == '_' => self.identifier(start),
+            _ => {
+                self.error(format!("Unexpected character '{}'", c), start);
+                return None;
+            },
+        };
+
+        Some(Token::with_span(token_type, Span::new(start, self.position())))
     }
 
     fn skip_whitespace(&mut self) {
@@ -107,22 +194,14 @@ impl Lexer {
 
             let c = self.peek();
             match c {
-                ' ' | '\r' | '\t' => {
+                ' ' | '\r' | '\t' | '\n' => {
                     self.advance();
-                    self.column += 1;
-                },
-                '\n' => {
-                    self.advance();
-                    self.line += 1;
-                    self.column = 1;
                 },
                 '/' => {
                     if self.peek_next() == '/' {
-                        // 跳过注释
-                        while !self.is_at_end() && self.peek() != '\n' {
-                            self.advance();
-                            self.column += 1;
-                        }
+                        self.line_comment();
+                    } else if self.peek_next() == '*' {
+                        self.block_comment();
                     } else {
                         break;
                     }
@@ -132,34 +211,99 @@ impl Lexer {
         }
     }
 
+    fn line_comment(&mut self) {
+        let start = self.current;
+        // 跳过注释
+        while !self.is_at_end() && self.peek() != '\n' && self.peek() != '\r' {
+            self.advance();
+        }
+
+        // 以 /// 开头（但不是 ////）的是文档注释
+        let text = &self.source[start..self.current];
+        if let Some(doc) = text.strip_prefix("///") {
+            if !doc.starts_with('/') {
+                let doc = doc.strip_prefix(' ').unwrap_or(doc);
+                self.pending_doc.push(doc.trim_end().to_string());
+            }
+        }
+    }
+
+    // 跳过块注释，支持嵌套
+    fn block_comment(&mut self) {
+        let start = self.position();
+        self.advance(); // 跳过 /*
+        self.advance();
+
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error("Unterminated block comment".to_string(), start);
+                return;
+            }
+            let c = self.advance();
+            match c {
+                '/' if self.peek() == '*' => {
+                    self.advance();
+                    depth += 1;
+                },
+                '*' if self.peek() == '/' => {
+                    self.advance();
+                    depth -= 1;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            offset: self.base_offset + self.current,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    // 读取一个字符并更新行列号：CRLF 算一次换行，制表符跳到下一个制表位
     fn advance(&mut self) -> char {
-        let c = self.source[self.current];
-        self.current += 1;
+        let c = self.peek();
+        self.current += c.len_utf8();
+        match c {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            },
+            '\r' => {
+                if self.peek() != '\n' {
+                    self.line += 1;
+                    self.column = 1;
+                }
+            },
+            '\t' => self.column += self.tab_width - (self.column - 1) % self.tab_width,
+            _ => self.column += 1,
+        }
         c
     }
 
+    // 从 start 到当前位置的源码
+    fn text_from(&self, start: Position) -> &str {
+        &self.source[start.offset - self.base_offset..self.current]
+    }
+
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source[self.current]
-        }
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.source[self.current + 1]
-        }
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
     fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() || self.source[self.current] != expected {
+        if self.is_at_end() || self.peek() != expected {
             false
         } else {
-            self.current += 1;
-            self.column += 1;
+            self.advance();
             true
         }
     }
@@ -168,19 +312,13 @@ impl Lexer {
         self.current >= self.source.len()
     }
 
-    fn identifier(&mut self) -> Token {
-        let start = self.current - 1;
-        let start_column = self.column - 1;
-
+    fn identifier(&mut self, start: Position) -> TokenType {
         while !self.is_at_end() && (self.peek().is_alphanumeric() || self.peek() == '_') {
             self.advance();
-            self.column += 1;
         }
 
-        let text: String = self.source[start..self.current].iter().collect();
-        let token_type = self.identifier_type(&text);
-
-        Token::new(token_type, self.line, start_column)
+        let text = self.text_from(start);
+        self.identifier_type(text)
     }
 
     fn identifier_type(&self, text: &str) -> TokenType {
@@ -192,6 +330,16 @@ impl Lexer {
             "for" => TokenType::For,
             "while" => TokenType::While,
             "return" => TokenType::Return,
+            "as" => TokenType::As,
+            "struct" => TokenType::Struct,
+            "enum" => TokenType::Enum,
+            "match" => TokenType::Match,
+            "try" => TokenType::Try,
+            "catch" => TokenType::Catch,
+            "finally" => TokenType::Finally,
+            "throw" => TokenType::Throw,
+            "const" => TokenType::Const,
+            "import" => TokenType::Import,
             "int" => TokenType::Int,
             "float" => TokenType::Float,
             "bool" => TokenType::Bool,
@@ -202,57 +350,116 @@ impl Lexer {
         }
     }
 
-    fn number(&mut self) -> Token {
-        let start = self.current - 1;
-        let start_column = self.column - 1;
-
+    fn number(&mut self, start: Position) -> TokenType {
         while !self.is_at_end() && self.peek().is_ascii_digit() {
             self.advance();
-            self.column += 1;
         }
 
         if !self.is_at_end() && self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
-            self.column += 1;
             while !self.is_at_end() && self.peek().is_ascii_digit() {
                 self.advance();
-                self.column += 1;
             }
 
-            let text: String = self.source[start..self.current].iter().collect();
+            let text = self.text_from(start);
             let value: f32 = text.parse().unwrap();
-            Token::new(TokenType::FloatLiteral(value), self.line, start_column)
+            TokenType::FloatLiteral(value)
         } else {
-            let text: String = self.source[start..self.current].iter().collect();
-            let value: i32 = text.parse().unwrap();
-            Token::new(TokenType::IntLiteral(value), self.line, start_column)
+            let text = self.text_from(start);
+            match text.parse() {
+                Ok(value) => TokenType::IntLiteral(value),
+                Err(_) => {
+                    self.error(format!("Integer literal out of range: {}", text), start);
+                    TokenType::IntLiteral(0)
+                },
+            }
         }
     }
 
-    fn string(&mut self) -> Token {
-        let start_line = self.line;
-        let start_column = self.column - 1;
-        let start_pos = self.current;
+    fn string(&mut self, start: Position) -> TokenType {
+        let content_start = self.current;
 
         while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '\n' {
-                self.line += 1;
-                self.column = 1;
-            } else {
-                self.column += 1;
-            }
             self.advance();
         }
 
+        let text = self.source[content_start..self.current].to_string();
         if self.is_at_end() {
-            panic!("Unterminated string at line {}, column {}", start_line, start_column);
+            self.error("Unterminated string".to_string(), start);
+        } else {
+            self.advance(); // 跳过右引号
+        }
+        TokenType::StringLiteral(text)
+    }
+
+    fn interpolated_string(&mut self, start: Position) -> TokenType {
+        self.advance(); // 跳过左引号
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            if self.is_at_end() {
+                self.error("Unterminated string".to_string(), start);
+                break;
+            }
+            let brace = self.position();
+            let c = self.advance();
+            match c {
+                '"' => break,
+                '{' if self.peek() == '{' => {
+                    self.advance();
+                    literal.push('{');
+                },
+                '}' if self.peek() == '}' => {
+                    self.advance();
+                    literal.push('}');
+                },
+                '{' => {
+                    if !literal.is_empty() {
+                        parts.push(StringPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.extend(self.interpolation_expr());
+                },
+                '}' => self.error("Unmatched '}' in interpolated string".to_string(), brace),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(StringPart::Literal(literal));
         }
 
-        self.advance(); // 跳过右引号
-        self.column += 1;
-        let end_pos = self.current;
+        TokenType::InterpolatedString(parts)
+    }
 
-        let text: String = self.source[start_pos..end_pos - 1].iter().collect();
-        Token::new(TokenType::StringLiteral(text), self.line, start_column)
+    // 读取花括号内的表达式源码，直到匹配的右花括号（跳过嵌套的花括号和字符串）；出错时返回 None
+    fn interpolation_expr(&mut self) -> Option<StringPart> {
+        let expr_start = self.position();
+        let mut depth = 0;
+        let mut in_string = false;
+        loop {
+            if self.is_at_end() {
+                // 由 interpolated_string 报告未结束的字符串
+                return None;
+            }
+            match self.peek() {
+                '"' => in_string = !in_string,
+                '{' if !in_string => depth += 1,
+                '}' if !in_string => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                },
+                _ => {},
+            }
+            self.advance();
+        }
+        let text = self.text_from(expr_start).to_string();
+        self.advance(); // 跳过右花括号
+        if text.trim().is_empty() {
+            self.error("Empty expression in interpolated string".to_string(), expr_start);
+            return None;
+        }
+        Some(StringPart::Expr(text, expr_start))
     }
-}
\ No newline at end of file
+}
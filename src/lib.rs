@@ -1,27 +1,56 @@
 mod ast;
 mod bin_format;
 mod compiler;
+mod diagnostic;
 mod ffi;
 mod lexer;
+mod module;
+mod optimizer;
 mod parser;
+mod register;
+mod stdlib;
 mod token;
+mod types;
 mod vm;
 
 // 公共 API 导出
 pub use ast::*;
 pub use bin_format::{load_from_file, save_to_file};
-pub use compiler::{CompileResult, Compiler, Constant, GlobalVarInfo, FunctionInfo, OpCode};
+pub use compiler::{Backend, CompileResult, Compiler, Constant, ConstantInfo, EnumInfo, ExceptionHandler, FieldInfo, GlobalVarInfo, FunctionInfo, OpCode, StructInfo, VariantInfo};
+pub use diagnostic::{Diagnostic, DiagnosticRenderer, Label, Severity};
 pub use lexer::Lexer;
+pub use module::{FileLoader, MemoryLoader, Module, ModuleLoader, ModuleResolver};
 pub use parser::Parser;
-pub use token::TokenType;
-pub use vm::VM;
+pub use register::{Operand, RegInstr, RegisterFunction, RegisterHandler};
+pub use token::{Position, Span, StringPart, Token, TokenType};
+pub use types::Type;
+pub use vm::{LocalHandlers, Program, RuntimeError, SendHandlers, SendVM, VM};
 pub use ffi::{DkvScriptCompileResult, DkvScriptVM}; // （不需要 pub use FFI 函数，因为已经用 #[no_mangle] 标记）
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u16)]
+#[allow(clippy::upper_case_acronyms)]
 enum SYSCALL {
     PRINT = 0x01,
     DKVCOMMAND = 0x02,
+    PARSEINT = 0x03,
+    PARSEFLOAT = 0x04,
+    // 执行 DKV 命令，返回 Result::Ok(输出) 或 Result::Err(错误信息)
+    TRYCOMMAND = 0x05,
+
+    // 字符串标准库
+    LEN = 0x10,
+    SUBSTR = 0x11,
+    FIND = 0x12,
+    SPLIT = 0x13,
+    JOIN = 0x14,
+    TRIM = 0x15,
+    UPPER = 0x16,
+    LOWER = 0x17,
+    STARTSWITH = 0x18,
+    ENDSWITH = 0x19,
+    REPLACE = 0x1A,
+    FORMAT = 0x1B,
 }
 
 impl From<u16> for SYSCALL {
@@ -29,6 +58,21 @@ impl From<u16> for SYSCALL {
         match value {
             0x01 => SYSCALL::PRINT,
             0x02 => SYSCALL::DKVCOMMAND,
+            0x03 => SYSCALL::PARSEINT,
+            0x04 => SYSCALL::PARSEFLOAT,
+            0x05 => SYSCALL::TRYCOMMAND,
+            0x10 => SYSCALL::LEN,
+            0x11 => SYSCALL::SUBSTR,
+            0x12 => SYSCALL::FIND,
+            0x13 => SYSCALL::SPLIT,
+            0x14 => SYSCALL::JOIN,
+            0x15 => SYSCALL::TRIM,
+            0x16 => SYSCALL::UPPER,
+            0x17 => SYSCALL::LOWER,
+            0x18 => SYSCALL::STARTSWITH,
+            0x19 => SYSCALL::ENDSWITH,
+            0x1A => SYSCALL::REPLACE,
+            0x1B => SYSCALL::FORMAT,
             _ => panic!("Invalid syscall id"),
         }
     }
@@ -36,16 +80,24 @@ impl From<u16> for SYSCALL {
 
 /// 编译源代码的便捷函数
 pub fn do_compile(source: &str) -> Result<CompileResult, Box<dyn std::error::Error>> {
-    // 词法分析
-    let lexer = Lexer::new(source.to_string());
-    
-    // 语法分析
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
-    
-    // 编译
+    compile_source(source).map_err(|diagnostics| {
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        messages.join("\n").into()
+    })
+}
+
+/// 编译源代码，返回所有语法错误或编译错误，可交给 DiagnosticRenderer 显示。
+/// import 的模块相对于当前目录在文件系统中查找
+pub fn compile_source(source: &str) -> Result<CompileResult, Vec<Diagnostic>> {
+    compile_source_with_resolver(source, &ModuleResolver::default(), "")
+}
+
+/// 编译源代码，import 的模块由 resolver 查找和加载，base_dir 为源代码所在目录
+pub fn compile_source_with_resolver(source: &str, resolver: &ModuleResolver, base_dir: &str) -> Result<CompileResult, Vec<Diagnostic>> {
+    // 词法分析和语法分析（包括所有导入的模块）
+    let modules = resolver.resolve(source, base_dir)?;
+
+    // 编译并链接
     let compiler = Compiler::new();
-    let compile_result = compiler.compile(&ast);
-    
-    Ok(compile_result)
+    compiler.compile_modules_with_diagnostics(&modules)
 }
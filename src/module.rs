@@ -0,0 +1,226 @@
+// 模块系统：按 import 语句查找、加载和解析脚本文件，检测循环导入，由编译器链接为一个 CompileResult
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::ASTNode;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::Span;
+
+// 模块文件的扩展名，模块名中不包含
+const MODULE_EXTENSION: &str = ".dkvs";
+
+/// 模块加载器：按规范化后的路径读取模块源代码，模块不存在时返回 None
+pub trait ModuleLoader {
+    fn load(&self, path: &str) -> Option<String>;
+}
+
+/// 从文件系统加载模块
+pub struct FileLoader;
+
+impl ModuleLoader for FileLoader {
+    fn load(&self, path: &str) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+}
+
+/// 从内存加载模块，用于把脚本嵌入宿主程序
+#[derive(Default)]
+pub struct MemoryLoader {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一个模块，路径按与 import 相同的规则规范化
+    pub fn with_module(mut self, path: &str, source: &str) -> Self {
+        self.modules.insert(normalize_path(path), source.to_string());
+        self
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn load(&self, path: &str) -> Option<String> {
+        self.modules.get(path).cloned()
+    }
+}
+
+/// 解析完成的模块
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// 模块名：去掉扩展名的规范化路径（如 lib/util），作为函数和全局变量的命名空间；主程序为空字符串
+    pub name: String,
+    pub ast: Box<ASTNode>,
+    /// 各 import 语句的路径对应的模块名
+    pub imports: HashMap<String, String>,
+}
+
+/// 模块解析器：先在导入者所在目录中查找模块，再依次在各搜索路径中查找
+pub struct ModuleResolver {
+    loader: Box<dyn ModuleLoader>,
+    search_paths: Vec<String>,
+}
+
+// 解析过程中的状态
+struct Resolution {
+    modules: Vec<Module>,
+    // 已解析完成的模块名
+    resolved: HashSet<String>,
+    // 正在解析的模块（从主程序到当前模块），用于检测循环导入
+    stack: Vec<String>,
+}
+
+impl Default for ModuleResolver {
+    fn default() -> Self {
+        Self::new(FileLoader)
+    }
+}
+
+impl ModuleResolver {
+    pub fn new(loader: impl ModuleLoader + 'static) -> Self {
+        ModuleResolver {
+            loader: Box::new(loader),
+            search_paths: Vec::new(),
+        }
+    }
+
+    pub fn with_search_path(mut self, path: &str) -> Self {
+        self.search_paths.push(normalize_path(path));
+        self
+    }
+
+    /// 从主程序开始解析所有导入的模块。base_dir 为主程序所在目录（空字符串表示当前目录），
+    /// 返回的模块按依赖顺序排列：被导入的模块在前，主程序在最后
+    pub fn resolve(&self, source: &str, base_dir: &str) -> Result<Vec<Module>, Vec<Diagnostic>> {
+        let mut resolution = Resolution {
+            modules: Vec::new(),
+            resolved: HashSet::new(),
+            stack: vec!["<main>".to_string()],
+        };
+        self.resolve_module(String::new(), &normalize_path(base_dir), source, &mut resolution)?;
+        Ok(resolution.modules)
+    }
+
+    fn resolve_module(&self, name: String, dir: &str, source: &str, resolution: &mut Resolution) -> Result<(), Vec<Diagnostic>> {
+        let mut parser = Parser::new(Lexer::new(source.to_string()));
+        let (ast, diagnostics) = parser.parse_with_diagnostics();
+        if !diagnostics.is_empty() {
+            // 主程序的错误保留源码区间；模块的错误在说明中给出模块和位置
+            return Err(if name.is_empty() {
+                diagnostics
+            } else {
+                diagnostics.into_iter().map(|diagnostic| Self::in_module(diagnostic, &name)).collect()
+            });
+        }
+
+        let mut imports = HashMap::new();
+        if let ASTNode::Program(statements) = ast.as_ref() {
+            for stmt in statements {
+                let ASTNode::Import(path, _, _, span) = stmt.as_ref() else {
+                    continue;
+                };
+                let (module_path, module_source) = self.load(dir, path)
+                    .map_err(|diagnostics| diagnostics.into_iter().map(|diagnostic| Self::at_import(diagnostic, &name, *span)).collect::<Vec<_>>())?;
+                let module_name = module_path.strip_suffix(MODULE_EXTENSION).unwrap_or(&module_path).to_string();
+                if let Some(start) = resolution.stack.iter().position(|importer| importer == &module_name) {
+                    let mut cycle = resolution.stack[start..].to_vec();
+                    cycle.push(module_name);
+                    let message = format!("Import cycle: {}", cycle.join(" -> "));
+                    return Err(vec![Self::at_import(Diagnostic::new(message, Span::default()), &name, *span)]);
+                }
+                if !resolution.resolved.contains(&module_name) {
+                    resolution.stack.push(module_name.clone());
+                    self.resolve_module(module_name.clone(), parent_dir(&module_path), &module_source, resolution)?;
+                    resolution.stack.pop();
+                }
+                imports.insert(path.clone(), module_name);
+            }
+        }
+
+        resolution.resolved.insert(name.clone());
+        resolution.modules.push(Module { name, ast, imports });
+        Ok(())
+    }
+
+    // 查找并读取模块，返回规范化后的路径和源代码
+    fn load(&self, dir: &str, path: &str) -> Result<(String, String), Vec<Diagnostic>> {
+        let candidates: Vec<String> = if path.starts_with('/') {
+            vec![normalize_path(path)]
+        } else {
+            std::iter::once(dir).chain(self.search_paths.iter().map(String::as_str))
+                .map(|dir| join_path(dir, path))
+                .collect()
+        };
+        for candidate in &candidates {
+            if let Some(source) = self.loader.load(candidate) {
+                return Ok((candidate.clone(), source));
+            }
+        }
+        let diagnostic = Diagnostic::new(format!("Module not found: {}", path), Span::default())
+            .with_note(format!("searched: {}", candidates.join(", ")));
+        Err(vec![diagnostic])
+    }
+
+    // import 语句的错误定位到模块路径；导入者不是主程序时改为写在说明中
+    fn at_import(diagnostic: Diagnostic, importer: &str, span: Span) -> Diagnostic {
+        let diagnostic = Diagnostic { span, ..diagnostic };
+        if importer.is_empty() {
+            diagnostic
+        } else {
+            Self::in_module(diagnostic, importer)
+        }
+    }
+
+    // 模块中的语法错误和编译错误：源码区间对应的是模块文件，不能用主程序的源码显示，改为写在说明中
+    pub(crate) fn in_module(diagnostic: Diagnostic, name: &str) -> Diagnostic {
+        let start = diagnostic.span.start;
+        let note = format!("in module {} at line {}, column {}", name, start.line, start.column);
+        Diagnostic { span: Span::default(), ..diagnostic }.with_note(note)
+    }
+}
+
+/// import 没有指定别名时使用的模块别名：文件名去掉扩展名，如 "lib/util.dkvs" 为 util
+pub fn default_alias(path: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    file_name.strip_suffix(MODULE_EXTENSION).unwrap_or(file_name).to_string()
+}
+
+// 规范化路径：去掉空段和 "."，处理 ".."，统一使用 / 分隔
+fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {},
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if path.starts_with('/') {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+fn join_path(dir: &str, path: &str) -> String {
+    if dir.is_empty() {
+        normalize_path(path)
+    } else {
+        normalize_path(&format!("{}/{}", dir, path))
+    }
+}
+
+fn parent_dir(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) => "/",
+        Some(index) => &path[..index],
+        None => "",
+    }
+}
@@ -0,0 +1,235 @@
+// 字节码优化：常量条件跳转的化简、跳转链的合并和不可达代码的删除（编译器优化级别 >= 1 时启用），
+// 以及把常见的指令序列合并为超级指令（优化级别 >= 2 时启用）
+use crate::compiler::{Constant, ExceptionHandler, FunctionInfo, OpCode, OPLEN, OP_ARGOFF};
+
+// 解码后的指令，跳转的目标为指令编号
+struct Instruction {
+    opcode: OpCode,
+    arg: u64,
+    target: Option<usize>,
+    removed: bool,
+}
+
+/// 按优化级别优化函数的字节码，同时调整跳转偏移量和异常处理表
+pub(crate) fn optimize_function(function: &mut FunctionInfo, constants: &[Constant], level: u8) {
+    let mut instructions = decode(&function.bytecode);
+    let jump_targets: Vec<bool> = {
+        let mut jump_targets = vec![false; instructions.len() + 1];
+        for target in instructions.iter().filter_map(|instruction| instruction.target) {
+            jump_targets[target] = true;
+        }
+        jump_targets
+    };
+
+    fold_constant_branches(&mut instructions, &jump_targets, constants);
+    thread_jumps(&mut instructions);
+    let reachable = reachable(&instructions, &function.handlers);
+    for (instruction, reachable) in instructions.iter_mut().zip(reachable) {
+        instruction.removed |= !reachable;
+    }
+    remove_jumps_to_next(&mut instructions);
+    if level >= 2 {
+        fuse_superinstructions(&mut instructions, &function.handlers);
+    }
+
+    let (bytecode, positions) = encode(&instructions);
+    function.bytecode = bytecode;
+    function.handlers = function.handlers.iter()
+        .map(|handler| ExceptionHandler {
+            start: position_of(&positions, handler.start),
+            end: position_of(&positions, handler.end),
+            target: position_of(&positions, handler.target),
+            stack_depth: handler.stack_depth,
+        })
+        // 保护的指令都被删除后，处理程序也不再需要
+        .filter(|handler| handler.start < handler.end)
+        .collect();
+}
+
+fn decode(bytecode: &[u8]) -> Vec<Instruction> {
+    bytecode.chunks_exact(OPLEN)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let opcode = OpCode::from_byte(chunk[0]);
+            let arg = u64::from_le_bytes(chunk[OP_ARGOFF..OPLEN].try_into().unwrap());
+            // 跳转偏移量相对于跳转指令本身，VM 只读取低 16 位
+            let target = matches!(opcode, OpCode::Jmp | OpCode::Jz | OpCode::CmpLtJz)
+                .then(|| (index as isize + arg as i16 as isize / OPLEN as isize) as usize);
+            Instruction { opcode, arg, target, removed: false }
+        })
+        .collect()
+}
+
+// 条件为常量的 Jz：条件为 false 时改为 Jmp，为 true 时删除。
+// Jz 本身是跳转目标时栈上的条件不一定是该常量，不做处理
+fn fold_constant_branches(instructions: &mut [Instruction], jump_targets: &[bool], constants: &[Constant]) {
+    for index in 1..instructions.len() {
+        let (load, jz) = (&instructions[index - 1], &instructions[index]);
+        if !matches!(load.opcode, OpCode::LoadConst) || !matches!(jz.opcode, OpCode::Jz) || load.removed || jump_targets[index] {
+            continue;
+        }
+        let Some(Constant::Bool(condition)) = constants.get(load.arg as usize) else {
+            continue;
+        };
+        if *condition {
+            instructions[index - 1].removed = true;
+            instructions[index].removed = true;
+        } else {
+            let target = jz.target;
+            instructions[index - 1] = Instruction { opcode: OpCode::Jmp, arg: 0, target, removed: false };
+            instructions[index].removed = true;
+        }
+    }
+}
+
+// 跳转目标为 Jmp 时直接跳转到最终目标
+fn thread_jumps(instructions: &mut [Instruction]) {
+    for index in 0..instructions.len() {
+        let Some(mut target) = instructions[index].target else {
+            continue;
+        };
+        // 限制步数，避免死循环中的跳转环
+        for _ in 0..instructions.len() {
+            match instructions.get(target) {
+                Some(Instruction { opcode: OpCode::Jmp, target: Some(next), removed: false, .. }) if *next != target => target = *next,
+                _ => break,
+            }
+        }
+        instructions[index].target = Some(target);
+    }
+}
+
+// 从函数入口和异常处理程序开始标记可达的指令。处理程序只在它保护的范围内有可达指令时才可达
+fn reachable(instructions: &[Instruction], handlers: &[ExceptionHandler]) -> Vec<bool> {
+    let mut reachable = vec![false; instructions.len()];
+    let mut worklist = vec![0];
+    loop {
+        while let Some(index) = worklist.pop() {
+            if index >= instructions.len() || reachable[index] {
+                continue;
+            }
+            reachable[index] = true;
+            let instruction = &instructions[index];
+            if instruction.removed {
+                worklist.push(index + 1);
+                continue;
+            }
+            match instruction.opcode {
+                OpCode::Jmp => worklist.extend(instruction.target),
+                OpCode::Jz => {
+                    worklist.push(index + 1);
+                    worklist.extend(instruction.target);
+                },
+                OpCode::Ret | OpCode::Exit | OpCode::Throw => {},
+                _ => worklist.push(index + 1),
+            }
+        }
+        worklist.extend(handlers.iter()
+            .filter(|handler| reachable[handler.start as usize / OPLEN..handler.end as usize / OPLEN].contains(&true))
+            .map(|handler| handler.target as usize / OPLEN)
+            .filter(|target| *target < instructions.len() && !reachable[*target]));
+        if worklist.is_empty() {
+            return reachable;
+        }
+    }
+}
+
+// 跳转到下一条保留的指令的 Jmp 没有作用
+fn remove_jumps_to_next(instructions: &mut [Instruction]) {
+    for index in 0..instructions.len() {
+        let instruction = &instructions[index];
+        if instruction.removed || !matches!(instruction.opcode, OpCode::Jmp) {
+            continue;
+        }
+        let next = (index + 1..instructions.len()).find(|next| !instructions[*next].removed).unwrap_or(instructions.len());
+        if instruction.target.is_some_and(|target| (index + 1..=next).contains(&target)) {
+            instructions[index].removed = true;
+        }
+    }
+}
+
+// 把连续的指令合并为超级指令，减少 VM 的指令分派次数：
+//   LoadLocal n, Inc, StoreLocal n              => IncLocal n（Dec 同理）
+//   LoadLocal n, LoadConst c, Add, StoreLocal n => AddConstLocal n | c << 16
+//   CmpLt, Jz                                   => CmpLtJz
+//   LoadLocal a, LoadLocal b                    => LoadLocal2 a | b << 16
+// 被合并的指令（第一条除外）不能是跳转目标或异常处理表的边界
+fn fuse_superinstructions(instructions: &mut [Instruction], handlers: &[ExceptionHandler]) {
+    let live: Vec<usize> = (0..instructions.len()).filter(|index| !instructions[*index].removed).collect();
+    // 跳转到被删除的指令时实际执行的是其后第一条保留的指令
+    let mut next_live = vec![instructions.len(); instructions.len() + 1];
+    for index in (0..instructions.len()).rev() {
+        next_live[index] = if instructions[index].removed { next_live[index + 1] } else { index };
+    }
+    let mut entries = vec![false; instructions.len() + 1];
+    let boundaries = handlers.iter()
+        .flat_map(|handler| [handler.start, handler.end, handler.target])
+        .map(|offset| offset as usize / OPLEN);
+    for boundary in instructions.iter().filter_map(|instruction| instruction.target).chain(boundaries) {
+        entries[next_live[boundary.min(instructions.len())]] = true;
+    }
+
+    let mut position = 0;
+    while position < live.len() {
+        let window: Vec<usize> = live[position..].iter()
+            .take(4)
+            .enumerate()
+            .take_while(|(offset, index)| *offset == 0 || !entries[**index])
+            .map(|(_, index)| *index)
+            .collect();
+        let ops: Vec<(OpCode, u64)> = window.iter().map(|index| (instructions[*index].opcode, instructions[*index].arg)).collect();
+        let fused = match ops.as_slice() {
+            [(OpCode::LoadLocal, a), (OpCode::LoadConst, c), (OpCode::Add, _), (OpCode::StoreLocal, b), ..] if a == b => {
+                Some((4, OpCode::AddConstLocal, a | c << 16))
+            },
+            [(OpCode::LoadLocal, a), (OpCode::Inc, _), (OpCode::StoreLocal, b), ..] if a == b => Some((3, OpCode::IncLocal, *a)),
+            [(OpCode::LoadLocal, a), (OpCode::Dec, _), (OpCode::StoreLocal, b), ..] if a == b => Some((3, OpCode::DecLocal, *a)),
+            [(OpCode::CmpLt, _), (OpCode::Jz, _), ..] => Some((2, OpCode::CmpLtJz, 0)),
+            [(OpCode::LoadLocal, a), (OpCode::LoadLocal, b), ..] => Some((2, OpCode::LoadLocal2, a | b << 16)),
+            _ => None,
+        };
+        let Some((count, opcode, arg)) = fused else {
+            position += 1;
+            continue;
+        };
+        // CmpLtJz 沿用 Jz 的跳转目标
+        let target = instructions[window[count - 1]].target;
+        instructions[window[0]] = Instruction { opcode, arg, target, removed: false };
+        for index in &window[1..count] {
+            instructions[*index].removed = true;
+        }
+        position += count;
+    }
+}
+
+// 重新编码保留的指令，返回字节码和每个原指令编号（包括末尾）对应的新字节偏移量。
+// 被删除的指令对应其后第一条保留的指令
+fn encode(instructions: &[Instruction]) -> (Vec<u8>, Vec<usize>) {
+    let mut positions = vec![0; instructions.len() + 1];
+    let mut position = instructions.iter().filter(|instruction| !instruction.removed).count() * OPLEN;
+    positions[instructions.len()] = position;
+    for (index, instruction) in instructions.iter().enumerate().rev() {
+        if !instruction.removed {
+            position -= OPLEN;
+        }
+        positions[index] = position;
+    }
+
+    let mut bytecode = Vec::with_capacity(positions[instructions.len()]);
+    for (index, instruction) in instructions.iter().enumerate() {
+        if instruction.removed {
+            continue;
+        }
+        let arg = match instruction.target {
+            Some(target) => (positions[target] as i64 - positions[index] as i64) as u64,
+            None => instruction.arg,
+        };
+        bytecode.push(instruction.opcode as u8);
+        bytecode.extend_from_slice(&arg.to_le_bytes());
+    }
+    (bytecode, positions)
+}
+
+fn position_of(positions: &[usize], offset: u16) -> u16 {
+    positions[offset as usize / OPLEN] as u16
+}
@@ -1,10 +1,26 @@
-use crate::ast::ASTNode;
+use std::collections::HashSet;
+
+use crate::ast::{ASTNode, Pattern};
+use crate::diagnostic::Diagnostic;
 use crate::lexer::Lexer;
-use crate::token::{Token, TokenType};
+use crate::module::default_alias;
+use crate::token::{Span, StringPart, Token, TokenType};
 
 pub struct Parser {
     lexer: Lexer,
     current_token: Token,
+    // 上一个标记的区间，节点的区间从它的第一个标记开始到这里结束
+    previous_span: Span,
+    // 向前看一个标记（按需读取）
+    peek_token: Option<Token>,
+    // 已收集的语法错误
+    diagnostics: Vec<Diagnostic>,
+    // 出错后直到下一个同步点之前不再报告错误，避免连锁误报
+    panic_mode: bool,
+    // if/while/for 的头部不允许结构体字面量，否则 `if x {` 中的 `x {` 会被当作字面量
+    struct_literals_allowed: bool,
+    // 已导入模块的别名，`alias::name` 解析为模块中的函数或全局变量而不是枚举值
+    module_aliases: HashSet<String>,
 }
 
 impl Parser {
@@ -12,17 +28,115 @@ impl Parser {
         let mut parser = Parser {
             lexer,
             current_token: Token::new(TokenType::Eof, 0, 0),
+            previous_span: Span::default(),
+            peek_token: None,
+            diagnostics: Vec::new(),
+            panic_mode: false,
+            struct_literals_allowed: true,
+            module_aliases: HashSet::new(),
         };
-        parser.current_token = parser.lexer.next_token();
+        parser.current_token = parser.next_token();
         parser
     }
 
+    /// 解析整个程序，遇到语法错误时 panic（报告第一个错误）
     pub fn parse(&mut self) -> Box<ASTNode> {
-        dbg!(self.parse_program())
+        let (ast, diagnostics) = self.parse_with_diagnostics();
+        if let Some(diagnostic) = diagnostics.first() {
+            panic!("{}", diagnostic);
+        }
+        ast
+    }
+
+    /// 解析整个程序并收集所有语法错误。出错的语句被跳过到下一个同步点（`;`、`}` 或语句关键字），
+    /// 对应位置在 AST 中为 ASTNode::Error，其余部分照常解析，可供编辑器等工具使用
+    pub fn parse_with_diagnostics(&mut self) -> (Box<ASTNode>, Vec<Diagnostic>) {
+        let ast = self.parse_program();
+        (ast, std::mem::take(&mut self.diagnostics))
+    }
+
+    // 从词法分析器读取下一个标记，词法错误一并加入诊断信息
+    fn next_token(&mut self) -> Token {
+        let token = self.lexer.next_token();
+        self.diagnostics.append(&mut self.lexer.take_diagnostics());
+        token
     }
 
     fn advance(&mut self) {
-        self.current_token = self.lexer.next_token();
+        self.previous_span = self.current_token.span;
+        self.current_token = match self.peek_token.take() {
+            Some(token) => token,
+            None => self.next_token(),
+        };
+    }
+
+    // 查看当前标记之后的下一个标记，不前进
+    fn peek(&mut self) -> &TokenType {
+        if self.peek_token.is_none() {
+            self.peek_token = Some(self.next_token());
+        }
+        &self.peek_token.as_ref().unwrap().token_type
+    }
+
+    // 从 start 开始到上一个标记结束的区间
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous_span)
+    }
+
+    // 在指定的结构体字面量规则下解析，结束后恢复原来的规则
+    fn with_struct_literals<T>(&mut self, allowed: bool, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = std::mem::replace(&mut self.struct_literals_allowed, allowed);
+        let result = parse(self);
+        self.struct_literals_allowed = outer;
+        result
+    }
+
+    // 在当前标记处记录一个语法错误
+    fn error(&mut self, message: String, expected: Vec<String>) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        self.diagnostics.push(Diagnostic::new(message, self.current_token.span).with_expected(expected));
+    }
+
+    // 跳过标记直到语句边界，然后恢复报告错误
+    fn synchronize(&mut self) {
+        loop {
+            match self.current_token.token_type {
+                TokenType::Eof | TokenType::RBrace |
+                TokenType::Let | TokenType::Const | TokenType::Fn | TokenType::Struct | TokenType::Enum | TokenType::If | TokenType::For |
+                TokenType::While | TokenType::Return | TokenType::Try | TokenType::Throw | TokenType::Import => break,
+                TokenType::Semicolon => {
+                    self.advance();
+                    break;
+                },
+                _ => self.advance(),
+            }
+        }
+        self.panic_mode = false;
+    }
+
+    fn statement_starts() -> Vec<String> {
+        let mut starts: Vec<String> = [TokenType::Let, TokenType::Const, TokenType::If, TokenType::For, TokenType::While, TokenType::Fn,
+         TokenType::Struct, TokenType::Enum, TokenType::Return, TokenType::Try, TokenType::Throw,
+         TokenType::Import, TokenType::LBrace, TokenType::Semicolon]
+            .iter()
+            .map(TokenType::describe)
+            .collect();
+        // 表达式语句
+        starts.extend(Self::expression_starts());
+        starts
+    }
+
+    fn expression_starts() -> Vec<String> {
+        [TokenType::Identifier(String::new()), TokenType::IntLiteral(0), TokenType::FloatLiteral(0.0),
+         TokenType::BoolLiteral(false), TokenType::StringLiteral(String::new()),
+         TokenType::InterpolatedString(Vec::new()), TokenType::LParen, TokenType::LBracket,
+         TokenType::Minus, TokenType::Not, TokenType::Match]
+            .iter()
+            .map(TokenType::describe)
+            .collect()
     }
 
     fn expect_token(&mut self, expected_type: TokenType) {
@@ -37,11 +151,9 @@ impl Parser {
         };
         
         if !tokens_match {
-            panic!("Expected token {:?}, but got {:?} at line {}, column {}", 
-                   expected_type, 
-                   self.current_token.token_type, 
-                   self.current_token.line, 
-                   self.current_token.column);
+            let message = format!("Expected token {:?}, but got {:?}", expected_type, self.current_token.token_type);
+            self.error(message, vec![expected_type.describe()]);
+            return;
         }
         self.advance();
     }
@@ -49,28 +161,83 @@ impl Parser {
     fn parse_program(&mut self) -> Box<ASTNode> {
         let mut statements = Vec::new();
         while self.current_token.token_type != TokenType::Eof {
-            statements.push(dbg!(self.parse_statement()));
+            statements.push(self.parse_statement());
+            if self.panic_mode {
+                self.synchronize();
+            }
         }
         Box::new(ASTNode::Program(statements))
     }
 
     fn parse_statement(&mut self) -> Box<ASTNode> {
-        println!("parse_statement, current token: {:?}", self.current_token.token_type);
+        // 文档注释只附加到函数定义和变量声明上，其余位置的文档注释按普通注释处理
+        if let Some(doc) = self.current_token.doc.clone() {
+            match self.current_token.token_type {
+                TokenType::Let => return Box::new(ASTNode::Documented(doc, self.parse_variable_decl())),
+                TokenType::Const => return Box::new(ASTNode::Documented(doc, self.parse_const_decl())),
+                TokenType::Fn => return Box::new(ASTNode::Documented(doc, self.parse_function_def())),
+                TokenType::Struct => return Box::new(ASTNode::Documented(doc, self.parse_struct_def())),
+                TokenType::Enum => return Box::new(ASTNode::Documented(doc, self.parse_enum_def())),
+                _ => {},
+            }
+        }
+        // 标识符后跟 =、++、-- 时是赋值语句，否则是表达式语句
+        let is_assignment = matches!(self.current_token.token_type, TokenType::Identifier(_))
+            && matches!(self.peek(), TokenType::Equal | TokenType::Increment | TokenType::Decrement);
         match &self.current_token.token_type {
             TokenType::Let => self.parse_variable_decl(),
+            TokenType::Const => self.parse_const_decl(),
             TokenType::If => self.parse_if_statement(),
             TokenType::For => self.parse_for_loop(),
             TokenType::While => self.parse_while_loop(),
             TokenType::Fn => self.parse_function_def(),
+            TokenType::Struct => self.parse_struct_def(),
+            TokenType::Enum => self.parse_enum_def(),
+            TokenType::Match => {
+                // 与 if 一样，语句位置的 match 之后不需要分号
+                let statement = Box::new(ASTNode::ExprStatement(self.parse_match()));
+                if self.current_token.token_type == TokenType::Semicolon {
+                    self.advance();
+                }
+                statement
+            },
             TokenType::Return => self.parse_return(),
+            TokenType::Try => self.parse_try(),
+            TokenType::Throw => self.parse_throw(),
+            TokenType::Import => self.parse_import(),
             TokenType::LBrace => self.parse_block(),
-            TokenType::LParen => self.parse_expression(),
             TokenType::Semicolon => {
                 self.expect_token(TokenType::Semicolon); // 跳过分号
                 Box::new(ASTNode::Block(Vec::new())) // 返回一个空的代码块
             },
-            TokenType::Identifier(_) => self.parse_assignment_or_function_call(),
-            _ => panic!("Unexpected token in statement: {:?}", self.current_token.token_type),
+            TokenType::Identifier(_) if is_assignment => {
+                let assignment = self.parse_assignment();
+                self.expect_token(TokenType::Semicolon); // 跳过分号
+                assignment
+            },
+            TokenType::Identifier(_) | TokenType::IntLiteral(_) | TokenType::FloatLiteral(_) |
+            TokenType::BoolLiteral(_) | TokenType::StringLiteral(_) | TokenType::InterpolatedString(_) |
+            TokenType::LParen | TokenType::LBracket | TokenType::Minus | TokenType::Not |
+            TokenType::Int | TokenType::Float | TokenType::Bool | TokenType::String => {
+                // 表达式语句，值被丢弃；字段访问后跟 = 时是字段赋值
+                let expr = self.parse_expression();
+                let statement = match *expr {
+                    ASTNode::FieldAccess(object, field, span) if self.current_token.token_type == TokenType::Equal => {
+                        self.advance(); // 跳过 =
+                        let value = self.parse_expression();
+                        Box::new(ASTNode::FieldAssignment(object, field, value, span))
+                    },
+                    expr => Box::new(ASTNode::ExprStatement(Box::new(expr))),
+                };
+                self.expect_token(TokenType::Semicolon); // 跳过分号
+                statement
+            },
+            _ => {
+                let message = format!("Unexpected token in statement: {:?}", self.current_token.token_type);
+                self.error(message, Self::statement_starts());
+                self.advance(); // 跳过该标记，保证继续前进
+                Box::new(ASTNode::Error)
+            },
         }
     }
 
@@ -80,7 +247,8 @@ impl Parser {
         let name = if let TokenType::Identifier(name) = &self.current_token.token_type {
             name.clone()
         } else {
-            panic!("Expected identifier after 'let'")
+            self.error("Expected identifier after 'let'".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
         };
         self.advance();
 
@@ -88,19 +256,11 @@ impl Parser {
         if let TokenType::Colon = self.current_token.token_type {
             self.expect_token(TokenType::Colon); // 跳过冒号
         } else {
-            panic!("Expected colon after variable name")
+            self.error("Expected colon after variable name".to_string(), vec![TokenType::Colon.describe()]);
         }
 
         // 处理类型关键字
-        let type_name = match &self.current_token.token_type {
-            TokenType::Int => "int".to_string(),
-            TokenType::Float => "float".to_string(),
-            TokenType::Bool => "bool".to_string(),
-            TokenType::String => "string".to_string(),
-            TokenType::Identifier(type_name) => type_name.clone(),
-            _ => panic!("Expected valid type after colon: {:?}", self.current_token.token_type)
-        };
-        self.advance();
+        let type_name = self.parse_type();
 
         let initializer = if let TokenType::Equal = self.current_token.token_type {
               self.expect_token(TokenType::Equal); // 跳过等号
@@ -114,16 +274,42 @@ impl Parser {
         Box::new(ASTNode::VariableDecl(name, type_name, initializer))
     }
 
+    // const NAME: type = expr;
+    fn parse_const_decl(&mut self) -> Box<ASTNode> {
+        self.expect_token(TokenType::Const); // 跳过 const
+
+        let TokenType::Identifier(name) = &self.current_token.token_type else {
+            self.error("Expected identifier after 'const'".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
+        };
+        let name = name.clone();
+        self.advance();
+
+        self.expect_token(TokenType::Colon); // 跳过冒号
+        let type_name = self.parse_type();
+        // 常量必须有初始值
+        self.expect_token(TokenType::Equal); // 跳过 =
+        let value = self.parse_expression();
+        self.expect_token(TokenType::Semicolon); // 跳过分号
+
+        Box::new(ASTNode::ConstDecl(name, type_name, value))
+    }
+
     fn parse_if_statement(&mut self) -> Box<ASTNode> {
         self.expect_token(TokenType::If); // 跳过 if
 
-        let condition = self.parse_expression();
+        let condition = self.with_struct_literals(false, Self::parse_expression);
 
         let then_branch = self.parse_block();
 
         let else_branch = if let TokenType::Else = self.current_token.token_type {
               self.expect_token(TokenType::Else); // 跳过 else
-              Some(self.parse_block())
+              if let TokenType::If = self.current_token.token_type {
+                  // else if：把后续的 if 语句作为 else 分支中唯一的语句
+                  Some(Box::new(ASTNode::Block(vec![self.parse_if_statement()])))
+              } else {
+                  Some(self.parse_block())
+              }
           } else {
               None
           };
@@ -133,7 +319,14 @@ impl Parser {
 
     fn parse_for_loop(&mut self) -> Box<ASTNode> {
         self.expect_token(TokenType::For); // 跳过 for
+        let (initializer, condition, increment) = self.with_struct_literals(false, Self::parse_for_header);
+        let body = self.parse_block();
 
+        Box::new(ASTNode::ForLoop(initializer, condition, increment, body))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn parse_for_header(&mut self) -> (Option<Box<ASTNode>>, Option<Box<ASTNode>>, Option<Box<ASTNode>>) {
         let initializer = if let TokenType::Identifier(_) = &self.current_token.token_type {
             let assignment = self.parse_assignment();
             self.expect_token(TokenType::Semicolon); // 跳过 ;
@@ -141,7 +334,6 @@ impl Parser {
         } else {
             None
         };
-        println!("parse_for_loop, initializer: {:?}", initializer);
 
         let condition = if self.current_token.token_type != TokenType::Semicolon {
               let expr = self.parse_expression();
@@ -151,7 +343,6 @@ impl Parser {
               self.expect_token(TokenType::Semicolon); // 跳过 ;
               None
           };
-        println!("parse_for_loop, condition: {:?}", condition);
 
         let increment = if self.current_token.token_type != TokenType::RParen {
             let expr = self.parse_assignment();
@@ -159,23 +350,17 @@ impl Parser {
         } else {
             None
         };
-        println!("parse_for_loop, increment: {:?}", increment);
-
-        let body = self.parse_block();
 
-        Box::new(ASTNode::ForLoop(initializer, condition, increment, body))
+        (initializer, condition, increment)
     }
 
     fn parse_while_loop(&mut self) -> Box<ASTNode> {
-        println!("Entering parse_while_loop, current token: {:?}", self.current_token.token_type);
         self.expect_token(TokenType::While); // 跳过 while
  
-        let condition = self.parse_expression();
+        let condition = self.with_struct_literals(false, Self::parse_expression);
 
-        println!("parse_while_loop, condition: {:?}", condition);
         let body = self.parse_block();
 
-        println!("Exiting parse_while_loop, current token: {:?}", self.current_token.token_type);
         Box::new(ASTNode::WhileLoop(condition, body))
     }
 
@@ -185,17 +370,246 @@ impl Parser {
         let name = if let TokenType::Identifier(name) = &self.current_token.token_type {
             name.clone()
         } else {
-            panic!("Expected function name")
+            self.error("Expected function name".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
         };
+        let span = self.current_token.span;
         self.advance();
 
+        let (params, return_type, body) = self.parse_function_rest();
+        Box::new(ASTNode::FunctionDef(name, params, return_type, body, span))
+    }
+
+    // 结构体定义：struct Name { field: type, ... }
+    fn parse_struct_def(&mut self) -> Box<ASTNode> {
+        self.expect_token(TokenType::Struct); // 跳过 struct
+
+        let name = if let TokenType::Identifier(name) = &self.current_token.token_type {
+            name.clone()
+        } else {
+            self.error("Expected struct name".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
+        };
+        self.advance();
+
+        self.expect_token(TokenType::LBrace); // 跳过 {
+        let mut fields = Vec::new();
+        while let TokenType::Identifier(field_name) = &self.current_token.token_type {
+            let field_name = field_name.clone();
+            self.advance();
+            self.expect_token(TokenType::Colon); // 跳过 :
+            let type_name = self.parse_type();
+            fields.push((field_name, type_name));
+
+            if self.current_token.token_type != TokenType::Comma {
+                break;
+            }
+            self.expect_token(TokenType::Comma); // 跳过 ,
+        }
+        self.expect_token(TokenType::RBrace); // 跳过 }
+
+        Box::new(ASTNode::StructDef(name, fields))
+    }
+
+    // 枚举定义：enum Name { A, B(type, ...), ... }
+    fn parse_enum_def(&mut self) -> Box<ASTNode> {
+        self.expect_token(TokenType::Enum); // 跳过 enum
+
+        let name = if let TokenType::Identifier(name) = &self.current_token.token_type {
+            name.clone()
+        } else {
+            self.error("Expected enum name".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
+        };
+        self.advance();
+
+        self.expect_token(TokenType::LBrace); // 跳过 {
+        let mut variants = Vec::new();
+        while let TokenType::Identifier(variant_name) = &self.current_token.token_type {
+            let variant_name = variant_name.clone();
+            self.advance();
+            let mut payload_types = Vec::new();
+            if self.current_token.token_type == TokenType::LParen {
+                self.advance(); // 跳过 (
+                loop {
+                    payload_types.push(self.parse_type());
+                    if self.current_token.token_type != TokenType::Comma {
+                        break;
+                    }
+                    self.expect_token(TokenType::Comma); // 跳过 ,
+                }
+                self.expect_token(TokenType::RParen); // 跳过 )
+            }
+            variants.push((variant_name, payload_types));
+
+            if self.current_token.token_type != TokenType::Comma {
+                break;
+            }
+            self.expect_token(TokenType::Comma); // 跳过 ,
+        }
+        self.expect_token(TokenType::RBrace); // 跳过 }
+
+        Box::new(ASTNode::EnumDef(name, variants))
+    }
+
+    // 枚举值中 :: 之后的部分：Variant 或 Variant(args)
+    fn parse_enum_variant(&mut self, enum_name: String, start: Span) -> Box<ASTNode> {
+        self.expect_token(TokenType::DoubleColon); // 跳过 ::
+        let TokenType::Identifier(variant) = &self.current_token.token_type else {
+            self.error("Expected variant name after '::'".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
+        };
+        let variant = variant.clone();
+        self.advance();
+
+        let args = if self.current_token.token_type == TokenType::LParen {
+            self.advance(); // 跳过 (
+            let args = self.parse_args();
+            self.expect_token(TokenType::RParen); // 跳过 )
+            args
+        } else {
+            Vec::new()
+        };
+        Box::new(ASTNode::EnumVariant(enum_name, variant, args, self.span_from(start)))
+    }
+
+    // 模块中的函数或全局变量：alias::name 或 alias::name(args)
+    fn parse_qualified_name(&mut self, alias: String, start: Span) -> Box<ASTNode> {
+        self.expect_token(TokenType::DoubleColon); // 跳过 ::
+        let TokenType::Identifier(member) = &self.current_token.token_type else {
+            self.error(format!("Expected name after '{}::'", alias), vec![TokenType::Identifier(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
+        };
+        let name = format!("{}::{}", alias, member);
+        self.advance();
+
+        if self.current_token.token_type == TokenType::LParen {
+            self.parse_function_call(name, start)
+        } else {
+            Box::new(ASTNode::Identifier(name, self.span_from(start)))
+        }
+    }
+
+    // match 表达式：match expr { pattern => expr, pattern => { ... } ... }
+    fn parse_match(&mut self) -> Box<ASTNode> {
+        let start = self.current_token.span;
+        self.expect_token(TokenType::Match); // 跳过 match
+        let scrutinee = self.with_struct_literals(false, Self::parse_expression);
+
+        self.expect_token(TokenType::LBrace); // 跳过 {
+        let mut arms = Vec::new();
+        while !matches!(self.current_token.token_type, TokenType::RBrace | TokenType::Eof) {
+            let pattern_start = self.current_token.span;
+            let pattern = self.parse_pattern();
+            let pattern_span = self.span_from(pattern_start);
+            self.expect_token(TokenType::FatArrow); // 跳过 =>
+            if self.panic_mode {
+                break;
+            }
+
+            // 代码块分支之后的逗号可以省略
+            let is_block = self.current_token.token_type == TokenType::LBrace;
+            let body = if is_block {
+                self.parse_block()
+            } else {
+                self.with_struct_literals(true, Self::parse_expression)
+            };
+            arms.push((pattern, body, pattern_span));
+
+            if self.current_token.token_type == TokenType::Comma {
+                self.advance();
+            } else if !is_block {
+                break;
+            }
+        }
+        self.expect_token(TokenType::RBrace); // 跳过 }
+
+        Box::new(ASTNode::Match(scrutinee, arms, self.span_from(start)))
+    }
+
+    // 模式：`_` 或 Enum::Variant(binding, ...)
+    fn parse_pattern(&mut self) -> Pattern {
+        let TokenType::Identifier(name) = &self.current_token.token_type else {
+            self.error(format!("Expected pattern, but got {:?}", self.current_token.token_type),
+                       vec![TokenType::Identifier(String::new()).describe()]);
+            return Pattern::Wildcard;
+        };
+        let name = name.clone();
+        self.advance();
+        if name == "_" {
+            return Pattern::Wildcard;
+        }
+
+        self.expect_token(TokenType::DoubleColon); // 跳过 ::
+        let variant = match &self.current_token.token_type {
+            TokenType::Identifier(variant) => variant.clone(),
+            _ => {
+                self.error("Expected variant name after '::'".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+                return Pattern::Wildcard;
+            },
+        };
+        self.advance();
+
+        let mut bindings = Vec::new();
+        if self.current_token.token_type == TokenType::LParen {
+            self.advance(); // 跳过 (
+            while let TokenType::Identifier(binding) = &self.current_token.token_type {
+                bindings.push(binding.clone());
+                self.advance();
+                if self.current_token.token_type != TokenType::Comma {
+                    break;
+                }
+                self.expect_token(TokenType::Comma); // 跳过 ,
+            }
+            self.expect_token(TokenType::RParen); // 跳过 )
+        }
+        Pattern::Variant(name, variant, bindings)
+    }
+
+    // 结构体字面量中花括号内的部分：field: expr, ...
+    fn parse_struct_literal(&mut self, name: String, start: Span) -> Box<ASTNode> {
+        self.expect_token(TokenType::LBrace); // 跳过 {
+        let mut fields = Vec::new();
+        while let TokenType::Identifier(field_name) = &self.current_token.token_type {
+            let field_name = field_name.clone();
+            self.advance();
+            self.expect_token(TokenType::Colon); // 跳过 :
+            fields.push((field_name, self.parse_expression()));
+
+            if self.current_token.token_type != TokenType::Comma {
+                break;
+            }
+            self.expect_token(TokenType::Comma); // 跳过 ,
+        }
+        self.expect_token(TokenType::RBrace); // 跳过 }
+
+        Box::new(ASTNode::StructLiteral(name, fields, self.span_from(start)))
+    }
+
+    // 匿名函数：fn(params) [-> type] { ... }
+    fn parse_lambda(&mut self) -> Box<ASTNode> {
+        let start = self.current_token.span;
+        self.expect_token(TokenType::Fn); // 跳过 fn
+        let (params, return_type, body) = self.parse_function_rest();
+        Box::new(ASTNode::Lambda(params, return_type, body, self.span_from(start)))
+    }
+
+    // 函数名之后的部分：参数列表、可选的返回类型和函数体
+    fn parse_function_rest(&mut self) -> (Vec<(String, String)>, Option<String>, Box<ASTNode>) {
         self.expect_token(TokenType::LParen); // 跳过 (
         let params = self.parse_params();
         self.expect_token(TokenType::RParen); // 跳过 )
 
-        let body = self.parse_block();
+        // 可选的返回类型 -> type
+        let return_type = if self.current_token.token_type == TokenType::Arrow {
+            self.advance();
+            Some(self.parse_type())
+        } else {
+            None
+        };
 
-        Box::new(ASTNode::FunctionDef(name, params, body))
+        let body = self.parse_block();
+        (params, return_type, body)
     }
 
     fn parse_params(&mut self) -> Vec<(String, String)> {
@@ -206,20 +620,13 @@ impl Parser {
                 let name = if let TokenType::Identifier(name) = &self.current_token.token_type {
                     name.clone()
                 } else {
-                    panic!("Expected parameter name")
-                };                   
+                    self.error("Expected parameter name".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+                    break;
+                };
                 self.advance(); // 跳过参数名
 
 
-                let type_name = match &self.current_token.token_type {
-                    TokenType::Int => "int".to_string(),
-                    TokenType::Float => "float".to_string(),
-                    TokenType::Bool => "bool".to_string(),
-                    TokenType::String => "string".to_string(),
-                    TokenType::Identifier(type_name) => type_name.clone(),
-                    _ => panic!("Expected valid type after colon: {:?}", self.current_token.token_type)
-                };
-                self.advance(); // 跳过参数类型
+                let type_name = self.parse_type(); // 跳过参数类型
 
                 params.push((name, type_name));
 
@@ -233,22 +640,43 @@ impl Parser {
         params
     }
 
-    fn parse_function_call(&mut self, name: String) -> Box<ASTNode> {
-        println!("Entering parse_function_call, name: {:?}, current token: {:?}", name, self.current_token.token_type);
+    fn parse_type(&mut self) -> String {
+        let type_name = match &self.current_token.token_type {
+            TokenType::Int => "int".to_string(),
+            TokenType::Float => "float".to_string(),
+            TokenType::Bool => "bool".to_string(),
+            TokenType::String => "string".to_string(),
+            TokenType::Fn => "fn".to_string(),
+            TokenType::Identifier(type_name) => type_name.clone(),
+            _ => {
+                let message = format!("Expected valid type after colon: {:?}", self.current_token.token_type);
+                let expected = [TokenType::Int, TokenType::Float, TokenType::Bool, TokenType::String, TokenType::Identifier(String::new())]
+                    .iter()
+                    .map(TokenType::describe)
+                    .collect();
+                self.error(message, expected);
+                return String::new();
+            },
+        };
+        self.advance();
+        type_name
+    }
+
+    fn parse_function_call(&mut self, name: String, start: Span) -> Box<ASTNode> {
         self.expect_token(TokenType::LParen); // 跳过 (
         let args = self.parse_args();
         self.expect_token(TokenType::RParen); // 跳过 )
-        println!("Exiting parse_function_call, current token: {:?}", self.current_token.token_type);
-        Box::new(ASTNode::FunctionCall(name, args))
+        Box::new(ASTNode::FunctionCall(name, args, self.span_from(start)))
     }
 
+    #[allow(clippy::vec_box)]
     fn parse_args(&mut self) -> Vec<Box<ASTNode>> {
-        println!("Entering parse_args, current token: {:?}", self.current_token.token_type);
         let mut args = Vec::new();
 
         if self.current_token.token_type != TokenType::RParen {
             loop {
-                args.push(self.parse_expression());
+                // 括号内允许结构体字面量
+                args.push(self.with_struct_literals(true, Self::parse_expression));
 
                 if self.current_token.token_type != TokenType::Comma {
                     break;
@@ -256,11 +684,11 @@ impl Parser {
                 self.expect_token(TokenType::Comma); // 跳过 ,
             }
         }
-        println!("Exiting parse_args, current token: {:?}", self.current_token.token_type);
         args
     }
 
     fn parse_return(&mut self) -> Box<ASTNode> {
+        let span = self.current_token.span;
         self.expect_token(TokenType::Return); // 跳过 return
 
         let expr = if self.current_token.token_type != TokenType::Semicolon {
@@ -271,29 +699,126 @@ impl Parser {
 
         self.expect_token(TokenType::Semicolon); // 跳过分号
 
-        Box::new(ASTNode::Return(expr))
+        Box::new(ASTNode::Return(expr, span))
+    }
+
+    fn parse_try(&mut self) -> Box<ASTNode> {
+        self.expect_token(TokenType::Try); // 跳过 try
+        let body = self.parse_block();
+
+        let catch = if self.current_token.token_type == TokenType::Catch {
+            self.advance(); // 跳过 catch
+            self.expect_token(TokenType::LParen);
+            let name = if let TokenType::Identifier(name) = &self.current_token.token_type {
+                name.clone()
+            } else {
+                self.error("Expected error variable name after 'catch ('".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+                return Box::new(ASTNode::Error);
+            };
+            self.advance();
+            self.expect_token(TokenType::RParen);
+            Some((name, self.parse_block()))
+        } else {
+            None
+        };
+
+        let finally = if self.current_token.token_type == TokenType::Finally {
+            self.advance(); // 跳过 finally
+            Some(self.parse_block())
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            self.error("Expected catch or finally after try block".to_string(),
+                       vec![TokenType::Catch.describe(), TokenType::Finally.describe()]);
+        }
+        Box::new(ASTNode::TryCatch(body, catch, finally))
+    }
+
+    fn parse_throw(&mut self) -> Box<ASTNode> {
+        self.expect_token(TokenType::Throw); // 跳过 throw
+        let expr = self.parse_expression();
+        self.expect_token(TokenType::Semicolon); // 跳过分号
+        Box::new(ASTNode::Throw(expr))
+    }
+
+    // import "path" [as alias]; 或 import { name, ... } from "path";
+    fn parse_import(&mut self) -> Box<ASTNode> {
+        self.expect_token(TokenType::Import); // 跳过 import
+
+        let mut names = Vec::new();
+        if self.current_token.token_type == TokenType::LBrace {
+            self.advance(); // 跳过 {
+            while let TokenType::Identifier(name) = &self.current_token.token_type {
+                names.push(name.clone());
+                self.advance();
+                if self.current_token.token_type != TokenType::Comma {
+                    break;
+                }
+                self.advance(); // 跳过 ,
+            }
+            self.expect_token(TokenType::RBrace); // 跳过 }
+            // from 不是关键字，可以继续用作变量名和字段名
+            if self.current_token.token_type != TokenType::Identifier("from".to_string()) {
+                self.error("Expected 'from' after imported names".to_string(), vec!["'from'".to_string()]);
+                return Box::new(ASTNode::Error);
+            }
+            self.advance(); // 跳过 from
+        }
+
+        let TokenType::StringLiteral(path) = &self.current_token.token_type else {
+            self.error("Expected module path after 'import'".to_string(), vec![TokenType::StringLiteral(String::new()).describe()]);
+            return Box::new(ASTNode::Error);
+        };
+        let path = path.clone();
+        let span = self.current_token.span;
+        self.advance();
+
+        let alias = if names.is_empty() {
+            let alias = if self.current_token.token_type == TokenType::As {
+                self.advance(); // 跳过 as
+                let TokenType::Identifier(alias) = &self.current_token.token_type else {
+                    self.error("Expected module alias after 'as'".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+                    return Box::new(ASTNode::Error);
+                };
+                let alias = alias.clone();
+                self.advance();
+                alias
+            } else {
+                default_alias(&path)
+            };
+            self.module_aliases.insert(alias.clone());
+            Some(alias)
+        } else {
+            None
+        };
+        self.expect_token(TokenType::Semicolon); // 跳过分号
+        Box::new(ASTNode::Import(path, alias, names, span))
     }
 
     fn parse_block(&mut self) -> Box<ASTNode> {
-        println!("Entering parse_block, current token: {:?}", self.current_token.token_type);
+        self.with_struct_literals(true, Self::parse_block_statements)
+    }
+
+    fn parse_block_statements(&mut self) -> Box<ASTNode> {
         self.expect_token(TokenType::LBrace); // 跳过 {
         let mut statements = Vec::new();
 
-        while self.current_token.token_type != TokenType::RBrace {
-            statements.push(dbg!(self.parse_statement()));
+        while !matches!(self.current_token.token_type, TokenType::RBrace | TokenType::Eof) {
+            statements.push(self.parse_statement());
+            if self.panic_mode {
+                self.synchronize();
+            }
         }
 
         self.expect_token(TokenType::RBrace); // 跳过 }
 
-        println!("Exiting parse_block, current token: {:?}", self.current_token.token_type);
         Box::new(ASTNode::Block(statements))
     }
 
     fn parse_expression(&mut self) -> Box<ASTNode> {
-        println!("Entering parse_expression, current token: {:?}", self.current_token.token_type);
-        let x= self.parse_logical_or();
-        println!("Exiting parse_expression, current token: {:?}", self.current_token.token_type);
-        x
+        self.parse_logical_or()
     }
 
     fn parse_logical_or(&mut self) -> Box<ASTNode> {
@@ -302,7 +827,8 @@ impl Parser {
         while let TokenType::Or = self.current_token.token_type {
             self.advance();
             let right = self.parse_logical_and();
-            left = Box::new(ASTNode::BinaryExpr(left, "||".to_string(), right));
+            let span = left.span().to(right.span());
+            left = Box::new(ASTNode::BinaryExpr(left, "||".to_string(), right, span));
         }
 
         left
@@ -314,14 +840,14 @@ impl Parser {
         while let TokenType::And = self.current_token.token_type {
             self.advance();
             let right = self.parse_equality();
-            left = Box::new(ASTNode::BinaryExpr(left, "&&".to_string(), right));
+            let span = left.span().to(right.span());
+            left = Box::new(ASTNode::BinaryExpr(left, "&&".to_string(), right, span));
         }
 
         left
     }
 
     fn parse_equality(&mut self) -> Box<ASTNode> {
-        println!("Entering parse_equality, current token: {:?}", self.current_token.token_type);
         let mut left = self.parse_relational();
 
         while matches!(
@@ -335,23 +861,20 @@ impl Parser {
             };
             self.advance();
             let right = self.parse_relational();
-            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right));
+            let span = left.span().to(right.span());
+            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right, span));
         }
 
-        println!("Exiting parse_equality, left: {:?}", left);
         left
     }
 
     fn parse_relational(&mut self) -> Box<ASTNode> {
-        println!("Entering parse_relational, current token: {:?}", self.current_token.token_type);
         let mut left = self.parse_additive();
-        println!("After parse_additive, current token: {:?}", self.current_token.token_type);
 
         while matches!(
             self.current_token.token_type,
             TokenType::LessThan | TokenType::LessThanOrEqual | TokenType::GreaterThan | TokenType::GreaterThanOrEqual
         ) {
-            println!("Found relational operator: {:?}", self.current_token.token_type);
             let operator = match self.current_token.token_type {
                 TokenType::LessThan => "<",
                 TokenType::LessThanOrEqual => "<=",
@@ -360,12 +883,11 @@ impl Parser {
                 _ => unreachable!(),
             };
             self.advance();
-            println!("After advancing, current token: {:?}", self.current_token.token_type);
             let right = self.parse_additive();
-            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right));
+            let span = left.span().to(right.span());
+            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right, span));
         }
 
-        println!("Exiting parse_relational, left: {:?}", left);
         left
     }
 
@@ -383,14 +905,15 @@ impl Parser {
             };
             self.advance();
             let right = self.parse_multiplicative();
-            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right));
+            let span = left.span().to(right.span());
+            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right, span));
         }
 
         left
     }
 
     fn parse_multiplicative(&mut self) -> Box<ASTNode> {
-        let mut left = self.parse_unary();
+        let mut left = self.parse_cast();
 
         while matches!(
             self.current_token.token_type,
@@ -402,18 +925,33 @@ impl Parser {
                 _ => unreachable!(),
             };
             self.advance();
-            let right = self.parse_unary();
-            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right));
+            let right = self.parse_cast();
+            let span = left.span().to(right.span());
+            left = Box::new(ASTNode::BinaryExpr(left, operator.to_string(), right, span));
         }
 
         left
     }
 
+    fn parse_cast(&mut self) -> Box<ASTNode> {
+        let mut expr = self.parse_unary();
+
+        while let TokenType::As = self.current_token.token_type {
+            self.advance(); // 跳过 as
+            let type_name = self.parse_type();
+            let span = self.span_from(expr.span());
+            expr = Box::new(ASTNode::Cast(expr, type_name, span));
+        }
+
+        expr
+    }
+
     fn parse_unary(&mut self) -> Box<ASTNode> {
         if matches!(
             self.current_token.token_type,
             TokenType::Not | TokenType::Minus
         ) {
+            let start = self.current_token.span;
             let operator = match self.current_token.token_type {
                 TokenType::Not => "!",
                 TokenType::Minus => "-",
@@ -421,110 +959,198 @@ impl Parser {
             };
             self.advance();
             let right = self.parse_unary();
-            return Box::new(ASTNode::UnaryExpr(operator.to_string(), right));
+            return Box::new(ASTNode::UnaryExpr(operator.to_string(), right, self.span_from(start)));
+        }
+
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Box<ASTNode> {
+        let mut expr = self.parse_primary();
+
+        loop {
+            match self.current_token.token_type {
+                TokenType::LBracket => {
+                    self.advance(); // 跳过 [
+                    let index = self.with_struct_literals(true, Self::parse_expression);
+                    self.expect_token(TokenType::RBracket); // 跳过 ]
+                    let span = self.span_from(expr.span());
+                    expr = Box::new(ASTNode::Index(expr, index, span));
+                },
+                TokenType::LParen => {
+                    // 调用函数值，如 f(1)(2) 或 callbacks[0](x)
+                    self.advance(); // 跳过 (
+                    let args = self.parse_args();
+                    self.expect_token(TokenType::RParen); // 跳过 )
+                    let span = self.span_from(expr.span());
+                    expr = Box::new(ASTNode::IndirectCall(expr, args, span));
+                },
+                TokenType::Dot => {
+                    self.advance(); // 跳过 .
+                    let TokenType::Identifier(field) = &self.current_token.token_type else {
+                        self.error("Expected field name after '.'".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+                        return Box::new(ASTNode::Error);
+                    };
+                    let field = field.clone();
+                    self.advance();
+                    let span = self.span_from(expr.span());
+                    expr = Box::new(ASTNode::FieldAccess(expr, field, span));
+                },
+                _ => break,
+            }
         }
 
-        self.parse_primary()
+        expr
     }
 
     fn parse_primary(&mut self) -> Box<ASTNode> {
+        let start = self.current_token.span;
         let token_type = &self.current_token.token_type;
-        println!("Entering parse_primary, current token: {:?}", token_type);
         match token_type {
             TokenType::IntLiteral(value) => {
                 let cloned_value = *value;
                 self.advance();
-                Box::new(ASTNode::IntLiteral(cloned_value))
+                Box::new(ASTNode::IntLiteral(cloned_value, start))
             },
             TokenType::FloatLiteral(value) => {
                 let cloned_value = *value;
                 self.advance();
-                Box::new(ASTNode::FloatLiteral(cloned_value))
+                Box::new(ASTNode::FloatLiteral(cloned_value, start))
             },
             TokenType::BoolLiteral(value) => {
                 let cloned_value = *value;
                 self.advance();
-                Box::new(ASTNode::BoolLiteral(cloned_value))
+                Box::new(ASTNode::BoolLiteral(cloned_value, start))
             },
             TokenType::StringLiteral(value) => {
                 let cloned_value = value.clone();
                 self.advance();
-                Box::new(ASTNode::StringLiteral(cloned_value))
+                Box::new(ASTNode::StringLiteral(cloned_value, start))
+            },
+            TokenType::InterpolatedString(parts) => {
+                let parts = parts.clone();
+                self.advance();
+                self.parse_interpolation(parts, start)
             },
             TokenType::Identifier(name) => {
                 let name = name.clone();
                 self.advance();
-                println!("parse_primary, identifier: {:?}", name);
-                println!("parse_primary, current token: {:?}", self.current_token.token_type);
                 if let TokenType::LParen = self.current_token.token_type {
-                    self.parse_function_call(name)
+                    self.parse_function_call(name, start)
+                } else if self.current_token.token_type == TokenType::DoubleColon && self.module_aliases.contains(&name) {
+                    self.parse_qualified_name(name, start)
+                } else if self.current_token.token_type == TokenType::DoubleColon {
+                    self.parse_enum_variant(name, start)
+                } else if self.current_token.token_type == TokenType::LBrace && self.struct_literals_allowed {
+                    self.parse_struct_literal(name, start)
                 } else {
-                    Box::new(ASTNode::Identifier(name))
+                    Box::new(ASTNode::Identifier(name, start))
                 }
             },
+            TokenType::Int | TokenType::Float | TokenType::Bool | TokenType::String => {
+                // 转换函数形式：int(x)、float(x)、bool(x)、string(x)
+                let type_name = self.parse_type();
+                self.expect_token(TokenType::LParen); // 跳过 (
+                let expr = self.parse_expression();
+                self.expect_token(TokenType::RParen); // 跳过 )
+                Box::new(ASTNode::Cast(expr, type_name, self.span_from(start)))
+            },
+            TokenType::Fn => self.parse_lambda(),
+            TokenType::Match => self.parse_match(),
             TokenType::LParen => {
                 self.advance();
-                let expr = self.parse_expression();
+                let expr = self.with_struct_literals(true, Self::parse_expression);
                 self.expect_token(TokenType::RParen); // 跳过 )
                 expr
             },
+            TokenType::LBracket => {
+                self.advance(); // 跳过 [
+                let mut elements = Vec::new();
+                if self.current_token.token_type != TokenType::RBracket {
+                    loop {
+                        elements.push(self.with_struct_literals(true, Self::parse_expression));
+                        if self.current_token.token_type != TokenType::Comma {
+                            break;
+                        }
+                        self.expect_token(TokenType::Comma); // 跳过 ,
+                    }
+                }
+                self.expect_token(TokenType::RBracket); // 跳过 ]
+                Box::new(ASTNode::ArrayLiteral(elements, self.span_from(start)))
+            },
             TokenType::LBrace => {
                 // LBrace 应该在 parse_statement 方法中被处理
                 // 但如果它到达了这里，我们需要适当处理
                 self.parse_block()
             },
-            _ => panic!("Unexpected token in primary expression: {:?}", token_type),
+            _ => {
+                let message = format!("Unexpected token in primary expression: {:?}", token_type);
+                self.error(message, Self::expression_starts());
+                Box::new(ASTNode::Error)
+            },
+        }
+    }
+
+    // 插值字符串：字面部分没有单独的位置，使用整个字符串的区间
+    fn parse_interpolation(&mut self, parts: Vec<StringPart>, span: Span) -> Box<ASTNode> {
+        let mut nodes = Vec::new();
+        for part in parts {
+            match part {
+                StringPart::Literal(text) => nodes.push(Box::new(ASTNode::StringLiteral(text, span))),
+                StringPart::Expr(source, start) => {
+                    // 花括号内的表达式用独立的解析器解析，位置从表达式在外层源码中的起点开始
+                    let mut parser = Parser::new(Lexer::new_at(source, start));
+                    let expr = parser.parse_expression();
+                    if parser.current_token.token_type != TokenType::Eof {
+                        let message = format!("Unexpected token {:?} in interpolated expression", parser.current_token.token_type);
+                        parser.error(message, vec![TokenType::Eof.describe()]);
+                    }
+                    if !parser.diagnostics.is_empty() && !self.panic_mode {
+                        self.diagnostics.append(&mut parser.diagnostics);
+                        self.panic_mode = true;
+                    }
+                    nodes.push(expr);
+                },
+            }
         }
+        Box::new(ASTNode::Interpolation(nodes, span))
     }
 
-    fn parse_assignment_with_identifier(&mut self, identifier: String) -> Box<ASTNode> {
+    fn parse_assignment_with_identifier(&mut self, identifier: String, span: Span) -> Box<ASTNode> {
         match self.current_token.token_type {
             TokenType::Equal => {
                 self.advance(); // 跳过=
                 let expr = self.parse_expression();
-                Box::new(ASTNode::Assignment(identifier, expr))
+                Box::new(ASTNode::Assignment(identifier, expr, span))
             }
             TokenType::Increment => {
                 self.advance(); // 跳过++
-                Box::new(ASTNode::Increment(identifier))
+                Box::new(ASTNode::Increment(identifier, span))
             }
             TokenType::Decrement => {
                 self.advance(); // 跳过--
-                Box::new(ASTNode::Decrement(identifier))
+                Box::new(ASTNode::Decrement(identifier, span))
             }
             _ => {
-                panic!("Unexpected token in assignment: {:?}", self.current_token.token_type)
+                let message = format!("Unexpected token in assignment: {:?}", self.current_token.token_type);
+                let expected = [TokenType::Equal, TokenType::Increment, TokenType::Decrement]
+                    .iter()
+                    .map(TokenType::describe)
+                    .collect();
+                self.error(message, expected);
+                Box::new(ASTNode::Error)
             }
         }
     }
 
     fn parse_assignment(&mut self) -> Box<ASTNode> {
-        println!("parse_assignment, current token: {:?}", self.current_token.token_type);
         if let TokenType::Identifier(name) = self.current_token.token_type.clone() {
+            let span = self.current_token.span;
             self.advance(); // 跳过标识符
-            self.parse_assignment_with_identifier(name)
+            self.parse_assignment_with_identifier(name, span)
         } else {
-            panic!("Expected identifier in assignment")
+            self.error("Expected identifier in assignment".to_string(), vec![TokenType::Identifier(String::new()).describe()]);
+            Box::new(ASTNode::Error)
         }
     }
-
-    fn parse_assignment_or_function_call(&mut self) -> Box<ASTNode> {
-        println!("parse_statement, identifier token: {:?}", self.current_token.token_type);
-        // 保存当前token以便后续使用
-        let identifier_token = self.current_token.clone();
-        if let TokenType::Identifier(name) = &identifier_token.token_type {
-            // 查看下一个token
-            self.advance();
-
-            match &self.current_token.token_type {
-                TokenType::Equal |
-                TokenType::Increment |
-                TokenType::Decrement => self.parse_assignment_with_identifier(name.clone()),
-                TokenType::LParen => self.parse_function_call(name.clone()),
-                _ => panic!("Unexpected token after identifier: {:?}", self.current_token.token_type),
-            }
-        } else {
-            panic!("Expected identifier in assignment or function call")
-        }
-    }
-}
\ No newline at end of file
+}
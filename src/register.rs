@@ -0,0 +1,524 @@
+// 寄存器后端：把栈式字节码翻译为三地址码，由 VM 的寄存器解释器执行。
+// 每个函数的寄存器依次为局部变量和临时寄存器，栈深度为 d 的操作数放在第 local_count + d 个寄存器中；
+// 常量和局部变量在被使用前不复制，直接作为指令的操作数，结果写入局部变量时直接写入目标寄存器
+use crate::compiler::{EnumInfo, FunctionInfo, OpCode, StructInfo, OPLEN, OP_ARGOFF};
+use crate::types::Type;
+use crate::SYSCALL;
+
+/// 指令的源操作数：寄存器或常量池中的常量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Reg(u16),
+    Const(u16),
+}
+
+/// 寄存器指令。start 开始的连续寄存器保存多个操作数，顺序与栈式字节码中的入栈顺序相同
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegInstr {
+    Move { dst: u16, src: Operand },
+    LoadGlobal { dst: u16, global: u16 },
+    StoreGlobal { global: u16, src: Operand },
+    NewCell { dst: u16, src: Operand },
+    LoadUpvalue { dst: u16, upvalue: u16 },
+    LoadCell { dst: u16, cell: u16 },
+    StoreCell { cell: u16, src: Operand },
+    /// Not、Inc、Dec 或 Neg
+    Unary { op: OpCode, dst: u16, src: Operand },
+    /// 算术、比较或下标运算
+    Binary { op: OpCode, dst: u16, lhs: Operand, rhs: Operand },
+    Cast { dst: u16, src: Operand, target: Type },
+    NewArray { dst: u16, start: u16, count: u16 },
+    Concat { dst: u16, start: u16, count: u16 },
+    NewStruct { dst: u16, struct_index: u16, start: u16 },
+    GetField { dst: u16, src: u16, field: u16 },
+    /// 修改 object 寄存器中结构体的字段
+    SetField { object: u16, field: u16, src: Operand },
+    NewEnum { dst: u16, enum_index: u16, variant: u16, start: u16 },
+    IsVariant { dst: u16, src: u16, enum_index: u16, variant: u16 },
+    Jmp { target: u32 },
+    Jz { cond: Operand, target: u32 },
+    /// 参数逆序存放在 start 开始的寄存器中（最后一个寄存器为第一个参数）
+    Call { dst: u16, function: u16, start: u16 },
+    CallIndirect { dst: u16, callee: u16, start: u16, argc: u16 },
+    MakeClosure { dst: u16, function: u16, start: u16, count: u16 },
+    Ret { src: Operand },
+    Throw { src: Operand },
+    /// 参数的存放方式与 Call 相同，print 不写入 dst
+    Syscall { dst: u16, syscall: u16, start: u16, argc: u16 },
+    Exit,
+}
+
+/// 异常处理表项：[start, end) 范围内的指令出错时，错误值写入 error_register 并跳转到 target
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterHandler {
+    pub start: u32,
+    pub end: u32,
+    pub target: u32,
+    pub error_register: u16,
+}
+
+/// 翻译后的函数
+#[derive(Debug, Clone)]
+pub struct RegisterFunction {
+    pub name: String,
+    pub param_count: u8,
+    /// 局部变量和临时寄存器的总数
+    pub register_count: u16,
+    pub code: Vec<RegInstr>,
+    pub handlers: Vec<RegisterHandler>,
+}
+
+// 翻译过程中的状态
+struct Translator<'a> {
+    functions: &'a [FunctionInfo],
+    structs: &'a [StructInfo],
+    enums: &'a [EnumInfo],
+    local_count: u16,
+    code: Vec<RegInstr>,
+    // 当前的操作数栈，None 表示不可达
+    stack: Option<Vec<Operand>>,
+    // 最后一条指令的结果寄存器可以改为局部变量（中间没有跳转目标）
+    retargetable: bool,
+}
+
+/// 把一个函数的栈式字节码翻译为寄存器指令
+pub(crate) fn translate_function(function: &FunctionInfo, functions: &[FunctionInfo], structs: &[StructInfo], enums: &[EnumInfo]) -> RegisterFunction {
+    let instructions: Vec<(OpCode, u64)> = function.bytecode.chunks_exact(OPLEN)
+        .map(|chunk| (OpCode::from_byte(chunk[0]), u64::from_le_bytes(chunk[OP_ARGOFF..OPLEN].try_into().unwrap())))
+        .collect();
+    let mut translator = Translator {
+        functions,
+        structs,
+        enums,
+        local_count: function.local_count as u16,
+        code: Vec::new(),
+        stack: Some(Vec::new()),
+        retargetable: false,
+    };
+    let depths = translator.stack_depths(&instructions, function);
+
+    let mut labels = vec![false; instructions.len() + 1];
+    for (index, (opcode, arg)) in instructions.iter().enumerate() {
+        if let Some(target) = jump_target(index, *opcode, *arg) {
+            labels[target] = true;
+        }
+    }
+    for handler in &function.handlers {
+        labels[handler.target as usize / OPLEN] = true;
+    }
+    let handler_starts: Vec<usize> = function.handlers.iter().map(|handler| handler.start as usize / OPLEN).collect();
+
+    // 每条栈式指令对应的第一条寄存器指令，用于调整跳转目标和异常处理表
+    let mut positions = Vec::with_capacity(instructions.len() + 1);
+    for (index, (opcode, arg)) in instructions.iter().enumerate() {
+        if labels[index] {
+            // 跳转目标处的操作数必须都在各自的临时寄存器中
+            translator.flush();
+            translator.stack = depths[index].map(|depth| translator.canonical(depth));
+            translator.retargetable = false;
+        } else if handler_starts.contains(&index) {
+            // 处理程序恢复的栈深度以下的操作数在 try 开始前放入临时寄存器
+            translator.flush();
+        }
+        positions.push(translator.code.len() as u32);
+        if translator.stack.is_some() {
+            translator.translate(index, *opcode, *arg);
+        }
+    }
+    positions.push(translator.code.len() as u32);
+
+    for instruction in &mut translator.code {
+        if let RegInstr::Jmp { target } | RegInstr::Jz { target, .. } = instruction {
+            *target = positions[*target as usize];
+        }
+    }
+    let handlers: Vec<RegisterHandler> = function.handlers.iter()
+        .map(|handler| RegisterHandler {
+            start: positions[handler.start as usize / OPLEN],
+            end: positions[handler.end as usize / OPLEN],
+            target: positions[handler.target as usize / OPLEN],
+            error_register: translator.local_count + handler.stack_depth,
+        })
+        .collect();
+    // 读取的寄存器都由某条指令写入，寄存器数由写入的寄存器决定
+    let register_count = translator.code.iter_mut()
+        .filter_map(|instruction| result_register(instruction).map(|dst| *dst + 1))
+        .chain(handlers.iter().map(|handler| handler.error_register + 1))
+        .fold(translator.local_count, u16::max);
+    RegisterFunction {
+        name: function.name.clone(),
+        param_count: function.param_count,
+        register_count,
+        code: translator.code,
+        handlers,
+    }
+}
+
+// 跳转指令的目标（栈式指令编号）
+fn jump_target(index: usize, opcode: OpCode, arg: u64) -> Option<usize> {
+    matches!(opcode, OpCode::Jmp | OpCode::Jz | OpCode::CmpLtJz)
+        .then(|| (index as isize + arg as i16 as isize / OPLEN as isize) as usize)
+}
+
+impl Translator<'_> {
+    // 各条指令执行前的栈深度，不可达的指令为 None
+    fn stack_depths(&self, instructions: &[(OpCode, u64)], function: &FunctionInfo) -> Vec<Option<usize>> {
+        let mut depths = vec![None; instructions.len() + 1];
+        let mut worklist = vec![(0, 0)];
+        worklist.extend(function.handlers.iter().map(|handler| (handler.target as usize / OPLEN, handler.stack_depth as usize + 1)));
+        while let Some((index, depth)) = worklist.pop() {
+            if index >= instructions.len() || depths[index].is_some() {
+                continue;
+            }
+            depths[index] = Some(depth);
+            let (opcode, arg) = instructions[index];
+            let next = (depth as i32 + self.stack_effect(opcode, arg)) as usize;
+            if let Some(target) = jump_target(index, opcode, arg) {
+                worklist.push((target, next));
+            }
+            if !matches!(opcode, OpCode::Jmp | OpCode::Ret | OpCode::Throw | OpCode::Exit) {
+                worklist.push((index + 1, next));
+            }
+        }
+        depths
+    }
+
+    fn stack_effect(&self, opcode: OpCode, arg: u64) -> i32 {
+        match opcode {
+            OpCode::LoadConst | OpCode::LoadGlobal | OpCode::LoadLocal | OpCode::LoadUpvalue | OpCode::Dup | OpCode::IsVariant => 1,
+            OpCode::StoreGlobal | OpCode::StoreLocal | OpCode::Pop | OpCode::SetField | OpCode::Jz | OpCode::Ret | OpCode::Throw => -1,
+            OpCode::StoreCell | OpCode::CmpLtJz => -2,
+            OpCode::LoadLocal2 => 2,
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Index |
+            OpCode::CmpEq | OpCode::CmpNe | OpCode::CmpLt | OpCode::CmpGt | OpCode::CmpLe | OpCode::CmpGe => -1,
+            OpCode::NewArray | OpCode::Concat => 1 - arg as u16 as i32,
+            OpCode::NewStruct => 1 - self.structs[arg as u16 as usize].fields.len() as i32,
+            OpCode::NewEnum => 1 - self.payload_count(arg) as i32,
+            OpCode::Call => 1 - self.functions[arg as u16 as usize].param_count as i32,
+            OpCode::CallIndirect => -(arg as u16 as i32),
+            OpCode::MakeClosure => 1 - (arg >> 16) as u16 as i32,
+            OpCode::Syscall => {
+                let argc = (arg >> 16) as u16 as i32;
+                if matches!(SYSCALL::from(arg as u16), SYSCALL::PRINT) { -argc } else { 1 - argc }
+            },
+            _ => 0,
+        }
+    }
+
+    fn payload_count(&self, arg: u64) -> usize {
+        self.enums[arg as u16 as usize].variants[(arg >> 16) as u16 as usize].payload_types.len()
+    }
+
+    // 栈深度为 depth 的操作数所在的临时寄存器
+    fn temp(&self, depth: usize) -> u16 {
+        self.local_count + depth as u16
+    }
+
+    fn canonical(&self, depth: usize) -> Vec<Operand> {
+        (0..depth).map(|depth| Operand::Reg(self.temp(depth))).collect()
+    }
+
+    fn stack(&mut self) -> &mut Vec<Operand> {
+        self.stack.as_mut().expect("translating unreachable code")
+    }
+
+    fn depth(&self) -> usize {
+        self.stack.as_ref().map_or(0, Vec::len)
+    }
+
+    fn emit(&mut self, instruction: RegInstr) {
+        self.code.push(instruction);
+        self.retargetable = true;
+    }
+
+    fn push(&mut self, operand: Operand) {
+        self.stack().push(operand);
+    }
+
+    // 压入结果：结果写入当前栈顶对应的临时寄存器
+    fn push_result(&mut self) -> u16 {
+        let dst = self.temp(self.depth());
+        self.push(Operand::Reg(dst));
+        dst
+    }
+
+    fn pop(&mut self) -> Operand {
+        self.stack().pop().expect("operand stack underflow")
+    }
+
+    // 弹出操作数并保证它在寄存器中（可以是局部变量）
+    fn pop_reg(&mut self) -> u16 {
+        match self.pop() {
+            Operand::Reg(register) => register,
+            operand => {
+                self.push(operand);
+                self.pop_temp()
+            },
+        }
+    }
+
+    // 弹出操作数并保证它在自己的临时寄存器中
+    fn pop_temp(&mut self) -> u16 {
+        let depth = self.depth() - 1;
+        self.flush_from(depth);
+        self.pop();
+        self.temp(depth)
+    }
+
+    // 把从 from 开始的操作数放入各自的临时寄存器
+    fn flush_from(&mut self, from: usize) {
+        let Some(stack) = &self.stack else {
+            return;
+        };
+        let moves: Vec<(u16, Operand)> = stack.iter().enumerate().skip(from)
+            .map(|(depth, operand)| (self.temp(depth), *operand))
+            .filter(|(dst, operand)| *operand != Operand::Reg(*dst))
+            .collect();
+        for (dst, src) in moves {
+            self.emit(RegInstr::Move { dst, src });
+        }
+        for depth in from..self.depth() {
+            self.stack()[depth] = Operand::Reg(self.temp(depth));
+        }
+    }
+
+    fn flush(&mut self) {
+        self.flush_from(0);
+    }
+
+    // 弹出栈顶的 count 个操作数，它们依次位于返回的寄存器开始的连续寄存器中
+    fn pop_args(&mut self, count: usize) -> u16 {
+        let start = self.depth() - count;
+        self.flush_from(start);
+        self.stack().truncate(start);
+        self.temp(start)
+    }
+
+    // 写入局部变量前，把栈上引用它的操作数复制到临时寄存器
+    fn detach_local(&mut self, local: u16) {
+        let Some(stack) = &self.stack else {
+            return;
+        };
+        let moves: Vec<usize> = stack.iter().enumerate()
+            .filter(|(_, operand)| **operand == Operand::Reg(local))
+            .map(|(depth, _)| depth)
+            .collect();
+        for depth in moves {
+            let dst = self.temp(depth);
+            self.emit(RegInstr::Move { dst, src: Operand::Reg(local) });
+            self.stack()[depth] = Operand::Reg(dst);
+        }
+    }
+
+    fn store_local(&mut self, local: u16, src: Operand) {
+        self.detach_local(local);
+        if src == Operand::Reg(local) {
+            return;
+        }
+        // 值是上一条指令刚算出的临时结果时，直接写入局部变量
+        if let Operand::Reg(temp) = src {
+            let referenced = self.stack.as_ref().is_some_and(|stack| stack.contains(&src));
+            if self.retargetable && temp >= self.local_count && !referenced {
+                if let Some(dst) = self.code.last_mut().and_then(result_register) {
+                    if *dst == temp {
+                        *dst = local;
+                        return;
+                    }
+                }
+            }
+        }
+        self.emit(RegInstr::Move { dst: local, src });
+    }
+
+    fn translate(&mut self, index: usize, opcode: OpCode, arg: u64) {
+        let low = arg as u16;
+        let high = (arg >> 16) as u16;
+        match opcode {
+            OpCode::LoadConst => self.push(Operand::Const(low)),
+            OpCode::LoadLocal => self.push(Operand::Reg(low)),
+            OpCode::LoadLocal2 => {
+                self.push(Operand::Reg(low));
+                self.push(Operand::Reg(high));
+            },
+            OpCode::StoreLocal => {
+                let src = self.pop();
+                self.store_local(low, src);
+            },
+            OpCode::LoadGlobal => {
+                let dst = self.push_result();
+                self.emit(RegInstr::LoadGlobal { dst, global: low });
+            },
+            OpCode::StoreGlobal => {
+                let src = self.pop();
+                self.emit(RegInstr::StoreGlobal { global: low, src });
+            },
+            OpCode::Pop => {
+                self.pop();
+            },
+            OpCode::Dup => {
+                let top = *self.stack().last().expect("operand stack underflow");
+                self.push(top);
+            },
+            OpCode::Swap => {
+                let (b, a) = (self.pop(), self.pop());
+                let depth = self.depth();
+                // 经过临时寄存器交换，避免两个操作数互相覆盖
+                let scratch = self.temp(depth + 2);
+                self.emit(RegInstr::Move { dst: scratch, src: b });
+                self.emit(RegInstr::Move { dst: self.temp(depth + 1), src: a });
+                self.emit(RegInstr::Move { dst: self.temp(depth), src: Operand::Reg(scratch) });
+                self.push(Operand::Reg(self.temp(depth)));
+                self.push(Operand::Reg(self.temp(depth + 1)));
+            },
+            OpCode::NewCell => {
+                let src = self.pop();
+                let dst = self.push_result();
+                self.emit(RegInstr::NewCell { dst, src });
+            },
+            OpCode::LoadUpvalue => {
+                let dst = self.push_result();
+                self.emit(RegInstr::LoadUpvalue { dst, upvalue: low });
+            },
+            OpCode::LoadCell => {
+                let cell = self.pop_reg();
+                let dst = self.push_result();
+                self.emit(RegInstr::LoadCell { dst, cell });
+            },
+            OpCode::StoreCell => {
+                let cell = self.pop_reg();
+                let src = self.pop();
+                self.emit(RegInstr::StoreCell { cell, src });
+            },
+            OpCode::Not | OpCode::Inc | OpCode::Dec | OpCode::Neg => {
+                let src = self.pop();
+                let dst = self.push_result();
+                self.emit(RegInstr::Unary { op: opcode, dst, src });
+            },
+            OpCode::IncLocal | OpCode::DecLocal => {
+                self.detach_local(low);
+                let op = if matches!(opcode, OpCode::IncLocal) { OpCode::Inc } else { OpCode::Dec };
+                self.emit(RegInstr::Unary { op, dst: low, src: Operand::Reg(low) });
+            },
+            OpCode::AddConstLocal => {
+                self.detach_local(low);
+                self.emit(RegInstr::Binary { op: OpCode::Add, dst: low, lhs: Operand::Reg(low), rhs: Operand::Const(high) });
+            },
+            OpCode::Cast => {
+                let target = Type::from_tag(low as u8).unwrap_or_else(|| panic!("Invalid cast target type: {}", low));
+                let src = self.pop();
+                let dst = self.push_result();
+                self.emit(RegInstr::Cast { dst, src, target });
+            },
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Index |
+            OpCode::CmpEq | OpCode::CmpNe | OpCode::CmpLt | OpCode::CmpGt | OpCode::CmpLe | OpCode::CmpGe => {
+                let (rhs, lhs) = (self.pop(), self.pop());
+                let dst = self.push_result();
+                self.emit(RegInstr::Binary { op: opcode, dst, lhs, rhs });
+            },
+            OpCode::NewArray | OpCode::Concat => {
+                let start = self.pop_args(low as usize);
+                let dst = self.push_result();
+                self.emit(if matches!(opcode, OpCode::NewArray) {
+                    RegInstr::NewArray { dst, start, count: low }
+                } else {
+                    RegInstr::Concat { dst, start, count: low }
+                });
+            },
+            OpCode::NewStruct => {
+                let start = self.pop_args(self.structs[low as usize].fields.len());
+                let dst = self.push_result();
+                self.emit(RegInstr::NewStruct { dst, struct_index: low, start });
+            },
+            OpCode::GetField => {
+                let src = self.pop_reg();
+                let dst = self.push_result();
+                self.emit(RegInstr::GetField { dst, src, field: low });
+            },
+            OpCode::SetField => {
+                let src = self.pop();
+                // 结构体是值类型，修改的是临时寄存器中的副本
+                let object = self.pop_temp();
+                self.push(Operand::Reg(object));
+                self.emit(RegInstr::SetField { object, field: low, src });
+            },
+            OpCode::NewEnum => {
+                let start = self.pop_args(self.payload_count(arg));
+                let dst = self.push_result();
+                self.emit(RegInstr::NewEnum { dst, enum_index: low, variant: high, start });
+            },
+            OpCode::IsVariant => {
+                let src = self.pop_reg();
+                self.push(Operand::Reg(src));
+                let dst = self.push_result();
+                self.emit(RegInstr::IsVariant { dst, src, enum_index: low, variant: high });
+            },
+            OpCode::Jmp => {
+                self.flush();
+                let target = jump_target(index, opcode, arg).unwrap() as u32;
+                self.code.push(RegInstr::Jmp { target });
+                self.stack = None;
+            },
+            OpCode::Jz => {
+                let cond = self.pop();
+                self.flush();
+                let target = jump_target(index, opcode, arg).unwrap() as u32;
+                self.code.push(RegInstr::Jz { cond, target });
+            },
+            OpCode::CmpLtJz => {
+                let (rhs, lhs) = (self.pop(), self.pop());
+                let dst = self.temp(self.depth());
+                self.emit(RegInstr::Binary { op: OpCode::CmpLt, dst, lhs, rhs });
+                self.flush();
+                let target = jump_target(index, opcode, arg).unwrap() as u32;
+                self.code.push(RegInstr::Jz { cond: Operand::Reg(dst), target });
+            },
+            OpCode::Call => {
+                let start = self.pop_args(self.functions[low as usize].param_count as usize);
+                let dst = self.push_result();
+                self.emit(RegInstr::Call { dst, function: low, start });
+            },
+            OpCode::CallIndirect => {
+                let callee = self.pop_reg();
+                let start = self.pop_args(low as usize);
+                let dst = self.push_result();
+                self.emit(RegInstr::CallIndirect { dst, callee, start, argc: low });
+            },
+            OpCode::MakeClosure => {
+                let start = self.pop_args(high as usize);
+                let dst = self.push_result();
+                self.emit(RegInstr::MakeClosure { dst, function: low, start, count: high });
+            },
+            OpCode::Ret | OpCode::Throw => {
+                let src = self.pop();
+                self.code.push(if matches!(opcode, OpCode::Ret) { RegInstr::Ret { src } } else { RegInstr::Throw { src } });
+                self.stack = None;
+            },
+            OpCode::Syscall => {
+                let start = self.pop_args(high as usize);
+                let dst = self.temp(self.depth());
+                if !matches!(SYSCALL::from(low), SYSCALL::PRINT) {
+                    self.push_result();
+                }
+                self.emit(RegInstr::Syscall { dst, syscall: low, start, argc: high });
+            },
+            OpCode::Exit => {
+                self.code.push(RegInstr::Exit);
+                self.stack = None;
+            },
+        }
+    }
+}
+
+// 指令写入的结果寄存器
+fn result_register(instruction: &mut RegInstr) -> Option<&mut u16> {
+    match instruction {
+        RegInstr::Move { dst, .. } | RegInstr::LoadGlobal { dst, .. } | RegInstr::NewCell { dst, .. } |
+        RegInstr::LoadUpvalue { dst, .. } | RegInstr::LoadCell { dst, .. } | RegInstr::Unary { dst, .. } |
+        RegInstr::Binary { dst, .. } | RegInstr::Cast { dst, .. } | RegInstr::NewArray { dst, .. } |
+        RegInstr::Concat { dst, .. } | RegInstr::NewStruct { dst, .. } | RegInstr::GetField { dst, .. } |
+        RegInstr::NewEnum { dst, .. } | RegInstr::IsVariant { dst, .. } | RegInstr::Call { dst, .. } |
+        RegInstr::CallIndirect { dst, .. } | RegInstr::MakeClosure { dst, .. } | RegInstr::Syscall { dst, .. } => Some(dst),
+        _ => None,
+    }
+}
+
@@ -0,0 +1,134 @@
+// 内置函数（不依赖 VM 状态的系统调用）
+use crate::{types::{Type, ENUM_RESULT}, vm::{cast_value, Value}, SYSCALL};
+
+impl SYSCALL {
+    /// 参数类型列表，None 表示可变参数
+    pub(crate) fn param_types(&self) -> Option<&'static [Type]> {
+        match self {
+            SYSCALL::PRINT => Some(&[Type::Any]),
+            SYSCALL::DKVCOMMAND | SYSCALL::TRYCOMMAND => Some(&[Type::String]),
+            SYSCALL::PARSEINT => Some(&[Type::String, Type::Int]),
+            SYSCALL::PARSEFLOAT => Some(&[Type::String, Type::Float]),
+            SYSCALL::LEN => Some(&[Type::Any]),
+            SYSCALL::SUBSTR => Some(&[Type::String, Type::Int, Type::Int]),
+            SYSCALL::FIND => Some(&[Type::String, Type::String]),
+            SYSCALL::SPLIT => Some(&[Type::String, Type::String]),
+            SYSCALL::JOIN => Some(&[Type::Array, Type::String]),
+            SYSCALL::TRIM | SYSCALL::UPPER | SYSCALL::LOWER => Some(&[Type::String]),
+            SYSCALL::STARTSWITH | SYSCALL::ENDSWITH => Some(&[Type::String, Type::String]),
+            SYSCALL::REPLACE => Some(&[Type::String, Type::String, Type::String]),
+            SYSCALL::FORMAT => None,
+        }
+    }
+
+    pub(crate) fn return_type(&self) -> Type {
+        match self {
+            SYSCALL::PRINT => Type::Nil,
+            SYSCALL::PARSEINT | SYSCALL::LEN | SYSCALL::FIND => Type::Int,
+            SYSCALL::PARSEFLOAT => Type::Float,
+            SYSCALL::STARTSWITH | SYSCALL::ENDSWITH => Type::Bool,
+            SYSCALL::SPLIT => Type::Array,
+            SYSCALL::TRYCOMMAND => Type::Enum(ENUM_RESULT),
+            _ => Type::String,
+        }
+    }
+}
+
+/// 执行内置函数，args 按源代码中的参数顺序排列。参数不合法时返回运行时错误
+pub(crate) fn call_native(syscall: SYSCALL, args: Vec<Value>) -> Result<Value, String> {
+    let value = match (syscall, args.as_slice()) {
+        // parse_int(s, default)：解析失败时返回 default
+        (SYSCALL::PARSEINT, [Value::String(text), default]) => match text.trim().parse::<i32>() {
+            Ok(value) => Value::Int(value),
+            Err(_) => default.clone(),
+        },
+        // parse_float(s, default)：解析失败时返回 default
+        (SYSCALL::PARSEFLOAT, [Value::String(text), default]) => match text.trim().parse::<f32>() {
+            Ok(value) => Value::Float(value),
+            Err(_) => cast_value(default, Type::Float)?,
+        },
+        (SYSCALL::LEN, [Value::String(s)]) => Value::Int(s.chars().count() as i32),
+        (SYSCALL::LEN, [Value::Array(items)]) => Value::Int(items.len() as i32),
+        // substr(s, start, count)：按字符计数，超出范围的部分被截断
+        (SYSCALL::SUBSTR, [Value::String(s), Value::Int(start), Value::Int(count)]) => {
+            let start = (*start).max(0) as usize;
+            let count = (*count).max(0) as usize;
+            Value::String(s.chars().skip(start).take(count).collect::<String>().into())
+        },
+        // find(s, sub)：返回首次出现的字符位置，未找到返回 -1
+        (SYSCALL::FIND, [Value::String(s), Value::String(sub)]) => match s.find(&**sub) {
+            Some(byte_index) => Value::Int(s[..byte_index].chars().count() as i32),
+            None => Value::Int(-1),
+        },
+        // split(s, sep)：sep 为空时按字符拆分
+        (SYSCALL::SPLIT, [Value::String(s), Value::String(sep)]) => {
+            let parts = if sep.is_empty() {
+                s.chars().map(|c| Value::String(c.to_string().into())).collect()
+            } else {
+                s.split(&**sep).map(|part| Value::String(part.into())).collect()
+            };
+            Value::Array(parts)
+        },
+        (SYSCALL::JOIN, [Value::Array(items), Value::String(sep)]) => {
+            let parts: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+            Value::String(parts.join(sep).into())
+        },
+        (SYSCALL::TRIM, [Value::String(s)]) => Value::String(s.trim().into()),
+        (SYSCALL::UPPER, [Value::String(s)]) => Value::String(s.to_uppercase().into()),
+        (SYSCALL::LOWER, [Value::String(s)]) => Value::String(s.to_lowercase().into()),
+        (SYSCALL::STARTSWITH, [Value::String(s), Value::String(prefix)]) => Value::Bool(s.starts_with(&**prefix)),
+        (SYSCALL::ENDSWITH, [Value::String(s), Value::String(suffix)]) => Value::Bool(s.ends_with(&**suffix)),
+        (SYSCALL::REPLACE, [Value::String(s), Value::String(from), Value::String(to)]) => {
+            Value::String(s.replace(&**from, to).into())
+        },
+        (SYSCALL::FORMAT, [Value::String(fmt), rest @ ..]) => Value::String(format_values(fmt, rest)?.into()),
+        _ => return Err(format!("Invalid arguments for syscall {:?}: {:?}", syscall, args)),
+    };
+    Ok(value)
+}
+
+/// 将 fmt 中的 `{}` 依次替换为参数，`{{` 和 `}}` 输出花括号本身
+// 格式字符串中 {} 占位符的个数，与 format_values 的解析一致：{{ 和 }} 是转义的花括号
+pub(crate) fn count_placeholders(fmt: &str) -> usize {
+    let mut count = 0;
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+            },
+            ('{', Some('}')) => {
+                chars.next();
+                count += 1;
+            },
+            _ => {},
+        }
+    }
+    count
+}
+
+fn format_values(fmt: &str, args: &[Value]) -> Result<String, String> {
+    let mut result = String::with_capacity(fmt.len());
+    let mut args = args.iter();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                result.push(c);
+            },
+            ('{', Some('}')) => {
+                chars.next();
+                match args.next() {
+                    Some(arg) => result.push_str(&arg.to_string()),
+                    None => return Err(format!("format: not enough arguments for \"{}\"", fmt)),
+                }
+            },
+            _ => result.push(c),
+        }
+    }
+    if args.next().is_some() {
+        return Err(format!("format: too many arguments for \"{}\"", fmt));
+    }
+    Ok(result)
+}
@@ -2,7 +2,7 @@
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     // 关键字
-    Let, Fn, If, Else, For, While, Return,
+    Let, Const, Fn, If, Else, For, While, Return, As, Struct, Enum, Match, Try, Catch, Finally, Throw, Import,
     // 类型
     Int, Float, Bool, String,
     // 运算符
@@ -11,33 +11,152 @@ pub enum TokenType {
     And, Or, Not,
     Increment, Decrement,
     // 括号
-    LParen, RParen, LBrace, RBrace,
+    LParen, RParen, LBrace, RBrace, LBracket, RBracket,
     // 分隔符
-    Semicolon, Comma, Colon,
+    Semicolon, Comma, Colon, Arrow, Dot, DoubleColon, FatArrow,
     // 字面量
     Identifier(String),
     IntLiteral(i32),
     FloatLiteral(f32),
     BoolLiteral(bool),
     StringLiteral(String),
+    // 插值字符串 f"..."
+    InterpolatedString(Vec<StringPart>),
     // 特殊标记
     Eof,
 }
 
+impl TokenType {
+    /// 用于错误信息的标记描述
+    pub fn describe(&self) -> String {
+        let text = match self {
+            TokenType::Let => "let",
+            TokenType::Const => "const",
+            TokenType::Fn => "fn",
+            TokenType::If => "if",
+            TokenType::Else => "else",
+            TokenType::For => "for",
+            TokenType::While => "while",
+            TokenType::Return => "return",
+            TokenType::As => "as",
+            TokenType::Struct => "struct",
+            TokenType::Enum => "enum",
+            TokenType::Match => "match",
+            TokenType::Try => "try",
+            TokenType::Catch => "catch",
+            TokenType::Finally => "finally",
+            TokenType::Throw => "throw",
+            TokenType::Import => "import",
+            TokenType::Int => "int",
+            TokenType::Float => "float",
+            TokenType::Bool => "bool",
+            TokenType::String => "string",
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Multiply => "*",
+            TokenType::Divide => "/",
+            TokenType::Equal => "=",
+            TokenType::Equals => "==",
+            TokenType::NotEquals => "!=",
+            TokenType::LessThan => "<",
+            TokenType::LessThanOrEqual => "<=",
+            TokenType::GreaterThan => ">",
+            TokenType::GreaterThanOrEqual => ">=",
+            TokenType::And => "&&",
+            TokenType::Or => "||",
+            TokenType::Not => "!",
+            TokenType::Increment => "++",
+            TokenType::Decrement => "--",
+            TokenType::LParen => "(",
+            TokenType::RParen => ")",
+            TokenType::LBrace => "{",
+            TokenType::RBrace => "}",
+            TokenType::LBracket => "[",
+            TokenType::RBracket => "]",
+            TokenType::Semicolon => ";",
+            TokenType::Comma => ",",
+            TokenType::Colon => ":",
+            TokenType::Arrow => "->",
+            TokenType::Dot => ".",
+            TokenType::DoubleColon => "::",
+            TokenType::FatArrow => "=>",
+            TokenType::Identifier(_) => return "identifier".to_string(),
+            TokenType::IntLiteral(_) => return "integer literal".to_string(),
+            TokenType::FloatLiteral(_) => return "float literal".to_string(),
+            TokenType::BoolLiteral(_) => return "boolean literal".to_string(),
+            TokenType::StringLiteral(_) => return "string literal".to_string(),
+            TokenType::InterpolatedString(_) => return "interpolated string".to_string(),
+            TokenType::Eof => return "end of file".to_string(),
+        };
+        format!("'{}'", text)
+    }
+}
+
+// 插值字符串的组成部分
+#[derive(Debug, PartialEq, Clone)]
+pub enum StringPart {
+    Literal(String),
+    // 花括号内的表达式源码及其起始位置
+    Expr(String, Position),
+}
+
+// 源码位置。offset 为字节偏移，line/column 从 1 开始，column 按字符计数（制表符按制表位展开）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub offset: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+// 源码区间 [start, end)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    /// 覆盖两个区间的最小区间
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
 // 标记结构
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
+    // 起始行列号，等同于 span.start
     pub line: u32,
     pub column: u32,
+    pub span: Span,
+    // 紧邻该标记之前的文档注释（///）
+    pub doc: Option<String>,
 }
 
 impl Token {
     pub fn new(token_type: TokenType, line: u32, column: u32) -> Self {
+        let position = Position { offset: 0, line, column };
         Token {
             token_type,
             line,
             column,
+            span: Span::new(position, position),
+            doc: None,
+        }
+    }
+
+    pub fn with_span(token_type: TokenType, span: Span) -> Self {
+        Token {
+            token_type,
+            line: span.start.line,
+            column: span.start.column,
+            span,
+            doc: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,185 @@
+// 静态类型（编译期类型检查使用）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    // 数组（元素类型不做检查）
+    Array,
+    // 函数值（参数和返回值类型不做检查，调用时在运行时检查参数个数）
+    Function,
+    // 用户定义的结构体，编号为结构体表中的索引
+    Struct(u16),
+    // 枚举，编号为枚举表中的索引（内置的 Option 和 Result 固定在最前面）
+    Enum(u16),
+    // catch 得到的错误值，字段 message（错误信息）和 trace（抛出时的调用栈）
+    Error,
+    // 无返回值（如 print）
+    Nil,
+    // 编译期无法确定的类型（如用户函数返回值），不做检查
+    Any,
+}
+
+// 类型标记，与二进制文件中的常量类型编号保持一致
+pub const TYPE_TAG_NIL: u8 = 0;
+pub const TYPE_TAG_INT: u8 = 1;
+pub const TYPE_TAG_FLOAT: u8 = 2;
+pub const TYPE_TAG_BOOL: u8 = 3;
+pub const TYPE_TAG_STRING: u8 = 4;
+pub const TYPE_TAG_ARRAY: u8 = 5;
+pub const TYPE_TAG_FUNCTION: u8 = 6;
+pub const TYPE_TAG_STRUCT: u8 = 7;
+pub const TYPE_TAG_ENUM: u8 = 8;
+pub const TYPE_TAG_ERROR: u8 = 9;
+
+// 内置枚举在枚举表中的索引和变体编号
+// enum Option { Some(any), None }
+pub const ENUM_OPTION: u16 = 0;
+pub const VARIANT_SOME: u16 = 0;
+pub const VARIANT_NONE: u16 = 1;
+// enum Result { Ok(any), Err(string) }
+pub const ENUM_RESULT: u16 = 1;
+pub const VARIANT_OK: u16 = 0;
+pub const VARIANT_ERR: u16 = 1;
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "int" => Some(Type::Int),
+            "float" => Some(Type::Float),
+            "bool" => Some(Type::Bool),
+            "string" => Some(Type::String),
+            "array" => Some(Type::Array),
+            "fn" => Some(Type::Function),
+            "error" => Some(Type::Error),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::String => "string",
+            Type::Array => "array",
+            Type::Function => "fn",
+            Type::Struct(_) => "struct",
+            Type::Enum(_) => "enum",
+            Type::Error => "error",
+            Type::Nil => "nil",
+            Type::Any => "any",
+        }
+    }
+
+    pub fn tag(&self) -> u8 {
+        match self {
+            Type::Int => TYPE_TAG_INT,
+            Type::Float => TYPE_TAG_FLOAT,
+            Type::Bool => TYPE_TAG_BOOL,
+            Type::String => TYPE_TAG_STRING,
+            Type::Array => TYPE_TAG_ARRAY,
+            Type::Function => TYPE_TAG_FUNCTION,
+            Type::Struct(_) => TYPE_TAG_STRUCT,
+            Type::Enum(_) => TYPE_TAG_ENUM,
+            Type::Error => TYPE_TAG_ERROR,
+            Type::Nil | Type::Any => TYPE_TAG_NIL,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Type> {
+        match tag {
+            TYPE_TAG_NIL => Some(Type::Nil),
+            TYPE_TAG_INT => Some(Type::Int),
+            TYPE_TAG_FLOAT => Some(Type::Float),
+            TYPE_TAG_BOOL => Some(Type::Bool),
+            TYPE_TAG_STRING => Some(Type::String),
+            TYPE_TAG_ARRAY => Some(Type::Array),
+            TYPE_TAG_FUNCTION => Some(Type::Function),
+            TYPE_TAG_ERROR => Some(Type::Error),
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float | Type::Any)
+    }
+
+    /// 赋值兼容性：相同类型、未知类型，或 int 隐式提升为 float
+    pub fn is_assignable_to(&self, target: Type) -> bool {
+        *self == target
+            || *self == Type::Any
+            || target == Type::Any
+            || (*self == Type::Int && target == Type::Float)
+    }
+
+    /// 赋值时是否需要插入隐式转换
+    pub fn needs_promotion_to(&self, target: Type) -> bool {
+        *self == Type::Int && target == Type::Float
+    }
+
+    /// 显式转换（`as` 或 `int(...)` 等）是否合法
+    pub fn can_cast_to(&self, target: Type) -> bool {
+        match (self, target) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (_, Type::String) => *self != Type::Nil,
+            (_, Type::Array) => *self == Type::Array,
+            (_, Type::Function) => *self == Type::Function,
+            (_, Type::Struct(_) | Type::Enum(_) | Type::Error) => *self == target,
+            (Type::Int | Type::Float | Type::String, Type::Int | Type::Float) => true,
+            (Type::Int | Type::Bool | Type::String, Type::Bool) => true,
+            (Type::Bool, Type::Int) => true,
+            _ => false,
+        }
+    }
+
+    /// 二元运算的结果类型。int 与 float 混合运算时提升为 float
+    pub fn binary_result(op: &str, left: Type, right: Type) -> Result<Type, String> {
+        let mismatch = || Err(format!(
+            "Invalid operand types for '{}': {} and {}", op, left.name(), right.name()));
+        match op {
+            "+" | "-" | "*" | "/" => {
+                if op == "+" && left == Type::String && right == Type::String {
+                    return Ok(Type::String);
+                }
+                match (left, right) {
+                    (Type::Int, Type::Int) => Ok(Type::Int),
+                    (Type::Float, Type::Int | Type::Float) | (Type::Int, Type::Float) => Ok(Type::Float),
+                    (Type::Any, _) | (_, Type::Any) => {
+                        if left.is_numeric() && right.is_numeric() || op == "+" {
+                            Ok(Type::Any)
+                        } else {
+                            mismatch()
+                        }
+                    },
+                    _ => mismatch(),
+                }
+            },
+            "==" | "!=" => {
+                if left.is_assignable_to(right) || right.is_assignable_to(left) {
+                    Ok(Type::Bool)
+                } else {
+                    mismatch()
+                }
+            },
+            "<" | ">" | "<=" | ">=" => {
+                // 数值比较，或字符串按字典序比较
+                let strings = matches!((left, right), (Type::String | Type::Any, Type::String | Type::Any));
+                if left.is_numeric() && right.is_numeric() || strings {
+                    Ok(Type::Bool)
+                } else {
+                    mismatch()
+                }
+            },
+            "&&" | "||" => {
+                if left.is_assignable_to(Type::Bool) && right.is_assignable_to(Type::Bool) {
+                    Ok(Type::Bool)
+                } else {
+                    mismatch()
+                }
+            },
+            _ => Err(format!("Unknown binary operator: {}", op)),
+        }
+    }
+}
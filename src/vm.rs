@@ -1,4 +1,14 @@
-use crate::{compiler::{CompileResult, Constant, FunctionInfo, OpCode}, SYSCALL};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::{compiler::{CompileResult, Constant, EnumInfo, FunctionInfo, OpCode, StructInfo, OPLEN, OP_ARGOFF}, register::{Operand, RegInstr, RegisterFunction}, stdlib, types::{Type, ENUM_RESULT, VARIANT_ERR, VARIANT_OK}, SYSCALL};
+
+type DkvCommandHandler = Box<dyn FnMut(&str) -> Result<String, String>>;
+type PrintHandler = Box<dyn FnMut(&str)>;
 
 // 运行时值类型
 #[derive(Debug, Clone)]
@@ -6,10 +16,160 @@ pub enum Value {
     Int(i32),
     Float(f32),
     Bool(bool),
-    String(String),
+    // 字符串不可变，复制时只增加引用计数
+    String(Rc<str>),
+    Array(Vec<Value>),
+    Function(Rc<Closure>),
+    // 结构体：类型信息和按定义顺序排列的字段值
+    Struct(Rc<StructInfo>, Vec<Value>),
+    // 枚举值：类型信息、变体编号和负载
+    Enum(Rc<EnumInfo>, u16, Vec<Value>),
+    // 错误值：throw 或运行时错误产生，由 catch 得到
+    Error(Rc<RuntimeError>),
+    // 被闭包捕获的变量，外层函数和闭包共享同一个单元（只出现在局部变量槽和闭包中）
+    Cell(Rc<RefCell<Value>>),
     Null,
 }
 
+// 函数值：函数索引和捕获的变量
+#[derive(Debug)]
+pub struct Closure {
+    func_index: u16,
+    upvalues: Vec<Rc<RefCell<Value>>>,
+}
+
+// 运行时错误：错误信息和抛出时的脚本调用栈（从内到外的函数名）
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub trace: Vec<String>,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        for function in &self.trace {
+            write!(f, "\n    at {}", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::String(x) => write!(f, "{}", x),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Value::Function(_) => write!(f, "<fn>"),
+            Value::Struct(struct_info, fields) => {
+                write!(f, "{} {{ ", struct_info.name)?;
+                for (i, (field, value)) in struct_info.fields.iter().zip(fields).enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field.name, value)?;
+                }
+                write!(f, " }}")
+            },
+            Value::Enum(enum_info, variant, payload) => {
+                write!(f, "{}::{}", enum_info.name, enum_info.variants[*variant as usize].name)?;
+                if !payload.is_empty() {
+                    write!(f, "(")?;
+                    for (i, value) in payload.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", value)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            },
+            Value::Error(error) => write!(f, "Error: {}", error.message),
+            Value::Cell(cell) => write!(f, "{}", cell.borrow()),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl From<&Constant> for Value {
+    fn from(constant: &Constant) -> Self {
+        match constant {
+            Constant::Nil => Value::Null,
+            Constant::Int(value) => Value::Int(*value),
+            Constant::Float(value) => Value::Float(*value),
+            Constant::Bool(value) => Value::Bool(*value),
+            Constant::String(value) => Value::String(value.as_str().into()),
+        }
+    }
+}
+
+// 编译期求值：与运行时使用相同的运算，保证 const 的值与运行时计算的结果一致
+pub(crate) fn eval_unary_constant(op: &str, a: &Constant) -> Result<Constant, String> {
+    let a = Value::from(a);
+    let result = match op {
+        "-" => neg_values(&a)?,
+        "!" => not_values(&a)?,
+        _ => return Err(format!("Unknown unary operator: {}", op)),
+    };
+    value_to_constant(result)
+}
+
+pub(crate) fn eval_binary_constant(op: &str, a: &Constant, b: &Constant) -> Result<Constant, String> {
+    let (a, b) = (Value::from(a), Value::from(b));
+    let result = match op {
+        "+" => add_values(&a, &b)?,
+        "-" => sub_values(&a, &b)?,
+        "*" => mul_values(&a, &b)?,
+        "/" => div_values(&a, &b)?,
+        "==" => Value::Bool(eq_values(&a, &b)),
+        "!=" => Value::Bool(ne_values(&a, &b)),
+        "<" => Value::Bool(lt_values(&a, &b)?),
+        "<=" => Value::Bool(le_values(&a, &b)?),
+        ">" => Value::Bool(gt_values(&a, &b)?),
+        ">=" => Value::Bool(ge_values(&a, &b)?),
+        _ => return Err(format!("Unknown binary operator: {}", op)),
+    };
+    value_to_constant(result)
+}
+
+pub(crate) fn eval_cast_constant(a: &Constant, target: Type) -> Result<Constant, String> {
+    value_to_constant(cast_value(&Value::from(a), target)?)
+}
+
+fn value_to_constant(value: Value) -> Result<Constant, String> {
+    match value {
+        Value::Null => Ok(Constant::Nil),
+        Value::Int(value) => Ok(Constant::Int(value)),
+        Value::Float(value) => Ok(Constant::Float(value)),
+        Value::Bool(value) => Ok(Constant::Bool(value)),
+        Value::String(value) => Ok(Constant::String(value.to_string())),
+        value => Err(format!("{:?} is not a constant value", value)),
+    }
+}
+
+// 数值提升：int 与 float 混合运算时，int 提升为 float
+#[inline]
+fn promote(a: &Value, b: &Value) -> Option<(f32, f32)> {
+    match (a, b) {
+        (Value::Int(x), Value::Float(y)) => Some((*x as f32, *y)),
+        (Value::Float(x), Value::Int(y)) => Some((*x, *y as f32)),
+        _ => None,
+    }
+}
+
 // 比较函数
 #[inline]
 fn eq_values(a: &Value, b: &Value) -> bool {
@@ -17,9 +177,21 @@ fn eq_values(a: &Value, b: &Value) -> bool {
         (Value::Int(x), Value::Int(y)) => x == y,
         (Value::Float(x), Value::Float(y)) => x == y,
         (Value::Bool(x), Value::Bool(y)) => x == y,
-        (Value::String(x), Value::String(y)) => x == y,
+        // 共享同一个字符串时不必逐字节比较
+        (Value::String(x), Value::String(y)) => Rc::ptr_eq(x, y) || x == y,
+        (Value::Array(x), Value::Array(y)) => x.len() == y.len() && x.iter().zip(y).all(|(a, b)| eq_values(a, b)),
+        (Value::Function(x), Value::Function(y)) => {
+            x.func_index == y.func_index && x.upvalues.iter().zip(&y.upvalues).all(|(a, b)| Rc::ptr_eq(a, b))
+        },
+        (Value::Struct(x_info, x), Value::Struct(y_info, y)) => {
+            Rc::ptr_eq(x_info, y_info) && x.iter().zip(y).all(|(a, b)| eq_values(a, b))
+        },
+        (Value::Enum(x_info, x_variant, x), Value::Enum(y_info, y_variant, y)) => {
+            Rc::ptr_eq(x_info, y_info) && x_variant == y_variant && x.iter().zip(y).all(|(a, b)| eq_values(a, b))
+        },
+        (Value::Error(x), Value::Error(y)) => Rc::ptr_eq(x, y),
         (Value::Null, Value::Null) => true,
-        _ => false,
+        _ => promote(a, b).is_some_and(|(x, y)| x == y),
     }
 }
 
@@ -29,167 +201,538 @@ fn ne_values(a: &Value, b: &Value) -> bool {
 }
 
 #[inline]
-fn lt_values(a: &Value, b: &Value) -> bool {
+fn lt_values(a: &Value, b: &Value) -> Result<bool, String> {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => x < y,
-        (Value::Float(x), Value::Float(y)) => x < y,
-        _ => panic!("Invalid types {:?} and {:?} for less than comparison", a, b),
+        (Value::Int(x), Value::Int(y)) => Ok(x < y),
+        (Value::Float(x), Value::Float(y)) => Ok(x < y),
+        (Value::String(x), Value::String(y)) => Ok(x < y),
+        _ => match promote(a, b) {
+            Some((x, y)) => Ok(x < y),
+            None => Err(format!("Invalid types {:?} and {:?} for less than comparison", a, b)),
+        },
     }
 }
 
 #[inline]
-fn le_values(a: &Value, b: &Value) -> bool {
-    lt_values(a, b) || eq_values(a, b)
+fn le_values(a: &Value, b: &Value) -> Result<bool, String> {
+    Ok(lt_values(a, b)? || eq_values(a, b))
 }
 
 #[inline]
-fn gt_values(a: &Value, b: &Value) -> bool {
+fn gt_values(a: &Value, b: &Value) -> Result<bool, String> {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => x > y,
-        (Value::Float(x), Value::Float(y)) => x > y,
-        _ => panic!("Invalid types for greater than comparison"),
+        (Value::Int(x), Value::Int(y)) => Ok(x > y),
+        (Value::Float(x), Value::Float(y)) => Ok(x > y),
+        (Value::String(x), Value::String(y)) => Ok(x > y),
+        _ => match promote(a, b) {
+            Some((x, y)) => Ok(x > y),
+            None => Err("Invalid types for greater than comparison".to_string()),
+        },
     }
 }
 
 #[inline]
-fn ge_values(a: &Value, b: &Value) -> bool {
-    gt_values(a, b) || eq_values(a, b)
+fn ge_values(a: &Value, b: &Value) -> Result<bool, String> {
+    Ok(gt_values(a, b)? || eq_values(a, b))
 }
 
-// 算术运算函数
+// 整数运算溢出时的错误信息
+const INTEGER_OVERFLOW: &str = "Integer overflow";
+
+// 算术运算函数，类型不匹配、除以零或整数溢出时返回运行时错误
 #[inline]
-fn add_values(a: &Value, b: &Value) -> Value {
+fn add_values(a: &Value, b: &Value) -> Result<Value, String> {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => Value::Int(x + y),
-        (Value::Float(x), Value::Float(y)) => Value::Float(x + y),
-        (Value::String(x), Value::String(y)) => Value::String(format!("{}{}", x, y)),
-        _ => panic!("Invalid types for addition"),
+        (Value::Int(x), Value::Int(y)) => x.checked_add(*y).map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x + y)),
+        (Value::String(x), Value::String(y)) => {
+            let mut result = String::with_capacity(x.len() + y.len());
+            result.push_str(x);
+            result.push_str(y);
+            Ok(Value::String(result.into()))
+        },
+        _ => match promote(a, b) {
+            Some((x, y)) => Ok(Value::Float(x + y)),
+            None => Err("Invalid types for addition".to_string()),
+        },
     }
 }
 
 #[inline]
-fn sub_values(a: &Value, b: &Value) -> Value {
+fn sub_values(a: &Value, b: &Value) -> Result<Value, String> {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => Value::Int(x - y),
-        (Value::Float(x), Value::Float(y)) => Value::Float(x - y),
-        _ => panic!("Invalid types for subtraction"),
+        (Value::Int(x), Value::Int(y)) => x.checked_sub(*y).map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x - y)),
+        _ => match promote(a, b) {
+            Some((x, y)) => Ok(Value::Float(x - y)),
+            None => Err("Invalid types for subtraction".to_string()),
+        },
     }
 }
 
 #[inline]
-fn inc_values(a: &Value) -> Value {
+fn inc_values(a: &Value) -> Result<Value, String> {
     match a {
-        Value::Int(x) => Value::Int(x + 1),
-        Value::Float(x) => Value::Float(x + 1.0),
-        _ => panic!("Invalid type for increment"),
+        Value::Int(x) => x.checked_add(1).map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        Value::Float(x) => Ok(Value::Float(x + 1.0)),
+        _ => Err("Invalid type for increment".to_string()),
     }
 }
 
 #[inline]
-fn not_values(a: &Value) -> Value {
+fn not_values(a: &Value) -> Result<Value, String> {
     match a {
-        Value::Bool(x) => Value::Bool(!x),
-        _ => panic!("Invalid type for not operation"),
+        Value::Bool(x) => Ok(Value::Bool(!x)),
+        _ => Err("Invalid type for not operation".to_string()),
     }
 }
 
 #[inline]
-fn neg_values(a: &Value) -> Value {
+fn neg_values(a: &Value) -> Result<Value, String> {
     match a {
-        Value::Int(x) => Value::Int(-x),
-        Value::Float(x) => Value::Float(-x),
-        _ => panic!("Invalid type for negation"),
+        Value::Int(x) => x.checked_neg().map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        Value::Float(x) => Ok(Value::Float(-x)),
+        _ => Err("Invalid type for negation".to_string()),
     }
 }
 
 #[inline]
-fn dec_values(a: &Value) -> Value {
+fn dec_values(a: &Value) -> Result<Value, String> {
     match a {
-        Value::Int(x) => Value::Int(x - 1),
-        Value::Float(x) => Value::Float(x - 1.0),
-        _ => panic!("Invalid type for decrement"),
+        Value::Int(x) => x.checked_sub(1).map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        Value::Float(x) => Ok(Value::Float(x - 1.0)),
+        _ => Err("Invalid type for decrement".to_string()),
     }
 }
 
 #[inline]
-fn mul_values(a: &Value, b: &Value) -> Value {
+fn mul_values(a: &Value, b: &Value) -> Result<Value, String> {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => Value::Int(x * y),
-        (Value::Float(x), Value::Float(y)) => Value::Float(x * y),
-        _ => panic!("Invalid types for multiplication"),
+        (Value::Int(x), Value::Int(y)) => x.checked_mul(*y).map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        (Value::Float(x), Value::Float(y)) => Ok(Value::Float(x * y)),
+        _ => match promote(a, b) {
+            Some((x, y)) => Ok(Value::Float(x * y)),
+            None => Err("Invalid types for multiplication".to_string()),
+        },
     }
 }
 
 #[inline]
-fn div_values(a: &Value, b: &Value) -> Value {
+fn div_values(a: &Value, b: &Value) -> Result<Value, String> {
     match (a, b) {
-        (Value::Int(x), Value::Int(y)) => {
-            if *y == 0 {
-                panic!("Division by zero");
+        (Value::Int(_), Value::Int(0)) => Err("Division by zero".to_string()),
+        (Value::Int(x), Value::Int(y)) => x.checked_div(*y).map(Value::Int).ok_or_else(|| INTEGER_OVERFLOW.to_string()),
+        _ => {
+            let (x, y) = match (a, b) {
+                (Value::Float(x), Value::Float(y)) => (*x, *y),
+                _ => match promote(a, b) {
+                    Some(pair) => pair,
+                    None => return Err("Invalid types for division".to_string()),
+                },
+            };
+            if y == 0.0 {
+                return Err("Division by zero".to_string());
             }
-            Value::Int(x / y)
+            Ok(Value::Float(x / y))
         },
-        (Value::Float(x), Value::Float(y)) => {
-            if *y == 0.0 {
-                panic!("Division by zero");
-            }
-            Value::Float(x / y)
+    }
+}
+
+// 下标访问：字符串按字符索引，返回单个字符组成的字符串
+fn index_value(target: &Value, index: &Value) -> Result<Value, String> {
+    let Value::Int(i) = index else {
+        return Err(format!("Index must be int, got {:?}", index));
+    };
+    let item = match target {
+        Value::String(s) if *i >= 0 => s.chars().nth(*i as usize).map(|c| Value::String(c.to_string().into())),
+        Value::Array(items) if *i >= 0 => items.get(*i as usize).cloned(),
+        Value::String(_) | Value::Array(_) => None,
+        _ => return Err(format!("Cannot index into {:?}", target)),
+    };
+    item.ok_or_else(|| format!("Index out of range: {}", i))
+}
+
+// 类型转换函数
+pub(crate) fn cast_value(a: &Value, target: Type) -> Result<Value, String> {
+    let value = match (a, target) {
+        (Value::Int(x), Type::Int) => Value::Int(*x),
+        (Value::Float(x), Type::Int) => Value::Int(*x as i32),
+        (Value::Bool(x), Type::Int) => Value::Int(*x as i32),
+        (Value::String(x), Type::Int) => match x.trim().parse::<i32>() {
+            Ok(value) => Value::Int(value),
+            Err(_) => return Err(format!("Cannot convert \"{}\" to int", x)),
+        },
+        (Value::Int(x), Type::Float) => Value::Float(*x as f32),
+        (Value::Float(x), Type::Float) => Value::Float(*x),
+        (Value::String(x), Type::Float) => match x.trim().parse::<f32>() {
+            Ok(value) => Value::Float(value),
+            Err(_) => return Err(format!("Cannot convert \"{}\" to float", x)),
         },
-        _ => panic!("Invalid types for division"),
+        (Value::Bool(x), Type::Bool) => Value::Bool(*x),
+        (Value::Int(x), Type::Bool) => Value::Bool(*x != 0),
+        (Value::String(x), Type::Bool) => match x.trim() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => return Err(format!("Cannot convert \"{}\" to bool", x)),
+        },
+        (_, Type::String) => Value::String(a.to_string().into()),
+        _ => return Err(format!("Cannot convert {:?} to {}", a, target.name())),
+    };
+    Ok(value)
+}
+
+// 预先解码的指令：VM::new 时把字节码解码一次，执行时不再解析操作数。跳转目标为指令编号
+#[derive(Debug, Clone, Copy)]
+enum Instruction {
+    LoadConst(u16),
+    LoadGlobal(u16),
+    StoreGlobal(u16),
+    LoadLocal(u16),
+    StoreLocal(u16),
+    Pop,
+    Dup,
+    Swap,
+    NewCell,
+    LoadUpvalue(u16),
+    LoadCell,
+    StoreCell,
+    Not,
+    Inc,
+    Dec,
+    Neg,
+    Cast(Type),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    CmpEq,
+    CmpNe,
+    CmpLt,
+    CmpGt,
+    CmpLe,
+    CmpGe,
+    Index,
+    NewArray(u16),
+    Concat(u16),
+    NewStruct(u16),
+    GetField(u16),
+    SetField(u16),
+    // 枚举索引和变体编号
+    NewEnum(u16, u16),
+    IsVariant(u16, u16),
+    Jmp(u32),
+    Jz(u32),
+    Call(u16),
+    Ret,
+    // 函数索引和捕获的变量个数
+    MakeClosure(u16, u16),
+    CallIndirect(u16),
+    Throw,
+    IncLocal(u16),
+    DecLocal(u16),
+    // 局部索引和常量索引
+    AddConstLocal(u16, u16),
+    LoadLocal2(u16, u16),
+    CmpLtJz(u32),
+    // 系统调用和参数个数
+    Syscall(SYSCALL, u16),
+    Exit,
+}
+
+// 解码后的函数，异常处理表的位置也换算为指令编号
+#[derive(Debug)]
+struct DecodedFunction {
+    name: String,
+    param_count: u8,
+    local_count: u8,
+    code: Vec<Instruction>,
+    handlers: Vec<DecodedHandler>,
+}
+
+#[derive(Debug)]
+struct DecodedHandler {
+    start: usize,
+    end: usize,
+    target: usize,
+    stack_depth: usize,
+}
+
+impl DecodedFunction {
+    fn decode(function: FunctionInfo) -> Self {
+        let code = function.bytecode.chunks_exact(OPLEN).enumerate().map(|(index, chunk)| {
+            let arg = u64::from_le_bytes(chunk[OP_ARGOFF..OPLEN].try_into().unwrap());
+            let (low, high) = (arg as u16, (arg >> 16) as u16);
+            // 跳转偏移量相对于跳转指令本身，只使用低 16 位
+            let target = (index as isize + arg as i16 as isize / OPLEN as isize) as u32;
+            match OpCode::from_byte(chunk[0]) {
+                OpCode::LoadConst => Instruction::LoadConst(low),
+                OpCode::LoadGlobal => Instruction::LoadGlobal(low),
+                OpCode::StoreGlobal => Instruction::StoreGlobal(low),
+                OpCode::LoadLocal => Instruction::LoadLocal(low),
+                OpCode::StoreLocal => Instruction::StoreLocal(low),
+                OpCode::Pop => Instruction::Pop,
+                OpCode::Dup => Instruction::Dup,
+                OpCode::Swap => Instruction::Swap,
+                OpCode::NewCell => Instruction::NewCell,
+                OpCode::LoadUpvalue => Instruction::LoadUpvalue(low),
+                OpCode::LoadCell => Instruction::LoadCell,
+                OpCode::StoreCell => Instruction::StoreCell,
+                OpCode::Not => Instruction::Not,
+                OpCode::Inc => Instruction::Inc,
+                OpCode::Dec => Instruction::Dec,
+                OpCode::Neg => Instruction::Neg,
+                OpCode::Cast => match Type::from_tag(low as u8) {
+                    Some(target) => Instruction::Cast(target),
+                    None => panic!("Invalid cast target type: {}", low as u8),
+                },
+                OpCode::Add => Instruction::Add,
+                OpCode::Sub => Instruction::Sub,
+                OpCode::Mul => Instruction::Mul,
+                OpCode::Div => Instruction::Div,
+                OpCode::CmpEq => Instruction::CmpEq,
+                OpCode::CmpNe => Instruction::CmpNe,
+                OpCode::CmpLt => Instruction::CmpLt,
+                OpCode::CmpGt => Instruction::CmpGt,
+                OpCode::CmpLe => Instruction::CmpLe,
+                OpCode::CmpGe => Instruction::CmpGe,
+                OpCode::Index => Instruction::Index,
+                OpCode::NewArray => Instruction::NewArray(low),
+                OpCode::Concat => Instruction::Concat(low),
+                OpCode::NewStruct => Instruction::NewStruct(low),
+                OpCode::GetField => Instruction::GetField(low),
+                OpCode::SetField => Instruction::SetField(low),
+                OpCode::NewEnum => Instruction::NewEnum(low, high),
+                OpCode::IsVariant => Instruction::IsVariant(low, high),
+                OpCode::Jmp => Instruction::Jmp(target),
+                OpCode::Jz => Instruction::Jz(target),
+                OpCode::Call => Instruction::Call(low),
+                OpCode::Ret => Instruction::Ret,
+                OpCode::MakeClosure => Instruction::MakeClosure(low, high),
+                OpCode::CallIndirect => Instruction::CallIndirect(low),
+                OpCode::Throw => Instruction::Throw,
+                OpCode::IncLocal => Instruction::IncLocal(low),
+                OpCode::DecLocal => Instruction::DecLocal(low),
+                OpCode::AddConstLocal => Instruction::AddConstLocal(low, high),
+                OpCode::LoadLocal2 => Instruction::LoadLocal2(low, high),
+                OpCode::CmpLtJz => Instruction::CmpLtJz(target),
+                OpCode::Syscall => Instruction::Syscall(SYSCALL::from(low), high),
+                OpCode::Exit => Instruction::Exit,
+            }
+        }).collect();
+        let handlers = function.handlers.iter().map(|handler| DecodedHandler {
+            start: handler.start as usize / OPLEN,
+            end: handler.end as usize / OPLEN,
+            target: handler.target as usize / OPLEN,
+            stack_depth: handler.stack_depth as usize,
+        }).collect();
+        DecodedFunction {
+            name: function.name,
+            param_count: function.param_count,
+            local_count: function.local_count,
+            code,
+            handlers,
+        }
     }
 }
 
-pub struct VM {
+/// 编译后的程序：解码后的函数和类型信息等不可变的数据，可以通过 Arc 在多个 VM 之间共享
+#[derive(Debug)]
+pub struct Program {
     constants: Vec<Constant>,
+    // 各全局变量初始值在常量池中的索引
+    global_vars: Vec<Option<u16>>,
+    functions: Vec<DecodedFunction>,
+    // 寄存器后端的函数，为空时执行栈式字节码
+    register_functions: Vec<RegisterFunction>,
+    structs: Vec<StructInfo>,
+    enums: Vec<EnumInfo>,
+    entrypoint: u16,
+}
+
+impl Program {
+    /// 解码编译结果中的各函数
+    pub fn new(compile_result: CompileResult) -> Self {
+        Program {
+            constants: compile_result.constants,
+            global_vars: compile_result.global_vars.into_iter().map(|global_var| global_var.const_index).collect(),
+            functions: compile_result.functions.into_iter().map(DecodedFunction::decode).collect(),
+            register_functions: compile_result.register_functions,
+            structs: compile_result.structs,
+            enums: compile_result.enums,
+            entrypoint: compile_result.entrypoint,
+        }
+    }
+}
+
+/// 默认的处理函数类型：处理函数不要求 Send，VM 只能在创建它的线程中使用
+pub enum LocalHandlers {}
+
+/// 处理函数必须实现 Send，VM 可以转移到其他线程执行（见 SendVM）
+pub enum SendHandlers {}
+
+/// 处理函数都实现了 Send 的 VM，可以转移到其他线程执行
+pub type SendVM = VM<SendHandlers>;
+
+/// 虚拟机。类型参数表示处理函数是否要求 Send：默认的 VM<LocalHandlers> 不能跨线程转移，SendVM 可以
+pub struct VM<H = LocalHandlers> {
+    program: Arc<Program>,
+    // 常量池，字符串常量在各次运行之间共享
+    constants: Vec<Value>,
+    structs: Vec<Rc<StructInfo>>,
+    enums: Vec<Rc<EnumInfo>>,
     global_vars: Vec<Value>,
-    functions: Vec<FunctionInfo>,
     stack: Vec<Value>,
-    entrypoint: u16,
 
     pc: usize, // 程序计数器
     fp: usize, // 栈帧指针
+    // 当前执行的闭包，用于访问捕获的变量
+    closure: Option<Rc<Closure>>,
+    // 调用栈上各函数的索引，用于生成错误的调用栈
+    frames: Vec<u16>,
+    // 已执行的指令数
+    instruction_count: u64,
     
     // DKV command handler
-    dkv_command_handler: Option<Box<dyn FnMut(&str) -> Result<String, String>>>,
+    dkv_command_handler: Option<DkvCommandHandler>,
+    // print 输出处理函数，未设置时输出到标准输出
+    print_handler: Option<PrintHandler>,
+    // command() 失败时抛出异常，而不是返回 "Error: ..." 字符串
+    command_throws: bool,
+    handlers: PhantomData<H>,
 }
 
+// SAFETY: VM 中的 Rc（constants、structs、enums、global_vars、stack、closure 以及运行时值中的字符串、
+// 数组、结构体、闭包）都由这个 VM 自己创建，引用计数只在持有 VM 的线程中修改。转移 VM 时所有 Rc 的克隆一起转移，
+// 前提是下面的不变量成立：
+// - 没有 Rc 逃出 VM：公开方法只接收和返回 Arc<Program>、RuntimeError（只含 String）和基本类型，
+//   Value 只在 crate 内部可见；处理函数只得到 &str，返回的 String 复制到新的 Rc<str> 中
+// - 没有 Rc 从外部进入 VM：create 从 Program 中的 Constant、StructInfo、EnumInfo 的副本新建各个 Rc，
+//   Program 本身不含 Rc（由 Arc<Program> 要求的 Send + Sync 保证）
+// - 处理函数是 Send 的：SendVM 的 set_dkv_command_handler 和 set_print_handler 要求 F: Send
+// 新增返回 Value、Rc<StructInfo> 等的公开方法，或在 VM 之外保存运行时值（如 thread_local 缓存）都会破坏这些条件
+unsafe impl Send for VM<SendHandlers> {}
+
 impl VM {
     pub fn new(compile_result: CompileResult) -> Self {
+        VM::from_program(Arc::new(Program::new(compile_result)))
+    }
+
+    /// 创建执行共享程序的 VM，不需要重新解码
+    pub fn from_program(program: Arc<Program>) -> Self {
+        VM::create(program)
+    }
+
+    /// 设置DKV命令处理函数
+    pub fn set_dkv_command_handler<F>(&mut self, handler: Option<F>) 
+    where 
+        F: FnMut(&str) -> Result<String, String> + 'static,
+    {
+        self.dkv_command_handler = handler.map(|h| Box::new(h) as DkvCommandHandler);
+    }
+
+    /// 设置print输出处理函数
+    pub fn set_print_handler<F>(&mut self, handler: Option<F>)
+    where
+        F: FnMut(&str) + 'static,
+    {
+        self.print_handler = handler.map(|h| Box::new(h) as PrintHandler);
+    }
+}
+
+impl SendVM {
+    /// 创建可以转移到其他线程的 VM，多个线程中的 VM 可以共享同一个程序
+    pub fn from_program_send(program: Arc<Program>) -> Self {
+        VM::create(program)
+    }
+
+    /// 设置DKV命令处理函数，处理函数在执行 VM 的线程中调用
+    pub fn set_dkv_command_handler<F>(&mut self, handler: Option<F>)
+    where
+        F: FnMut(&str) -> Result<String, String> + Send + 'static,
+    {
+        self.dkv_command_handler = handler.map(|h| Box::new(h) as DkvCommandHandler);
+    }
+
+    /// 设置print输出处理函数，处理函数在执行 VM 的线程中调用
+    pub fn set_print_handler<F>(&mut self, handler: Option<F>)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.print_handler = handler.map(|h| Box::new(h) as PrintHandler);
+    }
+}
+
+impl<H> VM<H> {
+    fn create(program: Arc<Program>) -> Self {
         let mut vm = VM {
-            constants: compile_result.constants,
+            constants: program.constants.iter().map(Value::from).collect(),
+            structs: program.structs.iter().cloned().map(Rc::new).collect(),
+            enums: program.enums.iter().cloned().map(Rc::new).collect(),
+            program,
             global_vars: Vec::new(),
-            functions: compile_result.functions,
             stack: Vec::new(),
             pc: 0,
             fp: 0,
-            entrypoint: compile_result.entrypoint,
+            closure: None,
+            frames: Vec::new(),
+            instruction_count: 0,
             dkv_command_handler: None,
+            print_handler: None,
+            command_throws: false,
+            handlers: PhantomData,
         };
-
-        // 初始化全局变量
-        for global_var in compile_result.global_vars {
-            if let Some(const_index) = global_var.const_index {
-                let value = vm.get_constant(const_index);
-                vm.global_vars.push(value);
-            } else {
-                vm.global_vars.push(Value::Null);
-            }
-        }
+        vm.reset();
         vm
     }
-    
-    /// 设置DKV命令处理函数
-    pub fn set_dkv_command_handler<F>(&mut self, handler: Option<F>) 
-    where 
-        F: FnMut(&str) -> Result<String, String> + 'static,
-    {
-        self.dkv_command_handler = handler.map(|h| Box::new(h) as Box<dyn FnMut(&str) -> Result<String, String>>);
+
+    /// 执行的程序
+    pub fn program(&self) -> &Arc<Program> {
+        &self.program
+    }
+
+    /// 把全局变量恢复为初始值并清空栈，之后可以再次运行程序；处理函数等设置保持不变
+    pub fn reset(&mut self) {
+        let global_vars = self.program.global_vars.iter().map(|const_index| match const_index {
+            Some(const_index) => self.get_constant(*const_index),
+            None => Value::Null,
+        }).collect();
+        self.global_vars = global_vars;
+        self.stack.clear();
+        self.pc = 0;
+        self.fp = 0;
+        self.closure = None;
+        self.frames.clear();
+        self.instruction_count = 0;
     }
 
+    /// 设置 command() 失败时是否抛出异常（默认返回 "Error: ..." 字符串）
+    pub fn set_command_throws(&mut self, command_throws: bool) {
+        self.command_throws = command_throws;
+    }
+
+    /// 已执行的指令数，用于比较不同优化级别下 VM 的指令分派次数
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// 运行程序，未被 catch 的异常导致 panic
     pub fn run(&mut self) {
+        if let Err(error) = self.try_run() {
+            panic!("Uncaught exception: {}", error);
+        }
+    }
+
+    /// 运行程序，返回未被 catch 的异常
+    pub fn try_run(&mut self) -> Result<(), RuntimeError> {
         // 调用主函数
-        if self.entrypoint < self.functions.len() as u16 {
-            self.call_function(self.entrypoint);
+        let entrypoint = self.program.entrypoint;
+        if entrypoint < self.program.functions.len() as u16 {
+            let result = if self.program.register_functions.is_empty() {
+                self.call_function(entrypoint)
+            } else {
+                self.call_register_function(entrypoint, Vec::new(), None).map(|_| ())
+            };
+            result.map_err(|error| error.as_ref().clone())
         } else {
             panic!("Entry point function not found");
         }
@@ -197,286 +740,764 @@ impl VM {
 
     fn get_constant(&self, index: u16) -> Value {
         if index < self.constants.len() as u16 {
-            match &self.constants[index as usize] {
-                Constant::Nil => Value::Null,
-                Constant::Int(value) => Value::Int(*value),
-                Constant::Float(value) => Value::Float(*value),
-                Constant::Bool(value) => Value::Bool(*value),
-                Constant::String(value) => Value::String(value.clone()),
-            }
+            self.constants[index as usize].clone()
         } else {
             panic!("Constant index out of bounds: {}", index);
         }
     }
 
-    fn call_function(&mut self, func_index: u16) {
-        if func_index >= self.functions.len() as u16 {
+    fn call_function(&mut self, func_index: u16) -> Result<(), Rc<RuntimeError>> {
+        if func_index >= self.program.functions.len() as u16 {
             panic!("Function index out of bounds: {}", func_index);
         }
 
-        let func = &self.functions[func_index as usize];
-        println!("Calling function: {}", func.name);
+        let func = &self.program.functions[func_index as usize];
+        let (param_count, local_count) = (func.param_count as usize, func.local_count as usize);
         let old_fp: usize = self.fp;
         let return_addr = self.pc;
-        println!("Call function {} old_fp:{} return addr: {}", func.name, old_fp, return_addr);
 
         // 检查参数数量（栈上的参数数量必须大于等于参数数量）
         // stack为空时代表入口点函数，此时不必检查
-        if !self.stack.is_empty() && func.param_count as usize > self.stack.len() - old_fp - 2 {
-            panic!("Incorrect number of arguments for function {}: expected {}, got {}", func.name, func.param_count, self.stack.len() - old_fp - 2);
+        if !self.stack.is_empty() && param_count > self.stack.len() - old_fp - 2 {
+            panic!("Incorrect number of arguments for function {}: expected {}, got {}", func.name, param_count, self.stack.len() - old_fp - 2);
         }
-        let args = (0..func.param_count).into_iter().map(|_i| {
-            self.stack.pop().unwrap()
-        }).collect::<Vec<_>>();
 
-        // 保存返回地址和旧的帧指针
+        // 栈顶的参数是逆序压入的：在参数之前插入返回地址和旧的帧指针，再把参数恢复为正序
+        let args_start = self.stack.len() - param_count;
         self.stack.push(Value::Int(return_addr as i32));
         self.stack.push(Value::Int(old_fp as i32));
+        self.stack[args_start..].rotate_right(2);
+        self.stack[args_start + 2..].reverse();
 
-        // 设置新的帧指针
-        self.fp = self.stack.len() - 2;
-
-        // 设置程序计数器到函数的字节码开始位置
+        // 设置新的帧指针，程序计数器指向函数的第一条指令
+        self.fp = args_start;
         self.pc = 0;
 
-        // 压入参数
-        self.stack.extend(args.into_iter());
         // 准备局部变量
-        for _ in 0..func.local_count-func.param_count {
-            self.stack.push(Value::Null);
-        }
-    
+        self.stack.resize(self.fp + 2 + local_count, Value::Null);
+
         // 执行函数
-        self.execute_function(func_index);
-        println!("Call function return");
+        self.frames.push(func_index);
+        let result = self.execute_function(func_index);
+        self.frames.pop();
+        result
     }
 
-    fn execute_function(&mut self, func_index: u16) {
-        if func_index >= self.functions.len() as u16 {
+    fn execute_function(&mut self, func_index: u16) -> Result<(), Rc<RuntimeError>> {
+        let program = self.program.clone();
+        let Some(func) = program.functions.get(func_index as usize) else {
             panic!("Function index out of bounds: {}", func_index);
+        };
+        loop {
+            let Err(error) = self.execute_bytecode(&func.code) else {
+                return Ok(());
+            };
+            // 出错时 pc 指向出错指令的下一条，在异常处理表中查找覆盖该指令的处理程序
+            let pc = self.pc - 1;
+            let handler = func.handlers.iter().find(|handler| (handler.start..handler.end).contains(&pc));
+            match handler {
+                Some(handler) => {
+                    self.stack.truncate(self.fp + 2 + func.local_count as usize + handler.stack_depth);
+                    self.stack.push(Value::Error(error));
+                    self.pc = handler.target;
+                },
+                None => {
+                    // 没有处理程序：清理栈帧，交给调用者处理
+                    self.leave_frame();
+                    return Err(error);
+                },
+            }
         }
+    }
+
+    // 清理当前栈帧，恢复调用者的程序计数器和帧指针
+    fn leave_frame(&mut self) {
+        let Some(Value::Int(return_addr)) = self.stack.get(self.fp).cloned() else {
+            panic!("invalid return_addr. fp: {}", self.fp);
+        };
+        let Some(Value::Int(old_fp)) = self.stack.get(self.fp + 1).cloned() else {
+            panic!("invalid old_fp. fp: {}", self.fp);
+        };
+        self.stack.truncate(self.fp);
+        self.pc = return_addr as usize;
+        self.fp = old_fp as usize;
+    }
 
-        let func = self.functions[func_index as usize].clone();
-        self.execute_bytecode(&func.bytecode);
+    // 生成运行时错误，调用栈为当前正在执行的各函数
+    fn runtime_error(&self, message: String) -> Rc<RuntimeError> {
+        let trace = self.frames.iter().rev().map(|func_index| self.program.functions[*func_index as usize].name.clone()).collect();
+        Rc::new(RuntimeError { message, trace })
     }
 
-    fn execute_bytecode(&mut self, bytecode: &[u8]) {
-        while self.pc < bytecode.len() {
-            println!("Stack: {:?}", self.stack);
-            println!("PC: {}", self.pc);
-            let opcode = OpCode::from_byte(bytecode[self.pc]);
-            println!("Executing opcode: {:02x}", opcode);
+    // 执行预先解码的指令，self.pc 为下一条指令的编号
+    fn execute_bytecode(&mut self, code: &[Instruction]) -> Result<(), Rc<RuntimeError>> {
+        while let Some(&instruction) = code.get(self.pc) {
             self.pc += 1;
+            self.instruction_count += 1;
 
-            match opcode {
-                OpCode::LoadConst => {
-                    let const_index = self.read_u16(bytecode);
+            match instruction {
+                Instruction::LoadConst(const_index) => {
                     let value = self.get_constant(const_index);
                     self.stack.push(value);
                 },
-                OpCode::LoadGlobal => {
-                    let var_index = self.read_u16(bytecode);
-                    if var_index < self.global_vars.len() as u16 {
-                        let value = self.global_vars[var_index as usize].clone();
-                        self.stack.push(value);
-                    } else {
-                        panic!("Global variable index out of bounds: {}", var_index);
+                Instruction::Pop => {
+                    if self.stack.pop().is_none() {
+                        panic!("Stack underflow");
                     }
                 },
-                OpCode::StoreGlobal => {
-                    let var_index = self.read_u16(bytecode);
-                    if var_index < self.global_vars.len() as u16 {
-                        if let Some(value) = self.stack.pop() {
-                            self.global_vars[var_index as usize] = value;
-                        } else {
-                            panic!("Stack underflow");
-                        }
+                Instruction::Dup => {
+                    if let Some(value) = self.stack.last() {
+                        self.stack.push(value.clone());
                     } else {
+                        panic!("Stack underflow");
+                    }
+                },
+                Instruction::Swap => {
+                    let len = self.stack.len();
+                    if len < 2 {
+                        panic!("Stack underflow");
+                    }
+                    self.stack.swap(len - 1, len - 2);
+                },
+                Instruction::NewCell => {
+                    let Some(value) = self.stack.pop() else {
+                        panic!("Stack underflow");
+                    };
+                    self.stack.push(Value::Cell(Rc::new(RefCell::new(value))));
+                },
+                Instruction::LoadUpvalue(upvalue_index) => {
+                    let upvalue_index = upvalue_index as usize;
+                    let cell = match &self.closure {
+                        Some(closure) if upvalue_index < closure.upvalues.len() => closure.upvalues[upvalue_index].clone(),
+                        _ => panic!("Upvalue index out of bounds: {}", upvalue_index),
+                    };
+                    self.stack.push(Value::Cell(cell));
+                },
+                Instruction::LoadCell => {
+                    let Some(Value::Cell(cell)) = self.stack.pop() else {
+                        panic!("LoadCell applied to non-cell value");
+                    };
+                    let value = cell.borrow().clone();
+                    self.stack.push(value);
+                },
+                Instruction::StoreCell => {
+                    let (Some(Value::Cell(cell)), Some(value)) = (self.stack.pop(), self.stack.pop()) else {
+                        panic!("StoreCell applied to non-cell value");
+                    };
+                    *cell.borrow_mut() = value;
+                },
+                Instruction::LoadGlobal(var_index) => {
+                    let Some(value) = self.global_vars.get(var_index as usize) else {
                         panic!("Global variable index out of bounds: {}", var_index);
+                    };
+                    self.stack.push(value.clone());
+                },
+                Instruction::StoreGlobal(var_index) => {
+                    let Some(value) = self.stack.pop() else {
+                        panic!("Stack underflow");
+                    };
+                    match self.global_vars.get_mut(var_index as usize) {
+                        Some(slot) => *slot = value,
+                        None => panic!("Global variable index out of bounds: {}", var_index),
+                    }
+                },
+                Instruction::LoadLocal(local_index) => {
+                    let stack_index = self.local_slot(local_index);
+                    self.stack.push(self.stack[stack_index].clone());
+                },
+                Instruction::StoreLocal(local_index) => {
+                    let stack_index = self.local_slot(local_index);
+                    let Some(value) = self.stack.pop() else {
+                        panic!("Stack underflow");
+                    };
+                    self.stack[stack_index] = value;
+                },
+                Instruction::IncLocal(local_index) => {
+                    let stack_index = self.local_slot(local_index);
+                    let value = inc_values(&self.stack[stack_index]).map_err(|message| self.runtime_error(message))?;
+                    self.stack[stack_index] = value;
+                },
+                Instruction::DecLocal(local_index) => {
+                    let stack_index = self.local_slot(local_index);
+                    let value = dec_values(&self.stack[stack_index]).map_err(|message| self.runtime_error(message))?;
+                    self.stack[stack_index] = value;
+                },
+                Instruction::AddConstLocal(local_index, const_index) => {
+                    let stack_index = self.local_slot(local_index);
+                    let value = add_values(&self.stack[stack_index], &self.constants[const_index as usize])
+                        .map_err(|message| self.runtime_error(message))?;
+                    self.stack[stack_index] = value;
+                },
+                Instruction::LoadLocal2(first, second) => {
+                    let (first, second) = (self.local_slot(first), self.local_slot(second));
+                    self.stack.push(self.stack[first].clone());
+                    self.stack.push(self.stack[second].clone());
+                },
+                Instruction::CmpLtJz(target) => {
+                    let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) else {
+                        panic!("Stack underflow in comparison operation");
+                    };
+                    if !lt_values(&a, &b).map_err(|message| self.runtime_error(message))? {
+                        self.pc = target as usize;
                     }
                 },
-                OpCode::LoadLocal => {
-                    let local_index = self.read_u16(bytecode);
-                    println!("Load local {} fp:{}", local_index, self.fp);
-                    let stack_index = self.fp + 2 + local_index as usize;
-                    if stack_index < self.stack.len() {
-                        let value = self.stack[stack_index].clone();
+                Instruction::Add => self.binary_operation(add_values)?,
+                Instruction::Sub => self.binary_operation(sub_values)?,
+                Instruction::Mul => self.binary_operation(mul_values)?,
+                Instruction::Div => self.binary_operation(div_values)?,
+                Instruction::Not => self.unary_operation(not_values)?,
+                Instruction::Inc => self.unary_operation(inc_values)?,
+                Instruction::Dec => self.unary_operation(dec_values)?,
+                Instruction::Neg => self.unary_operation(neg_values)?,
+                Instruction::Cast(target) => {
+                    if let Some(a) = self.stack.pop() {
+                        let value = cast_value(&a, target).map_err(|message| self.runtime_error(message))?;
                         self.stack.push(value);
                     } else {
-                        panic!("Local variable index out of bounds: {}", local_index);
+                        panic!("Stack underflow in cast");
                     }
                 },
-                OpCode::StoreLocal => {
-                    let local_index = self.read_u16(bytecode);
-                    println!("Store local {} fp:{}", local_index, self.fp);
-                    let stack_index = self.fp + 2 + local_index as usize;
-                    if stack_index < self.stack.len() {
-                        if let Some(value) = self.stack.pop() {
-                            self.stack[stack_index] = value;
-                        } else {
-                            panic!("Stack underflow");
-                        }
-                    } else {
-                        panic!("Local variable index out of bounds: {}", local_index);
+                Instruction::CmpEq => self.comparison_operation(|a, b| Ok(eq_values(a, b)))?,
+                Instruction::CmpNe => self.comparison_operation(|a, b| Ok(ne_values(a, b)))?,
+                Instruction::CmpLt => self.comparison_operation(lt_values)?,
+                Instruction::CmpLe => self.comparison_operation(le_values)?,
+                Instruction::CmpGt => self.comparison_operation(gt_values)?,
+                Instruction::CmpGe => self.comparison_operation(ge_values)?,
+                Instruction::Index => self.binary_operation(index_value)?,
+                Instruction::NewArray(count) => {
+                    let count = count as usize;
+                    if count > self.stack.len() {
+                        panic!("Stack underflow in NewArray");
                     }
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::Array(items));
                 },
-                OpCode::Add => self.binary_operation(add_values),
-                OpCode::Sub => self.binary_operation(sub_values),
-                OpCode::Mul => self.binary_operation(mul_values),
-                OpCode::Div => self.binary_operation(div_values),
-                OpCode::Not => self.unary_operation(not_values),
-                OpCode::Inc => self.unary_operation(inc_values),
-                OpCode::Dec => self.unary_operation(dec_values),
-                OpCode::Neg => self.unary_operation(neg_values),
-                OpCode::CmpEq => self.comparison_operation(eq_values),
-                OpCode::CmpNe => self.comparison_operation(ne_values),
-                OpCode::CmpLt => self.comparison_operation(lt_values),
-                OpCode::CmpLe => self.comparison_operation(le_values),
-                OpCode::CmpGt => self.comparison_operation(gt_values),
-                OpCode::CmpGe => self.comparison_operation(ge_values),
-                OpCode::Jmp => {
-                    let offset = self.read_i16(bytecode) as isize;
-                    self.pc = (self.pc as isize -1 + offset) as usize;
-                    continue;
+                Instruction::NewStruct(struct_index) => {
+                    let struct_info = self.structs[struct_index as usize].clone();
+                    let count = struct_info.fields.len();
+                    if count > self.stack.len() {
+                        panic!("Stack underflow in NewStruct");
+                    }
+                    let fields = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::Struct(struct_info, fields));
+                },
+                Instruction::GetField(field_index) => {
+                    let field_index = field_index as usize;
+                    match self.stack.pop() {
+                        Some(Value::Struct(_, mut fields)) => self.stack.push(fields.swap_remove(field_index)),
+                        // 枚举的负载按位置访问
+                        Some(Value::Enum(_, _, mut payload)) => self.stack.push(payload.swap_remove(field_index)),
+                        // 错误值的字段：0 为错误信息，1 为调用栈
+                        Some(Value::Error(error)) => self.stack.push(match field_index {
+                            0 => Value::String(error.message.as_str().into()),
+                            _ => Value::Array(error.trace.iter().map(|function| Value::String(function.as_str().into())).collect()),
+                        }),
+                        Some(value) => return Err(self.runtime_error(format!("Cannot access field of {:?}", value))),
+                        None => panic!("Stack underflow in GetField"),
+                    }
+                },
+                Instruction::SetField(field_index) => {
+                    // 栈顶为新值，其下为结构体，写入后压回修改后的结构体
+                    let value = self.stack.pop().expect("Stack underflow in SetField");
+                    match self.stack.last_mut() {
+                        Some(Value::Struct(_, fields)) => fields[field_index as usize] = value,
+                        Some(value) => panic!("Cannot assign field of {:?}", value),
+                        None => panic!("Stack underflow in SetField"),
+                    }
+                },
+                Instruction::NewEnum(enum_index, variant) => {
+                    let enum_info = self.enums[enum_index as usize].clone();
+                    let count = enum_info.variants[variant as usize].payload_types.len();
+                    if count > self.stack.len() {
+                        panic!("Stack underflow in NewEnum");
+                    }
+                    let payload = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::Enum(enum_info, variant, payload));
+                },
+                Instruction::IsVariant(enum_index, variant) => {
+                    // 保留栈顶的枚举值，压入它是否为指定变体
+                    let enum_index = enum_index as usize;
+                    let result = match self.stack.last() {
+                        Some(Value::Enum(enum_info, value_variant, _)) if Rc::ptr_eq(enum_info, &self.enums[enum_index]) => {
+                            *value_variant == variant
+                        },
+                        Some(value) => {
+                            return Err(self.runtime_error(format!("Cannot match {:?} against enum {}", value, self.enums[enum_index].name)));
+                        },
+                        None => panic!("Stack underflow in IsVariant"),
+                    };
+                    self.stack.push(Value::Bool(result));
                 },
-                OpCode::Jz => {
-                    let offset = self.read_i16(bytecode) as isize;
-                    if let Some(Value::Bool(x)) = self.stack.pop() {
-                        if x {
-                            // do nothing
-                        } else {
-                            self.pc = (self.pc as isize -1 + offset) as usize;
-                            continue;
+                Instruction::Concat(count) => {
+                    // 一次性拼接 n 个值，非字符串值自动转换
+                    let count = count as usize;
+                    if count > self.stack.len() {
+                        panic!("Stack underflow in Concat");
+                    }
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let capacity = parts.iter().map(|part| match part {
+                        Value::String(s) => s.len(),
+                        _ => 8,
+                    }).sum();
+                    let mut result = String::with_capacity(capacity);
+                    for part in &parts {
+                        match part {
+                            Value::String(s) => result.push_str(s),
+                            _ => {
+                                let _ = write!(result, "{}", part);
+                            },
                         }
-                    } else {
+                    }
+                    self.stack.push(Value::String(result.into()));
+                },
+                Instruction::Jmp(target) => self.pc = target as usize,
+                Instruction::Jz(target) => {
+                    let Some(Value::Bool(x)) = self.stack.pop() else {
                         panic!("Jz operator applied to non-bool value");
+                    };
+                    if !x {
+                        self.pc = target as usize;
                     }
                 },
-                OpCode::Call => {
-                    let func_index = self.read_u16(bytecode);
-                    self.pc -= 1;
-                    self.call_function(func_index);
-                    self.pc += 1;
+                Instruction::Call(func_index) => {
+                    let outer_closure = self.closure.take();
+                    let result = self.call_function(func_index);
+                    self.closure = outer_closure;
+                    result?;
                 },
-                OpCode::Ret => {
-                    // 弹出返回值
-                    let Some(rv) = self.stack.pop() else {
-                        panic!("ret: return value missing in stack")
+                Instruction::MakeClosure(func_index, count) => {
+                    // 栈上依次为捕获的各变量的单元
+                    let count = count as usize;
+                    if count > self.stack.len() {
+                        panic!("Stack underflow in MakeClosure");
+                    }
+                    let upvalues = self.stack.split_off(self.stack.len() - count).into_iter().map(|value| match value {
+                        Value::Cell(cell) => cell,
+                        _ => panic!("MakeClosure applied to non-cell value"),
+                    }).collect();
+                    self.stack.push(Value::Function(Rc::new(Closure { func_index, upvalues })));
+                },
+                Instruction::CallIndirect(argc) => {
+                    // 栈顶为函数值，其下为逆序压入的参数
+                    let closure = match self.stack.pop() {
+                        Some(Value::Function(closure)) => closure,
+                        Some(value) => return Err(self.runtime_error(format!("Cannot call a value of {:?}", value))),
+                        None => panic!("Stack underflow in CallIndirect"),
                     };
-                    // 保存程序计数器和帧指针
-                    let Some(Value::Int(return_addr)) = self.stack.get(self.fp).cloned() else {
-                        panic!("invalid return_addr. fp: {}", self.fp);
+                    let func = &self.program.functions[closure.func_index as usize];
+                    if func.param_count as u16 != argc {
+                        let message = format!("Function {} expects {} arguments, got {}", func.name, func.param_count, argc);
+                        return Err(self.runtime_error(message));
+                    }
+                    let func_index = closure.func_index;
+                    let outer_closure = self.closure.replace(closure);
+                    let result = self.call_function(func_index);
+                    self.closure = outer_closure;
+                    result?;
+                },
+                Instruction::Throw => {
+                    // 字符串作为错误信息生成新的错误值，错误值原样重新抛出（保留原来的调用栈）
+                    let error = match self.stack.pop() {
+                        Some(Value::Error(error)) => error,
+                        Some(Value::String(message)) => self.runtime_error(message.to_string()),
+                        Some(value) => self.runtime_error(value.to_string()),
+                        None => panic!("Stack underflow in Throw"),
                     };
-                    let Some(Value::Int(old_fp)) = self.stack.get(self.fp + 1).cloned() else {
-                        panic!("invalid old_fp. fp: {}", self.fp);
+                    return Err(error);
+                },
+                Instruction::Ret => {
+                    // 弹出返回值，清理栈帧并恢复调用者的程序计数器和帧指针
+                    let Some(rv) = self.stack.pop() else {
+                        panic!("ret: return value missing in stack")
                     };
-                    // 清理栈帧
-                    self.stack.truncate(self.fp);
+                    self.leave_frame();
                     // 压入返回值
                     self.stack.push(rv);
-                    // 恢复程序计数器和帧指针
-                    self.pc = return_addr as usize;
-                    self.fp = old_fp as usize;
-                    return;
-                },
-                OpCode::Syscall => {
-                    let syscall_id = self.read_u16(bytecode);
-                    match SYSCALL::from(syscall_id) {
-                        SYSCALL::PRINT => {
-                            if let Some(value) = self.stack.pop() {
-                                self.print_value(&value);
-                            } else {
-                                panic!("Stack underflow in syscall PRINT");
-                            }
+                    return Ok(());
+                },
+                Instruction::Syscall(syscall, argc) => {
+                    let argc = argc as usize;
+                    if argc > self.stack.len() {
+                        panic!("Stack underflow in syscall {:?}", syscall);
+                    }
+                    // 参数逆序入栈，按参数顺序弹出
+                    let args = (0..argc).map(|_| self.stack.pop().unwrap()).collect();
+                    if let Some(result) = self.syscall(syscall, args)? {
+                        self.stack.push(result);
+                    }
+                },
+                Instruction::Exit => {
+                    // 退出程序执行
+                    return Ok(());
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // 执行系统调用，两种后端共用。print 没有返回值
+    fn syscall(&mut self, syscall: SYSCALL, args: Vec<Value>) -> Result<Option<Value>, Rc<RuntimeError>> {
+        let result = match syscall {
+            SYSCALL::PRINT => {
+                let Some(value) = args.first() else {
+                    panic!("Stack underflow in syscall PRINT");
+                };
+                self.print_value(value);
+                return Ok(None);
+            },
+            SYSCALL::DKVCOMMAND => {
+                let Some(Value::String(command)) = args.first() else {
+                    panic!("Stack underflow or invalid value type in syscall DKVCOMMAND");
+                };
+                match self.run_command(command) {
+                    Ok(output) => Value::String(output.into()),
+                    Err(err) if self.command_throws => return Err(self.runtime_error(err)),
+                    Err(err) => Value::String(format!("Error: {}", err).into()),
+                }
+            },
+            SYSCALL::TRYCOMMAND => {
+                let Some(Value::String(command)) = args.first() else {
+                    panic!("Stack underflow or invalid value type in syscall TRYCOMMAND");
+                };
+                let (variant, payload) = match self.run_command(command) {
+                    Ok(output) => (VARIANT_OK, Value::String(output.into())),
+                    Err(err) => (VARIANT_ERR, Value::String(err.into())),
+                };
+                let result_info = self.enums[ENUM_RESULT as usize].clone();
+                Value::Enum(result_info, variant, vec![payload])
+            },
+            // 其余系统调用为内置函数
+            syscall => stdlib::call_native(syscall, args).map_err(|message| self.runtime_error(message))?,
+        };
+        Ok(Some(result))
+    }
+
+    // 调用寄存器后端的函数：在栈顶分配函数的寄存器窗口，前 param_count 个寄存器为参数
+    fn call_register_function(&mut self, func_index: u16, args: Vec<Value>, closure: Option<Rc<Closure>>) -> Result<Value, Rc<RuntimeError>> {
+        let program = self.program.clone();
+        let Some(function) = program.register_functions.get(func_index as usize) else {
+            panic!("Function index out of bounds: {}", func_index);
+        };
+        let old_fp = self.fp;
+        let outer_closure = std::mem::replace(&mut self.closure, closure);
+        self.fp = self.stack.len();
+        self.stack.extend(args);
+        self.stack.resize(self.fp + function.register_count as usize, Value::Null);
+
+        self.frames.push(func_index);
+        let result = self.execute_register_function(function);
+        self.frames.pop();
+
+        self.stack.truncate(self.fp);
+        self.fp = old_fp;
+        self.closure = outer_closure;
+        result
+    }
+
+    fn execute_register_function(&mut self, function: &RegisterFunction) -> Result<Value, Rc<RuntimeError>> {
+        let mut pc = 0;
+        loop {
+            let error = match self.execute_registers(function, &mut pc) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            // 出错时 pc 指向出错的指令
+            let handler = function.handlers.iter().find(|handler| (handler.start..handler.end).contains(&(pc as u32)));
+            match handler {
+                Some(handler) => {
+                    self.stack[self.fp + handler.error_register as usize] = Value::Error(error);
+                    pc = handler.target as usize;
+                },
+                None => return Err(error),
+            }
+        }
+    }
+
+    fn execute_registers(&mut self, function: &RegisterFunction, pc: &mut usize) -> Result<Value, Rc<RuntimeError>> {
+        while let Some(instruction) = function.code.get(*pc) {
+            self.instruction_count += 1;
+            match instruction {
+                RegInstr::Move { dst, src } => {
+                    let value = self.operand(*src).into_owned();
+                    self.set_register(*dst, value);
+                },
+                RegInstr::LoadGlobal { dst, global } => {
+                    let Some(value) = self.global_vars.get(*global as usize).cloned() else {
+                        panic!("Global variable index out of bounds: {}", global);
+                    };
+                    self.set_register(*dst, value);
+                },
+                RegInstr::StoreGlobal { global, src } => {
+                    let value = self.operand(*src).into_owned();
+                    match self.global_vars.get_mut(*global as usize) {
+                        Some(slot) => *slot = value,
+                        None => panic!("Global variable index out of bounds: {}", global),
+                    }
+                },
+                RegInstr::NewCell { dst, src } => {
+                    let value = self.operand(*src).into_owned();
+                    self.set_register(*dst, Value::Cell(Rc::new(RefCell::new(value))));
+                },
+                RegInstr::LoadUpvalue { dst, upvalue } => {
+                    let cell = match &self.closure {
+                        Some(closure) if (*upvalue as usize) < closure.upvalues.len() => closure.upvalues[*upvalue as usize].clone(),
+                        _ => panic!("Upvalue index out of bounds: {}", upvalue),
+                    };
+                    self.set_register(*dst, Value::Cell(cell));
+                },
+                RegInstr::LoadCell { dst, cell } => {
+                    let Value::Cell(cell) = self.register(*cell) else {
+                        panic!("LoadCell applied to non-cell value");
+                    };
+                    let value = cell.borrow().clone();
+                    self.set_register(*dst, value);
+                },
+                RegInstr::StoreCell { cell, src } => {
+                    let value = self.operand(*src).into_owned();
+                    let Value::Cell(cell) = self.register(*cell) else {
+                        panic!("StoreCell applied to non-cell value");
+                    };
+                    *cell.borrow_mut() = value;
+                },
+                RegInstr::Unary { op, dst, src } => {
+                    let operation = match op {
+                        OpCode::Not => not_values,
+                        OpCode::Inc => inc_values,
+                        OpCode::Dec => dec_values,
+                        OpCode::Neg => neg_values,
+                        _ => panic!("Invalid unary operation: {:?}", op),
+                    };
+                    let value = operation(&self.operand(*src)).map_err(|message| self.runtime_error(message))?;
+                    self.set_register(*dst, value);
+                },
+                RegInstr::Binary { op, dst, lhs, rhs } => {
+                    let operation: fn(&Value, &Value) -> Result<Value, String> = match op {
+                        OpCode::Add => add_values,
+                        OpCode::Sub => sub_values,
+                        OpCode::Mul => mul_values,
+                        OpCode::Div => div_values,
+                        OpCode::Index => index_value,
+                        OpCode::CmpEq => |a, b| Ok(Value::Bool(eq_values(a, b))),
+                        OpCode::CmpNe => |a, b| Ok(Value::Bool(ne_values(a, b))),
+                        OpCode::CmpLt => |a, b| lt_values(a, b).map(Value::Bool),
+                        OpCode::CmpLe => |a, b| le_values(a, b).map(Value::Bool),
+                        OpCode::CmpGt => |a, b| gt_values(a, b).map(Value::Bool),
+                        OpCode::CmpGe => |a, b| ge_values(a, b).map(Value::Bool),
+                        _ => panic!("Invalid binary operation: {:?}", op),
+                    };
+                    let value = operation(&self.operand(*lhs), &self.operand(*rhs)).map_err(|message| self.runtime_error(message))?;
+                    self.set_register(*dst, value);
+                },
+                RegInstr::Cast { dst, src, target } => {
+                    let value = cast_value(&self.operand(*src), *target).map_err(|message| self.runtime_error(message))?;
+                    self.set_register(*dst, value);
+                },
+                RegInstr::NewArray { dst, start, count } => {
+                    let items = self.take_registers(*start, *count as usize);
+                    self.set_register(*dst, Value::Array(items));
+                },
+                RegInstr::Concat { dst, start, count } => {
+                    let mut result = String::new();
+                    for part in &self.stack[self.fp + *start as usize..self.fp + (*start + *count) as usize] {
+                        match part {
+                            Value::String(s) => result.push_str(s),
+                            _ => {
+                                let _ = write!(result, "{}", part);
+                            },
+                        }
+                    }
+                    self.set_register(*dst, Value::String(result.into()));
+                },
+                RegInstr::NewStruct { dst, struct_index, start } => {
+                    let struct_info = self.structs[*struct_index as usize].clone();
+                    let fields = self.take_registers(*start, struct_info.fields.len());
+                    self.set_register(*dst, Value::Struct(struct_info, fields));
+                },
+                RegInstr::GetField { dst, src, field } => {
+                    let value = match self.register(*src) {
+                        Value::Struct(_, fields) => fields[*field as usize].clone(),
+                        Value::Enum(_, _, payload) => payload[*field as usize].clone(),
+                        Value::Error(error) => match field {
+                            0 => Value::String(error.message.as_str().into()),
+                            _ => Value::Array(error.trace.iter().cloned().map(|function| Value::String(function.into())).collect()),
+                        },
+                        value => return Err(self.runtime_error(format!("Cannot access field of {:?}", value))),
+                    };
+                    self.set_register(*dst, value);
+                },
+                RegInstr::SetField { object, field, src } => {
+                    let value = self.operand(*src).into_owned();
+                    match &mut self.stack[self.fp + *object as usize] {
+                        Value::Struct(_, fields) => fields[*field as usize] = value,
+                        value => panic!("Cannot assign field of {:?}", value),
+                    }
+                },
+                RegInstr::NewEnum { dst, enum_index, variant, start } => {
+                    let enum_info = self.enums[*enum_index as usize].clone();
+                    let payload = self.take_registers(*start, enum_info.variants[*variant as usize].payload_types.len());
+                    self.set_register(*dst, Value::Enum(enum_info, *variant, payload));
+                },
+                RegInstr::IsVariant { dst, src, enum_index, variant } => {
+                    let result = match self.register(*src) {
+                        Value::Enum(enum_info, value_variant, _) if Rc::ptr_eq(enum_info, &self.enums[*enum_index as usize]) => {
+                            value_variant == variant
                         },
-                        SYSCALL::DKVCOMMAND => {
-                            if let Some(Value::String(command)) = self.stack.pop() {
-                                let result = if let Some(ref mut handler) = self.dkv_command_handler {
-                                    match handler(&command) {
-                                        Ok(output) => Value::String(output),
-                                        Err(err) => Value::String(format!("Error: {}", err)),
-                                    }
-                                } else {
-                                    Value::String(format!("Error: No DKV command handler set"))
-                                };
-                                self.stack.push(result);
-                            } else {
-                                panic!("Stack underflow or invalid value type in syscall DKVCOMMAND");
-                            }
+                        value => {
+                            return Err(self.runtime_error(format!("Cannot match {:?} against enum {}", value, self.enums[*enum_index as usize].name)));
                         },
-                        _ => panic!("Unknown syscall ID: 0x{:02x}", syscall_id),
+                    };
+                    self.set_register(*dst, Value::Bool(result));
+                },
+                RegInstr::Jmp { target } => {
+                    *pc = *target as usize;
+                    continue;
+                },
+                RegInstr::Jz { cond, target } => {
+                    let Value::Bool(condition) = *self.operand(*cond) else {
+                        panic!("Jz operator applied to non-bool value");
+                    };
+                    if !condition {
+                        *pc = *target as usize;
+                        continue;
                     }
                 },
-                OpCode::Exit => {
-                    // 退出程序执行
-                    return;
+                RegInstr::Call { dst, function, start } => {
+                    let param_count = self.program.register_functions[*function as usize].param_count as usize;
+                    let args = self.take_args(*start, param_count);
+                    let value = self.call_register_function(*function, args, None)?;
+                    self.set_register(*dst, value);
                 },
+                RegInstr::CallIndirect { dst, callee, start, argc } => {
+                    let closure = match self.register(*callee) {
+                        Value::Function(closure) => closure.clone(),
+                        value => return Err(self.runtime_error(format!("Cannot call a value of {:?}", value))),
+                    };
+                    let func = &self.program.functions[closure.func_index as usize];
+                    if func.param_count as u16 != *argc {
+                        let message = format!("Function {} expects {} arguments, got {}", func.name, func.param_count, argc);
+                        return Err(self.runtime_error(message));
+                    }
+                    let args = self.take_args(*start, *argc as usize);
+                    let value = self.call_register_function(closure.func_index, args, Some(closure))?;
+                    self.set_register(*dst, value);
+                },
+                RegInstr::MakeClosure { dst, function, start, count } => {
+                    let upvalues = self.take_registers(*start, *count as usize).into_iter().map(|value| match value {
+                        Value::Cell(cell) => cell,
+                        _ => panic!("MakeClosure applied to non-cell value"),
+                    }).collect();
+                    self.set_register(*dst, Value::Function(Rc::new(Closure { func_index: *function, upvalues })));
+                },
+                RegInstr::Ret { src } => return Ok(self.operand(*src).into_owned()),
+                RegInstr::Throw { src } => {
+                    let error = match self.operand(*src).into_owned() {
+                        Value::Error(error) => error,
+                        Value::String(message) => self.runtime_error(message.to_string()),
+                        value => self.runtime_error(value.to_string()),
+                    };
+                    return Err(error);
+                },
+                RegInstr::Syscall { dst, syscall, start, argc } => {
+                    let args = self.take_args(*start, *argc as usize);
+                    if let Some(value) = self.syscall(SYSCALL::from(*syscall), args)? {
+                        self.set_register(*dst, value);
+                    }
+                },
+                RegInstr::Exit => break,
             }
-            self.pc += 8;
+            *pc += 1;
         }
+        Ok(Value::Null)
     }
 
-    fn read_u16(&mut self, bytecode: &[u8]) -> u16 {
-        let value = u16::from_le_bytes([bytecode[self.pc], bytecode[self.pc + 1]]);
-        value
+    fn register(&self, register: u16) -> &Value {
+        &self.stack[self.fp + register as usize]
     }
 
-    fn read_i16(&mut self, bytecode: &[u8]) -> i16 {
-        let value = i16::from_le_bytes([bytecode[self.pc], bytecode[self.pc + 1]]);
-        value
+    fn set_register(&mut self, register: u16, value: Value) {
+        self.stack[self.fp + register as usize] = value;
     }
 
-    fn read_u64(&mut self, bytecode: &[u8]) -> u64 {
-        let value = u64::from_le_bytes([bytecode[self.pc], bytecode[self.pc + 1], bytecode[self.pc + 2], bytecode[self.pc + 3], bytecode[self.pc + 4], bytecode[self.pc + 5], bytecode[self.pc + 6], bytecode[self.pc + 7]]);
-        value
+    fn operand(&self, operand: Operand) -> Cow<'_, Value> {
+        match operand {
+            Operand::Reg(register) => Cow::Borrowed(self.register(register)),
+            Operand::Const(index) => Cow::Owned(self.get_constant(index)),
+        }
     }
 
-    fn read_i64(&mut self, bytecode: &[u8]) -> i64 {
-        let value = i64::from_le_bytes([bytecode[self.pc], bytecode[self.pc + 1], bytecode[self.pc + 2], bytecode[self.pc + 3], bytecode[self.pc + 4], bytecode[self.pc + 5], bytecode[self.pc + 6], bytecode[self.pc + 7]]);
-        value
+    // 取出连续的临时寄存器中的值（这些临时值不再使用）
+    fn take_registers(&mut self, start: u16, count: usize) -> Vec<Value> {
+        let start = self.fp + start as usize;
+        self.stack[start..start + count].iter_mut().map(|value| std::mem::replace(value, Value::Null)).collect()
     }
 
-    fn unary_operation(&mut self, op: fn(&Value) -> Value) {
+    // 取出逆序存放的参数，按参数顺序返回
+    fn take_args(&mut self, start: u16, count: usize) -> Vec<Value> {
+        let mut args = self.take_registers(start, count);
+        args.reverse();
+        args
+    }
+
+    // 通过 DKV 命令处理函数执行命令
+    fn run_command(&mut self, command: &str) -> Result<String, String> {
+        match self.dkv_command_handler {
+            Some(ref mut handler) => handler(command),
+            None => Err("No DKV command handler set".to_string()),
+        }
+    }
+
+    // 局部变量在栈上的位置
+    fn local_slot(&self, local_index: u16) -> usize {
+        let stack_index = self.fp + 2 + local_index as usize;
+        if stack_index >= self.stack.len() {
+            panic!("Local variable index out of bounds: {}", local_index);
+        }
+        stack_index
+    }
+
+    fn unary_operation(&mut self, op: fn(&Value) -> Result<Value, String>) -> Result<(), Rc<RuntimeError>> {
         if let Some(a) = self.stack.pop() {
-            let result = op(&a);
+            let result = op(&a).map_err(|message| self.runtime_error(message))?;
             self.stack.push(result);
+            Ok(())
         } else {
             panic!("Stack underflow in unary operation");
         }
     }
 
-    fn binary_operation(&mut self, op: fn(&Value, &Value) -> Value) {
+    fn binary_operation(&mut self, op: fn(&Value, &Value) -> Result<Value, String>) -> Result<(), Rc<RuntimeError>> {
         if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-            let result = op(&a, &b);
+            let result = op(&a, &b).map_err(|message| self.runtime_error(message))?;
             self.stack.push(result);
+            Ok(())
         } else {
             panic!("Stack underflow in binary operation");
         }
     }
 
-    fn comparison_operation(&mut self, op: fn(&Value, &Value) -> bool) {
+    fn comparison_operation(&mut self, op: fn(&Value, &Value) -> Result<bool, String>) -> Result<(), Rc<RuntimeError>> {
         if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-            let result = op(&a, &b);
+            let result = op(&a, &b).map_err(|message| self.runtime_error(message))?;
             self.stack.push(Value::Bool(result));
+            Ok(())
         } else {
             panic!("Stack underflow in comparison operation");
         }
     }
 
-    fn print_value(&self, value: &Value) {
-        match value {
-            Value::Int(x) => println!("{}", x),
-            Value::Float(x) => println!("{}", x),
-            Value::Bool(x) => println!("{}", x),
-            Value::String(x) => println!("{}", x),
-            Value::Null => println!("null"),
+    fn print_value(&mut self, value: &Value) {
+        let text = value.to_string();
+        if let Some(ref mut handler) = self.print_handler {
+            handler(&text);
+        } else {
+            println!("{}", text);
         }
     }
 }
 
 use num_traits::FromPrimitive;
 impl OpCode {
-    fn from_byte(byte: u8) -> OpCode {
+    pub(crate) fn from_byte(byte: u8) -> OpCode {
         match OpCode::from_u8(byte) {
             Some(op) => op,
             _ => panic!("Unknown opcode: {:#02x}", byte),
@@ -484,9 +1505,9 @@ impl OpCode {
     }
 }
 
-impl core::convert::Into<u8> for OpCode {
-    fn into(self) -> u8 {
-        self as u8
+impl From<OpCode> for u8 {
+    fn from(opcode: OpCode) -> u8 {
+        opcode as u8
     }
 }
 
@@ -1,4 +1,4 @@
-use dkv_script::{Lexer, Parser, Compiler, OpCode};
+use dkv_script::{Lexer, Parser, Compiler, Constant, OpCode, Type};
 
 #[test]
 fn test_compiler_constant() {
@@ -7,7 +7,7 @@ fn test_compiler_constant() {
     let mut parser = Parser::new(lexer);
     let ast = parser.parse();
     
-    let mut compiler = Compiler::new();
+    let compiler = Compiler::new();
     
     let compiled_chunk = compiler.compile(&ast);
     let compiled_fn = &compiled_chunk.functions[0];
@@ -30,7 +30,7 @@ fn test_compiler_variable_declaration() {
     let mut parser = Parser::new(lexer);
     let ast = parser.parse();
     
-    let mut compiler = Compiler::new();
+    let compiler = Compiler::new();
     let compiled_chunk = compiler.compile(&ast);
     
     // 验证函数被编译
@@ -50,7 +50,7 @@ fn test_compiler_assignment() {
     let mut parser = Parser::new(lexer);
     let ast = parser.parse();
     
-    let mut compiler = Compiler::new();
+    let compiler = Compiler::new();
     let compiled_chunk = compiler.compile(&ast);
     
     // 验证函数被编译
@@ -68,7 +68,7 @@ fn test_compiler_binary_expression() {
     let mut parser = Parser::new(lexer);
     let ast = parser.parse();
     
-    let mut compiler = Compiler::new();
+    let compiler = Compiler::new();
     let compiled_chunk = compiler.compile(&ast);
     
     // 验证函数被编译
@@ -88,7 +88,7 @@ fn test_compiler_function_definition() {
     let mut parser = Parser::new(lexer);
     let ast = parser.parse();
     
-    let mut compiler = Compiler::new();
+    let compiler = Compiler::new();
     let compiled_chunk = compiler.compile(&ast);
     
     // 验证函数被编译
@@ -96,4 +96,631 @@ fn test_compiler_function_definition() {
     // 验证函数包含参数
     let func = &compiled_chunk.functions[0];
     assert_eq!(func.param_count, 2);
-}
\ No newline at end of file
+}
+#[test]
+fn test_compiler_cast_emits_cast_opcode() {
+    let source = "let f: float = 2.5; let x: int = f as int;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiler = Compiler::new();
+    let compiled_chunk = compiler.compile(&ast);
+    let func = &compiled_chunk.functions[0];
+    assert!(func.bytecode.contains(&(OpCode::Cast as u8)));
+}
+
+#[test]
+fn test_compiler_rejects_implicit_float_to_int() {
+    let errors = compile_errors("let x: int = 1.5;\nlet s: string = \"a\";\nlet y: int = s;");
+    assert_eq!(errors, vec![
+        error("Type mismatch: expected int, found float", 1, 14),
+        error("Type mismatch: expected int, found string", 3, 14),
+    ]);
+}
+
+#[test]
+fn test_compiler_rejects_invalid_mixed_operands() {
+    let errors = compile_errors("let s: string = \"a\"; let x: int = s - 1; let b: bool = -true;");
+    assert_eq!(errors, vec![
+        error("Invalid operand types for '-': string and int", 1, 35),
+        error("Invalid operand type for '-': bool", 1, 56),
+    ]);
+}
+
+#[test]
+fn test_compiler_rejects_invalid_cast() {
+    let errors = compile_errors("let x: float = true as float;");
+    assert_eq!(errors, vec![error("Cannot convert bool to float", 1, 16)]);
+}
+
+#[test]
+fn test_compiler_checks_native_arity() {
+    let errors = compile_errors("let s: string = substr(\"abc\", 1);");
+    assert_eq!(errors, vec![error("Function substr expects 3 arguments, got 2", 1, 17)]);
+}
+
+#[test]
+fn test_compiler_interpolation_emits_single_concat() {
+    let source = "let k: string = \"a\"; let v: int = 1; let c: string = f\"SET {k} {v}\";";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let func = &compiled_chunk.functions[0];
+    let opcodes: Vec<u8> = func.bytecode.chunks(9).map(|op| op[0]).collect();
+    assert_eq!(opcodes.iter().filter(|op| **op == OpCode::Concat as u8).count(), 1);
+    assert!(!opcodes.contains(&(OpCode::Add as u8)));
+}
+
+#[test]
+fn test_compiler_pops_discarded_values() {
+    let source = "fn f() { return 1; } fn main() { f(); f() + 1; print(1); len(\"abc\"); }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let main = compiled_chunk.functions.iter().find(|func| func.name == "main").unwrap();
+    let opcodes: Vec<u8> = main.bytecode.chunks(9).map(|op| op[0]).collect();
+    // f()、f() + 1 和 len(...) 的结果被丢弃，print 不产生值
+    assert_eq!(opcodes.iter().filter(|op| **op == OpCode::Pop as u8).count(), 3);
+}
+
+#[test]
+fn test_compiler_entrypoint_discards_main_result() {
+    let source = "fn main() { return 1; }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let entrypoint = &compiled_chunk.functions[compiled_chunk.entrypoint as usize];
+    let opcodes: Vec<u8> = entrypoint.bytecode.chunks(9).map(|op| op[0]).collect();
+    assert_eq!(opcodes, vec![OpCode::Call as u8, OpCode::Pop as u8, OpCode::Exit as u8]);
+}
+
+fn compile_str(source: &str) {
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    Compiler::new().compile(&ast);
+}
+
+// 编译并返回所有错误的信息和起始位置（行、列）
+fn compile_errors(source: &str) -> Vec<(String, u32, u32)> {
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    match Compiler::new().compile_with_diagnostics(&ast) {
+        Ok(_) => panic!("Expected compile errors"),
+        Err(diagnostics) => diagnostics.into_iter()
+            .map(|diagnostic| (diagnostic.message, diagnostic.span.start.line, diagnostic.span.start.column))
+            .collect(),
+    }
+}
+
+fn error(message: &str, line: u32, column: u32) -> (String, u32, u32) {
+    (message.to_string(), line, column)
+}
+
+#[test]
+fn test_compiler_checks_user_function_arity() {
+    let errors = compile_errors("fn add(a int, b int) -> int { return a + b; } fn main() { print(add(1)); print(add(1, 2, 3)); }");
+    assert_eq!(errors, vec![
+        error("Function add expects 2 arguments, got 1", 1, 65),
+        error("Function add expects 2 arguments, got 3", 1, 80),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_user_function_argument_types() {
+    let errors = compile_errors("fn add(a int, b int) -> int { return a + b; } fn main() { add(1, \"2\"); add(true, 2); }");
+    assert_eq!(errors, vec![
+        error("Type mismatch in call to add: expected int, found string", 1, 66),
+        error("Type mismatch in call to add: expected int, found bool", 1, 76),
+    ]);
+}
+
+#[test]
+fn test_compiler_reports_unknown_function_and_continues() {
+    let errors = compile_errors("fn main() {\n    let n: int = sqare(2);\n    print(n);\n    n(1);\n}");
+    assert_eq!(errors, vec![
+        error("Unknown function: sqare", 2, 18),
+        error("Cannot call n of type int", 4, 5),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_format_arguments() {
+    let source = r#"
+const FMT: string = "{}:{}";
+fn main() {
+    print(format());
+    print(format(1, 2));
+    print(format("{} and {}", 1));
+    print(format("{{}} {}", 1, 2));
+    print(format(FMT, 1));
+    print(format("{{}} {} {}", 1, "a"));
+    let fmt: string = "{}";
+    print(format(fmt, 1, 2));
+}
+"#;
+    assert_eq!(compile_errors(source), vec![
+        error("Function format expects at least 1 argument, got 0", 4, 11),
+        error("Type mismatch in call to format: expected string, found int", 5, 18),
+        error("Format string expects 2 arguments, got 1", 6, 11),
+        error("Format string expects 1 arguments, got 2", 7, 11),
+        error("Format string expects 2 arguments, got 1", 8, 11),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_return_type() {
+    let errors = compile_errors("fn name() -> string { return 1; }");
+    assert_eq!(errors, vec![error("Type mismatch in return from name: expected string, found int", 1, 30)]);
+}
+
+#[test]
+fn test_compiler_rejects_empty_return_in_typed_function() {
+    let errors = compile_errors("fn name() -> string { if true { return; } return \"x\"; }");
+    assert_eq!(errors, vec![error("Function name must return a value of type string", 1, 33)]);
+}
+
+#[test]
+fn test_compiler_requires_return_on_all_paths() {
+    let errors = compile_errors("fn sign(n int) -> int { if n < 0 { return -1; } else if n > 0 { return 1; } }\nlet f: fn = fn() -> int { };");
+    assert_eq!(errors, vec![
+        error("Function sign must return a value of type int on all paths", 1, 4),
+        error("Function $lambda1 must return a value of type int on all paths", 2, 13),
+    ]);
+}
+
+#[test]
+fn test_compiler_uses_declared_return_type() {
+    let errors = compile_errors("fn name() -> string { return \"x\"; } fn main() { let n: int = name(); }");
+    assert_eq!(errors, vec![error("Type mismatch: expected int, found string", 1, 62)]);
+}
+
+#[test]
+fn test_compiler_accepts_returns_on_all_paths() {
+    let source = r#"
+fn sign(n int) -> int {
+    if n < 0 {
+        return -1;
+    } else if n > 0 {
+        return 1;
+    } else {
+        return 0;
+    }
+}
+fn first(n int) -> int {
+    while true {
+        if n > 10 {
+            return n;
+        }
+        n++;
+    }
+}
+fn untyped() {
+    print("no return");
+}
+"#;
+    compile_str(source);
+}
+
+#[test]
+#[should_panic(expected = "Duplicate function: f")]
+fn test_compiler_rejects_duplicate_function() {
+    compile_str("fn f() {} fn f() {}");
+}
+
+#[test]
+#[should_panic(expected = "Unknown identifier: x")]
+fn test_compiler_rejects_global_use_before_declaration() {
+    compile_str("let y: int = x; let x: int = 1;");
+}
+
+#[test]
+#[should_panic(expected = "Variable x conflicts with the global variable declared later at the top level")]
+fn test_compiler_rejects_block_variable_claiming_later_global() {
+    // 代码块中的 x 不能占用为后面的顶层全局变量预留的位置，否则该全局变量的值与其类型不符
+    compile_str("fn show() -> string { return x; } if true { let x: int = 1; print(x); } let x: string = \"a\";");
+}
+
+#[test]
+fn test_compiler_top_level_declarations_use_reserved_globals() {
+    let source = "fn show() -> string { return x; }\nif true { let y: int = 1; print(y); }\n/// documented\nlet x: string = \"a\";\nprint(show());";
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let names: Vec<&str> = compiled_chunk.global_vars.iter().map(|global| global.name.as_str()).collect();
+    assert_eq!(names, vec!["x", "y"]);
+}
+
+#[test]
+fn test_compiler_forward_call_uses_declared_signature() {
+    let source = "fn main() { let s: string = name(1); } fn name(id int) -> string { return f\"user:{id}\"; }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let names: Vec<&str> = compiled_chunk.functions.iter().map(|func| func.name.as_str()).collect();
+    assert_eq!(names, vec!["main", "name", "_entrypoint"]);
+    assert!(compiled_chunk.functions.iter().all(|func| !func.bytecode.is_empty()));
+}
+
+#[test]
+fn test_compiler_boxes_only_captured_locals() {
+    let source = "fn main() { let a: int = 1; let b: int = 2; let f: fn = fn() { return a; }; print(b); }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let main = compiled_chunk.functions.iter().find(|func| func.name == "main").unwrap();
+    let opcodes: Vec<u8> = main.bytecode.chunks(9).map(|op| op[0]).collect();
+    assert_eq!(opcodes.iter().filter(|op| **op == OpCode::NewCell as u8).count(), 1);
+    assert!(opcodes.contains(&(OpCode::MakeClosure as u8)));
+
+    let lambda = compiled_chunk.functions.iter().find(|func| func.name.starts_with("main$lambda")).unwrap();
+    let opcodes: Vec<u8> = lambda.bytecode.chunks(9).map(|op| op[0]).collect();
+    assert_eq!(&opcodes[..2], &[OpCode::LoadUpvalue as u8, OpCode::LoadCell as u8]);
+}
+
+#[test]
+#[should_panic(expected = "Cannot call n of type int")]
+fn test_compiler_rejects_calling_non_function() {
+    compile_str("fn main() { let n: int = 1; n(); }");
+}
+
+#[test]
+fn test_compiler_requires_all_struct_fields() {
+    let errors = compile_errors("struct User { name: string, age: int }\nfn main() {\n    let u: User = User { name: \"a\" };\n    let v: User = User { name: \"a\", name: \"b\", age: 1 };\n    let w: User = Admin { };\n}");
+    assert_eq!(errors, vec![
+        error("Missing field age in struct User", 3, 19),
+        error("Field name specified more than once", 4, 19),
+        error("Unknown struct: Admin", 5, 19),
+    ]);
+}
+
+#[test]
+fn test_compiler_rejects_unknown_struct_field() {
+    let errors = compile_errors("struct User { name: string }\nfn main() {\n    let u: User = User { name: \"a\", email: \"b\" };\n    print(u.email);\n    u.email = \"c\";\n}");
+    assert_eq!(errors, vec![
+        error("Struct User has no field email", 3, 19),
+        error("Struct User has no field email", 4, 11),
+        error("Struct User has no field email", 5, 5),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_struct_field_types() {
+    let errors = compile_errors("struct User { age: int } fn main() { let u: User = User { age: 1 }; u.age = \"2\"; }");
+    assert_eq!(errors, vec![error("Type mismatch: expected int, found string", 1, 77)]);
+}
+
+#[test]
+fn test_compiler_distinguishes_struct_types() {
+    let errors = compile_errors("struct User { age: int } struct Point { x: int } fn main() { let p: Point = User { age: 1 }; }");
+    assert_eq!(errors, vec![error("Type mismatch: expected Point, found User", 1, 77)]);
+}
+
+#[test]
+fn test_compiler_records_struct_metadata() {
+    let source = "struct Line { from: Point, to: Point } struct Point { x: int, y: float }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let names: Vec<&str> = compiled_chunk.structs.iter().map(|info| info.name.as_str()).collect();
+    assert_eq!(names, vec!["Line", "Point"]);
+    assert_eq!(compiled_chunk.structs[0].fields[1].field_type, Type::Struct(1));
+    assert_eq!(compiled_chunk.structs[1].fields[1].field_type, Type::Float);
+}
+
+#[test]
+fn test_compiler_rejects_non_exhaustive_match() {
+    let errors = compile_errors("enum Reply { Ok, Nil, Error(string) }\nfn main() {\n    let r: Reply = Reply::Ok;\n    match r { Reply::Ok => print(1), }\n}");
+    assert_eq!(errors, vec![error("Non-exhaustive match: missing Reply::Nil, Reply::Error", 4, 5)]);
+}
+
+#[test]
+fn test_compiler_rejects_duplicate_match_arm() {
+    let source = r#"
+enum Reply { Ok, Nil, Pair(int, int) }
+fn main() {
+    let r: Reply = Reply::Ok;
+    match r {
+        Reply::Ok => print(1),
+        Reply::Ok => print(2),
+        Reply::Pair(a) => print(a),
+        Reply::Missing => print(3),
+        _ => print(4),
+        Reply::Nil => print(5),
+    }
+    match 1 { _ => print(6) }
+}
+"#;
+    assert_eq!(compile_errors(source), vec![
+        error("Duplicate match arm for Reply::Ok", 7, 9),
+        error("Pattern Reply::Pair expects 2 bindings, got 1", 8, 9),
+        error("Enum Reply has no variant Missing", 9, 9),
+        error("Unreachable match arm after _", 11, 9),
+        error("Cannot match on a value of type int", 13, 11),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_match_arm_types() {
+    let source = r#"
+enum Reply { Ok, Nil }
+enum Other { A }
+fn main() {
+    let r: Reply = Reply::Ok;
+    let x: int = match r { Reply::Ok => 1, Reply::Nil => "nil" };
+    let y: int = match r { Reply::Ok => 1, Other::A => 2, _ => 3 };
+}
+"#;
+    assert_eq!(compile_errors(source), vec![
+        error("Match arms have incompatible types: int and string", 6, 18),
+        error("Pattern Other::A does not match type Reply", 7, 44),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_variant_arity() {
+    let errors = compile_errors("enum Reply { Ok, Error(string) }\nfn main() {\n    let r: Reply = Reply::Error();\n    let s: Reply = Reply::Nope;\n}");
+    assert_eq!(errors, vec![
+        error("Variant Reply::Error expects 1 values, got 0", 3, 20),
+        error("Enum Reply has no variant Nope", 4, 20),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_enum_types() {
+    let errors = compile_errors("enum Reply { Ok } fn main() { let r: Reply = try_command(\"PING\"); }");
+    assert_eq!(errors, vec![error("Type mismatch: expected Reply, found Result", 1, 46)]);
+}
+
+#[test]
+fn test_compiler_checks_throw_type() {
+    let errors = compile_errors("fn main() { throw 1; if 1 { } }");
+    assert_eq!(errors, vec![
+        error("Cannot throw a value of type int", 1, 19),
+        error("Condition must be bool, found int", 1, 25),
+    ]);
+}
+
+#[test]
+fn test_compiler_checks_error_fields() {
+    let errors = compile_errors("fn main() { try { } catch (e) { print(e.code); e.message = \"x\"; } }");
+    assert_eq!(errors, vec![
+        error("Type error has no field code", 1, 39),
+        error("Cannot assign field message of error", 1, 48),
+    ]);
+}
+
+#[test]
+fn test_compiler_exception_handlers() {
+    let source = "fn main() { try { print(1); } catch (e) { print(e.message); } finally { print(2); } }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let main = compiled_chunk.functions.iter().find(|func| func.name == "main").unwrap();
+    // try 块的异常由 catch 处理，catch 块的异常由 finally 处理
+    assert_eq!(main.handlers.len(), 2);
+    assert!(main.handlers.iter().all(|handler| handler.start < handler.end && handler.stack_depth == 0));
+    assert!(main.bytecode.contains(&(OpCode::Throw as u8)));
+}
+
+#[test]
+fn test_compiler_inlines_constants() {
+    let source = "const SIZE: int = 4 * 8; const NAME: string = \"n\"; fn main() { print(SIZE); }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let compiled_chunk = Compiler::new().compile(&ast);
+    // 常量不占用全局变量，值在编译期算出并导出
+    assert!(compiled_chunk.global_vars.is_empty());
+    assert_eq!(compiled_chunk.constant("SIZE"), Some(&Constant::Int(32)));
+    assert_eq!(compiled_chunk.constant("NAME"), Some(&Constant::String("n".to_string())));
+    let main = compiled_chunk.functions.iter().find(|func| func.name == "main").unwrap();
+    assert_eq!(main.bytecode[0], OpCode::LoadConst as u8);
+    assert!(!main.bytecode.contains(&(OpCode::LoadGlobal as u8)));
+}
+
+#[test]
+#[should_panic(expected = "Cannot assign to constant: SIZE")]
+fn test_compiler_rejects_constant_assignment() {
+    compile_str("const SIZE: int = 1; fn main() { SIZE = 2; }");
+}
+
+#[test]
+fn test_compiler_rejects_non_constant_initializer() {
+    let errors = compile_errors("let g: int = 1; const B: int = g + 1;\nfn f() -> int { return 1; }\nconst N: int = f();\nlet n: int = N + B;");
+    assert_eq!(errors, vec![
+        error("Cannot evaluate constant B: g is not a constant", 1, 32),
+        error("Cannot evaluate constant N: expression is not a compile-time constant", 3, 16),
+    ]);
+}
+
+#[test]
+fn test_compiler_reports_constant_evaluation_errors() {
+    let errors = compile_errors("const C: int = 1 / (2 - 2);");
+    assert_eq!(errors, vec![error("Cannot evaluate constant C: Division by zero", 1, 16)]);
+}
+
+#[test]
+fn test_compiler_reports_constant_overflow() {
+    let errors = compile_errors("const BIG: int = 2147483647 + 1;");
+    assert_eq!(errors, vec![error("Cannot evaluate constant BIG: Integer overflow", 1, 18)]);
+}
+
+#[test]
+fn test_compiler_checks_constant_types() {
+    let errors = compile_errors("const S: string = 1 + 2;\nconst S: int = 1;\nfn main() { const L: int = 1; }");
+    assert_eq!(errors, vec![
+        error("Type mismatch in constant S: expected string, found int", 1, 19),
+        error("Duplicate constant: S", 2, 16),
+        error("Constant L must be declared at the top level", 3, 28),
+    ]);
+}
+
+fn compile_optimized(source: &str) -> dkv_script::CompileResult {
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    Compiler::new().with_optimization_level(1).compile(&ast)
+}
+
+// 函数中各指令的操作码
+fn function_opcodes(compiled_chunk: &dkv_script::CompileResult, name: &str) -> Vec<u8> {
+    let func = compiled_chunk.functions.iter().find(|func| func.name == name).unwrap();
+    func.bytecode.chunks(9).map(|instruction| instruction[0]).collect()
+}
+
+#[test]
+fn test_compiler_folds_constant_expressions() {
+    let source = "fn main() { print(1 + 2 * 3 + 5); }";
+    let compiled_chunk = compile_optimized(source);
+    assert_eq!(function_opcodes(&compiled_chunk, "main"), vec![
+        OpCode::LoadConst as u8, OpCode::Syscall as u8,
+        OpCode::LoadConst as u8, OpCode::Ret as u8,
+    ]);
+    assert!(compiled_chunk.constants.contains(&Constant::Int(12)));
+
+    // 不优化时保留运算指令
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let compiled_chunk = Compiler::new().compile(&ast);
+    let opcodes = function_opcodes(&compiled_chunk, "main");
+    assert!(opcodes.contains(&(OpCode::Mul as u8)) && opcodes.contains(&(OpCode::Add as u8)));
+}
+
+#[test]
+fn test_compiler_folds_partially_constant_expressions() {
+    let compiled_chunk = compile_optimized("fn f(x int) -> int { return x + 60 * 60 * -(2 - 1); }");
+    assert_eq!(function_opcodes(&compiled_chunk, "f"), vec![
+        OpCode::LoadLocal as u8, OpCode::LoadConst as u8, OpCode::Add as u8, OpCode::Ret as u8,
+    ]);
+    assert!(compiled_chunk.constants.contains(&Constant::Int(-3600)));
+}
+
+#[test]
+fn test_compiler_does_not_fold_runtime_errors() {
+    // 除以零留到运行时抛出，可以被 catch 捕获
+    let compiled_chunk = compile_optimized("fn main() { print(1 / 0); }");
+    assert!(function_opcodes(&compiled_chunk, "main").contains(&(OpCode::Div as u8)));
+    // 整数溢出同样留到运行时
+    let compiled_chunk = compile_optimized("fn main() { print(2147483647 + 1); print(-(-2147483647 - 1)); }");
+    let opcodes = function_opcodes(&compiled_chunk, "main");
+    assert!(opcodes.contains(&(OpCode::Add as u8)));
+    assert!(opcodes.contains(&(OpCode::Neg as u8)));
+    let compiled_chunk = compile_optimized("fn main() { print((-2147483647 - 1) / -1); print(65536 * 65536); }");
+    let opcodes = function_opcodes(&compiled_chunk, "main");
+    assert!(opcodes.contains(&(OpCode::Div as u8)));
+    assert!(opcodes.contains(&(OpCode::Mul as u8)));
+}
+
+#[test]
+fn test_compiler_does_not_fold_shadowed_constants() {
+    let compiled_chunk = compile_optimized("const N: int = 2; fn f(N int) -> int { return N * 3; }");
+    assert_eq!(function_opcodes(&compiled_chunk, "f"), vec![
+        OpCode::LoadLocal as u8, OpCode::LoadConst as u8, OpCode::Mul as u8, OpCode::Ret as u8,
+    ]);
+}
+
+#[test]
+fn test_compiler_removes_dead_branches() {
+    let source = r#"
+const DEBUG: bool = false;
+fn main() {
+    if DEBUG { print("debug"); }
+    if 1 < 2 { print("then"); } else { print("else"); }
+}
+"#;
+    let compiled_chunk = compile_optimized(source);
+    // 只剩下 print("then") 和返回
+    assert_eq!(function_opcodes(&compiled_chunk, "main"), vec![
+        OpCode::LoadConst as u8, OpCode::Syscall as u8,
+        OpCode::LoadConst as u8, OpCode::Ret as u8,
+    ]);
+}
+
+#[test]
+fn test_compiler_removes_code_after_return() {
+    let compiled_chunk = compile_optimized("fn f() -> int { return 1; print(\"dead\"); return 2; }");
+    assert_eq!(function_opcodes(&compiled_chunk, "f"), vec![OpCode::LoadConst as u8, OpCode::Ret as u8]);
+}
+
+#[test]
+fn test_compiler_threads_jumps() {
+    let source = "fn f(a bool, b bool) { if a { if b { print(1); } else { print(2); } } else { print(3); } }";
+    let compiled_chunk = compile_optimized(source);
+    let func = compiled_chunk.functions.iter().find(|func| func.name == "f").unwrap();
+    for (pc, instruction) in func.bytecode.chunks(9).enumerate().map(|(index, instruction)| (index * 9, instruction)) {
+        if instruction[0] == OpCode::Jmp as u8 || instruction[0] == OpCode::Jz as u8 {
+            let offset = i16::from_le_bytes([instruction[1], instruction[2]]) as isize;
+            let target = (pc as isize + offset) as usize;
+            // 跳转不再落在另一条 Jmp 上，也不跳转到下一条指令
+            assert_ne!(func.bytecode.get(target).copied(), Some(OpCode::Jmp as u8));
+            assert_ne!(target, pc + 9);
+        }
+    }
+}
+
+#[test]
+fn test_compiler_remaps_exception_handlers() {
+    let source = r#"
+fn main() {
+    if false { print("dead"); }
+    try { print("try"); } catch (e) { print(e.message); }
+}
+"#;
+    let compiled_chunk = compile_optimized(source);
+    let main = compiled_chunk.functions.iter().find(|func| func.name == "main").unwrap();
+    assert_eq!(main.handlers.len(), 1);
+    let handler = &main.handlers[0];
+    // if false 被删除后 try 从第一条指令开始
+    assert_eq!(handler.start, 0);
+    assert_eq!(main.bytecode[handler.target as usize], OpCode::StoreLocal as u8);
+}
+
+#[test]
+fn test_compiler_fuses_superinstructions() {
+    let source = "fn main() { let i: int = 0; let sum: int = 0; while i < 10 { sum = sum + 3; i++; } print(sum + i); }";
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let compiled_chunk = Compiler::new().with_optimization_level(2).compile(&ast);
+    let opcodes = function_opcodes(&compiled_chunk, "main");
+    for opcode in [OpCode::AddConstLocal, OpCode::IncLocal, OpCode::CmpLtJz, OpCode::LoadLocal2] {
+        assert!(opcodes.contains(&(opcode as u8)), "missing {:?} in {:?}", opcode, opcodes);
+    }
+    for opcode in [OpCode::Inc, OpCode::CmpLt, OpCode::Jz] {
+        assert!(!opcodes.contains(&(opcode as u8)), "unfused {:?} in {:?}", opcode, opcodes);
+    }
+}
+
+#[test]
+fn test_compiler_fuses_at_jump_targets() {
+    // Jz 的目标可以是超级指令的第一条指令
+    let source = "fn f(a int, b int, c bool) -> int { if c { print(a); } return b + a; }";
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let compiled_chunk = Compiler::new().with_optimization_level(2).compile(&ast);
+    assert_eq!(function_opcodes(&compiled_chunk, "f"), vec![
+        OpCode::LoadLocal as u8, OpCode::Jz as u8,
+        OpCode::LoadLocal as u8, OpCode::Syscall as u8,
+        OpCode::LoadLocal2 as u8, OpCode::Add as u8, OpCode::Ret as u8,
+    ]);
+}
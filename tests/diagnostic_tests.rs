@@ -0,0 +1,143 @@
+use dkv_script::{compile_source, DiagnosticRenderer, Lexer, Parser};
+
+fn parse_errors(source: &str) -> Vec<dkv_script::Diagnostic> {
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    parser.parse_with_diagnostics().1
+}
+
+#[test]
+fn test_render_snippet_with_caret() {
+    let source = "let a: int = 1;\nlet b: int = 2\n}";
+    let diagnostics = parse_errors(source);
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").render(&diagnostics[0]);
+
+    let expected = "\
+error: Expected token Semicolon, but got RBrace
+ --> main.dkvs:3:1
+  |
+3 | }
+  | ^ expected ';'
+";
+    assert_eq!(rendered, expected);
+}
+
+#[test]
+fn test_render_underlines_whole_token_and_expands_tabs() {
+    let source = "fn f() {\n\tlet x: int = 1 + ;\n}";
+    let diagnostics = parse_errors(source);
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").render(&diagnostics[0]);
+
+    assert!(rendered.contains(" --> main.dkvs:2:22\n"));
+    assert!(rendered.contains("2 |     let x: int = 1 + ;\n"));
+    assert!(rendered.contains(&format!("  | {}^ expected identifier,", " ".repeat(21))));
+
+    let source = "let total: int = 1;\nprint(total total);";
+    let diagnostics = parse_errors(source);
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").render(&diagnostics[0]);
+    assert!(rendered.contains(&format!("  | {}^^^^^ expected ')'", " ".repeat(12))));
+}
+
+#[test]
+fn test_render_with_custom_tab_width() {
+    let source = "fn f() {\n\tlet x: int = 1 + ;\n}";
+    let mut lexer = Lexer::new(source.to_string());
+    lexer.set_tab_width(8);
+    let diagnostics = Parser::new(lexer).parse_with_diagnostics().1;
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").with_tab_width(8).render(&diagnostics[0]);
+
+    assert!(rendered.contains(" --> main.dkvs:2:26\n"));
+    assert!(rendered.contains("2 |         let x: int = 1 + ;\n"));
+    assert!(rendered.contains(&format!("  | {}^ expected identifier,", " ".repeat(25))));
+}
+
+#[test]
+fn test_unknown_variable_suggestion() {
+    let source = "let count: int = 0;\nfn main() {\n    cuont = 1;\n    total = 2;\n}";
+    let diagnostics = match compile_source(source) {
+        Ok(_) => panic!("Expected compile errors"),
+        Err(diagnostics) => diagnostics,
+    };
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].message, "Unknown variable: cuont");
+    assert_eq!(diagnostics[0].help, vec!["did you mean `count`?".to_string()]);
+    assert!(diagnostics[1].help.is_empty());
+
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").render(&diagnostics[0]);
+    assert!(rendered.starts_with("error: Unknown variable: cuont\n"));
+    assert!(rendered.contains(" --> main.dkvs:3:5\n"));
+    assert!(rendered.contains(&format!("  | {}^^^^^", " ".repeat(4))));
+    assert!(rendered.contains("= help: did you mean `count`?"));
+    assert_eq!(diagnostics[1].span.start.line, 4);
+}
+
+#[test]
+fn test_unknown_identifier_and_constant_assignment_spans() {
+    let source = "const LIMIT: int = 3;\nfn main() {\n    LIMIT = 4;\n    print(limt + 1);\n}";
+    let diagnostics = match compile_source(source) {
+        Ok(_) => panic!("Expected compile errors"),
+        Err(diagnostics) => diagnostics,
+    };
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].message, "Cannot assign to constant: LIMIT");
+    assert_eq!((diagnostics[0].span.start.line, diagnostics[0].span.start.column), (3, 5));
+    assert_eq!(diagnostics[1].message, "Unknown identifier: limt");
+    assert_eq!((diagnostics[1].span.start.line, diagnostics[1].span.start.column), (4, 11));
+    assert!(diagnostics[1].to_json().contains("\"span\":{\"start\":{"));
+}
+
+#[test]
+#[should_panic(expected = "Unknown variable: cuont at line 1, column 21 (did you mean `count`?)")]
+fn test_compile_panics_with_suggestion() {
+    let source = "let count: int = 0; cuont++;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+    dkv_script::Compiler::new().compile(&ast);
+}
+
+#[test]
+fn test_render_with_color() {
+    let source = "let x: int = ;";
+    let diagnostics = parse_errors(source);
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").with_color(true).render(&diagnostics[0]);
+
+    assert!(rendered.starts_with("\x1b[31merror\x1b[0m"));
+    assert!(rendered.contains("\x1b[34m-->\x1b[0m"));
+}
+
+#[test]
+fn test_diagnostic_json() {
+    let source = "let x: int = \"a\"\nprint(x);";
+    let diagnostics = parse_errors(source);
+    let json = diagnostics[0].to_json();
+
+    assert_eq!(json, concat!(
+        "{\"severity\":\"error\",\"message\":\"Expected token Semicolon, but got Identifier(\\\"print\\\")\",",
+        "\"span\":{\"start\":{\"offset\":17,\"line\":2,\"column\":1},\"end\":{\"offset\":22,\"line\":2,\"column\":6}},",
+        "\"label\":null,\"labels\":[],\"expected\":[\"';'\"],\"notes\":[],\"help\":[]}"
+    ));
+}
+
+#[test]
+fn test_try_without_catch_or_finally() {
+    let diagnostics = parse_errors("try { print(1); }\nprint(2);");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Expected catch or finally after try block");
+}
+
+#[test]
+fn test_type_mismatch_points_at_expression() {
+    let source = "let x: int = \"a\";";
+    let diagnostics = match compile_source(source) {
+        Ok(_) => panic!("Expected compile errors"),
+        Err(diagnostics) => diagnostics,
+    };
+
+    assert_eq!(diagnostics.len(), 1);
+    let rendered = DiagnosticRenderer::new(source, "main.dkvs").render(&diagnostics[0]);
+    assert!(rendered.starts_with("error: Type mismatch: expected int, found string\n --> main.dkvs:1:14\n"));
+    assert!(rendered.contains(&format!("  | {}^^^", " ".repeat(13))));
+}
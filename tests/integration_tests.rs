@@ -1,4 +1,6 @@
-use dkv_script::{do_compile, VM};
+use dkv_script::{compile_source_with_resolver, do_compile, load_from_file, save_to_file, Constant, MemoryLoader, ModuleResolver, VM};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[test]
 fn test_integration_simple_program() {
@@ -46,4 +48,189 @@ fn test_integration_nested_expressions() {
     
     // 由于VM没有提供直接访问局部变量的方法，我们无法直接验证变量值
     // 但至少我们验证了程序能够正常执行而不崩溃
-}
\ No newline at end of file
+}
+#[test]
+fn test_integration_documented_program() {
+    let source = "/// counter\nlet i: int = 0; /* nested /* block */ comment */\n/// entry\nfn main() { i = i + 1; }";
+    let compile_result = do_compile(source).unwrap();
+
+    let mut vm = VM::new(compile_result);
+    vm.run();
+}
+
+#[test]
+fn test_integration_struct_metadata_round_trip() {
+    let source = "struct Point { x: int, y: float } struct Line { from: Point, name: string } fn main() { let l: Line = Line { from: Point { x: 1, y: 2.0 }, name: \"l\" }; print(l.from.x); }";
+    let compile_result = do_compile(source).unwrap();
+    let path = std::env::temp_dir().join("dkv_struct_round_trip.cdkvs");
+    let path = path.to_str().unwrap();
+    save_to_file(&compile_result, path).unwrap();
+
+    let loaded = load_from_file(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.structs, compile_result.structs);
+    let mut vm = VM::new(loaded);
+    vm.run();
+}
+
+#[test]
+fn test_integration_enum_metadata_round_trip() {
+    let source = "struct Point { x: int } enum Shape { Dot(Point), Empty } fn main() { let s: Shape = Shape::Dot(Point { x: 1 }); match s { Shape::Dot(p) => print(p.x), Shape::Empty => print(0) } }";
+    let compile_result = do_compile(source).unwrap();
+    let path = std::env::temp_dir().join("dkv_enum_round_trip.cdkvs");
+    let path = path.to_str().unwrap();
+    save_to_file(&compile_result, path).unwrap();
+
+    let loaded = load_from_file(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.enums, compile_result.enums);
+    let mut vm = VM::new(loaded);
+    vm.run();
+}
+
+#[test]
+fn test_integration_exception_handlers_round_trip() {
+    let source = "fn main() { try { throw \"x\"; } catch (e) { print(e.message); } finally { print(1); } }";
+    let compile_result = do_compile(source).unwrap();
+    let path = std::env::temp_dir().join("dkv_handlers_round_trip.cdkvs");
+    let path = path.to_str().unwrap();
+    save_to_file(&compile_result, path).unwrap();
+
+    let loaded = load_from_file(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    for (loaded_func, func) in loaded.functions.iter().zip(&compile_result.functions) {
+        assert_eq!(loaded_func.handlers, func.handlers);
+    }
+    let mut vm = VM::new(loaded);
+    vm.run();
+}
+
+fn run_with_loader(source: &str, loader: MemoryLoader) -> Vec<String> {
+    let resolver = ModuleResolver::new(loader).with_search_path("lib");
+    let compile_result = compile_source_with_resolver(source, &resolver, "app").unwrap();
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.run();
+    let result = output.borrow().clone();
+    result
+}
+
+#[test]
+fn test_integration_import_modules() {
+    let loader = MemoryLoader::new()
+        .with_module("lib/util.dkvs", r#"
+import "strings.dkvs" as s;
+let prefix: string = "user:";
+let calls: int = 0;
+fn key(id int) -> string {
+    calls++;
+    return s::join2(prefix, string(id));
+}
+"#)
+        .with_module("lib/strings.dkvs", "fn join2(a string, b string) -> string { return a + b; }")
+        .with_module("app/local.dkvs", "let prefix: string = \"local\"; fn twice(x int) -> int { return x * 2; }");
+    let source = r#"
+import "util.dkvs";
+import { twice, prefix } from "local.dkvs";
+let calls: int = 100;
+fn main() {
+    print(util::key(1));
+    print(util::key(twice(21)));
+    print(util::calls);
+    print(calls);
+    print(util::prefix + " " + prefix);
+    let f: fn = util::key;
+    print(f(7));
+}
+"#;
+    assert_eq!(run_with_loader(source, loader), vec!["user:1", "user:42", "2", "100", "user: local", "user:7"]);
+}
+
+#[test]
+fn test_integration_import_errors() {
+    let resolve = |source: &str, loader: MemoryLoader| {
+        let resolver = ModuleResolver::new(loader);
+        match compile_source_with_resolver(source, &resolver, "") {
+            Ok(_) => panic!("expected errors"),
+            Err(diagnostics) => diagnostics,
+        }
+    };
+
+    let diagnostics = resolve("\nimport \"missing.dkvs\";", MemoryLoader::new());
+    assert_eq!(diagnostics[0].message, "Module not found: missing.dkvs");
+    assert_eq!((diagnostics[0].span.start.line, diagnostics[0].span.start.column), (2, 8));
+
+    let loader = MemoryLoader::new()
+        .with_module("a.dkvs", "import \"b.dkvs\";")
+        .with_module("b.dkvs", "fn f() {}\nimport \"a.dkvs\";");
+    let diagnostics = resolve("import \"a.dkvs\";", loader);
+    assert_eq!(diagnostics[0].message, "Import cycle: a -> b -> a");
+    assert_eq!(diagnostics[0].notes, vec!["in module b at line 2, column 8"]);
+
+    let loader = MemoryLoader::new().with_module("bad.dkvs", "\nlet x: int = ;");
+    let diagnostics = resolve("import \"bad.dkvs\";", loader);
+    assert_eq!(diagnostics[0].notes, vec!["in module bad at line 2, column 14"]);
+
+    let loader = MemoryLoader::new().with_module("lib.dkvs", "fn f() {\n    total = 1;\n}");
+    let diagnostics = resolve("import \"lib.dkvs\";", loader);
+    assert_eq!(diagnostics[0].message, "Unknown variable: total");
+    assert!(!diagnostics[0].has_location());
+    assert_eq!(diagnostics[0].notes, vec!["in module lib at line 2, column 5"]);
+}
+
+#[test]
+fn test_integration_import_unknown_name() {
+    let loader = MemoryLoader::new().with_module("lib.dkvs", "fn f() {}\nfn h(a int, b int) -> int { return a + b; }");
+    let resolver = ModuleResolver::new(loader);
+    let source = "import { missing } from \"lib.dkvs\";\nimport \"lib.dkvs\" as c;\nfn main() { print(c::h(1)); }";
+    let diagnostics = match compile_source_with_resolver(source, &resolver, "") {
+        Ok(_) => panic!("expected errors"),
+        Err(diagnostics) => diagnostics,
+    };
+    let errors: Vec<(&str, u32, u32)> = diagnostics.iter()
+        .map(|diagnostic| (diagnostic.message.as_str(), diagnostic.span.start.line, diagnostic.span.start.column))
+        .collect();
+    assert_eq!(errors, vec![
+        ("Module lib.dkvs has no function, global variable or constant missing", 1, 25),
+        ("Function c::h expects 2 arguments, got 1", 3, 19),
+    ]);
+
+    // 没有经过 ModuleResolver 的 import 无法解析
+    let ast = dkv_script::Parser::new(dkv_script::Lexer::new("import \"lib.dkvs\" as c;".to_string())).parse();
+    let diagnostics = dkv_script::Compiler::new().compile_with_diagnostics(&ast).unwrap_err();
+    assert_eq!(diagnostics[0].message, "Unresolved import: lib.dkvs");
+    assert_eq!(diagnostics[0].span.start.column, 8);
+}
+
+#[test]
+fn test_integration_import_from_file() {
+    let dir = std::env::temp_dir().join("dkv_import_from_file");
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("lib/math.dkvs"), "fn square(x int) -> int { return x * x; }").unwrap();
+    let resolver = ModuleResolver::default();
+    let compile_result = compile_source_with_resolver(
+        "import \"lib/math.dkvs\" as m; fn main() { print(m::square(3)); }", &resolver, dir.to_str().unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(compile_result.functions.iter().any(|func| func.name.ends_with("lib/math::square")));
+    let mut vm = VM::new(compile_result);
+    vm.run();
+}
+
+#[test]
+fn test_integration_exported_constants_round_trip() {
+    let loader = MemoryLoader::new().with_module("limits.dkvs", "const MAX_KEYS: int = 1000;");
+    let resolver = ModuleResolver::new(loader);
+    let source = "import \"limits.dkvs\"; import { MAX_KEYS } from \"limits.dkvs\"; const HALF: float = limits::MAX_KEYS / 2; fn main() { print(MAX_KEYS); }";
+    let compile_result = compile_source_with_resolver(source, &resolver, "").unwrap();
+    let path = std::env::temp_dir().join("dkv_constants_round_trip.cdkvs");
+    let path = path.to_str().unwrap();
+    save_to_file(&compile_result, path).unwrap();
+
+    let loaded = load_from_file(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.exported_constants, compile_result.exported_constants);
+    assert_eq!(loaded.constant("limits::MAX_KEYS"), Some(&Constant::Int(1000)));
+    assert_eq!(loaded.constant("HALF"), Some(&Constant::Float(500.0)));
+}
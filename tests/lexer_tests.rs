@@ -1,4 +1,6 @@
-use dkv_script::{Lexer, TokenType};
+#![allow(clippy::approx_constant, clippy::bool_assert_comparison)]
+
+use dkv_script::{Lexer, StringPart, TokenType};
 
 #[test]
 fn test_lexer_basic_tokens() {
@@ -147,4 +149,226 @@ fn test_lexer_increment_decrement() {
 
     
     assert_eq!(lexer.next_token().token_type, TokenType::Eof);
-}
\ No newline at end of file
+}
+#[test]
+fn test_lexer_brackets() {
+    let source = "s[0] [1, 2]";
+    let mut lexer = Lexer::new(source.to_string());
+
+    assert!(matches!(lexer.next_token().token_type, TokenType::Identifier(_)));
+    assert_eq!(lexer.next_token().token_type, TokenType::LBracket);
+    assert_eq!(lexer.next_token().token_type, TokenType::IntLiteral(0));
+    assert_eq!(lexer.next_token().token_type, TokenType::RBracket);
+    assert_eq!(lexer.next_token().token_type, TokenType::LBracket);
+    assert_eq!(lexer.next_token().token_type, TokenType::IntLiteral(1));
+    assert_eq!(lexer.next_token().token_type, TokenType::Comma);
+    assert_eq!(lexer.next_token().token_type, TokenType::IntLiteral(2));
+    assert_eq!(lexer.next_token().token_type, TokenType::RBracket);
+    assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+}
+
+#[test]
+fn test_lexer_interpolated_string() {
+    let source = "f\"SET {key} {{x}} {upper(\"v\")}\" f";
+    let mut lexer = Lexer::new(source.to_string());
+
+    match lexer.next_token().token_type {
+        TokenType::InterpolatedString(parts) => {
+            assert_eq!(parts.len(), 4);
+            assert_eq!(parts[0], StringPart::Literal("SET ".to_string()));
+            assert!(matches!(&parts[1], StringPart::Expr(text, _) if text == "key"));
+            assert_eq!(parts[2], StringPart::Literal(" {x} ".to_string()));
+            assert!(matches!(&parts[3], StringPart::Expr(text, _) if text == "upper(\"v\")"));
+        },
+        _ => panic!("Expected InterpolatedString"),
+    }
+    match lexer.next_token().token_type {
+        TokenType::Identifier(name) => assert_eq!(name, "f"),
+        _ => panic!("Expected Identifier"),
+    }
+    assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+}
+
+#[test]
+fn test_lexer_block_comments() {
+    let source = "a /* outer /* nested */ still comment */ b /**/ c";
+    let mut lexer = Lexer::new(source.to_string());
+
+    for expected in ["a", "b", "c"] {
+        match lexer.next_token().token_type {
+            TokenType::Identifier(name) => assert_eq!(name, expected),
+            _ => panic!("Expected Identifier"),
+        }
+    }
+    assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+}
+
+#[test]
+fn test_lexer_unterminated_block_comment() {
+    let source = "a /* outer /* nested */ never closed";
+    let mut lexer = Lexer::new(source.to_string());
+    while lexer.next_token().token_type != TokenType::Eof {}
+    let diagnostics = lexer.take_diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].to_string(), "Unterminated block comment at line 1, column 3");
+}
+
+#[test]
+fn test_lexer_recovers_from_errors() {
+    let source = "a # b & c 99999999999 \"open";
+    let mut lexer = Lexer::new(source.to_string());
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token().token_type;
+        if token == TokenType::Eof {
+            break;
+        }
+        tokens.push(token);
+    }
+    // 非法字符被跳过，超出范围的整数按 0 处理，未结束的字符串截止到源码末尾
+    assert_eq!(tokens, vec![
+        TokenType::Identifier("a".to_string()),
+        TokenType::Identifier("b".to_string()),
+        TokenType::Identifier("c".to_string()),
+        TokenType::IntLiteral(0),
+        TokenType::StringLiteral("open".to_string()),
+    ]);
+    let messages: Vec<String> = lexer.take_diagnostics().iter().map(|diagnostic| diagnostic.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Unexpected character '#' at line 1, column 3",
+        "Unexpected character '&' at line 1, column 7",
+        "Integer literal out of range: 99999999999 at line 1, column 11",
+        "Unterminated string at line 1, column 23",
+    ]);
+}
+
+#[test]
+fn test_lexer_doc_comments() {
+    let source = "/// first line\n///second line\n// plain\nfn f\n//// not doc\nlet";
+    let mut lexer = Lexer::new(source.to_string());
+
+    let token = lexer.next_token();
+    assert_eq!(token.token_type, TokenType::Fn);
+    assert_eq!(token.doc.as_deref(), Some("first line\nsecond line"));
+    assert_eq!(lexer.next_token().doc, None);
+    let token = lexer.next_token();
+    assert_eq!(token.token_type, TokenType::Let);
+    assert_eq!(token.doc, None);
+}
+
+#[test]
+fn test_lexer_spans() {
+    let source = "let x = 10;\n  print(\"hi\");";
+    let mut lexer = Lexer::new(source.to_string());
+
+    let token = lexer.next_token();
+    assert_eq!((token.span.start.line, token.span.start.column), (1, 1));
+    assert_eq!((token.span.end.line, token.span.end.column), (1, 4));
+    assert_eq!((token.span.start.offset, token.span.end.offset), (0, 3));
+    lexer.next_token(); // x
+    lexer.next_token(); // =
+    let token = lexer.next_token();
+    assert_eq!(token.token_type, TokenType::IntLiteral(10));
+    assert_eq!((token.line, token.column), (1, 9));
+    assert_eq!(&source[token.span.start.offset..token.span.end.offset], "10");
+    lexer.next_token(); // ;
+    let token = lexer.next_token();
+    assert_eq!((token.line, token.column), (2, 3));
+    lexer.next_token(); // (
+    let token = lexer.next_token();
+    assert_eq!(&source[token.span.start.offset..token.span.end.offset], "\"hi\"");
+    assert_eq!((token.span.end.line, token.span.end.column), (2, 13));
+}
+
+#[test]
+fn test_lexer_spans_utf8_crlf_tabs() {
+    let source = "名字 = \"值\";\r\n\tb\r\n\t  c";
+    let mut lexer = Lexer::new(source.to_string());
+
+    let token = lexer.next_token();
+    assert_eq!(token.token_type, TokenType::Identifier("名字".to_string()));
+    assert_eq!((token.span.start.column, token.span.end.column), (1, 3));
+    assert_eq!(token.span.end.offset, "名字".len());
+    lexer.next_token(); // =
+    let token = lexer.next_token();
+    assert_eq!((token.column, token.span.end.column), (6, 9));
+    lexer.next_token(); // ;
+    let token = lexer.next_token();
+    assert_eq!(token.token_type, TokenType::Identifier("b".to_string()));
+    assert_eq!((token.line, token.column), (2, 5));
+    let token = lexer.next_token();
+    assert_eq!((token.line, token.column), (3, 7));
+    let eof = lexer.next_token();
+    assert_eq!(eof.span.start, eof.span.end);
+    assert_eq!(eof.span.start.offset, source.len());
+}
+
+#[test]
+fn test_lexer_tab_width() {
+    let mut lexer = Lexer::new("\tx\n ab\tc".to_string());
+    lexer.set_tab_width(8);
+
+    assert_eq!(lexer.next_token().column, 9);
+    assert_eq!(lexer.next_token().column, 2);
+    assert_eq!(lexer.next_token().column, 9);
+}
+
+#[test]
+fn test_lexer_multiline_string_span() {
+    let source = "a \"one\ntwo\" b";
+    let mut lexer = Lexer::new(source.to_string());
+
+    lexer.next_token();
+    let token = lexer.next_token();
+    assert_eq!((token.span.start.line, token.span.start.column), (1, 3));
+    assert_eq!((token.span.end.line, token.span.end.column), (2, 5));
+    let token = lexer.next_token();
+    assert_eq!((token.line, token.column), (2, 6));
+}
+
+#[test]
+fn test_lexer_interpolation_expr_position() {
+    let source = "\n  f\"a {b}\"";
+    let mut lexer = Lexer::new(source.to_string());
+
+    match lexer.next_token().token_type {
+        TokenType::InterpolatedString(parts) => match &parts[1] {
+            StringPart::Expr(text, start) => {
+                assert_eq!(text, "b");
+                assert_eq!((start.line, start.column), (2, 8));
+                assert_eq!(&source[start.offset..start.offset + 1], "b");
+            },
+            _ => panic!("Expected Expr"),
+        },
+        _ => panic!("Expected InterpolatedString"),
+    }
+}
+
+#[test]
+fn test_lexer_arrow() {
+    let source = "-> - -- -1";
+    let mut lexer = Lexer::new(source.to_string());
+
+    assert_eq!(lexer.next_token().token_type, TokenType::Arrow);
+    assert_eq!(lexer.next_token().token_type, TokenType::Minus);
+    assert_eq!(lexer.next_token().token_type, TokenType::Decrement);
+    assert_eq!(lexer.next_token().token_type, TokenType::Minus);
+    assert_eq!(lexer.next_token().token_type, TokenType::IntLiteral(1));
+    assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+}
+
+#[test]
+fn test_lexer_enum_and_match_tokens() {
+    let source = "enum match Reply::Ok => = ==";
+    let mut lexer = Lexer::new(source.to_string());
+
+    assert_eq!(lexer.next_token().token_type, TokenType::Enum);
+    assert_eq!(lexer.next_token().token_type, TokenType::Match);
+    assert_eq!(lexer.next_token().token_type, TokenType::Identifier("Reply".to_string()));
+    assert_eq!(lexer.next_token().token_type, TokenType::DoubleColon);
+    assert_eq!(lexer.next_token().token_type, TokenType::Identifier("Ok".to_string()));
+    assert_eq!(lexer.next_token().token_type, TokenType::FatArrow);
+    assert_eq!(lexer.next_token().token_type, TokenType::Equal);
+    assert_eq!(lexer.next_token().token_type, TokenType::Equals);
+    assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+}
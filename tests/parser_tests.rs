@@ -1,6 +1,6 @@
-use std::os::linux::raw::stat;
+#![allow(clippy::approx_constant, clippy::bool_assert_comparison, clippy::single_match)]
 
-use dkv_script::{Lexer, Parser, ASTNode};
+use dkv_script::{Lexer, Parser, ASTNode, Pattern};
 
 #[test]
 fn test_parser_int_literal() {
@@ -21,7 +21,7 @@ fn test_parser_int_literal() {
                 count += 1;
                 let initializer = &**initializer.as_ref().unwrap();
                 match initializer {
-                    ASTNode::IntLiteral(value) => assert_eq!(*value, 42),
+                    ASTNode::IntLiteral(value, _) => assert_eq!(*value, 42),
                     _ => panic!("Expected IntLiteral"),
                 }
             });
@@ -50,7 +50,7 @@ fn test_parser_float_literal() {
                 count += 1;
                 let initializer = &**initializer.as_ref().unwrap();
                 match initializer {
-                    ASTNode::FloatLiteral(value) => assert!((*value - 3.14).abs() < 0.001),
+                    ASTNode::FloatLiteral(value, _) => assert!((*value - 3.14).abs() < 0.001),
                     _ => panic!("Expected FloatLiteral"),
                 }
             });
@@ -79,7 +79,7 @@ fn test_parser_bool_literal() {
                 count += 1;
                 let initializer = &**initializer.as_ref().unwrap();
                 match initializer {
-                    ASTNode::BoolLiteral(value) => assert_eq!(*value, true),
+                    ASTNode::BoolLiteral(value, _) => assert_eq!(*value, true),
                     _ => panic!("Expected BoolLiteral"),
                 }
             });
@@ -92,7 +92,7 @@ fn test_parser_bool_literal() {
                 count += 1;
                 let initializer = &**initializer.as_ref().unwrap();
                 match initializer {
-                    ASTNode::BoolLiteral(value) => assert_eq!(*value, false),
+                    ASTNode::BoolLiteral(value, _) => assert_eq!(*value, false),
                     _ => panic!("Expected BoolLiteral"),
                 }
             });
@@ -121,7 +121,7 @@ fn test_parser_string_literal() {
                 count += 1;
                 let initializer = &**initializer.as_ref().unwrap();
                 match initializer {
-                    ASTNode::StringLiteral(ref value) => assert_eq!(value, "hello world"),
+                    ASTNode::StringLiteral(ref value, _) => assert_eq!(value, "hello world"),
                     _ => panic!("Expected StringLiteral"),
                 }
             });
@@ -146,7 +146,7 @@ fn test_parser_variable_declaration() {
                     assert_eq!(name, "x");
                     assert_eq!(type_name, "int");
                     match expr.as_ref() {
-                        ASTNode::IntLiteral(value) => assert_eq!(*value, 42),
+                        ASTNode::IntLiteral(value, _) => assert_eq!(*value, 42),
                         _ => panic!("Expected IntLiteral"),
                     }
                 },
@@ -168,10 +168,10 @@ fn test_parser_assignment() {
         ASTNode::Program(ref statements) => {
             for stat in statements {
                 match stat.as_ref() {
-                    ASTNode::Assignment(ref name, ref expr) => {
+                    ASTNode::Assignment(ref name, ref expr, _) => {
                         assert_eq!(name, "x");
                         match expr.as_ref() {
-                            ASTNode::IntLiteral(value) => assert_eq!(*value, 42),
+                            ASTNode::IntLiteral(value, _) => assert_eq!(*value, 42),
                             _ => panic!("Expected IntLiteral"),
                         }
                     },
@@ -202,21 +202,21 @@ fn test_parser_binary_expression() {
                 count += 1;
                 let expr = &**expr.as_ref().unwrap();
                 match expr {
-                    ASTNode::BinaryExpr(ref left, ref op, ref right) => {
+                    ASTNode::BinaryExpr(ref left, ref op, ref right, _) => {
                         assert_eq!(op, "+");
                         match left.as_ref() {
-                            ASTNode::IntLiteral(value) => assert_eq!(*value, 1),
+                            ASTNode::IntLiteral(value, _) => assert_eq!(*value, 1),
                             _ => panic!("Expected IntLiteral"),
                         }
                         match right.as_ref() {
-                            ASTNode::BinaryExpr(ref nested_left, ref nested_op, ref nested_right) => {
+                            ASTNode::BinaryExpr(ref nested_left, ref nested_op, ref nested_right, _) => {
                                 assert_eq!(nested_op, "*");
                                 match nested_left.as_ref() {
-                                    ASTNode::IntLiteral(value) => assert_eq!(*value, 2),
+                                    ASTNode::IntLiteral(value, _) => assert_eq!(*value, 2),
                                     _ => panic!("Expected IntLiteral"),
                                 }
                                 match nested_right.as_ref() {
-                                    ASTNode::IntLiteral(value) => assert_eq!(*value, 3),
+                                    ASTNode::IntLiteral(value, _) => assert_eq!(*value, 3),
                                     _ => panic!("Expected IntLiteral"),
                                 }
                             },
@@ -243,7 +243,7 @@ fn test_parser_function_definition() {
         ASTNode::Program(ref statements) => {
             assert_eq!(statements.len(), 1);
             match statements[0].as_ref() {
-                ASTNode::FunctionDef(ref name, ref params, ref body) => {
+                ASTNode::FunctionDef(ref name, ref params, _, ref body, _) => {
                     assert_eq!(name, "add");
                     assert_eq!(params.len(), 2);
                     assert_eq!(params[0].0, "a");
@@ -255,16 +255,16 @@ fn test_parser_function_definition() {
                         ASTNode::Block(ref block_statements) => {
                             assert_eq!(block_statements.len(), 1);
                             match block_statements[0].as_ref() {
-                                ASTNode::Return(Some(ref expr)) => {
+                                ASTNode::Return(Some(ref expr), _) => {
                                     match expr.as_ref() {
-                                        ASTNode::BinaryExpr(ref left, ref op, ref right) => {
+                                        ASTNode::BinaryExpr(ref left, ref op, ref right, _) => {
                                             assert_eq!(op, "+");
                                             match left.as_ref() {
-                                                ASTNode::Identifier(ref id) => assert_eq!(id, "a"),
+                                                ASTNode::Identifier(ref id, _) => assert_eq!(id, "a"),
                                                 _ => panic!("Expected Identifier"),
                                             }
                                             match right.as_ref() {
-                                                ASTNode::Identifier(ref id) => assert_eq!(id, "b"),
+                                                ASTNode::Identifier(ref id, _) => assert_eq!(id, "b"),
                                                 _ => panic!("Expected Identifier"),
                                             }
                                         },
@@ -282,4 +282,490 @@ fn test_parser_function_definition() {
         },
         _ => panic!("Expected Program"),
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_parser_cast_expressions() {
+    let source = "let x: float = -a as float * 2; let y: int = int(\"42\");";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    match *ast {
+        ASTNode::Program(ref statements) => {
+            match statements[0].as_ref() {
+                ASTNode::VariableDecl(_, _, Some(ref expr)) => match expr.as_ref() {
+                    ASTNode::BinaryExpr(ref left, ref op, _, _) => {
+                        assert_eq!(op, "*");
+                        match left.as_ref() {
+                            ASTNode::Cast(ref inner, ref type_name, _) => {
+                                assert_eq!(type_name, "float");
+                                assert!(matches!(inner.as_ref(), ASTNode::UnaryExpr(_, _, _)));
+                            },
+                            _ => panic!("Expected Cast"),
+                        }
+                    },
+                    _ => panic!("Expected BinaryExpr"),
+                },
+                _ => panic!("Expected VariableDecl"),
+            }
+            match statements[1].as_ref() {
+                ASTNode::VariableDecl(_, _, Some(ref expr)) => match expr.as_ref() {
+                    ASTNode::Cast(ref inner, ref type_name, _) => {
+                        assert_eq!(type_name, "int");
+                        assert!(matches!(inner.as_ref(), ASTNode::StringLiteral(_, _)));
+                    },
+                    _ => panic!("Expected Cast"),
+                },
+                _ => panic!("Expected VariableDecl"),
+            }
+        },
+        _ => panic!("Expected Program"),
+    }
+}
+
+#[test]
+fn test_parser_doc_comments_attach_to_declarations() {
+    let source = "/// Retry limit\nlet retries: int = 3;\n/* block */ /// Adds numbers\n/// together\nfn add(a int, b int) { return a + b; }\n/// ignored\nretries = 4;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    match *ast {
+        ASTNode::Program(ref statements) => {
+            match statements[0].as_ref() {
+                ASTNode::Documented(ref doc, ref node) => {
+                    assert_eq!(doc, "Retry limit");
+                    assert!(matches!(node.as_ref(), ASTNode::VariableDecl(name, _, _) if name == "retries"));
+                },
+                _ => panic!("Expected Documented"),
+            }
+            match statements[1].as_ref() {
+                ASTNode::Documented(ref doc, ref node) => {
+                    assert_eq!(doc, "Adds numbers\ntogether");
+                    assert!(matches!(node.as_ref(), ASTNode::FunctionDef(name, ..) if name == "add"));
+                },
+                _ => panic!("Expected Documented"),
+            }
+            assert!(matches!(statements[2].as_ref(), ASTNode::Assignment(_, _, _)));
+            assert_eq!(statements.len(), 3);
+        },
+        _ => panic!("Expected Program"),
+    }
+}
+
+#[test]
+fn test_parser_collects_multiple_errors() {
+    let source = "let a: int = ;\nlet b: int = 2;\nfn f(x int) {\n    y = (1 + ;\n    print(x);\n}\nlet : int = 3;\nprint(b);";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let (ast, diagnostics) = parser.parse_with_diagnostics();
+
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!((diagnostics[0].span.start.line, diagnostics[0].span.start.column), (1, 14));
+    assert!(diagnostics[0].message.contains("Unexpected token in primary expression"));
+    assert!(diagnostics[0].expected.contains(&"identifier".to_string()));
+    assert_eq!((diagnostics[1].span.start.line, diagnostics[1].span.start.column), (4, 14));
+    assert_eq!(diagnostics[2].message, "Expected identifier after 'let'");
+    assert_eq!(diagnostics[2].span.start.line, 7);
+
+    // 出错语句之外的部分仍然被解析
+    match *ast {
+        ASTNode::Program(ref statements) => {
+            assert!(statements.iter().any(|stmt| matches!(stmt.as_ref(), ASTNode::VariableDecl(name, _, _) if name == "b")));
+            assert!(statements.iter().any(|stmt| matches!(stmt.as_ref(), ASTNode::FunctionDef(name, ..) if name == "f")));
+            assert!(statements.iter().any(|stmt| matches!(stmt.as_ref(), ASTNode::ExprStatement(call) if matches!(call.as_ref(), ASTNode::FunctionCall(name, _, _) if name == "print"))));
+        },
+        _ => panic!("Expected Program"),
+    }
+}
+
+#[test]
+fn test_parser_reports_lexical_errors() {
+    let source = "let a: int = 3000000000;\nlet b: int = 1 #;\nlet s: string = f\"{}\";\nprint(\"never closed);";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let (_, diagnostics) = parser.parse_with_diagnostics();
+
+    let messages: Vec<String> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+    assert_eq!(messages, [
+        "Integer literal out of range: 3000000000 at line 1, column 14",
+        "Unexpected character '#' at line 2, column 16",
+        "Empty expression in interpolated string at line 3, column 20",
+        "Unterminated string at line 4, column 7",
+        "Expected token RParen, but got Eof at line 4, column 22",
+    ]);
+}
+
+#[test]
+#[should_panic(expected = "Unexpected character '@' at line 1, column 14")]
+fn test_parser_parse_panics_on_lexical_error() {
+    let lexer = Lexer::new("let x: int = @;".to_string());
+    Parser::new(lexer).parse();
+}
+
+#[test]
+fn test_parser_expected_tokens() {
+    let source = "let x: int = 1\nlet y: int = 2;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let (_, diagnostics) = parser.parse_with_diagnostics();
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].expected, vec!["';'".to_string()]);
+    assert_eq!((diagnostics[0].span.start.line, diagnostics[0].span.start.column), (2, 1));
+}
+
+#[test]
+fn test_parser_unclosed_block_terminates() {
+    let source = "fn f() { let x: int = 1;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let (_, diagnostics) = parser.parse_with_diagnostics();
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].expected, vec!["'}'".to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "Expected token Semicolon, but got Let at line 2, column 1")]
+fn test_parser_parse_panics_on_first_error() {
+    let source = "let x: int = 1\nlet y: int = 2;\n)";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    parser.parse();
+}
+
+#[test]
+fn test_parser_else_if_chain() {
+    let source = "if a { x = 1; } else if b { x = 2; } else { x = 3; }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    match *ast {
+        ASTNode::Program(ref statements) => {
+            assert_eq!(statements.len(), 1);
+            let ASTNode::IfStatement(_, _, Some(ref else_branch)) = *statements[0] else {
+                panic!("Expected IfStatement with else branch");
+            };
+            let ASTNode::Block(ref nested) = **else_branch else {
+                panic!("Expected Block");
+            };
+            assert_eq!(nested.len(), 1);
+            assert!(matches!(nested[0].as_ref(), ASTNode::IfStatement(_, _, Some(_))));
+        },
+        _ => panic!("Expected Program"),
+    }
+}
+
+#[test]
+fn test_parser_expression_statements() {
+    let source = "f(x) + 1;\n\"literal\";\n(a);\nx;\nx++;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    match *ast {
+        ASTNode::Program(ref statements) => {
+            assert_eq!(statements.len(), 5);
+            assert!(matches!(statements[0].as_ref(), ASTNode::ExprStatement(expr) if matches!(expr.as_ref(), ASTNode::BinaryExpr(..))));
+            assert!(matches!(statements[1].as_ref(), ASTNode::ExprStatement(_)));
+            assert!(matches!(statements[2].as_ref(), ASTNode::ExprStatement(_)));
+            assert!(matches!(statements[3].as_ref(), ASTNode::ExprStatement(expr) if matches!(expr.as_ref(), ASTNode::Identifier(_, _))));
+            assert!(matches!(statements[4].as_ref(), ASTNode::Increment(_, _)));
+        },
+        _ => panic!("Expected Program"),
+    }
+}
+
+#[test]
+#[should_panic(expected = "Expected token Semicolon, but got RBrace at line 1, column 19")]
+fn test_parser_requires_semicolon_after_assignment() {
+    let source = "fn main() { x = 1 }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    parser.parse();
+}
+
+#[test]
+#[should_panic(expected = "Expected token Semicolon, but got Identifier(\"print\") at line 2, column 1")]
+fn test_parser_requires_semicolon_after_call() {
+    let source = "print(1)\nprint(2);";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    parser.parse();
+}
+
+#[test]
+fn test_parser_function_return_type() {
+    let source = "fn name(id int) -> string { return \"x\"; } fn log(msg string) { print(msg); }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::FunctionDef(name, params, return_type, ..) => {
+            assert_eq!(name, "name");
+            assert_eq!(params, &vec![("id".to_string(), "int".to_string())]);
+            assert_eq!(return_type.as_deref(), Some("string"));
+        },
+        _ => panic!("Expected FunctionDef"),
+    }
+    assert!(matches!(statements[1].as_ref(), ASTNode::FunctionDef(_, _, None, ..)));
+}
+
+#[test]
+fn test_parser_lambda_and_indirect_call() {
+    let source = "let f: fn = fn(x int) -> int { return x + 1; }; f(1)(2);";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::VariableDecl(name, type_name, Some(init)) => {
+            assert_eq!(name, "f");
+            assert_eq!(type_name, "fn");
+            match init.as_ref() {
+                ASTNode::Lambda(params, return_type, _, _) => {
+                    assert_eq!(params, &vec![("x".to_string(), "int".to_string())]);
+                    assert_eq!(return_type.as_deref(), Some("int"));
+                },
+                _ => panic!("Expected Lambda"),
+            }
+        },
+        _ => panic!("Expected VariableDecl"),
+    }
+    match statements[1].as_ref() {
+        ASTNode::ExprStatement(expr) => match expr.as_ref() {
+            ASTNode::IndirectCall(callee, args, _) => {
+                assert!(matches!(callee.as_ref(), ASTNode::FunctionCall(name, _, _) if name == "f"));
+                assert_eq!(args.len(), 1);
+            },
+            _ => panic!("Expected IndirectCall"),
+        },
+        _ => panic!("Expected ExprStatement"),
+    }
+}
+
+#[test]
+fn test_parser_struct_def_literal_and_fields() {
+    let source = "struct User { name: string, age: int, } let u: User = User { name: \"a\", age: 1 }; u.age = u.age + 1;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::StructDef(name, fields) => {
+            assert_eq!(name, "User");
+            assert_eq!(fields, &vec![("name".to_string(), "string".to_string()), ("age".to_string(), "int".to_string())]);
+        },
+        _ => panic!("Expected StructDef"),
+    }
+    match statements[1].as_ref() {
+        ASTNode::VariableDecl(_, type_name, Some(init)) => {
+            assert_eq!(type_name, "User");
+            match init.as_ref() {
+                ASTNode::StructLiteral(name, fields, _) => {
+                    assert_eq!(name, "User");
+                    let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+                    assert_eq!(names, vec!["name", "age"]);
+                },
+                _ => panic!("Expected StructLiteral"),
+            }
+        },
+        _ => panic!("Expected VariableDecl"),
+    }
+    match statements[2].as_ref() {
+        ASTNode::FieldAssignment(object, field, value, _) => {
+            assert!(matches!(object.as_ref(), ASTNode::Identifier(name, _) if name == "u"));
+            assert_eq!(field, "age");
+            assert!(matches!(value.as_ref(), ASTNode::BinaryExpr(left, _, _, _) if matches!(left.as_ref(), ASTNode::FieldAccess(_, field, _) if field == "age")));
+        },
+        _ => panic!("Expected FieldAssignment"),
+    }
+}
+
+#[test]
+fn test_parser_condition_brace_is_not_struct_literal() {
+    let source = "if ready { print(1); } while p == (Point { x: 1 }) { }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::IfStatement(condition, _, None) => {
+            assert!(matches!(condition.as_ref(), ASTNode::Identifier(name, _) if name == "ready"));
+        },
+        _ => panic!("Expected IfStatement"),
+    }
+    match statements[1].as_ref() {
+        ASTNode::WhileLoop(condition, _) => {
+            assert!(matches!(condition.as_ref(), ASTNode::BinaryExpr(_, _, right, _) if matches!(right.as_ref(), ASTNode::StructLiteral(..))));
+        },
+        _ => panic!("Expected WhileLoop"),
+    }
+}
+
+#[test]
+fn test_parser_enum_and_match() {
+    let source = "enum Reply { Ok, Error(string), Pair(int, int) } let n: int = match r { Reply::Pair(a, _) => a, Reply::Ok => { return 0; } _ => 1 };";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::EnumDef(name, variants) => {
+            assert_eq!(name, "Reply");
+            assert_eq!(variants[0], ("Ok".to_string(), Vec::new()));
+            assert_eq!(variants[1], ("Error".to_string(), vec!["string".to_string()]));
+            assert_eq!(variants[2], ("Pair".to_string(), vec!["int".to_string(), "int".to_string()]));
+        },
+        _ => panic!("Expected EnumDef"),
+    }
+    match statements[1].as_ref() {
+        ASTNode::VariableDecl(_, _, Some(value)) => match value.as_ref() {
+            ASTNode::Match(scrutinee, arms, _) => {
+                assert!(matches!(scrutinee.as_ref(), ASTNode::Identifier(name, _) if name == "r"));
+                assert_eq!(arms.len(), 3);
+                assert_eq!(arms[0].0, Pattern::Variant("Reply".to_string(), "Pair".to_string(), vec!["a".to_string(), "_".to_string()]));
+                assert!(matches!(arms[1].1.as_ref(), ASTNode::Block(_)));
+                assert_eq!(arms[2].0, Pattern::Wildcard);
+            },
+            _ => panic!("Expected Match"),
+        },
+        _ => panic!("Expected VariableDecl"),
+    }
+}
+
+#[test]
+fn test_parser_enum_variant_expression() {
+    let source = "let r: Reply = Reply::Error(\"boom\" + \"!\");";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::VariableDecl(_, _, Some(value)) => match value.as_ref() {
+            ASTNode::EnumVariant(enum_name, variant, args, _) => {
+                assert_eq!(enum_name, "Reply");
+                assert_eq!(variant, "Error");
+                assert_eq!(args.len(), 1);
+            },
+            _ => panic!("Expected EnumVariant"),
+        },
+        _ => panic!("Expected VariableDecl"),
+    }
+}
+
+#[test]
+fn test_parser_try_catch_finally() {
+    let source = "try { throw \"x\"; } catch (e) { print(e); } finally { print(1); } try { print(2); } finally { }";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::TryCatch(body, Some((name, _)), Some(_)) => {
+            assert_eq!(name, "e");
+            let ASTNode::Block(body) = body.as_ref() else {
+                panic!("Expected Block");
+            };
+            assert!(matches!(body[0].as_ref(), ASTNode::Throw(_)));
+        },
+        _ => panic!("Expected TryCatch"),
+    }
+    assert!(matches!(statements[1].as_ref(), ASTNode::TryCatch(_, None, Some(_))));
+}
+
+#[test]
+fn test_parser_import() {
+    let source = "import \"lib/util.dkvs\"; import \"x.dkvs\" as y; import { a, b } from \"z.dkvs\"; util::f(1); print(y::v); Reply::Ok;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    assert!(matches!(statements[0].as_ref(), ASTNode::Import(path, Some(alias), names, _) if path == "lib/util.dkvs" && alias == "util" && names.is_empty()));
+    assert!(matches!(statements[1].as_ref(), ASTNode::Import(_, Some(alias), ..) if alias == "y"));
+    match statements[2].as_ref() {
+        ASTNode::Import(path, None, names, _) => {
+            assert_eq!(path, "z.dkvs");
+            assert_eq!(names, &vec!["a".to_string(), "b".to_string()]);
+        },
+        _ => panic!("Expected Import"),
+    }
+    // 模块别名后的 :: 是模块中的名字，其余仍是枚举值
+    assert!(matches!(statements[3].as_ref(), ASTNode::ExprStatement(call) if matches!(call.as_ref(), ASTNode::FunctionCall(name, _, _) if name == "util::f")));
+    let ASTNode::ExprStatement(print) = statements[4].as_ref() else {
+        panic!("Expected ExprStatement");
+    };
+    assert!(matches!(print.as_ref(), ASTNode::FunctionCall(_, args, _) if matches!(args[0].as_ref(), ASTNode::Identifier(name, _) if name == "y::v")));
+    assert!(matches!(statements[5].as_ref(), ASTNode::ExprStatement(variant) if matches!(variant.as_ref(), ASTNode::EnumVariant(..))));
+}
+
+#[test]
+fn test_parser_const_decl() {
+    let source = "/// 最大值\nconst MAX: int = 1 + 2;";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    match statements[0].as_ref() {
+        ASTNode::Documented(doc, decl) => {
+            assert_eq!(doc, "最大值");
+            assert!(matches!(decl.as_ref(), ASTNode::ConstDecl(name, type_name, value)
+                if name == "MAX" && type_name == "int" && matches!(value.as_ref(), ASTNode::BinaryExpr(..))));
+        },
+        _ => panic!("Expected Documented"),
+    }
+}
+
+#[test]
+fn test_parser_expression_spans() {
+    let source = "let x: int = 0;\nx = (a + b.c) * f(1);";
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+
+    let ASTNode::Program(statements) = *ast else {
+        panic!("Expected Program");
+    };
+    let ASTNode::Assignment(_, expr, name_span) = statements[1].as_ref() else {
+        panic!("Expected Assignment");
+    };
+    let range = |span: dkv_script::Span| (span.start.line, span.start.column, span.end.column);
+    assert_eq!(range(*name_span), (2, 1, 2));
+    // 乘法的区间从左操作数开始（括号不计入）到函数调用的右括号
+    assert_eq!(range(expr.span()), (2, 6, 21));
+    let ASTNode::BinaryExpr(left, _, right, _) = expr.as_ref() else {
+        panic!("Expected BinaryExpr");
+    };
+    assert_eq!(range(left.span()), (2, 6, 13));
+    assert_eq!(range(right.span()), (2, 17, 21));
+}
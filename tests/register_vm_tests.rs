@@ -0,0 +1,328 @@
+use dkv_script::{Backend, Compiler, Lexer, Operand, Parser, RegInstr, RuntimeError, VM};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 运行程序，返回 print 的输出和 try_run 的结果
+fn run(source: &str, backend: Backend, level: u8) -> (Vec<String>, Result<(), RuntimeError>) {
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let compile_result = Compiler::new().with_optimization_level(level).with_backend(backend).compile(&ast);
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.set_dkv_command_handler(Some(|command: &str| match command.split_once(' ') {
+        Some(("GET", "a")) => Ok("1".to_string()),
+        Some(("SET", _)) => Ok("OK".to_string()),
+        _ => Err(format!("unknown command: {}", command)),
+    }));
+    let result = vm.try_run();
+    let output = output.borrow().clone();
+    (output, result)
+}
+
+// 在各优化级别下比较两种后端的输出和结果
+fn assert_same(source: &str) {
+    for level in 0..=2 {
+        let expected = run(source, Backend::Stack, level);
+        let actual = run(source, Backend::Register, level);
+        assert_eq!(actual, expected, "-O{}\n{}", level, source);
+    }
+}
+
+#[test]
+fn test_register_vm_arithmetic_and_strings() {
+    assert_same(r#"
+fn main() {
+    let a: int = 7;
+    let b: float = 2.5;
+    print(a + b * 2);
+    print(a / 2 - -a);
+    print(float(a) / 2);
+    print(!(a < 3) == (b >= 2.5));
+    let s: string = "x";
+    s = s + string(a) + "y";
+    print(s);
+    print(f"{a} and {b} and {s}");
+    print(len(s) + find(s, "y"));
+    print(upper(substr("hello", 1, 3)) + trim("  t "));
+    print(int("12") + parse_int("z", 5));
+    print(s[1]);
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_loops_and_recursion() {
+    assert_same(r#"
+fn fib(n int) -> int {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+fn main() {
+    let i: int = 0;
+    let sum: int = 0;
+    while i < 10 {
+        sum = sum + i;
+        i++;
+    }
+    print(sum);
+    for i = 0; i < 3; i++ {
+        print(fib(i + 10));
+    }
+    let n: int = 5;
+    while 0 < n { n--; }
+    print(n);
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_operand_order() {
+    // 局部变量作为操作数延迟读取，在它被修改前必须先读出
+    assert_same(r#"
+enum Wrapper { Some(int) }
+fn pair(a int, b int) -> string {
+    return f"{a},{b}";
+}
+fn main() {
+    let x: int = 1;
+    let y: int = 2;
+    let t: int = x;
+    x = y;
+    y = t;
+    print(pair(x, y));
+    let items: array = [x, y, x + y];
+    x = 0;
+    print(items);
+    let n: int = 5;
+    match Wrapper::Some(n + 1) {
+        Wrapper::Some(n) => print(pair(n, n + 1)),
+    }
+    print(n);
+    let s: string = "a";
+    s = s + s;
+    s = f"{s}-{s}";
+    print(s);
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_closures() {
+    assert_same(r#"
+fn make_counter() -> fn {
+    let count: int = 0;
+    return fn() -> int {
+        count++;
+        return count;
+    };
+}
+fn twice(f fn, x int) -> int {
+    return f(f(x));
+}
+fn main() {
+    let a: fn = make_counter();
+    a();
+    print(a());
+    print(make_counter()());
+    let total: int = 0;
+    let add: fn = fn(n int) { total = total + n; };
+    add(5);
+    total = 100;
+    add(1);
+    print(total);
+    let scale: int = 3;
+    print(twice(fn(x int) -> int { return x * scale; }, 2));
+    let callbacks: array = [];
+    let i: int = 0;
+    while i < 3 {
+        let n: int = i * 10;
+        callbacks = [callbacks, fn() -> int { return n; }];
+        i++;
+    }
+    print(callbacks[1]() + callbacks[0][1]());
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_structs_and_enums() {
+    assert_same(r#"
+struct Point { x: int, y: int }
+struct Line { from: Point, to: Point, name: string }
+enum Reply { Ok, Nil, Error(string), Value(string, int) }
+fn shift(p Point, dx int) -> Point {
+    p.x = p.x + dx;
+    return p;
+}
+fn describe(r Reply) -> string {
+    return match r {
+        Reply::Ok => "ok",
+        Reply::Nil => "nil",
+        Reply::Error(msg) => "error: " + msg,
+        Reply::Value(v, n) => f"{v} x{n}",
+    };
+}
+fn main() {
+    let p: Point = Point { y: 2, x: 1 };
+    let q: Point = shift(p, 9);
+    print(p);
+    let l: Line = Line { from: p, to: q, name: "l" };
+    l.to.y = 42;
+    print(l);
+    print(q.y);
+    print(p == (Point { x: 1, y: 2 }));
+    let replies: array = [Reply::Ok, Reply::Error("boom"), Reply::Value("v", 3), Reply::Nil];
+    let i: int = 0;
+    while i < len(replies) {
+        print(describe(replies[i]));
+        i++;
+    }
+    match try_command("GET a") {
+        Result::Ok(value) => print(value),
+        Result::Err(err) => print(err),
+    }
+    print(Option::Some(p));
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_exceptions() {
+    assert_same(r#"
+fn check(n int) -> int {
+    if n < 0 {
+        throw "negative: " + string(n);
+    }
+    return n;
+}
+fn early() -> int {
+    try {
+        return 1;
+    } finally {
+        print("finally");
+    }
+}
+fn main() {
+    try {
+        print(check(1) + 1);
+        print(check(-2));
+    } catch (e) {
+        print(e.message);
+        print(join(e.trace, " <- "));
+    }
+    print(early());
+    let items: array = [1, 2];
+    try {
+        try {
+            print(items[5]);
+        } catch (inner) {
+            print("inner: " + inner.message);
+            throw "from catch";
+        } finally {
+            print("inner finally");
+        }
+    } catch (outer) {
+        print("outer: " + outer.message);
+    }
+    let i: int = 0;
+    while i < 3 {
+        try {
+            if i == 1 {
+                throw "skip";
+            }
+            print(i + 100 / (2 - i));
+        } catch (e) {
+            print(e.message);
+        }
+        i++;
+    }
+    print(command("GET a") + command("GET b"));
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_globals_constants_and_uncaught_errors() {
+    assert_same(r#"
+const LIMIT: int = 4 * 10 + 2;
+let counter: int = 0;
+fn bump() -> int {
+    counter = counter + LIMIT;
+    return counter;
+}
+bump();
+print(bump());
+print(counter);
+"#);
+    assert_same("fn fail() { throw \"bad\"; } fn main() { print(\"before\"); fail(); print(\"after\"); }");
+    assert_same("fn main() { let zero: int = 0; print(1 / zero); }");
+    assert_same("fn inc(x int) -> int { return x + 1; } fn main() { let f: fn = inc; f(1, 2); }");
+}
+
+#[test]
+fn test_register_vm_allocates_locals_as_registers() {
+    // 局部变量直接作为操作数，结果直接写入局部变量，不经过临时寄存器
+    let source = "fn add(a int, b int) -> int { let c: int = a + b; return c; } fn main() { print(add(1, 2)); }";
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let compile_result = Compiler::new().with_backend(Backend::Register).compile(&ast);
+    let add = compile_result.register_functions.iter().find(|function| function.name == "add").unwrap();
+    assert_eq!(add.register_count, 3);
+    assert_eq!(add.code[0], RegInstr::Binary {
+        op: dkv_script::OpCode::Add,
+        dst: 2,
+        lhs: Operand::Reg(0),
+        rhs: Operand::Reg(1),
+    });
+    assert_eq!(add.code[1], RegInstr::Ret { src: Operand::Reg(2) });
+    assert!(Compiler::new().compile(&ast).register_functions.is_empty());
+}
+
+#[test]
+fn test_register_vm_superinstructions_and_handlers() {
+    assert_same(r#"
+struct Counter { count: int }
+fn main() {
+    let i: int = 0;
+    let total: float = 0.5;
+    let label: string = "x";
+    while i < 5 {
+        total = total + 2;
+        label = label + "y";
+        i++;
+    }
+    print(total);
+    print(label);
+    let c: Counter = Counter { count: 0 };
+    let inc: fn = fn() { c.count = c.count + 1; };
+    inc();
+    inc();
+    print(c.count);
+    let items: array = [1, "two"];
+    let k: int = 0;
+    try {
+        while items[1] < k { k++; }
+    } catch (e) {
+        print(e.message);
+    }
+    let parts: array = split("a,b,c", ",");
+    print(join(parts, "+") + format("{}-{}", len(parts), parts[2]));
+}
+"#);
+}
+
+#[test]
+fn test_register_vm_examples() {
+    let mut paths: Vec<_> = std::fs::read_dir("examples").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "dkvs"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        assert_same(&std::fs::read_to_string(path).unwrap());
+    }
+}
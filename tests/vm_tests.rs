@@ -1,4 +1,9 @@
-use dkv_script::{Lexer, Parser, Compiler, VM};
+use dkv_script::{do_compile, Backend, Lexer, Parser, Compiler, Program, SendVM, VM};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 #[test]
 fn test_vm_constant() {
@@ -77,4 +82,1034 @@ fn test_vm_variable_declaration_and_assignment() {
     
     let mut vm = VM::new(compile_result);
     vm.run();
-}
\ No newline at end of file
+}
+fn run_and_capture(source: &str) -> Vec<String> {
+    let compile_result = do_compile(source).unwrap();
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.run();
+    let result = output.borrow().clone();
+    result
+}
+
+#[test]
+fn test_vm_mixed_numeric_promotion() {
+    let output = run_and_capture("print(1 + 2.5); print(3 * 0.5); print(1 < 1.5); print(2 == 2.0); print(7 / 2);");
+    assert_eq!(output, vec!["3.5", "1.5", "true", "true", "3"]);
+}
+
+#[test]
+fn test_vm_implicit_float_promotion_on_store() {
+    let output = run_and_capture("let i: int = 3; let f: float = i; f = f / 2; print(f); let g: float = 1; print(g / 4);");
+    assert_eq!(output, vec!["1.5", "0.25"]);
+}
+
+#[test]
+fn test_vm_explicit_casts() {
+    let source = r#"
+        let f: float = 3.75;
+        print(f as int);
+        print(int("42") + 1);
+        print(float("2.5") * 2);
+        print(string(3.5) + "!");
+        print(bool("true"));
+        print(-2 as float / 4);
+    "#;
+    let output = run_and_capture(source);
+    assert_eq!(output, vec!["3", "43", "5", "3.5!", "true", "-0.5"]);
+}
+
+#[test]
+fn test_vm_parse_with_default() {
+    let source = r#"
+        print(parse_int("17", 0));
+        print(parse_int("abc", -1));
+        print(parse_float(" 1.25 ", 0.0));
+        print(parse_float("x", 0));
+    "#;
+    let output = run_and_capture(source);
+    assert_eq!(output, vec!["17", "-1", "1.25", "0"]);
+}
+
+#[test]
+#[should_panic(expected = "Cannot convert \"abc\" to int")]
+fn test_vm_invalid_string_to_int_cast() {
+    run_and_capture("print(int(\"abc\"));");
+}
+
+#[test]
+fn test_vm_string_library() {
+    let source = r#"
+        let reply: string = "  value:42  ";
+        let s: string = trim(reply);
+        print(len(s));
+        print(substr(s, 6, 2));
+        print(find(s, ":"));
+        print(find(s, "x"));
+        print(upper(s));
+        print(lower("ABC"));
+        print(starts_with(s, "value"));
+        print(ends_with(s, "41"));
+        print(replace("a-b-c", "-", "+"));
+        print(s[0] + s[len(s) - 1]);
+    "#;
+    let output = run_and_capture(source);
+    assert_eq!(output, vec!["8", "42", "5", "-1", "VALUE:42", "abc", "true", "false", "a+b+c", "v2"]);
+}
+
+#[test]
+fn test_vm_split_join_and_format() {
+    let source = r#"
+        let parts: array = split("k1,k2,k3", ",");
+        print(len(parts));
+        print(parts[1]);
+        print(join(parts, ";"));
+        print(join(["a", 1, true], "/"));
+        print(format("SET {} {} {{}}", "key", 3.5));
+    "#;
+    let output = run_and_capture(source);
+    assert_eq!(output, vec!["3", "k2", "k1;k2;k3", "a/1/true", "SET key 3.5 {}"]);
+}
+
+#[test]
+fn test_vm_string_comparison() {
+    let output = run_and_capture("print(\"abc\" < \"abd\"); print(\"b\" > \"abc\"); print(\"a\" <= \"a\");");
+    assert_eq!(output, vec!["true", "true", "true"]);
+}
+
+#[test]
+#[should_panic(expected = "Index out of range: 3")]
+fn test_vm_string_index_out_of_range() {
+    run_and_capture("let s: string = \"abc\"; print(s[3]);");
+}
+
+#[test]
+fn test_vm_interpolated_string() {
+    let source = r#"
+        let key: string = "user:1";
+        let count: int = 3;
+        let ratio: float = 0.5;
+        print(f"SET {key} {count + 1} {ratio} {count > 2} {{literal}}");
+        print(f"{upper(key)}");
+        print(f"");
+    "#;
+    let output = run_and_capture(source);
+    assert_eq!(output, vec!["SET user:1 4 0.5 true {literal}", "USER:1", ""]);
+}
+
+#[test]
+fn test_vm_else_if_chain() {
+    let source = r#"
+fn classify(n int) {
+    if n < 0 {
+        print("negative");
+    } else if n == 0 {
+        print("zero");
+    } else if n < 10 {
+        print("small");
+    } else {
+        print("large");
+    }
+}
+fn main() {
+    classify(-5);
+    classify(0);
+    classify(3);
+    classify(42);
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["negative", "zero", "small", "large"]);
+}
+
+#[test]
+fn test_vm_expression_statements_discard_values() {
+    let source = r#"
+fn answer() {
+    return 42;
+}
+fn main() {
+    let i: int = 0;
+    while i < 1000 {
+        answer();
+        answer() + 1;
+        command("PING");
+        upper("x");
+        i++;
+    }
+    print(answer());
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["42"]);
+}
+
+#[test]
+fn test_vm_stack_opcodes() {
+    use dkv_script::{CompileResult, Constant, FunctionInfo, OpCode};
+
+    // print(1) 前交换、复制栈顶：压入 1、"a"，交换后打印 1，再复制 "a" 打印两次
+    let print = 0x01u64 | (1 << 16);
+    let program: Vec<(OpCode, u64)> = vec![
+        (OpCode::LoadConst, 1),
+        (OpCode::LoadConst, 2),
+        (OpCode::Swap, 0),
+        (OpCode::Syscall, print),
+        (OpCode::Dup, 0),
+        (OpCode::Syscall, print),
+        (OpCode::Syscall, print),
+        (OpCode::LoadConst, 1),
+        (OpCode::Pop, 0),
+        (OpCode::Exit, 0),
+    ];
+    let mut bytecode = Vec::new();
+    for (opcode, arg) in program {
+        bytecode.push(opcode as u8);
+        bytecode.extend_from_slice(&arg.to_le_bytes());
+    }
+    let compile_result = CompileResult {
+        constants: vec![Constant::Nil, Constant::Int(1), Constant::String("a".to_string())],
+        global_vars: Vec::new(),
+        functions: vec![FunctionInfo {
+            name: "_entrypoint".to_string(),
+            param_count: 0,
+            local_count: 0,
+            bytecode,
+            handlers: Vec::new(),
+        }],
+        structs: Vec::new(),
+        enums: Vec::new(),
+        exported_constants: Vec::new(),
+        entrypoint: 0,
+        register_functions: Vec::new(),
+    };
+
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.run();
+    assert_eq!(*output.borrow(), vec!["1", "a", "a"]);
+}
+
+#[test]
+fn test_vm_typed_functions() {
+    let source = r#"
+fn half(x float) -> float {
+    return x / 2;
+}
+fn label(n int) -> string {
+    if n == 1 {
+        return "one";
+    }
+    return f"{n} items";
+}
+fn main() {
+    print(half(3));
+    print(label(1) + "/" + label(4));
+    print(len(label(12)));
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["1.5", "one/4 items", "8"]);
+}
+
+#[test]
+fn test_vm_forward_references_and_mutual_recursion() {
+    let source = r#"
+fn main() {
+    print(is_even(10));
+    print(is_odd(7));
+    print(describe(3));
+}
+fn is_even(n int) -> bool {
+    if n == 0 {
+        return true;
+    }
+    return is_odd(n - 1);
+}
+fn is_odd(n int) -> bool {
+    if n == 0 {
+        return false;
+    }
+    return is_even(n - 1);
+}
+fn describe(n int) -> string {
+    return f"{prefix}{n * scale}";
+}
+let prefix: string = "value=";
+let scale: int = 2;
+"#;
+    assert_eq!(run_and_capture(source), vec!["true", "true", "value=6"]);
+}
+
+#[test]
+fn test_vm_closure_counter() {
+    let source = r#"
+fn make_counter() -> fn {
+    let count: int = 0;
+    return fn() -> int {
+        count++;
+        return count;
+    };
+}
+fn main() {
+    let a: fn = make_counter();
+    let b: fn = make_counter();
+    a();
+    a();
+    print(a());
+    print(b());
+    print(make_counter()());
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["3", "1", "1"]);
+}
+
+#[test]
+fn test_vm_closure_captures_by_reference() {
+    let source = r#"
+fn main() {
+    let total: int = 0;
+    let add: fn = fn(n int) { total = total + n; };
+    let get: fn = fn() -> int { return total; };
+    add(5);
+    print(total);
+    total = 100;
+    add(1);
+    print(get());
+    let nested: fn = fn() -> fn { return fn() { total++; }; };
+    nested()();
+    print(total);
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["5", "101", "102"]);
+}
+
+#[test]
+fn test_vm_closures_capture_each_loop_iteration() {
+    let source = r#"
+fn main() {
+    let callbacks: array = [];
+    let i: int = 0;
+    while i < 3 {
+        let n: int = i * 10;
+        callbacks = [callbacks, fn() -> int { return n; }];
+        i++;
+    }
+    print(callbacks[1]() + callbacks[0][1]());
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["30"]);
+}
+
+#[test]
+fn test_vm_functions_as_values() {
+    let source = r#"
+fn twice(f fn, x int) -> int {
+    return f(f(x));
+}
+fn inc(x int) -> int {
+    return x + 1;
+}
+fn main() {
+    print(twice(inc, 1));
+    let scale: int = 3;
+    print(twice(fn(x int) -> int { return x * scale; }, 2));
+    let keys: array = ["b", "a"];
+    let each: fn = fn(items array, callback fn) {
+        let i: int = 0;
+        while i < len(items) {
+            callback(items[i]);
+            i++;
+        }
+    };
+    each(keys, fn(key string) { print(upper(key)); });
+    print(inc == inc);
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["3", "18", "B", "A", "true"]);
+}
+
+#[test]
+#[should_panic(expected = "Function inc expects 1 arguments, got 2")]
+fn test_vm_function_value_arity_mismatch() {
+    run_and_capture("fn inc(x int) -> int { return x + 1; } fn main() { let f: fn = inc; f(1, 2); }");
+}
+
+#[test]
+fn test_vm_structs() {
+    let source = r#"
+struct Point { x: int, y: int }
+struct Line { from: Point, to: Point, name: string }
+fn shift(p Point, dx int) -> Point {
+    p.x = p.x + dx;
+    return p;
+}
+fn main() {
+    let p: Point = Point { y: 2, x: 1 };
+    let q: Point = shift(p, 9);
+    print(p);
+    print(q.x + p.y);
+    let l: Line = Line { from: p, to: q, name: "l" };
+    l.to.y = 42;
+    print(l);
+    print(q.y);
+    print(p == (Point { x: 1, y: 2 }));
+    print(p == q);
+}
+"#;
+    assert_eq!(run_and_capture(source), vec![
+        "Point { x: 1, y: 2 }",
+        "12",
+        "Line { from: Point { x: 1, y: 2 }, to: Point { x: 10, y: 42 }, name: l }",
+        "2",
+        "true",
+        "false",
+    ]);
+}
+
+#[test]
+fn test_vm_closure_captures_struct_variable() {
+    let source = r#"
+struct Counter { count: int }
+fn main() {
+    let c: Counter = Counter { count: 0 };
+    let inc: fn = fn() { c.count = c.count + 1; };
+    inc();
+    inc();
+    print(c.count);
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["2"]);
+}
+
+#[test]
+fn test_vm_enums_and_match() {
+    let source = r#"
+enum Reply { Ok, Nil, Error(string), Value(string, int) }
+fn describe(r Reply) -> string {
+    return match r {
+        Reply::Ok => "ok",
+        Reply::Nil => "nil",
+        Reply::Error(msg) => "error: " + msg,
+        Reply::Value(v, n) => f"{v} x{n}",
+    };
+}
+fn is_error(r Reply) -> bool {
+    match r {
+        Reply::Error(_) => { return true; }
+        _ => { return false; }
+    }
+}
+fn main() {
+    let replies: array = [Reply::Ok, Reply::Error("boom"), Reply::Value("v", 3), Reply::Nil];
+    let i: int = 0;
+    while i < len(replies) {
+        print(describe(replies[i]));
+        i++;
+    }
+    let r: Reply = Reply::Error("boom");
+    print(r);
+    print(is_error(r));
+    print(is_error(Reply::Ok));
+    print(r == Reply::Error("boom"));
+    print(r == Reply::Error("other"));
+    match r {
+        Reply::Error(msg) => print(upper(msg)),
+        _ => print("not an error"),
+    }
+}
+"#;
+    assert_eq!(run_and_capture(source), vec![
+        "ok",
+        "error: boom",
+        "v x3",
+        "nil",
+        "Reply::Error(boom)",
+        "true",
+        "false",
+        "true",
+        "false",
+        "BOOM",
+    ]);
+}
+
+#[test]
+fn test_vm_option() {
+    let source = r#"
+fn position(items array, key string) -> Option {
+    let i: int = 0;
+    while i < len(items) {
+        if items[i] == key {
+            return Option::Some(i);
+        }
+        i++;
+    }
+    return Option::None;
+}
+fn main() {
+    let items: array = ["a", "b"];
+    let total: int = 0;
+    match position(items, "b") {
+        Option::Some(i) => { total = total + i; }
+        Option::None => { total = -1; }
+    }
+    print(total);
+    print(position(items, "z"));
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["1", "Option::None"]);
+}
+
+#[test]
+fn test_vm_try_command() {
+    let source = r#"
+fn get(key string) -> string {
+    return match try_command("GET " + key) {
+        Result::Ok(value) => value,
+        Result::Err(err) => "failed: " + err,
+    };
+}
+fn main() {
+    print(get("a"));
+    print(get("missing"));
+}
+"#;
+    let compile_result = do_compile(source).unwrap();
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.set_dkv_command_handler(Some(|command: &str| match command {
+        "GET a" => Ok("1".to_string()),
+        _ => Err("no such key".to_string()),
+    }));
+    vm.run();
+    assert_eq!(*output.borrow(), vec!["1", "failed: no such key"]);
+}
+
+#[test]
+fn test_vm_try_command_without_handler() {
+    let output = run_and_capture(r#"
+match try_command("PING") {
+    Result::Ok(_) => print("ok"),
+    Result::Err(err) => print(err),
+}
+"#);
+    assert_eq!(output, vec!["No DKV command handler set"]);
+}
+
+#[test]
+fn test_vm_catch_runtime_error() {
+    let source = r#"
+fn main() {
+    let zero: int = 0;
+    try {
+        print(1 / zero);
+        print("unreachable");
+    } catch (e) {
+        print(e.message);
+        print(e);
+    }
+    print("after");
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["Division by zero", "Error: Division by zero", "after"]);
+}
+
+#[test]
+fn test_vm_catch_integer_overflow() {
+    let source = r#"
+fn main() {
+    let min: int = -2147483647 - 1;
+    let max: int = 2147483647;
+    let minus_one: int = -1;
+    try {
+        print(min / minus_one);
+    } catch (e) {
+        print(e.message);
+    }
+    try {
+        print(max + 1);
+    } catch (e) {
+        print(e.message);
+    }
+    try {
+        print(min - 1);
+    } catch (e) {
+        print(e.message);
+    }
+    try {
+        print(max * 2);
+    } catch (e) {
+        print(e.message);
+    }
+    try {
+        print(-min);
+    } catch (e) {
+        print(e.message);
+    }
+    try {
+        max++;
+    } catch (e) {
+        print(e.message);
+    }
+    print(max);
+}
+"#;
+    let mut expected = vec!["Integer overflow"; 6];
+    expected.push("2147483647");
+    assert_eq!(run_and_capture(source), expected);
+}
+
+#[test]
+fn test_vm_throw_across_frames() {
+    let source = r#"
+fn check(n int) -> int {
+    if n < 0 {
+        throw "negative: " + string(n);
+    }
+    return n;
+}
+fn wrap(n int) -> int {
+    return check(n) + 1;
+}
+fn main() {
+    try {
+        print(wrap(1));
+        print(wrap(-2));
+    } catch (e) {
+        print(e.message);
+        print(join(e.trace, " <- "));
+    }
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["2", "negative: -2", "check <- wrap <- main <- _entrypoint"]);
+}
+
+#[test]
+fn test_vm_rethrow_keeps_trace() {
+    let source = r#"
+fn fail() {
+    throw "boom";
+}
+fn relay() {
+    try {
+        fail();
+    } catch (e) {
+        print("relay: " + e.message);
+        throw e;
+    }
+}
+fn main() {
+    try {
+        relay();
+    } catch (e) {
+        print(e.trace[0]);
+    }
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["relay: boom", "fail"]);
+}
+
+#[test]
+fn test_vm_finally() {
+    let source = r#"
+fn normal() {
+    try {
+        print("body");
+    } finally {
+        print("finally 1");
+    }
+}
+fn early() -> int {
+    try {
+        return 1;
+    } finally {
+        print("finally 2");
+    }
+}
+fn failing() {
+    try {
+        throw "oops";
+    } finally {
+        print("finally 3");
+    }
+}
+fn main() {
+    normal();
+    print(early());
+    try {
+        failing();
+    } catch (e) {
+        print("caught " + e.message);
+    } finally {
+        print("finally 4");
+    }
+}
+"#;
+    assert_eq!(run_and_capture(source), vec![
+        "body",
+        "finally 1",
+        "finally 2",
+        "1",
+        "finally 3",
+        "caught oops",
+        "finally 4",
+    ]);
+}
+
+#[test]
+fn test_vm_nested_try() {
+    let source = r#"
+fn main() {
+    let items: array = [1, 2];
+    try {
+        try {
+            print(items[5]);
+        } catch (inner) {
+            print("inner: " + inner.message);
+            throw "from catch";
+        } finally {
+            print("inner finally");
+        }
+    } catch (outer) {
+        print("outer: " + outer.message);
+    }
+    let i: int = 0;
+    while i < 3 {
+        try {
+            if i == 1 {
+                throw "skip";
+            }
+            print(i);
+        } catch (e) {
+            print(e.message);
+        }
+        i++;
+    }
+}
+"#;
+    assert_eq!(run_and_capture(source), vec![
+        "inner: Index out of range: 5",
+        "inner finally",
+        "outer: from catch",
+        "0",
+        "skip",
+        "2",
+    ]);
+}
+
+#[test]
+fn test_vm_command_throws() {
+    let source = r#"
+fn main() {
+    print(command("GET a"));
+    try {
+        command("GET missing");
+    } catch (e) {
+        print("caught " + e.message);
+    }
+}
+"#;
+    let compile_result = do_compile(source).unwrap();
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.set_dkv_command_handler(Some(|command: &str| match command {
+        "GET a" => Ok("1".to_string()),
+        _ => Err("no such key".to_string()),
+    }));
+    vm.set_command_throws(true);
+    vm.run();
+    assert_eq!(*output.borrow(), vec!["1", "caught no such key"]);
+}
+
+#[test]
+fn test_vm_try_run_returns_uncaught_error() {
+    let compile_result = do_compile("fn fail() { throw \"bad\"; } fn main() { fail(); }").unwrap();
+    let mut vm = VM::new(compile_result);
+    let error = vm.try_run().unwrap_err();
+    assert_eq!(error.message, "bad");
+    assert_eq!(error.trace, vec!["fail", "main", "_entrypoint"]);
+}
+
+#[test]
+#[should_panic(expected = "Uncaught exception: bad")]
+fn test_vm_uncaught_exception() {
+    run_and_capture("throw \"bad\";");
+}
+
+#[test]
+fn test_vm_constants() {
+    let source = r#"
+const BASE: int = 10;
+const LIMIT: int = BASE * 4 + 2;
+const RATIO: float = LIMIT / 4;
+const PREFIX: string = "user:" + string(BASE);
+const ENABLED: bool = !(LIMIT < 40);
+fn main() {
+    print(LIMIT);
+    print(RATIO);
+    print(PREFIX);
+    print(ENABLED);
+    // 局部变量可以遮蔽常量
+    let BASE: int = 1;
+    print(BASE);
+}
+"#;
+    assert_eq!(run_and_capture(source), vec!["42", "10", "user:10", "true", "1"]);
+}
+
+fn run_optimized_and_capture(source: &str) -> Vec<String> {
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer);
+    let ast = parser.parse();
+    let compile_result = Compiler::new().with_optimization_level(1).compile(&ast);
+    let mut vm = VM::new(compile_result);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+    vm.run();
+    let result = output.borrow().clone();
+    result
+}
+
+#[test]
+fn test_vm_optimized_programs_behave_the_same() {
+    let sources = [
+        "fn main() { print(1 + 2 * 3 + 5); print(-(2.5 * 2)); print(!(1 < 2)); print(float(7) / 2); print(\"a\" + string(1 + 1)); }",
+        r#"
+const DEBUG: bool = false;
+fn classify(n int) -> string {
+    if DEBUG { print("debug"); }
+    if n < 0 { return "negative"; } else if n == 0 { return "zero"; }
+    return "positive";
+    print("dead");
+}
+fn main() {
+    let i: int = -1;
+    while i < 2 { print(classify(i)); i++; }
+    for i = 0; i < 3; i++ { if true { print(i * 10); } }
+}
+"#,
+        r#"
+fn main() {
+    if false { print("dead"); }
+    try { print(1 / 0); } catch (e) { print(e.message); } finally { print("finally"); }
+    try { throw "a" + "b"; } catch (e) { print(e.message); }
+}
+"#,
+    ];
+    for source in sources {
+        assert_eq!(run_optimized_and_capture(source), run_and_capture(source), "{}", source);
+    }
+}
+
+#[test]
+fn test_vm_superinstructions() {
+    let source = r#"
+fn main() {
+    let i: int = 0;
+    let total: float = 0.5;
+    let label: string = "x";
+    while i < 5 {
+        total = total + 2;
+        label = label + "y";
+        i++;
+    }
+    let n: int = 3;
+    while 0 < n { n--; }
+    print(total);
+    print(label);
+    print(i - n);
+    let items: array = [1, "two"];
+    let k: int = 0;
+    try {
+        while items[1] < k { k++; }
+    } catch (e) {
+        print(e.message);
+    }
+}
+"#;
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    let fused = Compiler::new().with_optimization_level(2).compile(&ast);
+    let unfused = Compiler::new().with_optimization_level(1).compile(&ast);
+
+    let mut counts = Vec::new();
+    for compile_result in [unfused, fused] {
+        let mut vm = VM::new(compile_result);
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let sink = output.clone();
+        vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+        vm.run();
+        assert_eq!(output.borrow()[..3], ["10.5", "xyyyyy", "5"]);
+        assert_eq!(output.borrow().len(), 4);
+        counts.push(vm.instruction_count());
+    }
+    // 超级指令减少了执行的指令数
+    assert!(counts[1] < counts[0], "{:?}", counts);
+}
+
+#[test]
+fn test_vm_reset_and_rerun() {
+    let source = r#"
+const GREETING: string = "hi";
+let runs: int = 0;
+let label: string = GREETING;
+fn main() {
+    runs++;
+    label = label + "!";
+    print(f"{runs} {label}");
+    if command("GET mode") == "fail" {
+        throw "failed";
+    }
+}
+"#;
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    for backend in [Backend::Stack, Backend::Register] {
+        let mut vm = VM::new(Compiler::new().with_backend(backend).compile(&ast));
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let sink = output.clone();
+        vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+        let mode = Rc::new(RefCell::new("ok"));
+        let reply = mode.clone();
+        vm.set_dkv_command_handler(Some(move |_: &str| Ok(reply.borrow().to_string())));
+        vm.run();
+        let instructions = vm.instruction_count();
+        for _ in 0..3 {
+            vm.reset();
+            vm.run();
+            assert_eq!(vm.instruction_count(), instructions);
+        }
+        assert_eq!(*output.borrow(), ["1 hi!"; 4]);
+
+        // 未被 catch 的异常之后也可以重置并再次运行
+        *mode.borrow_mut() = "fail";
+        assert_eq!(vm.try_run().unwrap_err().message, "failed");
+        *mode.borrow_mut() = "ok";
+        vm.reset();
+        vm.run();
+        assert_eq!(vm.instruction_count(), instructions);
+        assert_eq!(output.borrow()[4..], ["1 hi!", "1 hi!"]);
+    }
+}
+
+#[test]
+fn test_vm_shared_program() {
+    let compile_result = do_compile(r#"
+let total: int = 0;
+fn add(n int) -> int { total = total + n; return total; }
+fn main() {
+    print(add(1) + add(2));
+    command("INCR runs");
+}
+"#).unwrap();
+    let program = Arc::new(Program::new(compile_result));
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let commands = Rc::new(RefCell::new(0));
+    let mut vms: Vec<VM> = (0..3).map(|_| {
+        let mut vm = VM::from_program(program.clone());
+        let sink = output.clone();
+        vm.set_print_handler(Some(move |text: &str| sink.borrow_mut().push(text.to_string())));
+        let counter = commands.clone();
+        vm.set_dkv_command_handler(Some(move |_: &str| {
+            *counter.borrow_mut() += 1;
+            Ok("OK".to_string())
+        }));
+        vm
+    }).collect();
+    assert_eq!(Arc::strong_count(&program), 4);
+    for vm in &mut vms {
+        vm.run();
+        assert!(Arc::ptr_eq(vm.program(), &program));
+    }
+    // 各 VM 的全局变量相互独立，处理函数在重置后保留
+    vms[0].reset();
+    vms[0].run();
+    assert_eq!(*output.borrow(), ["4", "4", "4", "4"]);
+    assert_eq!(*commands.borrow(), 4);
+}
+
+#[test]
+fn test_vm_program_and_send_vm_are_thread_safe() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>() {}
+    assert_send_sync::<Program>();
+    assert_send_sync::<Arc<Program>>();
+    assert_send::<SendVM>();
+}
+
+#[test]
+fn test_vm_many_threads_share_one_program() {
+    let source = r#"
+let prefix: string = "worker";
+fn fib(n int) -> int {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+fn main() {
+    let id: string = command("GET id");
+    let count: int = int(command("INCR " + id));
+    let cells: array = [];
+    let i: int = 0;
+    while i < 3 {
+        let n: int = i;
+        cells = [cells, fn() -> int { return n * 10; }];
+        i++;
+    }
+    print(f"{prefix}-{id} {count} {fib(12)} {cells[1]() + cells[0][1]()}");
+}
+"#;
+    let lexer = Lexer::new(source.to_string());
+    let ast = Parser::new(lexer).parse();
+    for backend in [Backend::Stack, Backend::Register] {
+        let program = Arc::new(Program::new(Compiler::new().with_backend(backend).compile(&ast)));
+        // 所有线程共享一个模拟的 DKV 存储
+        let store = Arc::new(Mutex::new(HashMap::<String, i32>::new()));
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        // 在主线程中创建 VM，再转移到工作线程中反复运行
+        let workers: Vec<_> = (0..8).map(|id| {
+            let mut vm = SendVM::from_program_send(program.clone());
+            let store = store.clone();
+            vm.set_dkv_command_handler(Some(move |command: &str| match command.split_once(' ') {
+                Some(("GET", "id")) => Ok(id.to_string()),
+                Some(("INCR", key)) => {
+                    let mut store = store.lock().unwrap();
+                    let count = store.entry(key.to_string()).or_insert(0);
+                    *count += 1;
+                    Ok(count.to_string())
+                },
+                _ => Err(format!("unknown command: {}", command)),
+            }));
+            let sink = output.clone();
+            vm.set_print_handler(Some(move |text: &str| sink.lock().unwrap().push(text.to_string())));
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    vm.reset();
+                    vm.run();
+                }
+                vm
+            })
+        }).collect();
+        for worker in workers {
+            let vm = worker.join().unwrap();
+            assert!(Arc::ptr_eq(vm.program(), &program));
+        }
+
+        let mut output = output.lock().unwrap().clone();
+        output.sort();
+        let mut expected: Vec<String> = (0..8)
+            .flat_map(|id| (1..=50).map(move |count| format!("worker-{} {} 144 30", id, count)))
+            .collect();
+        expected.sort();
+        assert_eq!(output, expected);
+        assert_eq!(Arc::strong_count(&program), 1);
+    }
+}